@@ -0,0 +1,725 @@
+use anyhow::{bail, Result};
+use midly::{MetaMessage, MidiMessage, TrackEventKind};
+use std::collections::HashMap;
+
+use crate::midi;
+
+/// Ticks per quarter note of the generated file
+const TICKS_PER_BEAT: u64 = 480;
+
+/// A whole note, the unit ABC lengths are fractions of
+const WHOLE: u64 = TICKS_PER_BEAT * 4;
+
+/// ABC has no dynamics worth following, so every note gets this
+const VELOCITY: u8 = 80;
+
+/// Semitones above C of the note letters C to B
+const SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Letters gaining a sharp, in key signature order; flats go backwards
+const SHARP_ORDER: &str = "FCGDAEB";
+
+/// Index of a note letter in `SEMITONES`
+fn letter_index(letter: char) -> Option<usize> {
+    "CDEFGAB".find(letter.to_ascii_uppercase())
+}
+
+/// One `V:` voice, as it is read
+#[derive(Default)]
+struct Voice {
+    id: String,
+    events: Vec<(u64, TrackEventKind<'static>)>,
+    tick: u64,
+    /// Accidentals written earlier in the bar, by natural pitch
+    bar_accidentals: HashMap<i32, i32>,
+    /// Start and length of the last note, chord or rest
+    last: Option<(u64, u64)>,
+    /// Pitches of the last note or chord and the index of each note-off
+    last_notes: Vec<(u8, usize)>,
+    /// Set by `-`: the next notes carry on the last ones of the same pitch
+    tie: bool,
+    /// Length factor for the next note, left by broken rhythm
+    broken: Option<(u64, u64)>,
+    /// Notes left in a tuplet and the factor they are played at
+    tuplet: Option<(u32, u64, u64)>,
+}
+
+impl Voice {
+    /// Sound `notes` together for `ticks`, or rest if there are none
+    fn play(&mut self, notes: &[u8], mut ticks: u64) {
+        if let Some((num, den)) = self.broken.take() {
+            ticks = ticks * num / den;
+        }
+        if let Some((left, num, den)) = self.tuplet {
+            ticks = ticks * num / den;
+            self.tuplet = (left > 1).then_some((left - 1, num, den));
+        }
+
+        let start = self.tick;
+        let mut played = Vec::new();
+        for &note in notes {
+            let tied = self.last_notes.iter().find(|&&(n, _)| n == note);
+            if let (true, Some(&(_, off))) = (self.tie, tied) {
+                self.events[off].0 = start + ticks;
+                played.push((note, off));
+                continue;
+            }
+            let key = note.into();
+            let on = midi(MidiMessage::NoteOn {
+                key,
+                vel: VELOCITY.into(),
+            });
+            self.events.push((start, on));
+            let off = midi(MidiMessage::NoteOff { key, vel: 0.into() });
+            self.events.push((start + ticks, off));
+            played.push((note, self.events.len() - 1));
+        }
+        self.tie = false;
+        self.last = Some((start, ticks));
+        self.last_notes = played;
+        self.tick += ticks;
+    }
+
+    /// `>` or `<` repeated `count` times: the last note takes length from
+    /// the next one (`>`) or gives it away (`<`)
+    fn broken_rhythm(&mut self, count: u32, longer_first: bool) {
+        let Some((start, length)) = self.last else {
+            return;
+        };
+        let short = length >> count.min(3);
+        let long = 2 * length - short;
+        let (first, second) = if longer_first {
+            (long, short)
+        } else {
+            (short, long)
+        };
+        for &(_, off) in &self.last_notes {
+            self.events[off].0 = start + first;
+        }
+        self.tick = start + first;
+        self.last = Some((start, first));
+        self.broken = Some((second, length));
+    }
+}
+
+fn midi(message: MidiMessage) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: 0.into(),
+        message,
+    }
+}
+
+/// The whole tune as it is read: fields that apply to every voice, and
+/// the voices themselves
+struct Tune {
+    voices: Vec<Voice>,
+    current: usize,
+    /// Sharps (1) and flats (-1) of the key signature, by letter from C
+    key: [i32; 7],
+    meter: Option<(u32, u32)>,
+    /// `L:` length (ticks), if given
+    unit: Option<u64>,
+    tempos: Vec<(u64, u32)>,
+    time_signatures: Vec<(u64, (u32, u32))>,
+}
+
+impl Tune {
+    fn new() -> Self {
+        Self {
+            voices: vec![Voice::default()],
+            current: 0,
+            key: [0; 7],
+            meter: None,
+            unit: None,
+            tempos: Vec::new(),
+            time_signatures: Vec::new(),
+        }
+    }
+
+    fn voice(&mut self) -> &mut Voice {
+        &mut self.voices[self.current]
+    }
+
+    /// Length of notes without one of their own (ticks). Without `L:`
+    /// it is an eighth, or a sixteenth in meters under 3/4.
+    fn unit(&self) -> u64 {
+        let short_meter = self
+            .meter
+            .is_some_and(|(num, den)| (num as f64 / den as f64) < 0.75);
+        self.unit
+            .unwrap_or(if short_meter { WHOLE / 16 } else { WHOLE / 8 })
+    }
+
+    fn bar_length(&self) -> u64 {
+        let (num, den) = self.meter.unwrap_or((4, 4));
+        WHOLE * num as u64 / den as u64
+    }
+
+    /// Apply a header or body field such as `K:G` or `L:1/8`
+    fn field(&mut self, field: char, value: &str) {
+        let value = value.trim();
+        match field {
+            'K' => self.key = key_signature(value),
+            'L' => {
+                if let Some((num, den)) = fraction(value) {
+                    self.unit = Some((WHOLE * num / den).max(1));
+                }
+            }
+            'M' => {
+                self.meter = meter(value);
+                if let Some(meter) = self.meter {
+                    let tick = self.voice().tick;
+                    self.time_signatures.push((tick, meter));
+                }
+            }
+            'Q' => {
+                if let Some(tempo) = tempo(value, self.unit()) {
+                    let tick = self.voice().tick;
+                    self.tempos.push((tick, tempo));
+                }
+            }
+            'V' => {
+                let id = value.split_whitespace().next().unwrap_or("").to_string();
+                self.current = match self.voices.iter().position(|v| v.id == id) {
+                    Some(i) => i,
+                    // Music before the first V: belongs to the first voice
+                    None if self.voices.len() == 1 && self.voices[0].id.is_empty() => {
+                        self.voices[0].id = id;
+                        0
+                    }
+                    None => {
+                        self.voices.push(Voice {
+                            id,
+                            ..Voice::default()
+                        });
+                        self.voices.len() - 1
+                    }
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Read a note at `chars[*i]`: its pitch, if in MIDI range, and length
+    fn note(&mut self, chars: &[char], i: &mut usize) -> Option<(Option<u8>, u64)> {
+        let mut accidental = None;
+        while let Some(&c) = chars.get(*i) {
+            accidental = match c {
+                '^' => Some(accidental.unwrap_or(0) + 1),
+                '_' => Some(accidental.unwrap_or(0) - 1),
+                '=' => Some(0),
+                _ => break,
+            };
+            *i += 1;
+        }
+        let letter = *chars.get(*i)?;
+        let index = letter_index(letter)?;
+        *i += 1;
+
+        let octave = if letter.is_ascii_uppercase() { 60 } else { 72 };
+        let mut natural = octave + SEMITONES[index];
+        while let Some(&c) = chars.get(*i) {
+            match c {
+                '\'' => natural += 12,
+                ',' => natural -= 12,
+                _ => break,
+            }
+            *i += 1;
+        }
+
+        let key = self.key[index];
+        let voice = self.voice();
+        let accidental = match accidental {
+            Some(accidental) => {
+                voice.bar_accidentals.insert(natural, accidental);
+                accidental
+            }
+            None => voice.bar_accidentals.get(&natural).copied().unwrap_or(key),
+        };
+        let pitch = u8::try_from(natural + accidental)
+            .ok()
+            .filter(|&p| p <= 127);
+        Some((pitch, self.length(chars, i)))
+    }
+
+    /// A length such as `3`, `/`, `3/2` or `//` after a note (ticks)
+    fn length(&self, chars: &[char], i: &mut usize) -> u64 {
+        let num = number(chars, i).unwrap_or(1);
+        let mut den = 1;
+        while chars.get(*i) == Some(&'/') {
+            *i += 1;
+            den *= number(chars, i).unwrap_or(2).max(1);
+        }
+        self.unit() * num / den
+    }
+
+    /// Read one line of music
+    fn body(&mut self, line: &str) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                // Guitar chords and annotations, decorations, grace notes
+                '"' | '!' | '+' | '{' => {
+                    let end = if c == '{' { '}' } else { c };
+                    i = chars[i + 1..]
+                        .iter()
+                        .position(|&e| e == end)
+                        .map_or(chars.len(), |at| i + at + 2);
+                }
+                '[' => {
+                    i += 1;
+                    match (chars.get(i), chars.get(i + 1)) {
+                        // An inline field, such as [K:D]
+                        (Some(&field), Some(':')) if field.is_ascii_alphabetic() => {
+                            let end = chars[i..]
+                                .iter()
+                                .position(|&e| e == ']')
+                                .map_or(chars.len(), |at| i + at);
+                            let value: String = chars[i + 2..end].iter().collect();
+                            self.field(field, &value);
+                            i = end + 1;
+                        }
+                        // A repeat ending, such as [1
+                        (Some(d), _) if d.is_ascii_digit() => {}
+                        _ => self.chord(&chars, &mut i),
+                    }
+                }
+                '|' => {
+                    self.voice().bar_accidentals.clear();
+                    i += 1;
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    if let Some((pitch, ticks)) = self.note(&chars, &mut i) {
+                        let notes: Vec<u8> = pitch.into_iter().collect();
+                        self.voice().play(&notes, ticks);
+                    } else {
+                        i += 1;
+                    }
+                }
+                'z' | 'x' => {
+                    i += 1;
+                    let ticks = self.length(&chars, &mut i);
+                    self.voice().play(&[], ticks);
+                }
+                // Whole bars of rest
+                'Z' | 'X' => {
+                    i += 1;
+                    let bars = number(&chars, &mut i).unwrap_or(1);
+                    let ticks = self.bar_length() * bars;
+                    self.voice().play(&[], ticks);
+                }
+                '-' => {
+                    self.voice().tie = true;
+                    i += 1;
+                }
+                '>' | '<' => {
+                    let count = chars[i..].iter().take_while(|&&e| e == c).count();
+                    self.voice().broken_rhythm(count as u32, c == '>');
+                    i += count;
+                }
+                '(' => {
+                    i += 1;
+                    if let Some(p) = number(&chars, &mut i) {
+                        self.tuplet(&chars, &mut i, p);
+                    }
+                }
+                // Bar line repeats, slurs, spacing and the rest
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// `(p:q:r`: the next r notes (default p) take the time of q
+    fn tuplet(&mut self, chars: &[char], i: &mut usize, p: u64) {
+        let mut q = None;
+        let mut r = None;
+        if chars.get(*i) == Some(&':') {
+            *i += 1;
+            q = number(chars, i);
+            if chars.get(*i) == Some(&':') {
+                *i += 1;
+                r = number(chars, i);
+            }
+        }
+        let compound = self.meter.is_some_and(|(num, _)| num % 3 == 0 && num > 3);
+        let q = q.unwrap_or(match p {
+            2 | 4 | 8 => 3,
+            3 | 6 => 2,
+            _ if compound => 3,
+            _ => 2,
+        });
+        if p > 0 {
+            self.voice().tuplet = Some((r.unwrap_or(p) as u32, q, p));
+        }
+    }
+
+    /// Notes between `[` and `]`, played together for the length of the
+    /// first one
+    fn chord(&mut self, chars: &[char], i: &mut usize) {
+        let mut notes = Vec::new();
+        let mut length = None;
+        while let Some(&c) = chars.get(*i) {
+            if c == ']' {
+                *i += 1;
+                break;
+            }
+            match self.note(chars, i) {
+                Some((pitch, ticks)) => {
+                    notes.extend(pitch);
+                    length.get_or_insert(ticks);
+                }
+                None => {
+                    if c == '-' {
+                        self.voice().tie = true;
+                    }
+                    *i += 1;
+                }
+            }
+        }
+        let unit = self.unit();
+        let outer = self.length(chars, i);
+        let ticks = length.unwrap_or(unit) * outer / unit;
+        self.voice().play(&notes, ticks);
+    }
+}
+
+fn number(chars: &[char], i: &mut usize) -> Option<u64> {
+    let mut value: Option<u64> = None;
+    while let Some(digit) = chars.get(*i).and_then(|c| c.to_digit(10)) {
+        value = Some(
+            value
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit as u64),
+        );
+        *i += 1;
+    }
+    value
+}
+
+/// `3/8` as (3, 8)
+fn fraction(value: &str) -> Option<(u64, u64)> {
+    let (num, den) = value.trim().split_once('/')?;
+    let num: u64 = num.trim().parse().ok()?;
+    let den: u64 = den.trim().parse().ok()?;
+    (num > 0 && den > 0).then_some((num, den))
+}
+
+/// `M:` as (beats, beat unit); `C` is 4/4 and `C|` is 2/2
+fn meter(value: &str) -> Option<(u32, u32)> {
+    match value.split_whitespace().next()? {
+        "C" => Some((4, 4)),
+        "C|" => Some((2, 2)),
+        meter => {
+            let (num, den) = meter.split_once('/')?;
+            // Additive meters such as 2+3/8
+            let num = num
+                .split('+')
+                .map(|n| n.trim().parse::<u32>().ok())
+                .sum::<Option<u32>>()?;
+            let den = den.trim().parse().ok()?;
+            (num > 0 && den > 0).then_some((num, den))
+        }
+    }
+}
+
+/// `Q:` as microseconds per quarter note. `1/4=120` counts quarters;
+/// a bare `120` counts `unit`-length notes.
+fn tempo(value: &str, unit: u64) -> Option<u32> {
+    // Drop text such as "Allegro"
+    let value: String = value.split('"').step_by(2).collect();
+    let (beat, bpm) = match value.split_once('=') {
+        Some((beats, bpm)) => {
+            let beat: f64 = beats
+                .split_whitespace()
+                .filter_map(fraction)
+                .map(|(num, den)| num as f64 / den as f64)
+                .sum();
+            (beat, bpm)
+        }
+        None => (0.0, value.as_str()),
+    };
+    let beat = if beat > 0.0 {
+        beat
+    } else {
+        unit as f64 / WHOLE as f64
+    };
+    let bpm: f64 = bpm.split_whitespace().next()?.parse().ok()?;
+    (bpm > 0.0).then(|| (60_000_000.0 / (bpm * beat * 4.0)) as u32)
+}
+
+/// `K:` as sharps (1) and flats (-1) by letter from C. Modes shift the
+/// signature from the tonic's major key: `K:Am` and `K:Ddor` have none.
+fn key_signature(value: &str) -> [i32; 7] {
+    let mut key = [0; 7];
+    let token = value.split_whitespace().next().unwrap_or("");
+    let mut chars = token.chars();
+    let Some(tonic) = chars.next().and_then(|t| SHARP_ORDER.find(t)) else {
+        return key;
+    };
+    let mut fifths = tonic as i32 - 1;
+    let rest = chars.as_str();
+    let mode = match rest.chars().next() {
+        Some('#') => {
+            fifths += 7;
+            &rest[1..]
+        }
+        Some('b') => {
+            fifths -= 7;
+            &rest[1..]
+        }
+        _ => rest,
+    };
+    let mode = mode.to_ascii_lowercase();
+    fifths += match mode.get(..3).unwrap_or(mode.as_str()) {
+        "" | "maj" | "ion" => 0,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "lyd" => 1,
+        "loc" => -5,
+        m if m.starts_with('m') || m == "aeo" => -3,
+        _ => 0,
+    };
+
+    let fifths = fifths.clamp(-7, 7);
+    let letters: Vec<char> = if fifths >= 0 {
+        SHARP_ORDER.chars().take(fifths as usize).collect()
+    } else {
+        SHARP_ORDER.chars().rev().take(-fifths as usize).collect()
+    };
+    for letter in letters {
+        if let Some(index) = letter_index(letter) {
+            key[index] = fifths.signum();
+        }
+    }
+    key
+}
+
+/// `T:` of an ABC tune, if it has one
+pub fn title(text: &str) -> Option<String> {
+    text.lines()
+        .find_map(|line| line.trim().strip_prefix("T:"))
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// A field line such as `K:G`, as its letter and value
+fn field_line(line: &str, in_body: bool) -> Option<(char, &str)> {
+    let mut chars = line.chars();
+    let field = chars.next().filter(|c| c.is_ascii_alphabetic())?;
+    // Note letters only start body lines, in the body
+    if in_body && ('A'..='G').contains(&field) {
+        return None;
+    }
+    let value = chars.as_str().strip_prefix(':')?;
+    Some((field, value))
+}
+
+/// Whether `text` looks like ABC notation: it has a `K:` field, which
+/// every tune needs before its music
+pub fn is_abc(text: &str) -> bool {
+    text.lines()
+        .any(|line| field_line(line.trim(), false).is_some_and(|(field, _)| field == 'K'))
+}
+
+/// Convert the first tune of ABC notation (`X:1`, `K:G`, `GABc|d2`) to a
+/// standard MIDI file. Voices become tracks; repeats play once, and
+/// decorations, grace notes and chord symbols are skipped.
+pub fn to_smf(text: &str) -> Result<Vec<u8>> {
+    let mut tune = Tune::new();
+    let mut in_body = false;
+    let mut seen_tune = false;
+    for line in text.lines() {
+        let line = line.split('%').next().unwrap_or("").trim();
+        match field_line(line, in_body) {
+            Some(('X', _)) if seen_tune => break,
+            Some(('X', _)) => seen_tune = true,
+            Some((field, value)) => {
+                tune.field(field, value);
+                in_body |= field == 'K';
+            }
+            None if in_body => tune.body(line),
+            // Free text in the header
+            None => {}
+        }
+    }
+    if !in_body {
+        bail!("Not an ABC tune: no K: field");
+    }
+    if tune.voices.iter().all(|voice| voice.events.is_empty()) {
+        bail!("The ABC tune has no notes");
+    }
+
+    // Tempo and meter are shared by all voices, so they go in the first
+    // track
+    let first = &mut tune.voices[0].events;
+    for (tick, tempo) in tune.tempos {
+        first.push((tick, TrackEventKind::Meta(MetaMessage::Tempo(tempo.into()))));
+    }
+    for (tick, (num, den)) in tune.time_signatures {
+        if den.is_power_of_two() && num <= u8::MAX as u32 {
+            let signature =
+                MetaMessage::TimeSignature(num as u8, den.trailing_zeros() as u8, 24, 8);
+            first.push((tick, TrackEventKind::Meta(signature)));
+        }
+    }
+
+    let tracks = tune
+        .voices
+        .into_iter()
+        .map(|voice| voice.events)
+        .filter(|events| !events.is_empty())
+        .collect();
+    midi::encode_smf(TICKS_PER_BEAT as u16, tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::Smf;
+
+    /// (tick, note, on) of every note event, by track
+    fn notes(text: &str) -> Vec<Vec<(u64, u8, bool)>> {
+        let data = to_smf(text).unwrap();
+        let smf = Smf::parse(&data).unwrap();
+        smf.tracks
+            .iter()
+            .map(|track| {
+                let mut tick = 0;
+                track
+                    .iter()
+                    .filter_map(|event| {
+                        tick += event.delta.as_int() as u64;
+                        match event.kind {
+                            TrackEventKind::Midi {
+                                message: MidiMessage::NoteOn { key, .. },
+                                ..
+                            } => Some((tick, key.as_int(), true)),
+                            TrackEventKind::Midi {
+                                message: MidiMessage::NoteOff { key, .. },
+                                ..
+                            } => Some((tick, key.as_int(), false)),
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Start tick and pitch of every note in the first track
+    fn starts(text: &str) -> Vec<(u64, u8)> {
+        notes(text)[0]
+            .iter()
+            .filter(|&&(_, _, on)| on)
+            .map(|&(tick, note, _)| (tick, note))
+            .collect()
+    }
+
+    #[test]
+    fn test_notes_and_lengths() {
+        // Eighths by default: C, a quarter D, a sixteenth c, a rest, E,
+        assert_eq!(
+            notes("X:1\nT:Scale\nK:C\nC D2 c/ z E,|")[0],
+            [
+                (0, 60, true),
+                (240, 60, false),
+                (240, 62, true),
+                (720, 62, false),
+                (720, 72, true),
+                (840, 72, false),
+                (1080, 52, true),
+                (1320, 52, false),
+            ]
+        );
+        // Sixteenths by default in short meters
+        assert_eq!(starts("M:2/4\nK:C\nCD"), [(0, 60), (120, 62)]);
+        assert_eq!(starts("L:1/4\nK:C\nc'2C,,"), [(0, 84), (960, 36)]);
+    }
+
+    #[test]
+    fn test_key_and_accidentals() {
+        // F is sharp in G; accidentals last until the bar line
+        assert_eq!(
+            starts("K:G\nF ^c c | c =F _B __B"),
+            [
+                (0, 66),
+                (240, 73),
+                (480, 73),
+                (720, 72),
+                (960, 65),
+                (1200, 70),
+                (1440, 69)
+            ]
+        );
+        assert_eq!(key_signature("Am"), [0; 7]);
+        assert_eq!(key_signature("Ddor"), [0; 7]);
+        assert_eq!(key_signature("Bb"), [0, 0, -1, 0, 0, 0, -1]);
+        assert_eq!(key_signature("F#m"), [1, 0, 0, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_chords_ties_and_rhythms() {
+        // The tie joins two crotchets into one note lasting a minim
+        assert_eq!(
+            notes("L:1/4\nK:C\n[CEG]2 C-C")[0],
+            [
+                (0, 60, true),
+                (0, 64, true),
+                (0, 67, true),
+                (960, 60, false),
+                (960, 64, false),
+                (960, 67, false),
+                (960, 60, true),
+                (1920, 60, false),
+            ]
+        );
+        // Broken rhythm, then a triplet in the time of two eighths
+        assert_eq!(
+            starts("K:C\nA>B (3cde f"),
+            [
+                (0, 69),
+                (360, 71),
+                (480, 72),
+                (640, 74),
+                (800, 76),
+                (960, 77)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fields() {
+        let data = to_smf("X:1\nM:6/8\nQ:1/4=60\nK:C\nC").unwrap();
+        let smf = Smf::parse(&data).unwrap();
+        let metas: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Some((tempo.as_int(), 0)),
+                TrackEventKind::Meta(MetaMessage::TimeSignature(num, den, ..)) => {
+                    Some((num as u32, den))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(metas, [(1_000_000, 0), (6, 3)]);
+        assert_eq!(tempo("\"Allegro\" 3/8=40", WHOLE / 8), Some(1_000_000));
+        assert_eq!(tempo("120", WHOLE / 4), Some(500_000));
+
+        // Voices become tracks, and only the first tune is read
+        let tracks = notes("X:1\nK:C\nV:1\nC\nV:2\nE\nV:1\nD\nX:2\nK:C\nB");
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0][2], (240, 62, true));
+        assert_eq!(tracks[1][0], (0, 64, true));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(to_smf("cdef").is_err());
+        assert!(to_smf("X:1\nK:C\n\"Am\" z4 |").is_err());
+        assert!(!is_abc("t120 o4 cdef"));
+        assert!(is_abc("X:1\nK:Em\nE"));
+    }
+}
@@ -0,0 +1,593 @@
+use serde::{Deserialize, Serialize};
+
+use crate::arrange;
+use crate::config::{AppConfig, Scale, TransposeObjective, MIN_TEMPO};
+use crate::keyboard::Modifier;
+use crate::mapper::{keystroke_to_midi, suggest_transpose, NoteMapper, DRUM_CHANNEL};
+use crate::midi::NoteEvent;
+use crate::playback::ScheduledEvent;
+
+/// Key presses closer together than this count as one chord
+const CHORD_WINDOW_MS: u64 = 30;
+
+/// Krumhansl-Kessler key profiles, indexed by semitones above the tonic
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Estimated key of a song
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyEstimate {
+    /// Pitch class of the tonic (0 = C)
+    pub tonic: u8,
+    pub minor: bool,
+    /// Human-readable key, e.g. "A minor"
+    pub name: String,
+    /// Correlation with the best-matching key profile (-1.0 to 1.0)
+    pub confidence: f64,
+}
+
+/// Estimate the key of a song from its duration-weighted pitch classes.
+/// Returns None if there are no pitched notes.
+pub fn detect_key(events: &[NoteEvent]) -> Option<KeyEstimate> {
+    let mut histogram = [0.0f64; 12];
+    for event in events.iter().filter(|e| e.channel != DRUM_CHANNEL) {
+        histogram[(event.note % 12) as usize] += event.duration_ms.max(1) as f64;
+    }
+    if histogram.iter().all(|&w| w == 0.0) {
+        return None;
+    }
+
+    let (mut tonic, mut minor, mut confidence) = (0u8, false, f64::MIN);
+    for candidate in 0..12u8 {
+        for (is_minor, profile) in [(false, &MAJOR_PROFILE), (true, &MINOR_PROFILE)] {
+            let rotated: Vec<f64> = (0..12)
+                .map(|pc| histogram[(pc + candidate as usize) % 12])
+                .collect();
+            let score = correlation(&rotated, profile);
+            if score > confidence {
+                (tonic, minor, confidence) = (candidate, is_minor, score);
+            }
+        }
+    }
+
+    Some(KeyEstimate {
+        tonic,
+        minor,
+        name: format!(
+            "{} {}",
+            NOTE_NAMES[tonic as usize],
+            if minor { "minor" } else { "major" }
+        ),
+        confidence,
+    })
+}
+
+/// Transpose that puts the song's key on degree 1 of the layout's scale,
+/// shifted by whole octaves to best fit the playable range, within the
+/// -24..=24 the config allows
+pub fn transpose_for_key(key: &KeyEstimate, events: &[NoteEvent], config: &AppConfig) -> i32 {
+    let layout_minor = matches!(
+        config.key_mapping.scale,
+        Scale::NaturalMinor | Scale::MinorPentatonic
+    );
+
+    // Use the relative major/minor when the song and layout disagree
+    let root = match (key.minor, layout_minor) {
+        (true, false) => (key.tonic + 3) % 12,
+        (false, true) => (key.tonic + 9) % 12,
+        _ => key.tonic,
+    } as i32;
+
+    // Smallest shift (-6..=5) that moves the root onto the reference pitch class
+    let reference_pc = (config.reference_midi_note % 12) as i32;
+    let base = (reference_pc - root + 6).rem_euclid(12) - 6;
+
+    let shifted: Vec<u8> = events
+        .iter()
+        .filter(|e| e.channel != DRUM_CHANNEL)
+        .map(|e| (e.note as i32 + base).clamp(0, 127) as u8)
+        .collect();
+    let mut transpose = base + suggest_transpose(&shifted, config);
+    while transpose > 24 {
+        transpose -= 12;
+    }
+    while transpose < -24 {
+        transpose += 12;
+    }
+    transpose
+}
+
+/// How a song's pitched notes fare at one transpose. Every note falls
+/// in exactly one of the three counts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransposeFit {
+    pub transpose: i32,
+    /// Notes played on a key without Shift or Ctrl
+    pub playable: usize,
+    /// Notes the layout can't reach, which are skipped
+    pub dropped: usize,
+    /// Notes that need Shift or Ctrl
+    pub accidentals: usize,
+}
+
+/// Try every transpose from -24 to +24 on the song's pitched notes,
+/// within the note range, for a chart to pick the best one from
+pub fn transpose_fits(
+    events: &[NoteEvent],
+    mapper: &dyn NoteMapper,
+    config: &AppConfig,
+) -> Vec<TransposeFit> {
+    let pitched: Vec<NoteEvent> = events
+        .iter()
+        .filter(|e| e.channel != DRUM_CHANNEL)
+        .cloned()
+        .collect();
+    let pitched = arrange::clamp_range(pitched, config.note_floor, config.note_ceiling);
+
+    let mut config = config.clone();
+    (-24..=24)
+        .map(|transpose| {
+            config.transpose = transpose;
+            let mut fit = TransposeFit {
+                transpose,
+                playable: 0,
+                dropped: 0,
+                accidentals: 0,
+            };
+            for event in &pitched {
+                match mapper.map(event, &config) {
+                    None => fit.dropped += 1,
+                    Some(k) if k.modifier == Modifier::None => fit.playable += 1,
+                    Some(_) => fit.accidentals += 1,
+                }
+            }
+            fit
+        })
+        .collect()
+}
+
+/// The fit that best meets `objective`, preferring the smallest shift
+/// on a tie; 0 if there are no fits
+pub fn best_transpose(fits: &[TransposeFit], objective: TransposeObjective) -> i32 {
+    fits.iter()
+        .min_by_key(|f| {
+            let cost = match objective {
+                TransposeObjective::Range => (f.dropped, f.accidentals),
+                TransposeObjective::Accidentals => (f.dropped + f.accidentals, f.dropped),
+            };
+            (cost, f.transpose.abs())
+        })
+        .map_or(0, |f| f.transpose)
+}
+
+/// How hard a song is to perform, with the measures behind the rating
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Difficulty {
+    /// Overall rating from 1 (easy) to 5 (very hard)
+    pub rating: u8,
+    /// Key presses per second at the current tempo
+    pub notes_per_second: f64,
+    /// Share of onsets that are chords (0.0 to 1.0)
+    pub chord_density: f64,
+    /// Share of key presses that need Shift or Ctrl (0.0 to 1.0)
+    pub accidentals: f64,
+    /// Widest chord in semitones, as a hand stretch when playing manually
+    pub hand_span: u8,
+}
+
+/// Rate how hard the keystroke timeline of a song is to play
+pub fn estimate_difficulty(events: &[ScheduledEvent], config: &AppConfig) -> Difficulty {
+    let presses: Vec<&ScheduledEvent> = events.iter().filter(|e| e.is_key_down).collect();
+    if presses.is_empty() {
+        return Difficulty {
+            rating: 1,
+            notes_per_second: 0.0,
+            chord_density: 0.0,
+            accidentals: 0.0,
+            hand_span: 0,
+        };
+    }
+
+    // Group presses into onsets, tracking each chord's pitch span
+    let mut onsets = 0;
+    let mut chords = 0;
+    let mut hand_span = 0;
+    let mut i = 0;
+    while i < presses.len() {
+        let start = presses[i].time_ms;
+        let chord: Vec<&ScheduledEvent> = presses[i..]
+            .iter()
+            .take_while(|e| e.time_ms - start <= CHORD_WINDOW_MS)
+            .copied()
+            .collect();
+        let pitches: Vec<u8> = chord
+            .iter()
+            .filter_map(|e| keystroke_to_midi(&e.key, e.modifier, config))
+            .collect();
+        if let (Some(low), Some(high)) = (pitches.iter().min(), pitches.iter().max()) {
+            hand_span = hand_span.max(high - low);
+        }
+        onsets += 1;
+        if chord.len() > 1 {
+            chords += 1;
+        }
+        i += chord.len();
+    }
+
+    let first = presses[0].time_ms;
+    let last = events.last().map_or(first, |e| e.time_ms);
+    let seconds = ((last - first) as f64 / 1000.0 / config.tempo_factor).max(1.0);
+    let notes_per_second = presses.len() as f64 / seconds;
+    let chord_density = chords as f64 / onsets as f64;
+    let accidentals = presses
+        .iter()
+        .filter(|e| e.modifier != Modifier::None)
+        .count() as f64
+        / presses.len() as f64;
+
+    // Speed dominates; chords, accidentals and stretches add to it
+    let mut score = (notes_per_second / 3.0).min(2.5) + chord_density + accidentals;
+    if hand_span > 12 {
+        score += 0.5;
+    }
+    let rating = (1.0 + score).round().clamp(1.0, 5.0) as u8;
+
+    Difficulty {
+        rating,
+        notes_per_second,
+        chord_density,
+        accidentals,
+        hand_span,
+    }
+}
+
+/// Window over which the keystroke rate is measured
+const RATE_WINDOW_MS: u64 = 1000;
+
+/// Fastest tempo that keeps a song within a keystroke rate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TempoSuggestion {
+    /// Suggested tempo factor, never above the current one
+    pub tempo_factor: f64,
+    /// Key presses in the busiest one-second window, in song time
+    pub peak_presses: usize,
+    /// Song time where the busiest window starts and ends (ms)
+    pub passage_start_ms: u64,
+    pub passage_end_ms: u64,
+}
+
+/// Suggest the tempo at which no one-second window of the song needs
+/// more than `max_per_second` key presses, and where the song is busiest
+pub fn suggest_tempo(
+    events: &[ScheduledEvent],
+    max_per_second: f64,
+    current: f64,
+) -> TempoSuggestion {
+    let presses: Vec<u64> = events
+        .iter()
+        .filter(|e| e.is_key_down)
+        .map(|e| e.time_ms)
+        .collect();
+
+    // Sliding window over press times for the busiest second
+    let (mut peak, mut passage_start) = (0, 0);
+    let mut first = 0;
+    for (last, &time) in presses.iter().enumerate() {
+        while time - presses[first] >= RATE_WINDOW_MS {
+            first += 1;
+        }
+        if last + 1 - first > peak {
+            peak = last + 1 - first;
+            passage_start = presses[first];
+        }
+    }
+
+    // At tempo t a second of song time takes 1/t seconds to play
+    let limit = if peak == 0 {
+        current
+    } else {
+        max_per_second / peak as f64
+    };
+    TempoSuggestion {
+        tempo_factor: current.min(limit).max(MIN_TEMPO),
+        peak_presses: peak,
+        passage_start_ms: passage_start,
+        passage_end_ms: passage_start + RATE_WINDOW_MS,
+    }
+}
+
+/// How often one physical key is pressed in a song, by modifier
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyUsage {
+    pub key: String,
+    pub plain: usize,
+    pub shift: usize,
+    pub ctrl: usize,
+}
+
+/// Press counts for every key of the layout, in layout order (unused
+/// keys included, at zero), followed by any other keys the song presses
+/// such as alternates and drum keys
+pub fn key_usage(events: &[ScheduledEvent], config: &AppConfig) -> Vec<KeyUsage> {
+    let mut usage: Vec<KeyUsage> = config
+        .key_mapping
+        .keys()
+        .map(|key| KeyUsage {
+            key: key.clone(),
+            ..KeyUsage::default()
+        })
+        .collect();
+
+    for event in events.iter().filter(|e| e.is_key_down) {
+        let index = match usage
+            .iter()
+            .position(|u| u.key.eq_ignore_ascii_case(&event.key))
+        {
+            Some(index) => index,
+            None => {
+                usage.push(KeyUsage {
+                    key: event.key.clone(),
+                    ..KeyUsage::default()
+                });
+                usage.len() - 1
+            }
+        };
+        let entry = &mut usage[index];
+        match event.modifier {
+            Modifier::None => entry.plain += 1,
+            Modifier::Shift => entry.shift += 1,
+            Modifier::Ctrl => entry.ctrl += 1,
+        }
+    }
+    usage
+}
+
+/// Key presses in one stretch of a song
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DensityBucket {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub presses: usize,
+    /// Presses per second of playing time at the current tempo
+    pub per_second: f64,
+}
+
+/// Key presses in each stretch of the song between consecutive
+/// `starts` (sorted), the last one running to the final event
+pub fn note_density(
+    events: &[ScheduledEvent],
+    starts: &[u64],
+    tempo_factor: f64,
+) -> Vec<DensityBucket> {
+    let end = events.last().map_or(0, |e| e.time_ms + 1);
+    let presses: Vec<u64> = events
+        .iter()
+        .filter(|e| e.is_key_down)
+        .map(|e| e.time_ms)
+        .collect();
+
+    let ends = starts.iter().skip(1).copied().chain(std::iter::once(end));
+    starts
+        .iter()
+        .zip(ends)
+        .filter(|&(&start_ms, end_ms)| end_ms > start_ms)
+        .map(|(&start_ms, end_ms)| {
+            let count = presses.partition_point(|&t| t < end_ms)
+                - presses.partition_point(|&t| t < start_ms);
+            let seconds = (end_ms - start_ms) as f64 / 1000.0 / tempo_factor;
+            DensityBucket {
+                start_ms,
+                end_ms,
+                presses: count,
+                per_second: count as f64 / seconds,
+            }
+        })
+        .collect()
+}
+
+/// Pearson correlation of two equally sized series
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+
+    let mut cov = 0.0;
+    let mut var_a = 0.0;
+    let mut var_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+
+    if var_a == 0.0 || var_b == 0.0 {
+        0.0
+    } else {
+        cov / (var_a.sqrt() * var_b.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(pitches: &[u8]) -> Vec<NoteEvent> {
+        pitches
+            .iter()
+            .enumerate()
+            .map(|(i, &note)| NoteEvent {
+                start_ms: i as u64 * 500,
+                duration_ms: 500,
+                note,
+                velocity: 100,
+                track: 0,
+                channel: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_g_major() {
+        // G major scale and tonic arpeggio
+        let events = notes(&[67, 69, 71, 72, 74, 76, 78, 79, 67, 71, 74, 79]);
+        let key = detect_key(&events).unwrap();
+        assert_eq!((key.tonic, key.minor), (7, false));
+
+        // G should land on C (the reference note's pitch class)
+        let transpose = transpose_for_key(&key, &events, &AppConfig::default());
+        assert_eq!((67 + transpose).rem_euclid(12), 0);
+        // A song far below the layout stays within the allowed transpose
+        let low: Vec<u8> = [67, 69, 71, 72, 74, 76, 78, 79].map(|n| n - 48).to_vec();
+        let transpose = transpose_for_key(&key, &notes(&low), &AppConfig::default());
+        assert!((-24..=24).contains(&transpose));
+        assert_eq!((67 + transpose).rem_euclid(12), 0);
+    }
+
+    #[test]
+    fn test_transpose_fits() {
+        let config = AppConfig::default();
+        let mapper = crate::mapper::mapper_for(&config).unwrap();
+        let fits = transpose_fits(&notes(&[60, 61, 84]), mapper.as_ref(), &config);
+        assert_eq!(fits.len(), 49);
+
+        let count = |transpose: i32| {
+            let fit = &fits[(transpose + 24) as usize];
+            assert_eq!(fit.transpose, transpose);
+            (fit.playable, fit.accidentals, fit.dropped)
+        };
+        assert_eq!(count(0), (1, 1, 1));
+        assert_eq!(count(-12), (2, 1, 0));
+        assert_eq!(count(-24), (1, 0, 2));
+        // Down a semitone the notes are B3, C4 and B5, all naturals
+        assert_eq!(best_transpose(&fits, TransposeObjective::Range), -1);
+
+        // Three accidentals, or one note dropped and no accidentals
+        let fits = [(0, 0, 3), (5, 1, 0)].map(|(transpose, dropped, accidentals)| TransposeFit {
+            transpose,
+            playable: 10,
+            dropped,
+            accidentals,
+        });
+        assert_eq!(best_transpose(&fits, TransposeObjective::Range), 0);
+        assert_eq!(best_transpose(&fits, TransposeObjective::Accidentals), 5);
+    }
+
+    #[test]
+    fn test_estimate_difficulty() {
+        let config = AppConfig::default();
+        let press = |time_ms, key: &str, modifier| ScheduledEvent {
+            time_ms,
+            key: key.to_string(),
+            modifier,
+            is_key_down: true,
+            track: 0,
+            channel: 0,
+        };
+
+        // A slow single-note melody is easy
+        let slow: Vec<_> = (0..10)
+            .map(|i| press(i * 1000, "A", Modifier::None))
+            .collect();
+        assert_eq!(estimate_difficulty(&slow, &config).rating, 1);
+
+        // Fast chords spanning two octaves with sharps are hard
+        let fast: Vec<_> = (0..100)
+            .flat_map(|i| {
+                [
+                    press(i * 100, "Z", Modifier::None),
+                    press(i * 100, "R", Modifier::Shift),
+                ]
+            })
+            .collect();
+        let difficulty = estimate_difficulty(&fast, &config);
+        assert_eq!(difficulty.hand_span, 30);
+        assert_eq!(difficulty.chord_density, 1.0);
+        assert_eq!(difficulty.rating, 5);
+    }
+
+    #[test]
+    fn test_suggest_tempo() {
+        // Calm first second, then 20 presses within the next
+        let times = [0, 500].into_iter().chain((0..20).map(|i| 2000 + i * 40));
+        let events: Vec<_> = times
+            .map(|time_ms| ScheduledEvent {
+                time_ms,
+                key: "A".to_string(),
+                modifier: Modifier::None,
+                is_key_down: true,
+                track: 0,
+                channel: 0,
+            })
+            .collect();
+
+        let suggestion = suggest_tempo(&events, 10.0, 1.0);
+        assert_eq!(suggestion.peak_presses, 20);
+        assert_eq!(suggestion.passage_start_ms, 2000);
+        assert_eq!(suggestion.tempo_factor, 0.5);
+
+        // Already slow enough: keep the current tempo
+        assert_eq!(suggest_tempo(&events, 100.0, 0.8).tempo_factor, 0.8);
+    }
+
+    #[test]
+    fn test_note_density() {
+        let events: Vec<_> = [0, 100, 900, 1000, 2500]
+            .into_iter()
+            .map(|time_ms| ScheduledEvent {
+                time_ms,
+                key: "A".to_string(),
+                modifier: Modifier::None,
+                is_key_down: true,
+                track: 0,
+                channel: 0,
+            })
+            .collect();
+
+        // The last bucket runs to just after the final press
+        let density = note_density(&events, &[0, 1000, 2000], 0.5);
+        let counts: Vec<(u64, usize)> = density.iter().map(|b| (b.end_ms, b.presses)).collect();
+        assert_eq!(counts, [(1000, 3), (2000, 1), (2501, 1)]);
+
+        // Half tempo: a second of song takes two to play
+        assert_eq!(density[0].per_second, 1.5);
+    }
+
+    #[test]
+    fn test_key_usage() {
+        let config = AppConfig::default();
+        let events: Vec<_> = [
+            ("A", Modifier::None),
+            ("A", Modifier::Shift),
+            ("A", Modifier::None),
+        ]
+        .into_iter()
+        .map(|(key, modifier)| ScheduledEvent {
+            time_ms: 0,
+            key: key.to_string(),
+            modifier,
+            is_key_down: true,
+            track: 0,
+            channel: 0,
+        })
+        .collect();
+
+        let usage = key_usage(&events, &config);
+        assert_eq!(usage.len(), config.key_mapping.key_count());
+        let a = usage.iter().find(|u| u.key == "A").unwrap();
+        assert_eq!((a.plain, a.shift, a.ctrl), (2, 1, 0));
+        assert_eq!(usage[0].plain, 0);
+    }
+
+    #[test]
+    fn test_detect_empty() {
+        assert!(detect_key(&[]).is_none());
+    }
+}
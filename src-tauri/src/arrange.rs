@@ -0,0 +1,329 @@
+use crate::config::{AppConfig, Arrangement, LayoutMode, RollConfig, VelocityCurve};
+use crate::mapper::{key_pitch, DRUM_CHANNEL};
+use crate::midi::{Beat, NoteEvent};
+
+/// Notes starting closer together than this share a time slice
+const ONSET_WINDOW_MS: u64 = 30;
+
+/// Drop notes outside `note_floor`..=`note_ceiling`, reduce a song's
+/// notes according to the chosen arrangement, then place them for two
+/// hands if `split_hands` is enabled and re-strike long notes as set by
+/// `rolls` and `retrigger_ms`. `beats` is the song's beat grid, in the
+/// same time as the notes.
+pub fn arrange(events: Vec<NoteEvent>, beats: &[Beat], config: &AppConfig) -> Vec<NoteEvent> {
+    let events = clamp_range(events, config.note_floor, config.note_ceiling);
+    let events = match config.arrangement {
+        Arrangement::Full => events,
+        Arrangement::Melody => extract_melody(&events),
+    };
+    let events = split_hands(events, config);
+    let events = roll(events, beats, &config.rolls);
+    retrigger(events, config.retrigger_ms)
+}
+
+/// Keep drums and the pitched notes within `floor..=ceiling`
+pub fn clamp_range(mut events: Vec<NoteEvent>, floor: u8, ceiling: u8) -> Vec<NoteEvent> {
+    events.retain(|e| e.channel == DRUM_CHANNEL || (floor..=ceiling).contains(&e.note));
+    events
+}
+
+/// Run every note's velocity through `curve`. Done before anything else
+/// reads velocities: user scripts filtering on them, and the polyphony
+/// limit keeping the loudest notes of a chord.
+pub fn shape_velocities(mut events: Vec<NoteEvent>, curve: &VelocityCurve) -> Vec<NoteEvent> {
+    if *curve != VelocityCurve::Linear {
+        for event in &mut events {
+            event.velocity = curve.apply(event.velocity);
+        }
+    }
+    events
+}
+
+/// Play pitched notes lasting at least `min_beats` as `per_beat` strikes
+/// per beat, on the beat grid so the roll follows tempo changes. Songs
+/// with fewer than two beats have no grid and are left alone.
+pub fn roll(events: Vec<NoteEvent>, beats: &[Beat], rolls: &RollConfig) -> Vec<NoteEvent> {
+    if !rolls.enabled || beats.len() < 2 {
+        return events;
+    }
+
+    // Subdivision points, running one beat past the last so notes held
+    // to the very end are covered
+    let times: Vec<u64> = beats.iter().map(|b| b.time_ms).collect();
+    let last = times[times.len() - 1];
+    let past_end = last + (last - times[times.len() - 2]);
+    let intervals = times
+        .windows(2)
+        .map(|w| (w[0], w[1]))
+        .chain(std::iter::once((last, past_end)));
+    let per_beat = rolls.per_beat as u64;
+    let mut grid = Vec::new();
+    for (from, to) in intervals {
+        grid.extend((0..per_beat).map(|k| from + (to - from) * k / per_beat));
+    }
+
+    let mut rolled: Vec<NoteEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        let end_ms = event.start_ms + event.duration_ms;
+        let beat = times
+            .partition_point(|&t| t <= event.start_ms)
+            .clamp(1, times.len() - 1);
+        let beat_ms = times[beat] - times[beat - 1];
+        let long = event.duration_ms as f64 >= rolls.min_beats * beat_ms as f64;
+        if event.channel == DRUM_CHANNEL || !long {
+            rolled.push(event);
+            continue;
+        }
+
+        let from = grid.partition_point(|&t| t <= event.start_ms);
+        let to = grid.partition_point(|&t| t < end_ms);
+        let mut strikes = vec![event.start_ms];
+        strikes.extend(&grid[from..to]);
+        strikes.push(end_ms);
+        rolled.extend(strikes.windows(2).map(|w| NoteEvent {
+            start_ms: w[0],
+            duration_ms: w[1] - w[0],
+            ..event.clone()
+        }));
+    }
+    rolled.sort_by_key(|e| e.start_ms);
+    rolled
+}
+
+/// Split every pitched note longer than `every_ms` into repeated strikes
+/// `every_ms` apart covering the same time. Drums are left alone.
+pub fn retrigger(events: Vec<NoteEvent>, every_ms: u64) -> Vec<NoteEvent> {
+    if every_ms == 0 {
+        return events;
+    }
+
+    let mut struck: Vec<NoteEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        if event.channel == DRUM_CHANNEL || event.duration_ms <= every_ms {
+            struck.push(event);
+            continue;
+        }
+        let end_ms = event.start_ms + event.duration_ms;
+        struck.extend(
+            (event.start_ms..end_ms)
+                .step_by(every_ms as usize)
+                .map(|start_ms| NoteEvent {
+                    start_ms,
+                    duration_ms: every_ms.min(end_ms - start_ms),
+                    ..event.clone()
+                }),
+        );
+    }
+    struck.sort_by_key(|e| e.start_ms);
+    struck
+}
+
+/// Fold each hand's notes by octaves onto its own keys: the left hand
+/// onto the lowest row and the right hand onto the rows above, each with
+/// its own transpose. Notes keep their track, so both hands can still be
+/// told apart afterwards.
+pub fn split_hands(events: Vec<NoteEvent>, config: &AppConfig) -> Vec<NoteEvent> {
+    let split = &config.split_hands;
+    let key_count = config.key_mapping.key_count();
+    let low_row = config.key_mapping.rows.first().map_or(0, Vec::len);
+    if !split.enabled
+        || config.key_mapping.mode == LayoutMode::Drums
+        || low_row == 0
+        || low_row >= key_count
+    {
+        return events;
+    }
+
+    // Ranges are in transposed pitch, like `key_pitch`
+    let left = (key_pitch(0, config), key_pitch(low_row - 1, config));
+    let right = (key_pitch(low_row, config), key_pitch(key_count - 1, config));
+
+    events
+        .into_iter()
+        .filter_map(|mut event| {
+            if event.channel == DRUM_CHANNEL {
+                return Some(event);
+            }
+            let is_left = if split.left_tracks.is_empty() {
+                event.note < split.split_note
+            } else {
+                split.left_tracks.contains(&event.track)
+            };
+            let ((low, high), hand_transpose) = if is_left {
+                (left, split.left_transpose)
+            } else {
+                (right, split.right_transpose)
+            };
+
+            let mut pitch = event.note as i32 + config.transpose + hand_transpose;
+            while pitch < low && pitch + 12 <= high {
+                pitch += 12;
+            }
+            while pitch > high && pitch - 12 >= low {
+                pitch -= 12;
+            }
+            event.note = u8::try_from(pitch - config.transpose)
+                .ok()
+                .filter(|&n| n <= 127)?;
+            Some(event)
+        })
+        .collect()
+}
+
+/// Skyline melody: the highest note of each time slice, as a single line.
+/// Drums are dropped, octave doublings that would leap away from the
+/// line are folded back, and accompaniment entering under a held melody
+/// note is ignored.
+pub fn extract_melody(events: &[NoteEvent]) -> Vec<NoteEvent> {
+    let mut pitched: Vec<&NoteEvent> = events
+        .iter()
+        .filter(|e| e.channel != DRUM_CHANNEL)
+        .collect();
+    pitched.sort_by_key(|e| e.start_ms);
+
+    let mut melody: Vec<NoteEvent> = Vec::new();
+    let mut i = 0;
+    while i < pitched.len() {
+        let start = pitched[i].start_ms;
+        let mut slice: Vec<&NoteEvent> = pitched[i..]
+            .iter()
+            .take_while(|e| e.start_ms - start <= ONSET_WINDOW_MS)
+            .copied()
+            .collect();
+        i += slice.len();
+        slice.sort_by_key(|e| std::cmp::Reverse(e.note));
+
+        let mut chosen = slice[0];
+        if let Some(previous) = melody.last() {
+            // Prefer a lower doubling of the top note over a leap past an octave
+            if chosen.note > previous.note + 12 {
+                if let Some(doubling) = slice.iter().find(|e| {
+                    e.note % 12 == chosen.note % 12 && e.note.abs_diff(previous.note) <= 12
+                }) {
+                    chosen = doubling;
+                }
+            }
+
+            let sounding = previous.start_ms + previous.duration_ms > chosen.start_ms;
+            if sounding && previous.note > chosen.note {
+                continue;
+            }
+        }
+        melody.push(chosen.clone());
+    }
+
+    // One voice: each note ends where the next begins
+    for i in 1..melody.len() {
+        let next_start = melody[i].start_ms;
+        let note = &mut melody[i - 1];
+        note.duration_ms = note.duration_ms.min(next_start - note.start_ms);
+    }
+    melody
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::note;
+
+    #[test]
+    fn test_extract_melody() {
+        let events = vec![
+            // C major chord under a melody E5
+            note(0, 1000, 48),
+            note(0, 1000, 52),
+            note(5, 400, 76),
+            // Accompaniment under the held melody note
+            note(200, 200, 55),
+            // Melody G5 doubled two octaves up
+            note(500, 500, 79),
+            note(500, 500, 91),
+        ];
+        let melody: Vec<(u64, u64, u8)> = extract_melody(&events)
+            .iter()
+            .map(|n| (n.start_ms, n.duration_ms, n.note))
+            .collect();
+        assert_eq!(melody, [(5, 400, 76), (500, 500, 79)]);
+    }
+
+    #[test]
+    fn test_roll() {
+        // 500 ms beats, then 250 ms beats from 1000 ms
+        let beats = [0, 500, 1000, 1250, 1500].map(|time_ms| Beat {
+            time_ms,
+            bar: 1,
+            beat: 1,
+            time_signature: (4, 4),
+        });
+        let rolls = RollConfig {
+            enabled: true,
+            min_beats: 1.5,
+            per_beat: 2,
+        };
+        let events = vec![note(600, 900, 60), note(0, 600, 64)];
+        let notes: Vec<(u64, u64, u8)> = roll(events, &beats, &rolls)
+            .iter()
+            .map(|n| (n.start_ms, n.duration_ms, n.note))
+            .collect();
+        // Eighths of each beat, so the roll speeds up with the tempo
+        assert_eq!(
+            notes,
+            [
+                (0, 600, 64),
+                (600, 150, 60),
+                (750, 250, 60),
+                (1000, 125, 60),
+                (1125, 125, 60),
+                (1250, 125, 60),
+                (1375, 125, 60)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clamp_range() {
+        let mut kick = note(0, 100, 35);
+        kick.channel = DRUM_CHANNEL;
+        let events = vec![note(0, 100, 28), kick, note(0, 100, 36), note(0, 100, 97)];
+        let notes: Vec<u8> = clamp_range(events, 36, 96).iter().map(|n| n.note).collect();
+        assert_eq!(notes, [35, 36]);
+    }
+
+    #[test]
+    fn test_retrigger() {
+        let mut drum = note(0, 1000, 38);
+        drum.channel = DRUM_CHANNEL;
+        let events = vec![note(0, 500, 60), note(100, 100, 64), drum];
+        let notes: Vec<(u64, u64, u8)> = retrigger(events, 200)
+            .iter()
+            .map(|n| (n.start_ms, n.duration_ms, n.note))
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                (0, 200, 60),
+                (0, 1000, 38),
+                (100, 100, 64),
+                (200, 200, 60),
+                (400, 100, 60)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_hands() {
+        let mut config = AppConfig::default();
+        config.split_hands.enabled = true;
+        config.split_hands.right_transpose = 12;
+
+        // Default rows: C3-B3 on the low row, C4-B5 above
+        let events = [36, 59, 64, 72].map(|n| note(0, 100, n)).to_vec();
+        let notes: Vec<u8> = split_hands(events, &config)
+            .iter()
+            .map(|n| n.note)
+            .collect();
+        // Left: C2 folds up and B3 fits. Right, an octave up: E4 plays E5,
+        // while C5 would be C6, past the top, and folds back
+        assert_eq!(notes, [48, 59, 76, 72]);
+    }
+}
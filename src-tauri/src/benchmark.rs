@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::thread;
+use std::time::Instant;
+
+use crate::playback::TICK;
+
+/// Length of the synthetic song
+const BENCHMARK_MS: u64 = 5000;
+
+/// A three-key chord starts this often, and each key is held for half of it
+const CHORD_INTERVAL_MS: u64 = 50;
+const CHORD_SIZE: usize = 3;
+
+/// Events later than this are audibly out of time
+const LATE_MS: f64 = 5.0;
+
+/// How closely events fired to their scheduled times
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingReport {
+    pub events: usize,
+    pub mean_error_ms: f64,
+    pub p95_error_ms: f64,
+    pub max_error_ms: f64,
+    /// Share of events later than `LATE_MS`, as a percentage
+    pub late_percent: f64,
+    /// Whether timing is tight enough for a live performance
+    pub passed: bool,
+}
+
+/// Play a dense synthetic song with the same tick loop as the playback
+/// engine, recording when each event fires instead of sending keys.
+/// Blocks for about five seconds.
+pub fn run() -> TimingReport {
+    let scheduled = synthetic_timeline();
+    let mut errors_ms = Vec::with_capacity(scheduled.len());
+
+    let started = Instant::now();
+    let mut next = 0;
+    while next < scheduled.len() {
+        let position_ms = started.elapsed().as_secs_f64() * 1000.0;
+        while next < scheduled.len() && scheduled[next] as f64 <= position_ms {
+            // The mock backend: note when the event fired
+            let fired_ms = started.elapsed().as_secs_f64() * 1000.0;
+            errors_ms.push(fired_ms - scheduled[next] as f64);
+            next += 1;
+        }
+        thread::sleep(TICK);
+    }
+    report(errors_ms)
+}
+
+/// Scheduled times of every key down and key up, in order
+fn synthetic_timeline() -> Vec<u64> {
+    let mut times = Vec::new();
+    for start_ms in (0..BENCHMARK_MS).step_by(CHORD_INTERVAL_MS as usize) {
+        times.extend([start_ms; CHORD_SIZE]);
+        times.extend([start_ms + CHORD_INTERVAL_MS / 2; CHORD_SIZE]);
+    }
+    times
+}
+
+fn report(mut errors_ms: Vec<f64>) -> TimingReport {
+    errors_ms.sort_by(f64::total_cmp);
+    let events = errors_ms.len();
+    let count = events.max(1) as f64;
+    let mean_error_ms = errors_ms.iter().sum::<f64>() / count;
+    let p95_index = (events * 95 / 100).min(events.saturating_sub(1));
+    let p95_error_ms = errors_ms.get(p95_index).copied().unwrap_or(0.0);
+    let max_error_ms = errors_ms.last().copied().unwrap_or(0.0);
+    let late = errors_ms.iter().filter(|&&e| e > LATE_MS).count();
+
+    TimingReport {
+        events,
+        mean_error_ms,
+        p95_error_ms,
+        max_error_ms,
+        late_percent: late as f64 * 100.0 / count,
+        passed: p95_error_ms <= LATE_MS && max_error_ms <= LATE_MS * 4.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        // 19 events on time and one 30 ms late
+        let mut errors = vec![0.5; 19];
+        errors.push(30.0);
+        let report = report(errors);
+        assert_eq!(report.events, 20);
+        assert_eq!(report.p95_error_ms, 30.0);
+        assert_eq!(report.late_percent, 5.0);
+        assert!(!report.passed);
+    }
+}
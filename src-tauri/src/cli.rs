@@ -0,0 +1,164 @@
+use anyhow::{anyhow, bail, Result};
+use std::sync::mpsc;
+
+use crate::config::{describe_errors, AppConfig};
+use crate::instance;
+use crate::midi;
+use crate::playback::{PlaybackEngine, PlaybackEvent};
+
+const USAGE: &str = "\
+Usage: wwmp [<file.mid> | play <file.mid> [options]]
+
+Without arguments the player window opens; with just a file it opens
+with that file loaded.
+
+Options for play:
+  --transpose <semitones>  Shift all notes, e.g. -3
+  --tempo <factor>         Playback speed, e.g. 0.9
+  --delay <ms>             Countdown before the first note
+  --no-accidentals         Skip notes that need Shift/Ctrl";
+
+/// Options for `wwmp play`, applied on top of the saved config
+#[derive(Debug, Default, PartialEq)]
+struct PlayArgs {
+    path: String,
+    transpose: Option<i32>,
+    tempo: Option<f64>,
+    delay_ms: Option<u64>,
+    no_accidentals: bool,
+}
+
+/// Run a command-line invocation without opening the webview.
+/// Returns `None` when there are no arguments, or just a file to open,
+/// and the GUI should start, otherwise the process exit code.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.first()?;
+    if instance::file_argument(args).is_some() {
+        return None;
+    }
+    attach_console();
+
+    let result = match command.as_str() {
+        "play" => parse_play(&args[1..]).and_then(play),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(anyhow!("Unknown command '{}'\n\n{}", command, USAGE)),
+    };
+
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Some(1)
+        }
+    }
+}
+
+fn parse_play(args: &[String]) -> Result<PlayArgs> {
+    let mut play = PlayArgs::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| anyhow!("{} needs a value", name));
+        match arg.as_str() {
+            "--transpose" => play.transpose = Some(value(arg)?.parse()?),
+            "--tempo" => play.tempo = Some(value(arg)?.parse()?),
+            "--delay" => play.delay_ms = Some(value(arg)?.parse()?),
+            "--no-accidentals" => play.no_accidentals = true,
+            _ if arg.starts_with("--") => bail!("Unknown option '{}'", arg),
+            _ if play.path.is_empty() => play.path = arg.clone(),
+            _ => bail!("Unexpected argument '{}'", arg),
+        }
+    }
+
+    if play.path.is_empty() {
+        bail!("No MIDI file given\n\n{}", USAGE);
+    }
+    Ok(play)
+}
+
+/// Play a song to completion with the saved config plus any overrides
+fn play(args: PlayArgs) -> Result<()> {
+    let (mut config, report) = AppConfig::load()?;
+    for problem in &report.reset {
+        eprintln!("Reset to default: {}", problem);
+    }
+    for warning in &report.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if let Some(transpose) = args.transpose {
+        config.transpose = transpose;
+    }
+    if let Some(tempo) = args.tempo {
+        config.tempo_factor = tempo;
+    }
+    if let Some(delay_ms) = args.delay_ms {
+        config.start_delay_ms = delay_ms;
+    }
+    if args.no_accidentals {
+        config.accidentals = false;
+    }
+
+    let errors = config.validate();
+    if !errors.is_empty() {
+        bail!("Invalid options: {}", describe_errors(&errors));
+    }
+
+    let midi_file = midi::load_file(&args.path, config.note_pairing)?;
+    let info = midi_file.info(config.transpose);
+    println!("Playing {} ({} notes)", args.path, info.note_count);
+
+    let (tx, rx) = mpsc::channel();
+    let mut engine = PlaybackEngine::new();
+    engine.set_listener(move |event| {
+        if matches!(
+            event,
+            PlaybackEvent::Finished { .. } | PlaybackEvent::Stopped { .. }
+        ) {
+            let _ = tx.send(());
+        }
+    });
+    engine.start(&midi_file, &config)?;
+
+    // An empty song never starts the playback thread
+    if engine.is_playing() {
+        let _ = rx.recv();
+    }
+    Ok(())
+}
+
+/// Release builds are GUI-subsystem executables with no console of their
+/// own, so borrow the one we were started from for output
+#[cfg(windows)]
+fn attach_console() {
+    use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    let _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_play_args() {
+        let play = parse_play(&args(&["song.mid", "--transpose", "-3", "--tempo", "0.9"])).unwrap();
+        assert_eq!(play.path, "song.mid");
+        assert_eq!(play.transpose, Some(-3));
+        assert_eq!(play.tempo, Some(0.9));
+        assert!(!play.no_accidentals);
+
+        assert!(parse_play(&args(&[])).is_err());
+        assert!(parse_play(&args(&["song.mid", "--tempo"])).is_err());
+        assert!(parse_play(&args(&["song.mid", "--loud"])).is_err());
+        assert!(parse_play(&args(&["a.mid", "b.mid"])).is_err());
+    }
+}
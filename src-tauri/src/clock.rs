@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use std::net::UdpSocket;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager};
+
+use crate::AppState;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_OFFSET_S: u64 = 2_208_988_800;
+
+/// Milliseconds since the Unix epoch on this machine's clock
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Start the loaded song when our clock reads `local_ms`, unless another
+/// start is scheduled or the schedule is cancelled first. The song starts
+/// right then, without the start delay or count-in.
+pub fn start_at(app: &AppHandle, local_ms: u64) {
+    let state = app.state::<AppState>();
+    let expected = state.scheduled_start.fetch_add(1, Ordering::SeqCst) + 1;
+    let app = app.clone();
+    thread::spawn(move || {
+        let wait = local_ms.saturating_sub(now_ms());
+        thread::sleep(Duration::from_millis(wait));
+        let state = app.state::<AppState>();
+        if state.scheduled_start.load(Ordering::SeqCst) == expected {
+            let _ = crate::start_scheduled_playback(&state);
+        }
+    });
+}
+
+/// Drop any start waiting in `start_at`
+pub fn cancel_scheduled_start(state: &AppState) {
+    state.scheduled_start.fetch_add(1, Ordering::SeqCst);
+}
+
+/// How far `server`'s clock (host or host:port) is ahead of ours, from a
+/// single SNTP exchange
+pub fn ntp_offset_ms(server: &str) -> Result<i64> {
+    let address = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:123", server)
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+
+    let mut request = [0u8; 48];
+    request[0] = 0x1B; // version 3, client mode
+    let sent_ms = now_ms();
+    socket.send_to(&request, address.as_str())?;
+
+    let mut response = [0u8; 48];
+    let (len, _) = socket.recv_from(&mut response)?;
+    let received_ms = now_ms();
+    if len < response.len() {
+        bail!("Short NTP reply from {}", server);
+    }
+
+    let server_received_ms = ntp_to_unix_ms(&response[32..40]);
+    let server_sent_ms = ntp_to_unix_ms(&response[40..48]);
+    Ok(sntp_offset(
+        sent_ms,
+        server_received_ms,
+        server_sent_ms,
+        received_ms,
+    ))
+}
+
+/// An NTP timestamp (32-bit seconds, 32-bit fraction) as Unix milliseconds
+fn ntp_to_unix_ms(timestamp: &[u8]) -> u64 {
+    let seconds = u32::from_be_bytes(timestamp[0..4].try_into().unwrap()) as u64;
+    let fraction = u32::from_be_bytes(timestamp[4..8].try_into().unwrap()) as u64;
+    seconds.saturating_sub(NTP_UNIX_OFFSET_S) * 1000 + ((fraction * 1000) >> 32)
+}
+
+/// The standard SNTP clock offset from the four exchange timestamps
+fn sntp_offset(
+    sent_ms: u64,
+    server_received_ms: u64,
+    server_sent_ms: u64,
+    received_ms: u64,
+) -> i64 {
+    let there = server_received_ms as i64 - sent_ms as i64;
+    let back = server_sent_ms as i64 - received_ms as i64;
+    (there + back) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sntp_offset() {
+        // Half a second past 2001-09-09 01:46:40 UTC (Unix 1_000_000_000)
+        let seconds = (NTP_UNIX_OFFSET_S + 1_000_000_000) as u32;
+        let mut timestamp = seconds.to_be_bytes().to_vec();
+        timestamp.extend(0x8000_0000u32.to_be_bytes());
+        assert_eq!(ntp_to_unix_ms(&timestamp), 1_000_000_000_500);
+
+        // Server 250 ms ahead, 20 ms each way
+        assert_eq!(sntp_offset(1000, 1270, 1275, 1045), 250);
+    }
+}
@@ -1,11 +1,21 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::gamepad;
+use crate::keyboard;
+use crate::mapper;
+
+/// Slowest and fastest allowed tempo factors
+pub const MIN_TEMPO: f64 = 0.25;
+pub const MAX_TEMPO: f64 = 4.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
-    /// MIDI note that maps to Medium octave, degree 1 (default: C4 = 60)
+    /// MIDI note played by the layout's reference key (default: C4 = 60)
     pub reference_midi_note: u8,
 
     /// Tempo multiplier (1.0 = normal speed)
@@ -14,30 +24,645 @@ pub struct AppConfig {
     /// Transpose in semitones (-24 to +24)
     pub transpose: i32,
 
+    /// Written notes below `note_floor` or above `note_ceiling` are
+    /// ignored before mapping, e.g. 36 to drop everything below C2.
+    /// Drums are kept.
+    pub note_floor: u8,
+    pub note_ceiling: u8,
+
     /// Maximum simultaneous notes (1-3)
     pub max_polyphony: u8,
 
+    /// Most key presses per second the game reliably registers, used to
+    /// suggest a tempo
+    pub max_keys_per_second: f64,
+
     /// Delay before playback starts (ms)
     pub start_delay_ms: u64,
 
-    /// Key mappings for each octave
+    /// Start at the song's first note rather than at its beginning,
+    /// skipping any empty bars before it
+    pub skip_silence: bool,
+
+    /// With `skip_silence`, how long before the first note to start (ms)
+    pub lead_in_ms: u64,
+
+    /// Shifts every keystroke later (positive) or earlier (negative) to
+    /// make up for input lag in the game or a Bluetooth keyboard (ms)
+    pub latency_offset_ms: i64,
+
+    /// Beat events for the UI during the start delay and the song
+    pub metronome: MetronomeConfig,
+
+    /// Send each note to the window as the game should sound it, so it
+    /// can be heard through headphones alongside the keystrokes
+    pub audio_preview: bool,
+    /// Loudness of the audio preview, 0.0 (silent) to 1.0
+    pub preview_volume: f64,
+    /// Audio output device id for the preview; empty uses the system default
+    pub preview_device: String,
+
+    /// Instrument layout: key rows and how they map to pitches
     pub key_mapping: KeyMapping,
 
+    /// Whether the instrument has Shift/Ctrl sharps and flats
+    pub accidentals: bool,
+
+    /// Whether a note between two degrees plays as a sharp of the lower
+    /// one or a flat of the upper one
+    pub accidental_spelling: AccidentalSpelling,
+
+    /// What happens to sharps and flats when `accidentals` is off
+    pub accidental_fallback: AccidentalFallback,
+
+    /// Drum note bindings used in `LayoutMode::Drums`
+    pub drum_keys: Vec<DrumKey>,
+
+    /// Detect each song's key on load and transpose its tonic to degree 1
+    pub auto_detect_key: bool,
+
+    /// Pick the best transpose by `transpose_objective` whenever a song
+    /// without remembered settings is loaded. `auto_detect_key` wins if
+    /// both are set.
+    pub auto_transpose: bool,
+    pub transpose_objective: TransposeObjective,
+
+    /// Repeats of a key closer together than this use its alternates (ms)
+    pub alternate_window_ms: u64,
+
+    /// Most presses per second of any one key; faster repeats, such as a
+    /// tremolo written out in the song, are thinned out. 0 for no limit
+    pub max_repeat_rate: f64,
+
+    /// Long notes played as measured rolls instead of a single strike
+    pub rolls: RollConfig,
+
+    /// Strike held notes again every this many ms, so long notes on a
+    /// quickly decaying instrument become a tremolo; 0 strikes once
+    pub retrigger_ms: u64,
+
+    /// How the song is reduced before mapping
+    pub arrangement: Arrangement,
+
+    /// How note velocities are reshaped before anything reads them
+    pub velocity_curve: VelocityCurve,
+
+    /// Which notes of a chord survive the polyphony limit
+    pub reduction: Reduction,
+
+    /// Which note-on a note-off ends when a channel sounds the same pitch
+    /// more than once at a time; applies when a file is loaded
+    pub note_pairing: NotePairing,
+
+    /// Two-handed play: the left hand on the lowest key row, the right
+    /// hand on the rows above
+    pub split_hands: SplitHandConfig,
+
+    /// Note mapping strategy by name (see `mapper::mapper_names`);
+    /// empty follows `key_mapping.mode`
+    pub mapper: String,
+
+    /// Rhai script run over the song's notes before mapping (empty for none)
+    pub script_path: String,
+
     /// Global hotkey bindings
     pub hotkeys: Hotkeys,
+
+    /// Controller buttons that drive playback
+    pub gamepad: GamepadConfig,
+
+    /// MIDI messages from a connected controller that drive playback
+    pub midi_remote: MidiRemoteConfig,
+
+    /// Follow MIDI clock from a DAW, so playback stays locked to it
+    pub midi_clock: MidiClockConfig,
+
+    /// Send MIDI clock while playing, so a DAW can follow instead
+    pub midi_clock_output: MidiClockOutputConfig,
+
+    /// Hide the main window to the tray icon when minimized
+    pub minimize_to_tray: bool,
+
+    /// Placement of the always-on-top overlay window
+    pub overlay: OverlayConfig,
+
+    /// Local WebSocket remote-control server (applies on restart)
+    pub websocket: WebSocketConfig,
+
+    /// Local REST API, in builds with the `rest-api` feature (applies on restart)
+    pub rest: RestConfig,
+
+    /// OSC remote control over UDP (applies on restart)
+    pub osc: OscConfig,
+
+    /// Networked ensemble performances with other WWMP instances
+    pub ensemble: EnsembleConfig,
+
+    /// NTP server (host or host:port) that `play_at` times are read
+    /// against; empty trusts the system clock
+    pub ntp_server: String,
 }
 
+/// A single validation problem, tied to the config field it concerns
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct KeyMapping {
-    pub high: Vec<String>,
-    pub medium: Vec<String>,
-    pub low: Vec<String>,
+pub struct ConfigError {
+    /// Dotted field path, e.g. `key_mapping.rows[2][0]`
+    pub field: String,
+    pub message: String,
 }
 
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// What was wrong with `config.json` when it was loaded, for the window
+/// to show
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConfigReport {
+    /// Invalid settings, each put back to its default
+    pub reset: Vec<ConfigError>,
+    /// Problems kept as they are, see `AppConfig::warnings`
+    pub warnings: Vec<ConfigError>,
+}
+
+impl ConfigReport {
+    pub fn is_empty(&self) -> bool {
+        self.reset.is_empty() && self.warnings.is_empty()
+    }
+}
+
+/// Join validation errors into a single message
+pub fn describe_errors(errors: &[ConfigError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Named configuration profiles; the active one is mirrored in `config.json`
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileStore {
+    pub active: String,
+    pub profiles: BTreeMap<String, AppConfig>,
+}
+
+/// Summary of available profiles for the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileList {
+    pub active: String,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "KeyMappingRepr")]
+pub struct KeyMapping {
+    /// Key rows from the lowest pitch to the highest. Rows are only a
+    /// grouping; pitches follow the keys in order across rows.
+    pub rows: Vec<Vec<String>>,
+
+    /// Position (across all rows) of the key that plays `reference_midi_note`
+    pub reference_index: usize,
+
+    /// How successive keys step through pitches
+    pub mode: LayoutMode,
+
+    /// Scale the keys follow in `LayoutMode::Diatonic`
+    pub scale: Scale,
+
+    /// Extra keys that play the same pitch as a layout key, used in
+    /// rotation for fast repeated notes
+    pub alternates: BTreeMap<String, Vec<String>>,
+
+    /// Tap mode: hold every key this long (ms) rather than for the
+    /// note's length, for games that treat any hold the same
+    pub tap_ms: Option<u64>,
+
+    /// Let a key fill more than one slot, for layouts that double up on
+    /// purpose. Repeats are always only warnings in a loaded config; this
+    /// lets `update_key_mapping` accept new ones too.
+    pub allow_duplicate_keys: bool,
+}
+
+/// How successive keys in a layout relate to each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutMode {
+    /// Each key is the next degree of the layout's scale
+    Diatonic,
+    /// Each key is one semitone above the previous
+    Chromatic,
+    /// Percussion only: channel 10 drum notes use `drum_keys`
+    Drums,
+}
+
+/// How a song's notes are reduced before mapping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arrangement {
+    /// Every note, subject to the polyphony limit
+    #[default]
+    Full,
+    /// Only the melody line, for dense multi-track files
+    Melody,
+}
+
+/// How notes between two scale degrees are spelled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccidentalSpelling {
+    /// Shift on the degree below
+    #[default]
+    Sharps,
+    /// Ctrl on the degree above
+    Flats,
+    /// Flats if the layout's key signature has flats (F, Bb, Eb, Ab, Db
+    /// and Gb major and their relative minors), otherwise sharps
+    KeySignature,
+}
+
+/// How a sharp or flat is played on an instrument without Shift/Ctrl
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccidentalFallback {
+    /// Leave the note out
+    #[default]
+    Skip,
+    /// Play the natural it is spelled from by `accidental_spelling`
+    Nearest,
+    /// Play the natural below in sharp keys and above in flat keys
+    KeySignature,
+}
+
+/// What `auto_transpose` tries to minimize
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransposeObjective {
+    /// Notes out of the layout's range, then accidentals
+    #[default]
+    Range,
+    /// Notes needing Shift or Ctrl, which the game registers least
+    /// reliably in chords, counting dropped notes as just as bad
+    Accidentals,
+}
+
+/// Which notes of a chord to keep when it exceeds `max_polyphony`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reduction {
+    /// The highest notes
+    #[default]
+    Highest,
+    /// Melody and bass, then the loudest inner notes
+    Voicing,
+}
+
+/// Reshapes note velocities before the arrangement and polyphony
+/// limit see them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocityCurve {
+    /// Velocities as written
+    #[default]
+    Linear,
+    /// Lifts quiet notes towards loud ones, for songs played softly
+    Log,
+    /// Straight lines between (input, output) points, inputs ascending;
+    /// velocities outside the points take the nearest end
+    Custom(Vec<(u8, u8)>),
+}
+
+impl VelocityCurve {
+    /// The velocity a note played at `velocity` is treated as
+    pub fn apply(&self, velocity: u8) -> u8 {
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Log => {
+                let x = velocity.min(127) as f64 / 127.0;
+                ((1.0 + 9.0 * x).log10() * 127.0).round() as u8
+            }
+            VelocityCurve::Custom(points) => {
+                let Some(&(first_in, first_out)) = points.first() else {
+                    return velocity;
+                };
+                if velocity <= first_in {
+                    return first_out;
+                }
+                for w in points.windows(2) {
+                    let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+                    if velocity <= x1 {
+                        let t = (velocity - x0) as f64 / (x1 - x0) as f64;
+                        return (y0 as f64 + t * (y1 as f64 - y0 as f64)).round() as u8;
+                    }
+                }
+                points[points.len() - 1].1
+            }
+        }
+    }
+}
+
+/// How a note-off is matched among overlapping notes of its pitch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotePairing {
+    /// It ends the earliest one still sounding
+    #[default]
+    Fifo,
+    /// It ends the latest one
+    Lifo,
+}
+
+/// Which notes the left hand plays, and each hand's own transpose
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitHandConfig {
+    pub enabled: bool,
+    /// Notes below this go to the left hand (unless `left_tracks` is set)
+    pub split_note: u8,
+    /// Tracks played by the left hand; empty splits by `split_note`
+    pub left_tracks: Vec<usize>,
+    /// Semitones added to each hand on top of `transpose`
+    pub left_transpose: i32,
+    pub right_transpose: i32,
+}
+
+/// Scale a diatonic layout is tuned to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scale {
+    Major,
+    NaturalMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    /// Semitone offsets of each degree from the tonic, ascending from 0
+    Custom(Vec<i32>),
+}
+
+impl Scale {
+    /// Semitone offset of each scale degree from the tonic
+    pub fn intervals(&self) -> &[i32] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Custom(intervals) => intervals,
+        }
+    }
+}
+
+/// A General MIDI percussion note bound to a key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrumKey {
+    /// GM drum note, e.g. 36 = kick, 38 = snare, 42 = closed hi-hat
+    pub note: u8,
+    pub key: String,
+}
+
+/// On-disk key mapping, accepting the original fixed three-octave format
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyMappingRepr {
+    Layout {
+        rows: Vec<Vec<String>>,
+        reference_index: usize,
+        mode: LayoutMode,
+        #[serde(default = "default_scale")]
+        scale: Scale,
+        #[serde(default)]
+        alternates: BTreeMap<String, Vec<String>>,
+        #[serde(default)]
+        tap_ms: Option<u64>,
+        #[serde(default)]
+        allow_duplicate_keys: bool,
+    },
+    Octaves {
+        high: Vec<String>,
+        medium: Vec<String>,
+        low: Vec<String>,
+    },
+}
+
+fn default_scale() -> Scale {
+    Scale::Major
+}
+
+impl From<KeyMappingRepr> for KeyMapping {
+    fn from(repr: KeyMappingRepr) -> Self {
+        match repr {
+            KeyMappingRepr::Layout {
+                rows,
+                reference_index,
+                mode,
+                scale,
+                alternates,
+                tap_ms,
+                allow_duplicate_keys,
+            } => Self {
+                rows,
+                reference_index,
+                mode,
+                scale,
+                alternates,
+                tap_ms,
+                allow_duplicate_keys,
+            },
+            KeyMappingRepr::Octaves { high, medium, low } => Self {
+                reference_index: low.len(),
+                rows: vec![low, medium, high],
+                mode: LayoutMode::Diatonic,
+                scale: Scale::Major,
+                alternates: BTreeMap::new(),
+                tap_ms: None,
+                allow_duplicate_keys: false,
+            },
+        }
+    }
+}
+
+impl KeyMapping {
+    /// All keys in pitch order
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.rows.iter().flatten()
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.rows.iter().map(|r| r.len()).sum()
+    }
+
+    /// Key at a position across all rows
+    pub fn key(&self, index: usize) -> Option<&String> {
+        self.keys().nth(index)
+    }
+}
+
+/// Global hotkeys, as accelerator strings like `F7` or `Alt+Up`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Hotkeys {
     pub play_pause: String,
     pub stop: String,
+    /// Emergency stop that releases every key, for when keys get stuck
+    pub panic: String,
+    /// Shift the live transpose by one semitone
+    pub transpose_up: String,
+    pub transpose_down: String,
+    /// Shift the live transpose by one octave
+    pub octave_up: String,
+    pub octave_down: String,
+    /// Nudge the live tempo factor by `tempo_step`
+    pub tempo_up: String,
+    pub tempo_down: String,
+    pub tempo_step: f64,
+    /// Play the waiting chord in step mode
+    pub step_next: String,
+    /// Toggle sending keystrokes, leaving the song and preview running
+    pub mute_keys: String,
+    /// Stop and play the song again from the start
+    pub restart: String,
+}
+
+/// XInput controller bindings, as button names from `GAMEPAD_BUTTONS`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadConfig {
+    pub enabled: bool,
+    pub play_pause: String,
+    pub stop: String,
+    pub panic: String,
+}
+
+/// Remote transport from a MIDI controller (pedal, pads, ...)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiRemoteConfig {
+    pub enabled: bool,
+    /// Input port name to listen on; empty picks the first available,
+    /// `rtpmidi:<udp port>` hosts a network MIDI session for this machine
+    /// and `rtpmidi:<udp port>@<ip>,<ip>` one the listed machines can join
+    pub port: String,
+    pub bindings: Vec<RemoteBinding>,
+}
+
+/// MIDI clock input: Start, Stop and Continue drive the transport, song
+/// position pointers seek, and the clock rate sets the tempo
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiClockConfig {
+    pub enabled: bool,
+    /// Input port name to listen on; empty picks the first available,
+    /// `rtpmidi:<udp port>` hosts a network MIDI session for this machine
+    /// and `rtpmidi:<udp port>@<ip>,<ip>` one the listed machines can join
+    pub port: String,
+}
+
+/// MIDI clock output: song position and Start/Stop/Continue follow the
+/// transport, with clock ticks at the song's tempo
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiClockOutputConfig {
+    pub enabled: bool,
+    /// Output port name to send on; empty picks the first available
+    pub port: String,
+}
+
+/// A MIDI message that triggers a transport action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteBinding {
+    pub trigger: RemoteTrigger,
+    pub action: RemoteAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RemoteTrigger {
+    /// Control change crossing into its "on" half (value >= 64)
+    Cc { controller: u8 },
+    /// Note-on with non-zero velocity
+    Note { note: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteAction {
+    Play,
+    Pause,
+    Stop,
+    /// Load and play the next MIDI file in the current song's folder
+    NextSong,
+}
+
+/// Overlay window geometry in logical pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverlayConfig {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    pub enabled: bool,
+    /// Port on 127.0.0.1 to listen on
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestConfig {
+    pub enabled: bool,
+    /// Port on 127.0.0.1 to listen on
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscConfig {
+    pub enabled: bool,
+    /// UDP port to listen on
+    pub port: u16,
+    /// Accept messages from other machines (e.g. TouchOSC on a tablet)
+    /// instead of only from 127.0.0.1
+    pub allow_lan: bool,
+}
+
+/// Turns long notes into repeated strikes on the song's beat grid
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RollConfig {
+    pub enabled: bool,
+    /// Notes lasting at least this many beats are rolled
+    pub min_beats: f64,
+    /// Strikes per beat, e.g. 4 for sixteenth notes in 4/4
+    pub per_beat: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetronomeConfig {
+    /// Count in through the start delay at the song's opening tempo,
+    /// ending on its first downbeat
+    pub count_in: bool,
+    /// Keep beating while the song plays
+    pub during_playback: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnsembleConfig {
+    /// TCP port a conductor accepts players on
+    pub port: u16,
+    /// Time between sending the song and the shared start, giving
+    /// players time to load it (ms)
+    pub lead_ms: u64,
+    /// Shared secret the conductor and its players all set; players
+    /// without it are turned away
+    pub token: String,
 }
 
 impl Default for AppConfig {
@@ -46,45 +671,258 @@ impl Default for AppConfig {
             reference_midi_note: 60, // C4
             tempo_factor: 1.0,
             transpose: 0,
+            note_floor: 0,
+            note_ceiling: 127,
             max_polyphony: 2,
+            max_keys_per_second: 20.0,
             start_delay_ms: 500,
+            skip_silence: false,
+            lead_in_ms: 500,
+            latency_offset_ms: 0,
+            metronome: MetronomeConfig::default(),
+            audio_preview: false,
+            preview_volume: 0.5,
+            preview_device: String::new(),
             key_mapping: KeyMapping::default(),
+            accidentals: true,
+            accidental_spelling: AccidentalSpelling::Sharps,
+            accidental_fallback: AccidentalFallback::Skip,
+            drum_keys: default_drum_keys(),
+            auto_detect_key: false,
+            auto_transpose: false,
+            transpose_objective: TransposeObjective::Range,
+            alternate_window_ms: 150,
+            max_repeat_rate: 0.0,
+            rolls: RollConfig::default(),
+            retrigger_ms: 0,
+            arrangement: Arrangement::Full,
+            velocity_curve: VelocityCurve::Linear,
+            reduction: Reduction::Highest,
+            note_pairing: NotePairing::Fifo,
+            split_hands: SplitHandConfig::default(),
+            mapper: String::new(),
+            script_path: String::new(),
             hotkeys: Hotkeys::default(),
+            gamepad: GamepadConfig::default(),
+            midi_remote: MidiRemoteConfig::default(),
+            midi_clock: MidiClockConfig::default(),
+            midi_clock_output: MidiClockOutputConfig::default(),
+            minimize_to_tray: false,
+            overlay: OverlayConfig::default(),
+            websocket: WebSocketConfig::default(),
+            rest: RestConfig::default(),
+            osc: OscConfig::default(),
+            ensemble: EnsembleConfig::default(),
+            ntp_server: String::new(),
         }
     }
 }
 
 impl Default for KeyMapping {
     fn default() -> Self {
+        let row = |keys: [&str; 7]| keys.into_iter().map(String::from).collect();
         Self {
-            high: vec!["Q", "W", "E", "R", "T", "Y", "U"]
-                .into_iter()
-                .map(String::from)
-                .collect(),
-            medium: vec!["A", "S", "D", "F", "G", "H", "J"]
-                .into_iter()
-                .map(String::from)
-                .collect(),
-            low: vec!["Z", "X", "C", "V", "B", "N", "M"]
-                .into_iter()
-                .map(String::from)
-                .collect(),
+            rows: vec![
+                row(["Z", "X", "C", "V", "B", "N", "M"]),
+                row(["A", "S", "D", "F", "G", "H", "J"]),
+                row(["Q", "W", "E", "R", "T", "Y", "U"]),
+            ],
+            reference_index: 7,
+            mode: LayoutMode::Diatonic,
+            scale: Scale::Major,
+            alternates: BTreeMap::new(),
+            tap_ms: None,
+            allow_duplicate_keys: false,
         }
     }
 }
 
+/// General MIDI kit pieces spread over the bottom two default rows
+fn default_drum_keys() -> Vec<DrumKey> {
+    [
+        (35, "Z"), // Acoustic bass drum
+        (36, "Z"), // Bass drum
+        (38, "X"), // Acoustic snare
+        (40, "X"), // Electric snare
+        (42, "C"), // Closed hi-hat
+        (44, "C"), // Pedal hi-hat
+        (46, "V"), // Open hi-hat
+        (49, "B"), // Crash cymbal
+        (51, "N"), // Ride cymbal
+        (41, "A"), // Low floor tom
+        (45, "S"), // Low tom
+        (48, "D"), // Hi-mid tom
+        (50, "F"), // High tom
+    ]
+    .into_iter()
+    .map(|(note, key)| DrumKey {
+        note,
+        key: key.to_string(),
+    })
+    .collect()
+}
+
+/// What a hotkey does when pressed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HotkeyAction {
+    PlayPause,
+    Stop,
+    Panic,
+    /// Shift the transpose by this many semitones
+    Transpose(i32),
+    /// Nudge the tempo by this many configured steps
+    Tempo(i32),
+    StepNext,
+    ToggleMute,
+    Restart,
+}
+
+/// Gives `Hotkeys` its lookups by field name, from one list of the binding
+/// fields and the action each runs
+macro_rules! hotkey_bindings {
+    ($($field:ident => $action:expr),* $(,)?) => {
+        impl Hotkeys {
+            /// Every binding, by its field name
+            pub fn bindings(&self) -> Vec<(&'static str, &String)> {
+                vec![$((stringify!($field), &self.$field)),*]
+            }
+
+            /// The binding with field name `name`, to rebind it
+            pub fn binding_mut(&mut self, name: &str) -> Option<&mut String> {
+                match name {
+                    $(stringify!($field) => Some(&mut self.$field),)*
+                    _ => None,
+                }
+            }
+
+            /// Every binding with the action it runs
+            pub fn actions(&self) -> Vec<(&String, HotkeyAction)> {
+                vec![$((&self.$field, $action)),*]
+            }
+        }
+    };
+}
+
+hotkey_bindings! {
+    play_pause => HotkeyAction::PlayPause,
+    stop => HotkeyAction::Stop,
+    panic => HotkeyAction::Panic,
+    transpose_up => HotkeyAction::Transpose(1),
+    transpose_down => HotkeyAction::Transpose(-1),
+    octave_up => HotkeyAction::Transpose(12),
+    octave_down => HotkeyAction::Transpose(-12),
+    tempo_up => HotkeyAction::Tempo(1),
+    tempo_down => HotkeyAction::Tempo(-1),
+    step_next => HotkeyAction::StepNext,
+    mute_keys => HotkeyAction::ToggleMute,
+    restart => HotkeyAction::Restart,
+}
+
 impl Default for Hotkeys {
     fn default() -> Self {
         Self {
             play_pause: "F7".to_string(),
             stop: "F8".to_string(),
+            panic: "F9".to_string(),
+            transpose_up: "Alt+Up".to_string(),
+            transpose_down: "Alt+Down".to_string(),
+            octave_up: "Alt+PageUp".to_string(),
+            octave_down: "Alt+PageDown".to_string(),
+            tempo_up: "Alt+Right".to_string(),
+            tempo_down: "Alt+Left".to_string(),
+            tempo_step: 0.05,
+            step_next: "F6".to_string(),
+            mute_keys: "Alt+M".to_string(),
+            restart: "F5".to_string(),
+        }
+    }
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            play_pause: "Start".to_string(),
+            stop: "Back".to_string(),
+            panic: "RightThumb".to_string(),
+        }
+    }
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        Self {
+            x: 24.0,
+            y: 24.0,
+            width: 320.0,
+            height: 110.0,
+        }
+    }
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 9321,
+        }
+    }
+}
+
+impl Default for RestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 9322,
+        }
+    }
+}
+
+impl Default for SplitHandConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            split_note: 60, // C4
+            left_tracks: Vec::new(),
+            left_transpose: 0,
+            right_transpose: 0,
+        }
+    }
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 9323,
+            allow_lan: false,
+        }
+    }
+}
+
+impl Default for RollConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_beats: 2.0,
+            per_beat: 4,
+        }
+    }
+}
+
+impl Default for EnsembleConfig {
+    fn default() -> Self {
+        Self {
+            port: 9324,
+            lead_ms: 3000,
+            token: String::new(),
         }
     }
 }
 
 impl AppConfig {
     /// Get the config directory path
-    fn config_dir() -> Result<PathBuf> {
+    pub(crate) fn config_dir() -> Result<PathBuf> {
         let proj_dirs = directories::ProjectDirs::from("com", "wwmp", "WWMP")
             .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?;
         Ok(proj_dirs.config_dir().to_path_buf())
@@ -95,18 +933,83 @@ impl AppConfig {
         Ok(Self::config_dir()?.join("config.json"))
     }
 
-    /// Load config from disk, or return default if not found
-    pub fn load() -> Result<Self> {
+    /// Load config from disk, or return default if not found. Invalid
+    /// settings go back to their defaults, keeping the rest, and are
+    /// listed in the report; only a file that can't be read is an error.
+    pub fn load() -> Result<(Self, ConfigReport)> {
         let path = Self::config_path()?;
         if path.exists() {
             let content = fs::read_to_string(&path)?;
             let config: AppConfig = serde_json::from_str(&content)?;
-            Ok(config)
+            Ok(config.repaired())
         } else {
-            Ok(Self::default())
+            Ok((Self::default(), ConfigReport::default()))
         }
     }
 
+    /// Load the config at startup. A file that can't be read at all is
+    /// copied to `config.invalid.json` before starting from the defaults,
+    /// so saving them later doesn't destroy it.
+    pub fn load_or_recover() -> (Self, ConfigReport) {
+        let e = match Self::load() {
+            Ok(loaded) => return loaded,
+            Err(e) => e,
+        };
+        let kept = Self::config_path().and_then(|path| {
+            let copy = path.with_file_name("config.invalid.json");
+            fs::copy(&path, &copy)?;
+            Ok(copy)
+        });
+        let message = match kept {
+            Ok(copy) => format!("Unreadable ({}), kept as {}", e, copy.display()),
+            Err(_) => format!("Unreadable ({})", e),
+        };
+        let reset = vec![ConfigError {
+            field: "config.json".into(),
+            message,
+        }];
+        let report = ConfigReport {
+            reset,
+            warnings: Vec::new(),
+        };
+        (Self::default(), report)
+    }
+
+    /// This config with every invalid setting put back to its default,
+    /// and the problems found. Settings are reset whole, e.g. the entire
+    /// `key_mapping` for one unknown key.
+    fn repaired(self) -> (Self, ConfigReport) {
+        let reset = self.validate();
+        if reset.is_empty() {
+            let warnings = self.warnings();
+            return (self, ConfigReport { reset, warnings });
+        }
+
+        let fields: Vec<&str> = reset
+            .iter()
+            .filter_map(|e| e.field.split(['.', '[']).next())
+            .collect();
+        let repaired = serde_json::to_value(&self).and_then(|mut value| {
+            let defaults = serde_json::to_value(Self::default())?;
+            if let Some(settings) = value.as_object_mut() {
+                for field in fields {
+                    if let Some(default) = defaults.get(field) {
+                        settings.insert(field.to_string(), default.clone());
+                    }
+                }
+            }
+            serde_json::from_value::<Self>(value)
+        });
+
+        // Problems resetting single settings can't fix mean starting over
+        let config = match repaired {
+            Ok(config) if config.validate().is_empty() => config,
+            _ => Self::default(),
+        };
+        let warnings = config.warnings();
+        (config, ConfigReport { reset, warnings })
+    }
+
     /// Save config to disk
     pub fn save(&self) -> Result<()> {
         let dir = Self::config_dir()?;
@@ -117,4 +1020,610 @@ impl AppConfig {
         fs::write(path, content)?;
         Ok(())
     }
+
+    /// Write this config to an arbitrary file for sharing or backup
+    pub fn export_to(&self, path: &str) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Read and check a config previously written by `export_to`
+    pub fn import_from(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let config: AppConfig = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Not a valid WWMP config: {}", e))?;
+
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(anyhow!("Invalid config: {}", describe_errors(&errors)));
+        }
+        Ok(config)
+    }
+
+    /// Check every field for out-of-range values and unusable keys
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        let mut error = |field: String, message: String| {
+            errors.push(ConfigError { field, message });
+        };
+
+        if self.reference_midi_note > 127 {
+            error(
+                "reference_midi_note".into(),
+                format!(
+                    "Must be a MIDI note 0-127, got {}",
+                    self.reference_midi_note
+                ),
+            );
+        }
+        if !(MIN_TEMPO..=MAX_TEMPO).contains(&self.tempo_factor) {
+            error(
+                "tempo_factor".into(),
+                format!(
+                    "Must be between {} and {}, got {}",
+                    MIN_TEMPO, MAX_TEMPO, self.tempo_factor
+                ),
+            );
+        }
+        if !(-24..=24).contains(&self.transpose) {
+            error(
+                "transpose".into(),
+                format!("Must be between -24 and +24, got {}", self.transpose),
+            );
+        }
+        if self.note_ceiling > 127 || self.note_floor > self.note_ceiling {
+            error(
+                "note_ceiling".into(),
+                format!(
+                    "Must be at least note_floor ({}) and at most 127, got {}",
+                    self.note_floor, self.note_ceiling
+                ),
+            );
+        }
+        if !(1..=3).contains(&self.max_polyphony) {
+            error(
+                "max_polyphony".into(),
+                format!("Must be between 1 and 3, got {}", self.max_polyphony),
+            );
+        }
+        if self.max_keys_per_second.is_nan() || self.max_keys_per_second <= 0.0 {
+            error(
+                "max_keys_per_second".into(),
+                format!("Must be above 0, got {}", self.max_keys_per_second),
+            );
+        }
+        let split = &self.split_hands;
+        if split.split_note > 127 {
+            error(
+                "split_hands.split_note".into(),
+                format!("Must be a MIDI note 0-127, got {}", split.split_note),
+            );
+        }
+        let hand_transposes = [
+            ("left_transpose", split.left_transpose),
+            ("right_transpose", split.right_transpose),
+        ];
+        for (name, transpose) in hand_transposes {
+            if !(-24..=24).contains(&transpose) {
+                error(
+                    format!("split_hands.{}", name),
+                    format!("Must be between -24 and +24, got {}", transpose),
+                );
+            }
+        }
+        if split.enabled && self.key_mapping.rows.len() < 2 {
+            error(
+                "split_hands.enabled".into(),
+                "Needs a layout with at least two key rows".into(),
+            );
+        }
+        if self.start_delay_ms > 10_000 {
+            error(
+                "start_delay_ms".into(),
+                format!("Must be at most 10000 ms, got {}", self.start_delay_ms),
+            );
+        }
+        if self.lead_in_ms > 10_000 {
+            error(
+                "lead_in_ms".into(),
+                format!("Must be at most 10000 ms, got {}", self.lead_in_ms),
+            );
+        }
+        if !(0.0..=100.0).contains(&self.max_repeat_rate) {
+            error(
+                "max_repeat_rate".into(),
+                format!(
+                    "Must be between 0 (no limit) and 100 per second, got {}",
+                    self.max_repeat_rate
+                ),
+            );
+        }
+        if self.rolls.min_beats.is_nan() || self.rolls.min_beats <= 0.0 {
+            error(
+                "rolls.min_beats".into(),
+                format!("Must be above 0, got {}", self.rolls.min_beats),
+            );
+        }
+        if !(1..=16).contains(&self.rolls.per_beat) {
+            error(
+                "rolls.per_beat".into(),
+                format!("Must be between 1 and 16, got {}", self.rolls.per_beat),
+            );
+        }
+        if self.retrigger_ms != 0 && !(50..=5000).contains(&self.retrigger_ms) {
+            error(
+                "retrigger_ms".into(),
+                format!(
+                    "Must be 0 (off) or between 50 and 5000 ms, got {}",
+                    self.retrigger_ms
+                ),
+            );
+        }
+        if !(-1000..=1000).contains(&self.latency_offset_ms) {
+            error(
+                "latency_offset_ms".into(),
+                format!(
+                    "Must be between -1000 and +1000 ms, got {}",
+                    self.latency_offset_ms
+                ),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.preview_volume) {
+            error(
+                "preview_volume".into(),
+                format!("Must be between 0.0 and 1.0, got {}", self.preview_volume),
+            );
+        }
+
+        if !(500..=30_000).contains(&self.ensemble.lead_ms) {
+            error(
+                "ensemble.lead_ms".into(),
+                format!(
+                    "Must be between 500 and 30000 ms, got {}",
+                    self.ensemble.lead_ms
+                ),
+            );
+        }
+
+        if !(self.hotkeys.tempo_step > 0.0 && self.hotkeys.tempo_step <= 1.0) {
+            error(
+                "hotkeys.tempo_step".into(),
+                format!(
+                    "Must be above 0 and at most 1.0, got {}",
+                    self.hotkeys.tempo_step
+                ),
+            );
+        }
+
+        let gamepad = &self.gamepad;
+        let buttons = [
+            ("play_pause", &gamepad.play_pause),
+            ("stop", &gamepad.stop),
+            ("panic", &gamepad.panic),
+        ];
+        for (name, button) in buttons {
+            if !button.is_empty() && !gamepad::is_supported_button(button) {
+                error(
+                    format!("gamepad.{}", name),
+                    format!("Unknown button: {}", button),
+                );
+            }
+        }
+
+        for (i, binding) in self.midi_remote.bindings.iter().enumerate() {
+            let value = match binding.trigger {
+                RemoteTrigger::Cc { controller } => controller,
+                RemoteTrigger::Note { note } => note,
+            };
+            if value > 127 {
+                error(
+                    format!("midi_remote.bindings[{}].trigger", i),
+                    format!("Must be 0-127, got {}", value),
+                );
+            }
+        }
+
+        if !self.mapper.is_empty() && !mapper::mapper_names().contains(&self.mapper) {
+            error("mapper".into(), format!("Unknown mapper: {}", self.mapper));
+        }
+
+        let key_count = self.key_mapping.key_count();
+        if key_count == 0 {
+            error("key_mapping.rows".into(), "Layout has no keys".into());
+        } else if self.key_mapping.reference_index >= key_count {
+            error(
+                "key_mapping.reference_index".into(),
+                format!(
+                    "Must be less than the number of keys ({}), got {}",
+                    key_count, self.key_mapping.reference_index
+                ),
+            );
+        }
+        if let Some(tap_ms) = self.key_mapping.tap_ms {
+            if !(1..=2000).contains(&tap_ms) {
+                error(
+                    "key_mapping.tap_ms".into(),
+                    format!("Must be between 1 and 2000 ms, got {}", tap_ms),
+                );
+            }
+        }
+        if let VelocityCurve::Custom(points) = &self.velocity_curve {
+            let ascending = points.windows(2).all(|w| w[0].0 < w[1].0);
+            let in_range = points.iter().all(|&(i, o)| i <= 127 && o <= 127);
+            if points.len() < 2 || !ascending || !in_range {
+                error(
+                    "velocity_curve".into(),
+                    "Needs at least two points, inputs ascending, all within 0-127".into(),
+                );
+            }
+        }
+        let intervals = self.key_mapping.scale.intervals();
+        let ascending = intervals.windows(2).all(|w| w[0] < w[1]);
+        if intervals.first() != Some(&0) || !ascending || intervals.iter().any(|&i| i >= 12) {
+            error(
+                "key_mapping.scale".into(),
+                "Intervals must start at 0 and rise within one octave (0-11)".into(),
+            );
+        }
+        for (primary, alternates) in &self.key_mapping.alternates {
+            for (i, key) in alternates.iter().enumerate() {
+                if !keyboard::is_supported_key(key) {
+                    error(
+                        format!("key_mapping.alternates.{}[{}]", primary, i),
+                        format!("Unknown key: {}", key),
+                    );
+                }
+            }
+        }
+        for (i, drum) in self.drum_keys.iter().enumerate() {
+            if drum.note > 127 {
+                error(
+                    format!("drum_keys[{}].note", i),
+                    format!("Must be a MIDI note 0-127, got {}", drum.note),
+                );
+            }
+            if !keyboard::is_supported_key(&drum.key) {
+                error(
+                    format!("drum_keys[{}].key", i),
+                    format!("Unknown key: {}", drum.key),
+                );
+            }
+        }
+        for (r, row) in self.key_mapping.rows.iter().enumerate() {
+            for (i, key) in row.iter().enumerate() {
+                if !keyboard::is_supported_key(key) {
+                    let slot = format!("key_mapping.rows[{}][{}]", r, i);
+                    error(slot, format!("Unknown key: {}", key));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Problems that don't stop the config being used: keys the layout
+    /// repeats. These are kept on load, so older layouts with a doubled
+    /// key still work; only new edits are checked more strictly.
+    pub fn warnings(&self) -> Vec<ConfigError> {
+        self.duplicate_keys()
+    }
+
+    /// Each slot of the layout repeating a key from an earlier one. The
+    /// game plays one pitch per key, so the notes meant for every slot but
+    /// one come out wrong - a whole octave, if a row is doubled.
+    pub fn duplicate_keys(&self) -> Vec<ConfigError> {
+        let mut duplicates = Vec::new();
+        // Key name -> first slot using it
+        let mut assigned: BTreeMap<String, String> = BTreeMap::new();
+        for (r, row) in self.key_mapping.rows.iter().enumerate() {
+            for (i, key) in row.iter().enumerate() {
+                if !keyboard::is_supported_key(key) {
+                    continue;
+                }
+                let slot = format!("key_mapping.rows[{}][{}]", r, i);
+                if let Some(first) = assigned.get(&key.to_ascii_uppercase()) {
+                    duplicates.push(ConfigError {
+                        message: format!("{} is already assigned to {}", key, first),
+                        field: slot,
+                    });
+                } else {
+                    assigned.insert(key.to_ascii_uppercase(), slot);
+                }
+            }
+        }
+        duplicates
+    }
+}
+
+impl ProfileStore {
+    /// Name given to the profile created from an existing `config.json`
+    pub const DEFAULT_PROFILE: &'static str = "Default";
+
+    /// Get the profiles file path
+    fn profiles_path() -> Result<PathBuf> {
+        Ok(AppConfig::config_dir()?.join("profiles.json"))
+    }
+
+    /// Load profiles from disk, or start with a single profile holding `current`
+    pub fn load(current: &AppConfig) -> Result<Self> {
+        let path = Self::profiles_path()?;
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(Self::new(current))
+        }
+    }
+
+    pub fn new(current: &AppConfig) -> Self {
+        let mut profiles = BTreeMap::new();
+        profiles.insert(Self::DEFAULT_PROFILE.to_string(), current.clone());
+        Self {
+            active: Self::DEFAULT_PROFILE.to_string(),
+            profiles,
+        }
+    }
+
+    /// Save profiles to disk
+    pub fn save(&self) -> Result<()> {
+        let dir = AppConfig::config_dir()?;
+        fs::create_dir_all(&dir)?;
+
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(Self::profiles_path()?, content)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> ProfileList {
+        ProfileList {
+            active: self.active.clone(),
+            names: self.profiles.keys().cloned().collect(),
+        }
+    }
+
+    /// The config last stored under the active profile name
+    pub fn active_config(&self) -> Option<&AppConfig> {
+        self.profiles.get(&self.active)
+    }
+
+    /// Store the live config under the active profile name
+    pub fn update_active(&mut self, current: &AppConfig) {
+        self.profiles.insert(self.active.clone(), current.clone());
+    }
+
+    /// Create a new profile as a copy of `current`
+    pub fn create(&mut self, name: &str, current: &AppConfig) -> Result<()> {
+        let name = validate_profile_name(name)?;
+        if self.profiles.contains_key(&name) {
+            return Err(anyhow!("Profile already exists: {}", name));
+        }
+        self.profiles.insert(name, current.clone());
+        Ok(())
+    }
+
+    /// Make `name` the active profile and return its config. A profile
+    /// with invalid settings (e.g. from a hand-edited file) is left
+    /// inactive.
+    pub fn switch(&mut self, name: &str, current: &AppConfig) -> Result<AppConfig> {
+        let config = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown profile: {}", name))?;
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "Profile {} is invalid: {}",
+                name,
+                describe_errors(&errors)
+            ));
+        }
+        self.update_active(current);
+        self.active = name.to_string();
+        Ok(config)
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        let new = validate_profile_name(new)?;
+        if self.profiles.contains_key(&new) {
+            return Err(anyhow!("Profile already exists: {}", new));
+        }
+        let config = self
+            .profiles
+            .remove(old)
+            .ok_or_else(|| anyhow!("Unknown profile: {}", old))?;
+        self.profiles.insert(new.clone(), config);
+        if self.active == old {
+            self.active = new;
+        }
+        Ok(())
+    }
+
+    /// Delete a profile; the active profile cannot be deleted
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        if self.active == name {
+            return Err(anyhow!("Cannot delete the active profile"));
+        }
+        self.profiles
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("Unknown profile: {}", name))
+    }
+}
+
+fn validate_profile_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(anyhow!("Profile name cannot be empty"));
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(AppConfig::default().validate().is_empty());
+    }
+
+    #[test]
+    fn test_velocity_curve() {
+        assert_eq!(VelocityCurve::Linear.apply(64), 64);
+
+        let log = VelocityCurve::Log;
+        assert_eq!((log.apply(0), log.apply(127)), (0, 127));
+        assert!(log.apply(32) > 64);
+
+        let custom = VelocityCurve::Custom(vec![(20, 0), (100, 80), (110, 127)]);
+        assert_eq!(custom.apply(10), 0);
+        assert_eq!(custom.apply(60), 40);
+        assert_eq!(custom.apply(105), 104);
+        assert_eq!(custom.apply(127), 127);
+    }
+
+    #[test]
+    fn test_validate_reports_fields() {
+        let mut config = AppConfig {
+            tempo_factor: 10.0,
+            ..AppConfig::default()
+        };
+        config.key_mapping.rows[2][2] = "F13".to_string();
+        config.key_mapping.reference_index = 21;
+
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "tempo_factor",
+                "key_mapping.reference_index",
+                "key_mapping.rows[2][2]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_resets_only_invalid_settings() {
+        let config = AppConfig {
+            tempo_factor: 10.0,
+            transpose: 5,
+            ..AppConfig::default()
+        };
+        let (config, report) = config.repaired();
+        assert_eq!(config.tempo_factor, AppConfig::default().tempo_factor);
+        assert_eq!(config.transpose, 5);
+        assert_eq!(report.reset.len(), 1);
+        assert_eq!(report.reset[0].field, "tempo_factor");
+
+        let (_, report) = AppConfig::default().repaired();
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let mut config = AppConfig::default();
+        config.key_mapping.rows[2][0] = "z".to_string();
+
+        // A repeat doesn't make a loaded config invalid, only warned about
+        assert!(config.validate().is_empty());
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field, "key_mapping.rows[2][0]");
+        assert_eq!(
+            warnings[0].message,
+            "z is already assigned to key_mapping.rows[0][0]"
+        );
+
+        let (repaired, report) = config.clone().repaired();
+        assert!(report.reset.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(repaired.key_mapping, config.key_mapping);
+    }
+
+    #[test]
+    fn test_legacy_key_mapping() {
+        let json = r#"{
+            "high": ["Q", "W", "E", "R", "T", "Y", "U"],
+            "medium": ["A", "S", "D", "F", "G", "H", "J"],
+            "low": ["Z", "X", "C", "V", "B", "N", "M"]
+        }"#;
+        let mapping: KeyMapping = serde_json::from_str(json).unwrap();
+        assert_eq!(mapping, KeyMapping::default());
+    }
+
+    #[test]
+    fn test_hotkey_bindings() {
+        let mut hotkeys = Hotkeys::default();
+        *hotkeys.binding_mut("tempo_up").unwrap() = "F10".into();
+        assert_eq!(hotkeys.tempo_up, "F10");
+        assert!(hotkeys.binding_mut("tempo_step").is_none());
+
+        let actions = hotkeys.actions();
+        assert_eq!(actions.len(), hotkeys.bindings().len());
+        assert!(actions.contains(&(&"F10".to_string(), HotkeyAction::Tempo(1))));
+    }
+
+    #[test]
+    fn test_profile_rename_delete() {
+        let mut store = ProfileStore::new(&AppConfig::default());
+        store.create("Lute", &AppConfig::default()).unwrap();
+        assert!(store.create(" Lute ", &AppConfig::default()).is_err());
+
+        // Renaming the active profile keeps it active
+        store.rename("Default", " Piano ").unwrap();
+        assert_eq!(store.list().names, ["Lute", "Piano"]);
+        assert_eq!(store.active, "Piano");
+        assert!(store.rename("Lute", "Piano").is_err());
+        assert!(store.rename("Lute", "  ").is_err());
+        assert!(store.rename("Harp", "Flute").is_err());
+
+        assert!(store.delete("Piano").is_err());
+        assert!(store.delete("Harp").is_err());
+        store.delete("Lute").unwrap();
+        assert_eq!(store.list().names, ["Piano"]);
+    }
+
+    #[test]
+    fn test_profile_switch() {
+        let mut store = ProfileStore::new(&AppConfig::default());
+        let lute = AppConfig {
+            transpose: 12,
+            ..AppConfig::default()
+        };
+        store.create("Lute", &lute).unwrap();
+        let mut broken = AppConfig::default();
+        broken.hotkeys.tempo_step = 0.0;
+        store.profiles.insert("Broken".into(), broken);
+
+        assert!(store.switch("Broken", &AppConfig::default()).is_err());
+        assert_eq!(store.active, "Default");
+
+        let current = AppConfig {
+            tempo_factor: 1.5,
+            ..AppConfig::default()
+        };
+        assert_eq!(store.switch("Lute", &current).unwrap(), lute);
+        assert_eq!(store.active, "Lute");
+        // The profile switched away from keeps its latest settings
+        assert_eq!(store.profiles["Default"], current);
+    }
+
+    #[test]
+    fn test_tempo_step_validation() {
+        let mut config = AppConfig::default();
+        config.hotkeys.tempo_step = 0.0;
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["hotkeys.tempo_step"]);
+    }
+
+    #[test]
+    fn test_gamepad_validation() {
+        let mut config = AppConfig::default();
+        config.gamepad.stop = "Turbo".into();
+        // Unbound buttons are fine
+        config.gamepad.panic = String::new();
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["gamepad.stop"]);
+    }
 }
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
+use std::fs;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::config::AppConfig;
+use crate::AppState;
+
+/// Event emitted after `config.json` was changed on disk and reloaded
+pub const CONFIG_CHANGED_EVENT: &str = "config://changed";
+
+/// Event emitted with a `ConfigReport` when a reloaded `config.json` had
+/// settings that were reset
+pub const CONFIG_REPORT_EVENT: &str = "config://report";
+
+/// Watch the config directory and reload `config.json` into `AppState`
+/// whenever it is edited outside the app
+pub fn watch_config(app: AppHandle) -> Result<()> {
+    let dir = AppConfig::config_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    thread::spawn(move || {
+        // The watcher stops when dropped, so keep it alive on this thread
+        let _watcher = watcher;
+
+        while let Ok(res) = rx.recv() {
+            let Ok(event) = res else { continue };
+            let touches_config = event
+                .paths
+                .iter()
+                .any(|p| p.file_name().is_some_and(|n| n == "config.json"));
+            if !touches_config || !(event.kind.is_modify() || event.kind.is_create()) {
+                continue;
+            }
+
+            // Editors often write in several steps; let them settle
+            thread::sleep(Duration::from_millis(100));
+            while rx.try_recv().is_ok() {}
+
+            // Keep the current config if the file is mid-edit
+            let Ok((loaded, report)) = AppConfig::load() else {
+                continue;
+            };
+
+            let state = app.state::<AppState>();
+            if !report.is_empty() {
+                let _ = app.emit_all(CONFIG_REPORT_EVENT, report.clone());
+            }
+            *state.config_report.lock().unwrap() = report;
+            if *state.config.lock().unwrap() == loaded {
+                continue;
+            }
+
+            // Same path as switching profiles, minus writing the file back
+            if let Ok(config) = crate::apply_config(&app, loaded) {
+                let _ = app.emit_all(CONFIG_CHANGED_EVENT, config);
+            }
+        }
+    });
+
+    Ok(())
+}
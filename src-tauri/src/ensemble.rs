@@ -0,0 +1,401 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+use crate::clock::{self, now_ms};
+use crate::config::AppConfig;
+use crate::midi::MidiFile;
+use crate::AppState;
+
+/// Emitted on the conductor whenever players join or leave
+pub const PLAYERS_EVENT: &str = "ensemble://players";
+
+/// Emitted on a player when the conductor hands it a song
+pub const SONG_EVENT: &str = "ensemble://song";
+
+/// Clock pings sent when joining; the one with the shortest round trip
+/// gives the offset estimate
+const SYNC_PINGS: usize = 8;
+
+/// How long joining waits to connect, and for each answer while the
+/// player introduces itself and syncs clocks
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages between conductor and players, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// First message from a player, with the ensemble's shared token
+    Hello {
+        name: String,
+        token: String,
+    },
+    /// The conductor's answer to a `Hello` with the right token
+    Welcome,
+    /// The conductor's answer to a `Hello` with the wrong token, just
+    /// before it hangs up
+    Rejected,
+    /// Clock sync request, with the player's clock when sent
+    Ping {
+        sent_ms: u64,
+    },
+    /// Clock sync answer, with the conductor's clock when answered
+    Pong {
+        sent_ms: u64,
+        conductor_ms: u64,
+    },
+    /// The song to perform and the tracks this player plays (empty for all)
+    Song {
+        file_name: String,
+        data: Vec<u8>,
+        tracks: Vec<usize>,
+    },
+    /// Start playing at this time on the conductor's clock
+    Start {
+        at_ms: u64,
+    },
+    Stop,
+}
+
+/// This instance's role in an ensemble
+pub enum Ensemble {
+    Conductor(Conductor),
+    Player(Player),
+}
+
+/// A connected player as shown to the conductor
+#[derive(Debug, Clone, Serialize)]
+pub struct EnsemblePlayer {
+    pub name: String,
+    /// Tracks assigned to this player; empty plays every track
+    pub tracks: Vec<usize>,
+}
+
+struct Connection {
+    player: EnsemblePlayer,
+    stream: TcpStream,
+}
+
+fn send(stream: &TcpStream, message: &Message) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    (&*stream).write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// How far the conductor's clock is ahead of ours, from one ping that
+/// we sent at `sent_ms` and got back at `received_ms`, assuming the
+/// answer was given halfway through the round trip
+fn clock_offset(sent_ms: u64, conductor_ms: u64, received_ms: u64) -> i64 {
+    let midpoint = sent_ms as i64 + (received_ms as i64 - sent_ms as i64) / 2;
+    conductor_ms as i64 - midpoint
+}
+
+/// The instance that distributes the song and counts everyone in
+pub struct Conductor {
+    connections: Arc<Mutex<Vec<Connection>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl Conductor {
+    /// Accept players on `port` from any machine on the network, if they
+    /// know `token`
+    pub fn host(app: AppHandle, port: u16, token: String) -> Result<Self> {
+        if token.is_empty() {
+            bail!("Set ensemble.token before conducting, so only your players can join");
+        }
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        {
+            let connections = connections.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let app = app.clone();
+                            let connections = connections.clone();
+                            let token = token.clone();
+                            thread::spawn(move || {
+                                let _ = serve_player(&app, &connections, &token, stream);
+                            });
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(100));
+                        }
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            connections,
+            running,
+        })
+    }
+
+    pub fn players(&self) -> Vec<EnsemblePlayer> {
+        let connections = self.connections.lock().unwrap();
+        connections.iter().map(|c| c.player.clone()).collect()
+    }
+
+    /// Choose the tracks a player performs; empty plays every track
+    pub fn assign(&self, name: &str, tracks: Vec<usize>) -> Result<()> {
+        let mut connections = self.connections.lock().unwrap();
+        let connection = connections
+            .iter_mut()
+            .find(|c| c.player.name == name)
+            .ok_or_else(|| anyhow!("No player named {}", name))?;
+        connection.player.tracks = tracks;
+        Ok(())
+    }
+
+    /// Send the song and each player's tracks, then a shared start time
+    /// `lead_ms` from now. The conductor plays its own enabled tracks at
+    /// the same moment.
+    pub fn perform(&self, app: &AppHandle, midi: &MidiFile, lead_ms: u64) -> Result<()> {
+        let data = fs::read(&midi.path)?;
+        let file_name = Path::new(&midi.path)
+            .file_name()
+            .map_or("song.mid".into(), |n| n.to_string_lossy().into_owned());
+
+        let at_ms = now_ms() + lead_ms;
+        let connections = self.connections.lock().unwrap();
+        for connection in connections.iter() {
+            let song = Message::Song {
+                file_name: file_name.clone(),
+                data: data.clone(),
+                tracks: connection.player.tracks.clone(),
+            };
+            send(&connection.stream, &song)?;
+            send(&connection.stream, &Message::Start { at_ms })?;
+        }
+        clock::start_at(app, at_ms);
+        Ok(())
+    }
+
+    /// Stop every player
+    pub fn stop(&self) {
+        for connection in self.connections.lock().unwrap().iter() {
+            let _ = send(&connection.stream, &Message::Stop);
+        }
+    }
+}
+
+impl Drop for Conductor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        for connection in self.connections.lock().unwrap().drain(..) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn emit_players(app: &AppHandle, connections: &Mutex<Vec<Connection>>) {
+    let players: Vec<EnsemblePlayer> = connections
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|c| c.player.clone())
+        .collect();
+    let _ = app.emit_all(PLAYERS_EVENT, players);
+}
+
+/// Check a player's token, then answer its clock pings and track it
+/// until it disconnects
+fn serve_player(
+    app: &AppHandle,
+    connections: &Mutex<Vec<Connection>>,
+    token: &str,
+    stream: TcpStream,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    // Anything but a prompt hello with the right token is hung up on
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let name = match serde_json::from_str(&line)? {
+        Message::Hello { name, token: given } if given == token => name,
+        _ => {
+            let _ = send(&stream, &Message::Rejected);
+            bail!("Player at {} gave the wrong token", peer);
+        }
+    };
+    stream.set_read_timeout(None)?;
+    send(&stream, &Message::Welcome)?;
+
+    connections.lock().unwrap().push(Connection {
+        player: EnsemblePlayer {
+            name,
+            tracks: Vec::new(),
+        },
+        stream: stream.try_clone()?,
+    });
+    emit_players(app, connections);
+
+    for line in reader.lines() {
+        match serde_json::from_str(&line?)? {
+            Message::Ping { sent_ms } => {
+                let conductor_ms = now_ms();
+                send(
+                    &stream,
+                    &Message::Pong {
+                        sent_ms,
+                        conductor_ms,
+                    },
+                )?;
+            }
+            _ => {}
+        }
+    }
+
+    connections
+        .lock()
+        .unwrap()
+        .retain(|c| c.stream.peer_addr().is_ok_and(|addr| addr != peer));
+    emit_players(app, connections);
+    Ok(())
+}
+
+/// This instance's membership in a conductor's ensemble
+pub struct Player {
+    stream: TcpStream,
+}
+
+impl Player {
+    /// Connect to a conductor at `address` (host:port) with the shared
+    /// `token`, measure the clock offset, then follow its songs and start
+    /// times in the background. Gives up if the conductor doesn't answer
+    /// within `HANDSHAKE_TIMEOUT` at any step.
+    pub fn join(app: AppHandle, address: &str, name: &str, token: &str) -> Result<Self> {
+        let target = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Unknown conductor address: {}", address))?;
+        let stream = TcpStream::connect_timeout(&target, HANDSHAKE_TIMEOUT)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        send(
+            &stream,
+            &Message::Hello {
+                name: name.to_string(),
+                token: token.to_string(),
+            },
+        )?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match serde_json::from_str(&line) {
+            Ok(Message::Welcome) => {}
+            Ok(Message::Rejected) => bail!("The conductor didn't accept the ensemble token"),
+            _ => bail!("No answer from a conductor at {}", address),
+        }
+
+        let mut best: Option<(u64, i64)> = None;
+        for _ in 0..SYNC_PINGS {
+            let started = Instant::now();
+            send(&stream, &Message::Ping { sent_ms: now_ms() })?;
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if let Message::Pong {
+                sent_ms,
+                conductor_ms,
+            } = serde_json::from_str(&line)?
+            {
+                let round_trip = started.elapsed().as_millis() as u64;
+                let offset = clock_offset(sent_ms, conductor_ms, now_ms());
+                if best.is_none_or(|(shortest, _)| round_trip < shortest) {
+                    best = Some((round_trip, offset));
+                }
+            }
+        }
+        let offset = best.map_or(0, |(_, offset)| offset);
+        // Songs and start times may be minutes apart
+        stream.set_read_timeout(None)?;
+
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                match serde_json::from_str(&line) {
+                    Ok(Message::Song {
+                        file_name,
+                        data,
+                        tracks,
+                    }) => {
+                        let _ = load_part(&app, &file_name, &data, &tracks);
+                    }
+                    Ok(Message::Start { at_ms }) => {
+                        let local_ms = (at_ms as i64 - offset).max(0) as u64;
+                        clock::start_at(&app, local_ms);
+                    }
+                    Ok(Message::Stop) => {
+                        crate::stop_all(&app.state::<AppState>());
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self { stream })
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Save a song sent by the conductor and load it with only our tracks
+/// enabled. The split is not remembered as the song's own settings.
+fn load_part(app: &AppHandle, file_name: &str, data: &[u8], tracks: &[usize]) -> Result<()> {
+    let dir = AppConfig::config_dir()?.join("ensemble");
+    fs::create_dir_all(&dir)?;
+    // Only the name is kept, so the conductor cannot write elsewhere
+    let name = Path::new(file_name)
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid file name: {}", file_name))?;
+    let path = dir.join(name);
+    fs::write(&path, data)?;
+
+    let info = crate::load_song(app, &path.to_string_lossy()).map_err(|e| anyhow!(e))?;
+    if !tracks.is_empty() {
+        let state = app.state::<AppState>();
+        let mut midi_file = state.midi_file.lock().unwrap();
+        if let Some(midi) = midi_file.as_mut() {
+            midi.disabled_tracks = (0..info.track_count)
+                .filter(|t| !tracks.contains(t))
+                .collect();
+        }
+    }
+    let _ = app.emit_all(SONG_EVENT, info);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_offset() {
+        // Sent at 1000, back at 1040: the conductor read 5020 at ~1020
+        assert_eq!(clock_offset(1000, 5020, 1040), 4000);
+        // A conductor clock behind ours gives a negative offset
+        assert_eq!(clock_offset(1000, 900, 1010), -105);
+    }
+}
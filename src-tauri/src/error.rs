@@ -0,0 +1,141 @@
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+use crate::config::{describe_errors, ConfigError};
+
+/// Errors returned by Tauri commands, tagged with a `code` so the UI can
+/// show a localized, actionable message rather than the raw text
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum CommandError {
+    /// A file to read does not exist
+    FileNotFound { message: String },
+    /// A file or request was not in the expected format (MIDI, JSON, ...)
+    ParseError { message: String },
+    /// A key name the keyboard backend cannot press
+    UnknownKey { key: String },
+    /// Sending keys, the keyboard hook or a MIDI device failed
+    BackendUnavailable { message: String },
+    /// The command works on the loaded song, and none is loaded
+    NoSongLoaded,
+    /// The change would leave the config invalid
+    InvalidConfig { errors: Vec<ConfigError> },
+    /// Reading or writing a file or socket failed
+    Io { message: String },
+    /// Anything else, such as a command that does not apply right now
+    Failed { message: String },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::FileNotFound { message }
+            | CommandError::ParseError { message }
+            | CommandError::BackendUnavailable { message }
+            | CommandError::Io { message }
+            | CommandError::Failed { message } => f.write_str(message),
+            CommandError::UnknownKey { key } => write!(f, "Unknown key: {}", key),
+            CommandError::NoSongLoaded => f.write_str("No MIDI file loaded"),
+            CommandError::InvalidConfig { errors } => f.write_str(&describe_errors(errors)),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Raised by the keyboard backend for a key it has no code for
+#[derive(Debug)]
+pub struct UnknownKey(pub String);
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown key: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownKey {}
+
+/// Raised when an OS input or MIDI backend refuses to work
+#[derive(Debug)]
+pub struct BackendUnavailable(pub String);
+
+impl fmt::Display for BackendUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BackendUnavailable {}
+
+impl From<anyhow::Error> for CommandError {
+    /// Classify by the first recognizable cause in the error's chain
+    fn from(error: anyhow::Error) -> Self {
+        let message = error.to_string();
+        for cause in error.chain() {
+            if let Some(UnknownKey(key)) = cause.downcast_ref() {
+                return CommandError::UnknownKey { key: key.clone() };
+            }
+            if cause.is::<BackendUnavailable>() {
+                return CommandError::BackendUnavailable { message };
+            }
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                return match e.kind() {
+                    io::ErrorKind::NotFound => CommandError::FileNotFound { message },
+                    _ => CommandError::Io { message },
+                };
+            }
+            if cause.is::<midly::Error>() || cause.is::<serde_json::Error>() {
+                return CommandError::ParseError { message };
+            }
+        }
+        CommandError::Failed { message }
+    }
+}
+
+impl From<serde_json::Error> for CommandError {
+    fn from(error: serde_json::Error) -> Self {
+        CommandError::ParseError {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<Vec<ConfigError>> for CommandError {
+    fn from(errors: Vec<ConfigError>) -> Self {
+        CommandError::InvalidConfig { errors }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Failed { message }
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Failed {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classify() {
+        let missing = std::fs::read("/no/such/file.mid").context("Opening song");
+        let error = CommandError::from(missing.unwrap_err());
+        assert!(matches!(error, CommandError::FileNotFound { .. }));
+
+        let key = CommandError::from(anyhow::Error::new(UnknownKey("F13".into())));
+        assert!(matches!(key, CommandError::UnknownKey { key } if key == "F13"));
+
+        let json = serde_json::to_value(CommandError::NoSongLoaded).unwrap();
+        assert_eq!(json, serde_json::json!({ "code": "no_song_loaded" }));
+    }
+}
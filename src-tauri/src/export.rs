@@ -0,0 +1,258 @@
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::fs;
+
+use crate::config::{AppConfig, LayoutMode};
+use crate::keyboard::Modifier;
+use crate::library;
+use crate::mapper::{keystroke_to_midi, DRUM_CHANNEL};
+use crate::midi::{self, MidiFile, NoteEvent};
+use crate::musicxml;
+use crate::playback::{self, ScheduledEvent};
+
+/// Write the song's keystrokes as an AutoHotkey v2 script that plays it
+/// with Send/Sleep, using the current tempo and start delay
+pub fn export_ahk(path: &str, midi: &MidiFile, config: &AppConfig) -> Result<()> {
+    let events = playback::song_timeline(midi, config)?;
+    let title = library::title_from_path(&midi.path);
+    fs::write(path, ahk_script(&title, &events, config))?;
+    Ok(())
+}
+
+/// Write the arrangement that will actually be played (after transpose,
+/// polyphony limiting and range dropping) as a MIDI file, with each
+/// keystroke turned back into the pitch it sounds in-game
+pub fn export_midi(path: &str, midi: &MidiFile, config: &AppConfig) -> Result<()> {
+    let events = playback::song_timeline(midi, config)?;
+    let mut notes = arrangement_notes(&events, config);
+    let at_tempo = |ms: u64| (ms as f64 / config.tempo_factor).round() as u64;
+    for note in &mut notes {
+        let end_ms = at_tempo(note.start_ms + note.duration_ms);
+        note.start_ms = at_tempo(note.start_ms);
+        note.duration_ms = end_ms - note.start_ms;
+    }
+    midi::save_file(path, &notes)
+}
+
+/// Keystrokes paired back into sounding notes, in song time
+fn arrangement_notes(events: &[ScheduledEvent], config: &AppConfig) -> Vec<NoteEvent> {
+    let channel = match config.key_mapping.mode {
+        LayoutMode::Drums => DRUM_CHANNEL,
+        _ => 0,
+    };
+
+    // A key can be pressed again before its earlier release, so releases
+    // end presses of the same keystroke in order
+    let mut pressed: HashMap<(&str, Modifier), VecDeque<u64>> = HashMap::new();
+    let mut notes = Vec::new();
+    for event in events {
+        let keystroke = (event.key.as_str(), event.modifier);
+        if event.is_key_down {
+            pressed
+                .entry(keystroke)
+                .or_default()
+                .push_back(event.time_ms);
+            continue;
+        }
+        let Some(start_ms) = pressed.get_mut(&keystroke).and_then(|s| s.pop_front()) else {
+            continue;
+        };
+        if let Some(note) = keystroke_to_midi(&event.key, event.modifier, config) {
+            notes.push(NoteEvent {
+                start_ms,
+                duration_ms: event.time_ms - start_ms,
+                note,
+                velocity: 100,
+                track: 0,
+                channel,
+            });
+        }
+    }
+    notes.sort_by_key(|n| n.start_ms);
+    notes
+}
+
+/// Write the arrangement that will actually be played as MusicXML, for
+/// printable sheet music of the in-game version
+pub fn export_musicxml(path: &str, midi: &MidiFile, config: &AppConfig) -> Result<()> {
+    let events = playback::song_timeline(midi, config)?;
+    let notes = arrangement_notes(&events, config);
+    let title = library::title_from_path(&midi.path);
+    let xml = musicxml::render(
+        &title,
+        &notes,
+        midi.bar_ms,
+        midi.time_signature,
+        config.tempo_factor,
+    );
+    fs::write(path, xml)?;
+    Ok(())
+}
+
+/// Bars shown per line of a keysheet
+const BARS_PER_LINE: usize = 4;
+
+/// Key presses closer together than this are written as one chord
+const CHORD_WINDOW_MS: u64 = 30;
+
+/// Write the keys to press as a plain-text keysheet, one bar per
+/// `|`-separated group, for learning the piece by hand
+pub fn export_keysheet(path: &str, midi: &MidiFile, config: &AppConfig) -> Result<()> {
+    let events = playback::song_timeline(midi, config)?;
+    let title = library::title_from_path(&midi.path);
+    fs::write(path, keysheet(&title, &events, midi.bar_ms))?;
+    Ok(())
+}
+
+fn keysheet(title: &str, events: &[ScheduledEvent], bar_ms: u64) -> String {
+    // Each bar holds its chords as (start time, key labels)
+    let mut bars: Vec<Vec<(u64, Vec<String>)>> = Vec::new();
+    for event in events.iter().filter(|e| e.is_key_down) {
+        let bar = (event.time_ms / bar_ms) as usize;
+        if bars.len() <= bar {
+            bars.resize(bar + 1, Vec::new());
+        }
+
+        let label = event.modifier.label(&event.key);
+        match bars[bar].last_mut() {
+            Some((start, keys)) if event.time_ms - *start <= CHORD_WINDOW_MS => keys.push(label),
+            _ => bars[bar].push((event.time_ms, vec![label])),
+        }
+    }
+
+    let rendered: Vec<String> = bars
+        .iter()
+        .map(|chords| {
+            if chords.is_empty() {
+                return "-".to_string();
+            }
+            let tokens: Vec<String> = chords
+                .iter()
+                .map(|(_, keys)| match keys.as_slice() {
+                    [key] => key.clone(),
+                    _ => format!("[{}]", keys.join(" ")),
+                })
+                .collect();
+            tokens.join(" ")
+        })
+        .collect();
+
+    let mut sheet = format!("{}\n\n", title);
+    for line in rendered.chunks(BARS_PER_LINE) {
+        sheet.push_str(&line.join(" | "));
+        sheet.push('\n');
+    }
+    sheet
+}
+
+fn ahk_script(title: &str, events: &[ScheduledEvent], config: &AppConfig) -> String {
+    let mut script = String::new();
+    let _ = writeln!(script, "; {} - exported from WWMP", title);
+    let _ = writeln!(
+        script,
+        "; Switch to the game during the start delay. Esc stops."
+    );
+    script.push_str("#Requires AutoHotkey v2.0\n");
+    script.push_str("SendMode \"Input\"\n\n");
+    let _ = writeln!(script, "Sleep {}", config.start_delay_ms);
+
+    // Sleep to each event's absolute time so rounding never accumulates
+    let mut elapsed_ms = 0;
+    for event in events {
+        let at_ms = (event.time_ms as f64 / config.tempo_factor).round() as u64;
+        if at_ms > elapsed_ms {
+            let _ = writeln!(script, "Sleep {}", at_ms - elapsed_ms);
+            elapsed_ms = at_ms;
+        }
+
+        let key = event.key.to_lowercase();
+        let modifier = match event.modifier {
+            Modifier::None => None,
+            Modifier::Shift => Some("LShift"),
+            Modifier::Ctrl => Some("LCtrl"),
+        };
+        // Same order as the engine: modifier wraps the key
+        let keys = match (modifier, event.is_key_down) {
+            (Some(m), true) => format!("{{{} down}}{{{} down}}", m, key),
+            (Some(m), false) => format!("{{{} up}}{{{} up}}", key, m),
+            (None, true) => format!("{{{} down}}", key),
+            (None, false) => format!("{{{} up}}", key),
+        };
+        let _ = writeln!(script, "Send \"{}\"", keys);
+    }
+
+    script.push_str("ExitApp\n\nEsc::ExitApp\n");
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    #[test]
+    fn test_arrangement_notes() {
+        let config = AppConfig::default();
+        let events = [
+            event(0, "A", Modifier::None, true),
+            event(100, "R", Modifier::Shift, true),
+            event(200, "A", Modifier::None, false),
+            event(300, "R", Modifier::Shift, false),
+        ];
+        let notes = arrangement_notes(&events, &config);
+        let summary: Vec<_> = notes
+            .iter()
+            .map(|n| (n.start_ms, n.duration_ms, n.note))
+            .collect();
+        assert_eq!(summary, [(0, 200, 60), (100, 200, 78)]);
+    }
+
+    #[test]
+    fn test_keysheet() {
+        let events = [
+            event(0, "A", Modifier::None, true),
+            event(10, "D", Modifier::None, true),
+            event(500, "S", Modifier::None, true),
+            event(2000, "F", Modifier::Shift, true),
+            event(2100, "F", Modifier::Shift, false),
+            event(6000, "G", Modifier::None, true),
+        ];
+        assert_eq!(
+            keysheet("Song", &events, 2000),
+            "Song\n\n[A D] S | Shift+F | - | G\n"
+        );
+    }
+
+    #[test]
+    fn test_ahk_script() {
+        let config = AppConfig {
+            tempo_factor: 2.0,
+            start_delay_ms: 1000,
+            ..AppConfig::default()
+        };
+        let events = [
+            event(0, "A", Modifier::None, true),
+            event(100, "A", Modifier::None, false),
+            event(100, "R", Modifier::Shift, true),
+            event(300, "R", Modifier::Shift, false),
+        ];
+        let script = ahk_script("Song", &events, &config);
+        let body: Vec<&str> = script
+            .lines()
+            .skip_while(|l| !l.starts_with("Sleep"))
+            .collect();
+        assert_eq!(
+            body[..7],
+            [
+                "Sleep 1000",
+                "Send \"{a down}\"",
+                "Sleep 50",
+                "Send \"{a up}\"",
+                "Send \"{LShift down}{r down}\"",
+                "Sleep 100",
+                "Send \"{r up}{LShift up}\"",
+            ]
+        );
+    }
+}
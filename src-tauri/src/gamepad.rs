@@ -0,0 +1,97 @@
+#[cfg(windows)]
+use std::thread;
+#[cfg(windows)]
+use std::time::Duration;
+#[cfg(windows)]
+use windows::Win32::UI::Input::XboxController::{
+    XInputGetState, XINPUT_GAMEPAD_A, XINPUT_GAMEPAD_B, XINPUT_GAMEPAD_BACK,
+    XINPUT_GAMEPAD_BUTTON_FLAGS, XINPUT_GAMEPAD_DPAD_DOWN, XINPUT_GAMEPAD_DPAD_LEFT,
+    XINPUT_GAMEPAD_DPAD_RIGHT, XINPUT_GAMEPAD_DPAD_UP, XINPUT_GAMEPAD_LEFT_SHOULDER,
+    XINPUT_GAMEPAD_LEFT_THUMB, XINPUT_GAMEPAD_RIGHT_SHOULDER, XINPUT_GAMEPAD_RIGHT_THUMB,
+    XINPUT_GAMEPAD_START, XINPUT_GAMEPAD_X, XINPUT_GAMEPAD_Y, XINPUT_STATE,
+};
+
+/// Button names usable in `GamepadConfig`
+#[rustfmt::skip]
+pub const GAMEPAD_BUTTONS: &[&str] = &[
+    "A", "B", "X", "Y", "Start", "Back",
+    "LeftShoulder", "RightShoulder", "LeftThumb", "RightThumb",
+    "DPadUp", "DPadDown", "DPadLeft", "DPadRight",
+];
+
+/// Check whether a button name is known
+pub fn is_supported_button(button: &str) -> bool {
+    GAMEPAD_BUTTONS.contains(&button)
+}
+
+#[cfg(windows)]
+fn button_flag(button: &str) -> XINPUT_GAMEPAD_BUTTON_FLAGS {
+    match button {
+        "A" => XINPUT_GAMEPAD_A,
+        "B" => XINPUT_GAMEPAD_B,
+        "X" => XINPUT_GAMEPAD_X,
+        "Y" => XINPUT_GAMEPAD_Y,
+        "Start" => XINPUT_GAMEPAD_START,
+        "Back" => XINPUT_GAMEPAD_BACK,
+        "LeftShoulder" => XINPUT_GAMEPAD_LEFT_SHOULDER,
+        "RightShoulder" => XINPUT_GAMEPAD_RIGHT_SHOULDER,
+        "LeftThumb" => XINPUT_GAMEPAD_LEFT_THUMB,
+        "RightThumb" => XINPUT_GAMEPAD_RIGHT_THUMB,
+        "DPadUp" => XINPUT_GAMEPAD_DPAD_UP,
+        "DPadDown" => XINPUT_GAMEPAD_DPAD_DOWN,
+        "DPadLeft" => XINPUT_GAMEPAD_DPAD_LEFT,
+        "DPadRight" => XINPUT_GAMEPAD_DPAD_RIGHT,
+        _ => XINPUT_GAMEPAD_BUTTON_FLAGS(0),
+    }
+}
+
+/// Poll connected XInput controllers on a background thread and call
+/// `on_press` with the name of each newly pressed button. Polling idles
+/// while `enabled` returns false.
+#[cfg(windows)]
+pub fn listen(
+    enabled: impl Fn() -> bool + Send + 'static,
+    on_press: impl Fn(&str) + Send + 'static,
+) {
+    thread::spawn(move || {
+        // Buttons held on each of the four controller slots last poll
+        let mut held = [0u16; 4];
+
+        loop {
+            if !enabled() {
+                held = [0; 4];
+                thread::sleep(Duration::from_millis(500));
+                continue;
+            }
+
+            for (slot, previous) in held.iter_mut().enumerate() {
+                let mut state = XINPUT_STATE::default();
+                let buttons = if unsafe { XInputGetState(slot as u32, &mut state) } == 0 {
+                    state.Gamepad.wButtons.0
+                } else {
+                    0 // Disconnected
+                };
+
+                let pressed = buttons & !*previous;
+                *previous = buttons;
+                for button in GAMEPAD_BUTTONS {
+                    if pressed & button_flag(button).0 != 0 {
+                        on_press(button);
+                    }
+                }
+            }
+
+            thread::sleep(Duration::from_millis(16));
+        }
+    });
+}
+
+// Non-Windows stub for development
+#[cfg(not(windows))]
+pub fn listen(
+    enabled: impl Fn() -> bool + Send + 'static,
+    on_press: impl Fn(&str) + Send + 'static,
+) {
+    let _ = (enabled, on_press);
+    println!("STUB: gamepad::listen()");
+}
@@ -0,0 +1,304 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::config::{AppConfig, GamepadConfig, HotkeyAction, Hotkeys, MAX_TEMPO, MIN_TEMPO};
+use crate::gamepad;
+use crate::keyboard_hook::{self, KeyCombo};
+use crate::mapper::keystroke_to_midi;
+use crate::AppState;
+
+/// Event emitted after a hotkey changed the transpose, with the new value
+pub const TRANSPOSE_CHANGED_EVENT: &str = "playback://transpose";
+
+/// Event emitted after a hotkey changed the tempo, with the new factor
+pub const TEMPO_CHANGED_EVENT: &str = "playback://tempo";
+
+/// Event emitted after a hotkey muted or unmuted keystrokes, with the new state
+pub const MUTED_CHANGED_EVENT: &str = "playback://muted";
+
+/// Active bindings, swapped out whenever hotkeys are re-registered
+static BINDINGS: Mutex<Vec<(KeyCombo, HotkeyAction)>> = Mutex::new(Vec::new());
+
+/// Whether the keyboard hook and its action thread are running
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+/// Receives the next key press instead of its binding, while a hotkey
+/// is being captured
+static CAPTURE: Mutex<Option<mpsc::Sender<KeyCombo>>> = Mutex::new(None);
+
+/// How long `capture_next` waits for a key
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a key combo would clash with if bound
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HotkeyConflict {
+    /// Already bound to another action, by its config field name
+    Binding { action: String },
+    /// A key of the instrument layout, which playing presses for `note`
+    InstrumentKey { note: u8 },
+}
+
+/// Register the configured global hotkeys, replacing any earlier bindings.
+/// Empty accelerators are left unbound.
+pub fn register_hotkeys(app: &AppHandle, hotkeys: &Hotkeys) -> Result<()> {
+    let (bindings, failed) = parse_bindings(hotkeys);
+    *BINDINGS.lock().unwrap() = bindings;
+
+    if !LISTENING.swap(true, Ordering::SeqCst) {
+        if let Err(e) = listen(app.clone()) {
+            LISTENING.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("Invalid hotkeys: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// The key combo of each non-empty binding, and the accelerators that
+/// don't parse. Bad bindings are skipped so the others still work.
+fn parse_bindings(hotkeys: &Hotkeys) -> (Vec<(KeyCombo, HotkeyAction)>, Vec<&str>) {
+    let mut bindings = Vec::new();
+    let mut failed = Vec::new();
+    for (accelerator, action) in hotkeys.actions() {
+        if accelerator.is_empty() {
+            continue;
+        }
+        match KeyCombo::parse(accelerator) {
+            Ok(combo) => bindings.push((combo, action)),
+            Err(_) => failed.push(accelerator.as_str()),
+        }
+    }
+    (bindings, failed)
+}
+
+/// Install the keyboard hook and a thread that runs matched actions.
+/// The hook must return quickly, so only the lock-free panic runs inline.
+fn listen(app: AppHandle) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let hook_app = app.clone();
+
+    keyboard_hook::install(move |combo| {
+        if let Some(capture) = CAPTURE.lock().unwrap().take() {
+            let _ = capture.send(combo.clone());
+            return true;
+        }
+        let bindings = BINDINGS.lock().unwrap();
+        match bindings.iter().find(|(c, _)| c == combo) {
+            Some((_, HotkeyAction::Panic)) => crate::panic_all(&hook_app.state::<AppState>()),
+            Some((_, action)) => {
+                let _ = tx.send(*action);
+            }
+            None => return false,
+        }
+        true
+    })?;
+
+    thread::spawn(move || {
+        while let Ok(action) = rx.recv() {
+            run_action(&app, action);
+        }
+    });
+    Ok(())
+}
+
+/// A key combo captured for a binding
+#[derive(Debug, Clone, Serialize)]
+pub struct HotkeyCapture {
+    pub accelerator: String,
+    /// Why it was not saved; empty if it was
+    pub conflicts: Vec<HotkeyConflict>,
+}
+
+/// Wait for the next physical key combo, swallowing it rather than
+/// running what it is bound to. None if Escape is pressed or nothing is
+/// within `CAPTURE_TIMEOUT`.
+pub fn capture_next() -> Result<Option<KeyCombo>> {
+    if !LISTENING.load(Ordering::SeqCst) {
+        bail!("Hotkeys are not being listened for");
+    }
+    let (tx, rx) = mpsc::channel();
+    *CAPTURE.lock().unwrap() = Some(tx);
+    let combo = rx.recv_timeout(CAPTURE_TIMEOUT).ok();
+    *CAPTURE.lock().unwrap() = None;
+    Ok(combo.filter(|c| c.to_string() != "Escape"))
+}
+
+/// What `combo` would clash with as the binding for `action`: the other
+/// hotkeys, and the instrument keys playback presses
+pub fn conflicts(combo: &KeyCombo, action: &str, config: &AppConfig) -> Vec<HotkeyConflict> {
+    let mut conflicts: Vec<HotkeyConflict> = config
+        .hotkeys
+        .bindings()
+        .into_iter()
+        .filter(|(name, accelerator)| {
+            *name != action && KeyCombo::parse(accelerator).is_ok_and(|bound| bound == *combo)
+        })
+        .map(|(name, _)| HotkeyConflict::Binding {
+            action: name.to_string(),
+        })
+        .collect();
+
+    // Playing never holds Alt, so an Alt combo can't be an instrument key
+    if !combo.alt {
+        if let Some(note) = keystroke_to_midi(&combo.key, combo.modifier(), config) {
+            conflicts.push(HotkeyConflict::InstrumentKey { note });
+        }
+    }
+    conflicts
+}
+
+/// Poll controllers for transport buttons. Bindings are read from the
+/// config on each press, so edits apply without re-registering.
+pub fn listen_gamepad(app: AppHandle) {
+    let handle = app.clone();
+    let enabled = move || {
+        let state = handle.state::<AppState>();
+        let enabled = state.config.lock().unwrap().gamepad.enabled;
+        enabled
+    };
+
+    gamepad::listen(enabled, move |button| {
+        let state = app.state::<AppState>();
+        let action = gamepad_action(&state.config.lock().unwrap().gamepad, button);
+        if let Some(action) = action {
+            run_action(&app, action);
+        }
+    });
+}
+
+fn gamepad_action(gamepad: &GamepadConfig, button: &str) -> Option<HotkeyAction> {
+    [
+        (&gamepad.play_pause, HotkeyAction::PlayPause),
+        (&gamepad.stop, HotkeyAction::Stop),
+        (&gamepad.panic, HotkeyAction::Panic),
+    ]
+    .into_iter()
+    .find(|(bound, _)| bound.as_str() == button)
+    .map(|(_, action)| action)
+}
+
+/// `factor` moved by `steps` tempo steps of `step`, kept within the
+/// allowed range
+fn nudged_tempo(factor: f64, step: f64, steps: i32) -> f64 {
+    (factor + step * steps as f64).clamp(MIN_TEMPO, MAX_TEMPO)
+}
+
+fn run_action(app: &AppHandle, action: HotkeyAction) {
+    let state = app.state::<AppState>();
+    match action {
+        HotkeyAction::PlayPause => {
+            let _ = crate::toggle_playback(&state);
+        }
+        HotkeyAction::Stop => crate::stop_all(&state),
+        HotkeyAction::Panic => crate::panic_all(&state),
+        HotkeyAction::Transpose(semitones) => {
+            let transpose = state.config.lock().unwrap().transpose + semitones;
+            if crate::apply_transpose(&state, transpose).is_ok() {
+                let _ = app.emit_all(TRANSPOSE_CHANGED_EVENT, transpose);
+            }
+        }
+        HotkeyAction::Tempo(steps) => {
+            let factor = {
+                let config = state.config.lock().unwrap();
+                nudged_tempo(config.tempo_factor, config.hotkeys.tempo_step, steps)
+            };
+            if crate::apply_tempo(&state, factor).is_ok() {
+                let _ = app.emit_all(TEMPO_CHANGED_EVENT, factor);
+            }
+        }
+        HotkeyAction::StepNext => {
+            let _ = crate::step_next_chord(app);
+        }
+        HotkeyAction::ToggleMute => {
+            let playback = state.playback.lock().unwrap();
+            let muted = !playback.is_muted();
+            playback.set_muted(muted);
+            let _ = app.emit_all(MUTED_CHANGED_EVENT, muted);
+        }
+        HotkeyAction::Restart => {
+            let _ = crate::restart_playback(&state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicts() {
+        let config = AppConfig::default();
+        let check = |accelerator, action| {
+            conflicts(&KeyCombo::parse(accelerator).unwrap(), action, &config)
+        };
+
+        assert_eq!(
+            check("F8", "play_pause"),
+            [HotkeyConflict::Binding {
+                action: "stop".into()
+            }]
+        );
+        // A binding doesn't clash with itself
+        assert!(check("F7", "play_pause").is_empty());
+        assert_eq!(
+            check("A", "restart"),
+            [HotkeyConflict::InstrumentKey { note: 60 }]
+        );
+        assert!(check("Alt+A", "restart").is_empty());
+    }
+
+    #[test]
+    fn test_parse_bindings() {
+        let mut hotkeys = Hotkeys::default();
+        hotkeys.stop = String::new();
+        hotkeys.restart = "Alt+Nope".into();
+        let (bindings, failed) = parse_bindings(&hotkeys);
+
+        let action = |accelerator| {
+            let combo = KeyCombo::parse(accelerator).unwrap();
+            bindings
+                .iter()
+                .find(|(c, _)| *c == combo)
+                .map(|(_, action)| *action)
+        };
+        assert_eq!(action("Alt+Up"), Some(HotkeyAction::Transpose(1)));
+        assert_eq!(action("Alt+PageDown"), Some(HotkeyAction::Transpose(-12)));
+        assert_eq!(action("Alt+Right"), Some(HotkeyAction::Tempo(1)));
+        assert_eq!(action("F9"), Some(HotkeyAction::Panic));
+        // Unbound and unparsable bindings are left out
+        assert_eq!(action("F8"), None);
+        assert_eq!(bindings.len(), 10);
+        assert_eq!(failed, ["Alt+Nope"]);
+    }
+
+    #[test]
+    fn test_nudged_tempo() {
+        assert!((nudged_tempo(1.0, 0.05, 1) - 1.05).abs() < 1e-9);
+        assert!((nudged_tempo(1.0, 0.05, -1) - 0.95).abs() < 1e-9);
+        assert_eq!(nudged_tempo(MAX_TEMPO, 0.05, 1), MAX_TEMPO);
+        assert_eq!(nudged_tempo(0.3, 0.1, -1), MIN_TEMPO);
+    }
+
+    #[test]
+    fn test_gamepad_action() {
+        let gamepad = GamepadConfig::default();
+        assert_eq!(
+            gamepad_action(&gamepad, "Start"),
+            Some(HotkeyAction::PlayPause)
+        );
+        assert_eq!(
+            gamepad_action(&gamepad, "RightThumb"),
+            Some(HotkeyAction::Panic)
+        );
+        assert_eq!(gamepad_action(&gamepad, "A"), None);
+    }
+}
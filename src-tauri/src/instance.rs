@@ -0,0 +1,169 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::config::AppConfig;
+use crate::error::CommandError;
+use crate::midi::MidiInfo;
+use crate::tray;
+
+/// Loopback port held by the running instance. A second launch finds it
+/// taken and hands its arguments over instead of starting.
+const INSTANCE_PORT: u16 = 9325;
+
+/// Sent by the running instance once it accepts a launch's arguments, so
+/// the launch knows it reached WWMP and not some other program
+const ACCEPTED: &str = "wwmp-instance-accepted";
+
+/// How long either side waits for the other's line
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a later launch sends the running instance
+#[derive(Debug, Serialize, Deserialize)]
+struct Handoff {
+    /// The running instance's token, which only this user can read, so
+    /// other programs can't have it open files
+    token: String,
+    args: Vec<String>,
+}
+
+/// File holding the running instance's token
+fn token_path() -> Result<PathBuf> {
+    Ok(AppConfig::config_dir()?.join("instance.token"))
+}
+
+/// A fresh token, from the OS-seeded keys of two `RandomState`s
+fn new_token() -> String {
+    let mut token = String::new();
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        token.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    token
+}
+
+/// Emitted with an `OpenedFile` when a file is opened from outside the
+/// window, e.g. by double-clicking a .mid while WWMP is running
+pub const OPEN_EVENT: &str = "app://open-file";
+
+/// A song opened by the OS rather than from the file dialog
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenedFile {
+    pub path: String,
+    pub info: MidiInfo,
+}
+
+/// Load a song handed to us on the command line
+pub fn open_file(app: &AppHandle, path: &str) -> Result<OpenedFile, CommandError> {
+    let info = crate::load_song(app, path)?;
+    Ok(OpenedFile {
+        path: path.to_string(),
+        info,
+    })
+}
+
+/// Become the running instance, or forward `args` to the one already
+/// running. Returns the listener to pass to `listen` if we are first,
+/// or `None` if this process should exit. An error means the running
+/// instance couldn't be reached, e.g. another program holds the port.
+pub fn acquire(args: &[String]) -> Result<Option<TcpListener>> {
+    match TcpListener::bind(("127.0.0.1", INSTANCE_PORT)) {
+        Ok(listener) => {
+            fs::create_dir_all(AppConfig::config_dir()?)?;
+            fs::write(token_path()?, new_token())?;
+            Ok(Some(listener))
+        }
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            let handoff = Handoff {
+                token: fs::read_to_string(token_path()?)?,
+                args: args.to_vec(),
+            };
+            let mut stream = TcpStream::connect(("127.0.0.1", INSTANCE_PORT))?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            let mut line = serde_json::to_string(&handoff)?;
+            line.push('\n');
+            stream.write_all(line.as_bytes())?;
+
+            let mut reply = String::new();
+            BufReader::new(stream).read_line(&mut reply)?;
+            if reply.trim_end() != ACCEPTED {
+                bail!("Port {} is in use by another program", INSTANCE_PORT);
+            }
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Accept arguments from later launches in the background. Connections
+/// without the token are ignored.
+pub fn listen(app: AppHandle, listener: TcpListener) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Some(args) = receive(stream) {
+                forwarded(&app, &args);
+            }
+        }
+    });
+}
+
+/// The arguments sent over `stream`, if it carries our token, answered
+/// with `ACCEPTED`
+fn receive(mut stream: TcpStream) -> Option<Vec<String>> {
+    let token = fs::read_to_string(token_path().ok()?).ok()?;
+    stream.set_read_timeout(Some(TIMEOUT)).ok()?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).ok()?;
+    let handoff: Handoff = serde_json::from_str(&line).ok()?;
+    if handoff.token != token {
+        return None;
+    }
+    stream
+        .write_all(format!("{}\n", ACCEPTED).as_bytes())
+        .ok()?;
+    Some(handoff.args)
+}
+
+/// Bring the window forward and open the file the second launch was
+/// given, if any
+fn forwarded(app: &AppHandle, args: &[String]) {
+    tray::show_main_window(app);
+    if let Some(path) = file_argument(args) {
+        if let Ok(opened) = open_file(app, path) {
+            let _ = app.emit_all(OPEN_EVENT, opened);
+        }
+    }
+}
+
+/// The file to open from a launch's arguments: a lone existing path, as
+/// passed by "Open with" or a double-click on an associated file
+pub fn file_argument(args: &[String]) -> Option<&str> {
+    match args {
+        [path] if Path::new(path).is_file() => Some(path),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_argument() {
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml").to_string();
+        let lone = std::slice::from_ref(&manifest);
+        assert_eq!(file_argument(lone), Some(manifest.as_str()));
+        assert_eq!(file_argument(&["play".into(), manifest]), None);
+        assert_eq!(file_argument(&["/no/such/song.mid".into()]), None);
+        assert_eq!(file_argument(&[]), None);
+    }
+}
@@ -1,51 +1,68 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 
 #[cfg(windows)]
 use windows::Win32::UI::Input::KeyboardAndMouse::{
     SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYBD_EVENT_FLAGS,
     KEYEVENTF_KEYUP, VIRTUAL_KEY,
     VK_LSHIFT, VK_LCONTROL,
-    VK_Q, VK_W, VK_E, VK_R, VK_T, VK_Y, VK_U,
-    VK_A, VK_S, VK_D, VK_F, VK_G, VK_H, VK_J,
-    VK_Z, VK_X, VK_C, VK_V, VK_B, VK_N, VK_M,
+    VK_OEM_1, VK_OEM_2, VK_OEM_COMMA, VK_OEM_PERIOD,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg(windows)]
+use crate::error::{BackendUnavailable, UnknownKey};
+
+/// Key names the backend can send, as used in key mappings
+pub const SUPPORTED_KEYS: &[&str] = &[
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M",
+    "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z",
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9",
+    ";", ",", ".", "/",
+];
+
+/// `dwExtraInfo` stamped on every event we send, so our own keyboard
+/// hook can tell them apart from the player's keystrokes
+#[cfg(windows)]
+pub const INJECTED_TAG: usize = 0x5757_4D50; // "WWMP"
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Modifier {
     None,
     Shift,  // Sharp
     Ctrl,   // Flat
 }
 
+impl Modifier {
+    /// How a key pressed with this modifier is written, e.g. `Shift+F`
+    pub fn label(self, key: &str) -> String {
+        match self {
+            Modifier::None => key.to_string(),
+            Modifier::Shift => format!("Shift+{}", key),
+            Modifier::Ctrl => format!("Ctrl+{}", key),
+        }
+    }
+}
+
 /// Convert a key string to a virtual key code
 #[cfg(windows)]
-fn key_to_vk(key: &str) -> Result<VIRTUAL_KEY> {
+pub(crate) fn key_to_vk(key: &str) -> Result<VIRTUAL_KEY> {
     match key.to_uppercase().as_str() {
-        "Q" => Ok(VK_Q),
-        "W" => Ok(VK_W),
-        "E" => Ok(VK_E),
-        "R" => Ok(VK_R),
-        "T" => Ok(VK_T),
-        "Y" => Ok(VK_Y),
-        "U" => Ok(VK_U),
-        "A" => Ok(VK_A),
-        "S" => Ok(VK_S),
-        "D" => Ok(VK_D),
-        "F" => Ok(VK_F),
-        "G" => Ok(VK_G),
-        "H" => Ok(VK_H),
-        "J" => Ok(VK_J),
-        "Z" => Ok(VK_Z),
-        "X" => Ok(VK_X),
-        "C" => Ok(VK_C),
-        "V" => Ok(VK_V),
-        "B" => Ok(VK_B),
-        "N" => Ok(VK_N),
-        "M" => Ok(VK_M),
-        _ => Err(anyhow!("Unknown key: {}", key)),
+        // Letter and digit virtual key codes match their ASCII values
+        k if k.len() == 1 && k.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            Ok(VIRTUAL_KEY(k.as_bytes()[0] as u16))
+        }
+        ";" => Ok(VK_OEM_1),
+        "," => Ok(VK_OEM_COMMA),
+        "." => Ok(VK_OEM_PERIOD),
+        "/" => Ok(VK_OEM_2),
+        _ => Err(UnknownKey(key.to_string()).into()),
     }
 }
 
+/// Check whether a key name is supported by the keyboard backend
+pub fn is_supported_key(key: &str) -> bool {
+    SUPPORTED_KEYS.contains(&key.to_uppercase().as_str())
+}
+
 #[cfg(windows)]
 fn modifier_to_vk(modifier: Modifier) -> Option<VIRTUAL_KEY> {
     match modifier {
@@ -71,7 +88,7 @@ fn create_key_input(vk: VIRTUAL_KEY, key_up: bool) -> INPUT {
                 wScan: 0,
                 dwFlags: flags,
                 time: 0,
-                dwExtraInfo: 0,
+                dwExtraInfo: INJECTED_TAG,
             },
         },
     }
@@ -83,7 +100,8 @@ fn send_inputs(inputs: &[INPUT]) -> Result<()> {
         SendInput(inputs, std::mem::size_of::<INPUT>() as i32)
     };
     if sent != inputs.len() as u32 {
-        return Err(anyhow!("SendInput failed: sent {} of {}", sent, inputs.len()));
+        let message = format!("SendInput failed: sent {} of {}", sent, inputs.len());
+        return Err(BackendUnavailable(message).into());
     }
     Ok(())
 }
@@ -125,21 +143,28 @@ pub fn release_key(key: &str, modifier: Modifier) -> Result<()> {
 /// Release all keys (panic button)
 #[cfg(windows)]
 pub fn release_all() -> Result<()> {
-    let all_keys = [
-        VK_Q, VK_W, VK_E, VK_R, VK_T, VK_Y, VK_U,
-        VK_A, VK_S, VK_D, VK_F, VK_G, VK_H, VK_J,
-        VK_Z, VK_X, VK_C, VK_V, VK_B, VK_N, VK_M,
-        VK_LSHIFT, VK_LCONTROL,
-    ];
-
-    let inputs: Vec<INPUT> = all_keys
+    let mut inputs: Vec<INPUT> = SUPPORTED_KEYS
         .iter()
-        .map(|&vk| create_key_input(vk, true))
+        .filter_map(|key| key_to_vk(key).ok())
+        .map(|vk| create_key_input(vk, true))
         .collect();
+    inputs.push(create_key_input(VK_LSHIFT, true));
+    inputs.push(create_key_input(VK_LCONTROL, true));
 
     send_inputs(&inputs)
 }
 
+/// Release every key whenever the app panics, before the panic is
+/// reported. Release builds abort on panic without unwinding, so drop
+/// guards alone would not run there.
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = release_all();
+        previous(info);
+    }));
+}
+
 // Non-Windows stubs for development
 #[cfg(not(windows))]
 pub fn press_key(key: &str, modifier: Modifier) -> Result<()> {
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+
+#[cfg(windows)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(windows)]
+use std::sync::{mpsc, Mutex, OnceLock};
+#[cfg(windows)]
+use std::thread;
+#[cfg(windows)]
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
+#[cfg(windows)]
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    VIRTUAL_KEY, VK_BACK, VK_CONTROL, VK_DELETE, VK_DOWN, VK_END, VK_ESCAPE, VK_F1, VK_HOME,
+    VK_INSERT, VK_LCONTROL, VK_LEFT, VK_LMENU, VK_LSHIFT, VK_MENU, VK_NEXT, VK_PAUSE, VK_PRIOR,
+    VK_RCONTROL, VK_RETURN, VK_RIGHT, VK_RMENU, VK_RSHIFT, VK_SHIFT, VK_SPACE, VK_TAB, VK_UP,
+};
+#[cfg(windows)]
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, GetMessageW, SetWindowsHookExW, HHOOK, KBDLLHOOKSTRUCT, MSG, WH_KEYBOARD_LL,
+    WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP,
+};
+
+#[cfg(windows)]
+use crate::error::BackendUnavailable;
+#[cfg(windows)]
+use crate::keyboard;
+use crate::keyboard::{Modifier, SUPPORTED_KEYS};
+
+/// Non-character key names usable in hotkeys, on top of `SUPPORTED_KEYS`
+#[rustfmt::skip]
+pub const HOTKEY_KEYS: &[&str] = &[
+    "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+    "F13", "F14", "F15", "F16", "F17", "F18", "F19", "F20", "F21", "F22", "F23", "F24",
+    "Up", "Down", "Left", "Right", "PageUp", "PageDown", "Home", "End",
+    "Insert", "Delete", "Space", "Escape", "Pause", "Tab", "Backspace", "Enter",
+];
+
+/// A key plus the modifiers held with it, e.g. `Alt+Up`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// Key name as spelled in `HOTKEY_KEYS` or `SUPPORTED_KEYS`
+    pub key: String,
+}
+
+impl KeyCombo {
+    /// The note modifier held with the key; Shift wins over Ctrl
+    pub fn modifier(&self) -> Modifier {
+        match (self.shift, self.ctrl) {
+            (true, _) => Modifier::Shift,
+            (_, true) => Modifier::Ctrl,
+            _ => Modifier::None,
+        }
+    }
+
+    /// Parse an accelerator string such as `Ctrl+Shift+F5`
+    pub fn parse(accelerator: &str) -> Result<Self> {
+        let mut combo = KeyCombo {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key: String::new(),
+        };
+
+        let mut parts: Vec<&str> = accelerator.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        for part in parts {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" | "cmdorctrl" => combo.ctrl = true,
+                "alt" => combo.alt = true,
+                "shift" => combo.shift = true,
+                _ => bail!("Unknown modifier '{}' in hotkey {}", part, accelerator),
+            }
+        }
+
+        combo.key = HOTKEY_KEYS
+            .iter()
+            .chain(SUPPORTED_KEYS)
+            .find(|k| k.eq_ignore_ascii_case(key))
+            .ok_or_else(|| anyhow!("Unknown key '{}' in hotkey {}", key, accelerator))?
+            .to_string();
+        Ok(combo)
+    }
+}
+
+/// Written as `parse` reads it, modifiers in a fixed order
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl"),
+            (self.alt, "Alt"),
+            (self.shift, "Shift"),
+        ] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        f.write_str(&self.key)
+    }
+}
+
+/// Called for each physical key press; returns true to swallow the key
+#[cfg(windows)]
+type Handler = Box<dyn Fn(&KeyCombo) -> bool + Send + Sync>;
+
+#[cfg(windows)]
+static HANDLER: OnceLock<Handler> = OnceLock::new();
+
+/// Called for each physical press (true) and release (false) the handler
+/// didn't swallow
+pub type Watcher = Box<dyn Fn(&KeyCombo, bool) + Send>;
+
+#[cfg(windows)]
+static WATCHER: Mutex<Option<Watcher>> = Mutex::new(None);
+
+/// Modifier state from physical keys only, so the Shift/Ctrl we inject
+/// for accidentals never change which hotkey matches
+#[cfg(windows)]
+static CTRL: AtomicBool = AtomicBool::new(false);
+#[cfg(windows)]
+static ALT: AtomicBool = AtomicBool::new(false);
+#[cfg(windows)]
+static SHIFT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(windows)]
+fn hotkey_vk(key: &str) -> Option<VIRTUAL_KEY> {
+    if let Some(n) = key.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        return (1..=24).contains(&n).then(|| VIRTUAL_KEY(VK_F1.0 + n - 1));
+    }
+    match key {
+        "Up" => Some(VK_UP),
+        "Down" => Some(VK_DOWN),
+        "Left" => Some(VK_LEFT),
+        "Right" => Some(VK_RIGHT),
+        "PageUp" => Some(VK_PRIOR),
+        "PageDown" => Some(VK_NEXT),
+        "Home" => Some(VK_HOME),
+        "End" => Some(VK_END),
+        "Insert" => Some(VK_INSERT),
+        "Delete" => Some(VK_DELETE),
+        "Space" => Some(VK_SPACE),
+        "Escape" => Some(VK_ESCAPE),
+        "Pause" => Some(VK_PAUSE),
+        "Tab" => Some(VK_TAB),
+        "Backspace" => Some(VK_BACK),
+        "Enter" => Some(VK_RETURN),
+        _ => keyboard::key_to_vk(key).ok(),
+    }
+}
+
+#[cfg(windows)]
+fn key_name(vk: VIRTUAL_KEY) -> Option<&'static str> {
+    HOTKEY_KEYS
+        .iter()
+        .chain(SUPPORTED_KEYS)
+        .copied()
+        .find(|k| hotkey_vk(k) == Some(vk))
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code >= 0 {
+        let event = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        let message = wparam.0 as u32;
+        let is_down = message == WM_KEYDOWN || message == WM_SYSKEYDOWN;
+        let is_up = message == WM_KEYUP || message == WM_SYSKEYUP;
+
+        // Our own SendInput events are tagged; never treat them as hotkeys
+        if event.dwExtraInfo != keyboard::INJECTED_TAG && (is_down || is_up) {
+            let vk = VIRTUAL_KEY(event.vkCode as u16);
+            let modifier = match vk {
+                VK_CONTROL | VK_LCONTROL | VK_RCONTROL => Some(&CTRL),
+                VK_MENU | VK_LMENU | VK_RMENU => Some(&ALT),
+                VK_SHIFT | VK_LSHIFT | VK_RSHIFT => Some(&SHIFT),
+                _ => None,
+            };
+
+            if let Some(flag) = modifier {
+                flag.store(is_down, Ordering::SeqCst);
+            } else if let Some(key) = key_name(vk) {
+                let combo = KeyCombo {
+                    ctrl: CTRL.load(Ordering::SeqCst),
+                    alt: ALT.load(Ordering::SeqCst),
+                    shift: SHIFT.load(Ordering::SeqCst),
+                    key: key.to_string(),
+                };
+                if is_down && HANDLER.get().is_some_and(|handler| handler(&combo)) {
+                    return LRESULT(1);
+                }
+                if let Some(watcher) = WATCHER.lock().unwrap().as_ref() {
+                    watcher(&combo, is_down);
+                }
+            }
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// Install a system-wide keyboard hook on its own thread, so hotkeys
+/// fire while the game has focus. Only one hook may be installed.
+#[cfg(windows)]
+pub fn install(handler: impl Fn(&KeyCombo) -> bool + Send + Sync + 'static) -> Result<()> {
+    if HANDLER.set(Box::new(handler)).is_err() {
+        bail!("Keyboard hook is already installed");
+    }
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || unsafe {
+        if let Err(e) = SetWindowsHookExW(WH_KEYBOARD_LL, Some(hook_proc), HINSTANCE::default(), 0)
+        {
+            let message = format!("SetWindowsHookExW failed: {}", e);
+            let _ = tx.send(Err(BackendUnavailable(message).into()));
+            return;
+        }
+        let _ = tx.send(Ok(()));
+
+        // The hook is only called while this thread pumps messages
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {}
+    });
+
+    rx.recv()?
+}
+
+/// Observe key presses and releases through the installed hook, or stop
+/// observing with `None`. Key repeat reports repeated presses.
+#[cfg(windows)]
+pub fn set_watcher(watcher: Option<Watcher>) {
+    *WATCHER.lock().unwrap() = watcher;
+}
+
+// Non-Windows stubs for development
+#[cfg(not(windows))]
+pub fn set_watcher(watcher: Option<Watcher>) {
+    let _ = watcher;
+    println!("STUB: keyboard_hook::set_watcher()");
+}
+
+#[cfg(not(windows))]
+pub fn install(handler: impl Fn(&KeyCombo) -> bool + Send + Sync + 'static) -> Result<()> {
+    let _ = handler;
+    println!("STUB: keyboard_hook::install()");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_combo() {
+        let combo = KeyCombo::parse("ctrl+Shift+pageup").unwrap();
+        assert!(combo.ctrl && combo.shift && !combo.alt);
+        assert_eq!(combo.key, "PageUp");
+
+        assert_eq!(KeyCombo::parse("F7").unwrap().key, "F7");
+        assert!(KeyCombo::parse("Alt+Nope").is_err());
+        assert!(KeyCombo::parse("Hyper+A").is_err());
+        assert!(KeyCombo::parse("").is_err());
+
+        // Written back in a canonical form
+        assert_eq!(combo.to_string(), "Ctrl+Shift+PageUp");
+        assert_eq!(KeyCombo::parse(&combo.to_string()).unwrap(), combo);
+    }
+}
@@ -0,0 +1,392 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::library_db::LibraryDb;
+use crate::midi::{self, MidiInfo};
+
+/// A song known to the library, with user-assigned metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub path: String,
+    pub title: String,
+    pub favorite: bool,
+    /// Free-form tags (genre, difficulty, game, ...), stored lowercase
+    pub tags: Vec<String>,
+    /// TrackName metadata captured when the file was last loaded
+    #[serde(default)]
+    pub track_names: Vec<String>,
+    /// Difficulty rating (1-5) from the last `estimate_difficulty`
+    #[serde(default)]
+    pub difficulty: Option<u8>,
+}
+
+/// Emitted with a `ScannedSong` as each file of a folder scan is parsed
+pub const SCAN_EVENT: &str = "library://scanned";
+
+/// One file of a folder scan, sent as soon as it has been parsed
+#[derive(Debug, Clone, Serialize)]
+pub struct ScannedSong {
+    pub path: String,
+    /// Files parsed so far, this one included
+    pub done: usize,
+    pub total: usize,
+    pub info: Option<MidiInfo>,
+    pub error: Option<String>,
+}
+
+/// How a folder scan went
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanSummary {
+    pub added: usize,
+    pub failed: usize,
+}
+
+/// A library entry matched by a search query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub entry: LibraryEntry,
+    pub score: u32,
+}
+
+/// Index of songs the user has organized, persisted in the library
+/// database
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Library {
+    pub entries: Vec<LibraryEntry>,
+}
+
+impl Library {
+    /// Load the library from the database
+    pub fn load() -> Result<Self> {
+        LibraryDb::open()?.library()
+    }
+
+    /// Save the library to the database
+    pub fn save(&self) -> Result<()> {
+        LibraryDb::open()?.save_library(self)
+    }
+
+    /// Get the entry for `path`, creating it if needed
+    pub fn entry_mut(&mut self, path: &str) -> &mut LibraryEntry {
+        let idx = match self.entries.iter().position(|e| e.path == path) {
+            Some(idx) => idx,
+            None => {
+                self.entries.push(LibraryEntry {
+                    path: path.to_string(),
+                    title: title_from_path(path),
+                    favorite: false,
+                    tags: Vec::new(),
+                    track_names: Vec::new(),
+                    difficulty: None,
+                });
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[idx]
+    }
+
+    /// Add or refresh a song after it has been loaded
+    pub fn record_load(&mut self, path: &str, info: &MidiInfo) {
+        self.entry_mut(path).track_names = info.track_names.clone();
+    }
+
+    /// Remember a song's estimated difficulty for sorting
+    pub fn set_difficulty(&mut self, path: &str, rating: u8) {
+        self.entry_mut(path).difficulty = Some(rating);
+    }
+
+    /// Mark or unmark a song as a favorite
+    pub fn set_favorite(&mut self, path: &str, favorite: bool) {
+        self.entry_mut(path).favorite = favorite;
+    }
+
+    /// Attach a tag to a song (no-op if already present)
+    pub fn add_tag(&mut self, path: &str, tag: &str) {
+        let tag = normalize_tag(tag);
+        if tag.is_empty() {
+            return;
+        }
+        let entry = self.entry_mut(path);
+        if !entry.tags.contains(&tag) {
+            entry.tags.push(tag);
+            entry.tags.sort();
+        }
+    }
+
+    /// Remove a tag from a song
+    pub fn remove_tag(&mut self, path: &str, tag: &str) {
+        let tag = normalize_tag(tag);
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.path == path) {
+            entry.tags.retain(|t| *t != tag);
+        }
+    }
+
+    /// All songs marked as favorite
+    pub fn favorites(&self) -> Vec<LibraryEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.favorite)
+            .cloned()
+            .collect()
+    }
+
+    /// All songs carrying `tag`
+    pub fn by_tag(&self, tag: &str) -> Vec<LibraryEntry> {
+        let tag = normalize_tag(tag);
+        self.entries
+            .iter()
+            .filter(|e| e.tags.contains(&tag))
+            .cloned()
+            .collect()
+    }
+
+    /// Every distinct tag in use, sorted
+    pub fn all_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.entries.iter().flat_map(|e| e.tags.clone()).collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Search titles, tags and track names, best matches first.
+    /// Every whitespace-separated term in `query` must match somewhere.
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut results: Vec<SearchResult> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let mut score = 0;
+                for term in &terms {
+                    let term_score = score_term(entry, term);
+                    if term_score == 0 {
+                        return None;
+                    }
+                    score += term_score;
+                }
+                if entry.favorite {
+                    score += 1;
+                }
+                Some(SearchResult {
+                    entry: entry.clone(),
+                    score,
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(a.entry.title.cmp(&b.entry.title))
+        });
+        results
+    }
+}
+
+/// Score how well a single lowercase term matches an entry (0 = no match)
+fn score_term(entry: &LibraryEntry, term: &str) -> u32 {
+    let title = entry.title.to_lowercase();
+    let title_score = if title == term {
+        100
+    } else if title.starts_with(term) {
+        80
+    } else if title.contains(term) {
+        60
+    } else {
+        0
+    };
+
+    let tag_score = entry
+        .tags
+        .iter()
+        .map(|t| {
+            if t == term {
+                50
+            } else if t.contains(term) {
+                30
+            } else {
+                0
+            }
+        })
+        .max()
+        .unwrap_or(0);
+
+    let track_score = if entry
+        .track_names
+        .iter()
+        .any(|n| n.to_lowercase().contains(term))
+    {
+        20
+    } else {
+        0
+    };
+
+    title_score.max(tag_score).max(track_score)
+}
+
+pub(crate) fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Use the file name without extension as a display title
+pub fn title_from_path(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// The song file after `path` in its folder, in file name order
+pub fn next_in_folder(path: &str) -> Option<PathBuf> {
+    let current = Path::new(path);
+    let mut files: Vec<PathBuf> = fs::read_dir(current.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| midi::is_song_file(&p.to_string_lossy()))
+        .collect();
+    files.sort();
+    files
+        .into_iter()
+        .find(|p| p.file_name() > current.file_name())
+}
+
+/// Song files in `folder` and its subfolders, in path order. Links to
+/// folders are not followed, so a link back up the tree cannot loop.
+pub fn song_files(folder: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut folders = vec![folder.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                folders.push(path);
+            } else if midi::is_song_file(&path.to_string_lossy()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Run `parse` on every file across a pool of worker threads, one per
+/// core, handing each result to `on_result` on the calling thread as soon
+/// as it is ready. Results arrive in the order they finish.
+pub fn parse_all<T: Send>(
+    files: &[PathBuf],
+    parse: impl Fn(&Path) -> T + Sync,
+    mut on_result: impl FnMut(&Path, T),
+) {
+    let workers = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .min(files.len());
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let (tx, next, parse) = (tx.clone(), &next, &parse);
+            scope.spawn(move || {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if tx.send((file, parse(file))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (file, result) in rx {
+            on_result(file, result);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, tags: &[&str], track_names: &[&str]) -> LibraryEntry {
+        LibraryEntry {
+            path: format!("{}.mid", title),
+            title: title.to_string(),
+            favorite: false,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            track_names: track_names.iter().map(|t| t.to_string()).collect(),
+            difficulty: None,
+        }
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let library = Library {
+            entries: vec![
+                entry("Moonlight Sonata", &["classical"], &["Piano"]),
+                entry("Moon", &[], &[]),
+                entry("Tavern Song", &["moon festival"], &[]),
+                entry("Battle", &[], &["Moonlit Flute"]),
+            ],
+        };
+
+        let titles: Vec<String> = library
+            .search("moon")
+            .into_iter()
+            .map(|r| r.entry.title)
+            .collect();
+        assert_eq!(
+            titles,
+            vec!["Moon", "Moonlight Sonata", "Tavern Song", "Battle"]
+        );
+    }
+
+    #[test]
+    fn test_search_requires_all_terms() {
+        let library = Library {
+            entries: vec![
+                entry("Moonlight Sonata", &["classical"], &[]),
+                entry("Moon", &[], &[]),
+            ],
+        };
+
+        let results = library.search("moon classical");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].entry.title, "Moonlight Sonata");
+        assert!(library.search("   ").is_empty());
+    }
+
+    #[test]
+    fn test_song_files_and_parse_all() {
+        let dir = std::env::temp_dir().join(format!("wwmp-scan-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b.mid", "a.mml", "notes.txt", "sub/c.midi"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let files = song_files(&dir);
+        assert_eq!(
+            files,
+            [
+                dir.join("a.mml"),
+                dir.join("b.mid"),
+                dir.join("sub").join("c.midi")
+            ]
+        );
+
+        let mut parsed = Vec::new();
+        let extension_len = |file: &Path| file.extension().unwrap().len();
+        parse_all(&files, extension_len, |_, len| parsed.push(len));
+        parsed.sort();
+        assert_eq!(parsed, [3, 3, 4]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,573 @@
+use anyhow::Result;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::AppConfig;
+use crate::library::{self, Library, LibraryEntry};
+use crate::song_settings::{SongSettings, SongSettingsStore};
+
+/// Bumped with each change to the tables below; `user_version` records
+/// which one a database was created with
+const SCHEMA_VERSION: i32 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS songs (
+        path TEXT PRIMARY KEY,
+        title TEXT NOT NULL,
+        favorite INTEGER NOT NULL DEFAULT 0,
+        track_names TEXT NOT NULL DEFAULT '[]',
+        difficulty INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS tags (
+        path TEXT NOT NULL REFERENCES songs (path) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (path, tag)
+    );
+    CREATE INDEX IF NOT EXISTS tags_by_tag ON tags (tag);
+    CREATE TABLE IF NOT EXISTS song_settings (
+        hash TEXT PRIMARY KEY,
+        settings TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS plays (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        played_at INTEGER NOT NULL,
+        settings TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS plays_by_time ON plays (played_at);
+";
+
+/// Order of `query` results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LibrarySort {
+    #[default]
+    Title,
+    /// Easiest first; unrated songs last
+    Difficulty,
+    /// Most recently added first
+    Added,
+}
+
+/// Filters for `query`; every one given must match
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LibraryQuery {
+    /// Text the title or a track name contains, ignoring case
+    pub text: Option<String>,
+    /// Tags the song carries, all of them
+    pub tags: Vec<String>,
+    pub favorite: Option<bool>,
+    pub min_difficulty: Option<u8>,
+    pub max_difficulty: Option<u8>,
+    pub sort: LibrarySort,
+    pub limit: Option<u32>,
+}
+
+/// A song played through to the end
+#[derive(Debug, Clone, Serialize)]
+pub struct Play {
+    pub path: String,
+    pub title: String,
+    /// Unix time (ms)
+    pub played_at: u64,
+    /// Transpose, tempo and the rest as they were for this play
+    pub settings: SongSettings,
+}
+
+/// How often a song was played through
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayCount {
+    pub path: String,
+    pub title: String,
+    pub plays: u32,
+    /// Unix time (ms)
+    pub last_played: u64,
+}
+
+/// The library, per-song settings and play history, stored in `library.db` in the
+/// config folder
+pub struct LibraryDb {
+    conn: Connection,
+}
+
+impl LibraryDb {
+    /// Open the database, creating it on first use from the JSON files
+    /// earlier versions kept
+    pub fn open() -> Result<Self> {
+        let dir = AppConfig::config_dir()?;
+        fs::create_dir_all(&dir)?;
+        let db = Self::with_connection(Connection::open(dir.join("library.db"))?)?;
+        db.migrate(&dir)?;
+        Ok(db)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Mark the tables as current, first importing the JSON files from `dir`
+    /// if they were only just made. Both happen in one transaction, so an
+    /// import that fails is tried again on the next start
+    fn migrate(&self, dir: &Path) -> Result<()> {
+        let version: i32 = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= SCHEMA_VERSION {
+            return Ok(());
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        if version == 0 {
+            import_json(&tx, dir)?;
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {};", SCHEMA_VERSION))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Every song, in the order they were added
+    pub fn library(&self) -> Result<Library> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, title, favorite, track_names, difficulty FROM songs ORDER BY rowid",
+        )?;
+        let mut entries = stmt
+            .query_map([], entry_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, tag FROM tags ORDER BY tag")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (path, tag) = row?;
+            tags.entry(path).or_default().push(tag);
+        }
+        for entry in &mut entries {
+            entry.tags = tags.remove(&entry.path).unwrap_or_default();
+        }
+        Ok(Library { entries })
+    }
+
+    /// Write every entry of `library`, in one transaction
+    pub fn save_library(&self, library: &Library) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        write_library(&tx, library)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Songs matching `query`, with their tags
+    pub fn query(&self, query: &LibraryQuery) -> Result<Vec<LibraryEntry>> {
+        let mut sql = String::from(
+            "SELECT path, title, favorite, track_names, difficulty FROM songs WHERE 1 = 1",
+        );
+        let mut values: Vec<Value> = Vec::new();
+        if let Some(text) = query.text.as_deref().filter(|t| !t.trim().is_empty()) {
+            sql += " AND (title LIKE ?1 ESCAPE '\\' OR track_names LIKE ?1 ESCAPE '\\')";
+            values.push(Value::Text(format!("%{}%", escape_like(text.trim()))));
+        }
+        for tag in &query.tags {
+            sql += &format!(
+                " AND path IN (SELECT path FROM tags WHERE tag = ?{})",
+                values.len() + 1
+            );
+            values.push(Value::Text(library::normalize_tag(tag)));
+        }
+        if let Some(favorite) = query.favorite {
+            sql += &format!(" AND favorite = ?{}", values.len() + 1);
+            values.push(Value::Integer(favorite as i64));
+        }
+        if let Some(min) = query.min_difficulty {
+            sql += &format!(" AND difficulty >= ?{}", values.len() + 1);
+            values.push(Value::Integer(min as i64));
+        }
+        if let Some(max) = query.max_difficulty {
+            sql += &format!(" AND difficulty <= ?{}", values.len() + 1);
+            values.push(Value::Integer(max as i64));
+        }
+        sql += match query.sort {
+            LibrarySort::Title => " ORDER BY title COLLATE NOCASE",
+            LibrarySort::Difficulty => {
+                " ORDER BY difficulty IS NULL, difficulty, title COLLATE NOCASE"
+            }
+            LibrarySort::Added => " ORDER BY rowid DESC",
+        };
+        if let Some(limit) = query.limit {
+            sql += &format!(" LIMIT {}", limit);
+        }
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut entries = stmt
+            .query_map(params_from_iter(values), entry_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut tags = self
+            .conn
+            .prepare("SELECT tag FROM tags WHERE path = ?1 ORDER BY tag")?;
+        for entry in &mut entries {
+            entry.tags = tags
+                .query_map([&entry.path], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+        }
+        Ok(entries)
+    }
+
+    /// Settings remembered for the song with content `hash`
+    pub fn song_settings(&self, hash: &str) -> Result<Option<SongSettings>> {
+        let settings: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT settings FROM song_settings WHERE hash = ?1",
+                [hash],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match settings {
+            Some(settings) => Some(serde_json::from_str(&settings)?),
+            None => None,
+        })
+    }
+
+    /// Settings of every song, by content hash
+    pub fn all_song_settings(&self) -> Result<HashMap<String, SongSettings>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT hash, settings FROM song_settings")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?;
+        let mut songs = HashMap::new();
+        for row in rows {
+            let (hash, settings) = row?;
+            songs.insert(hash, serde_json::from_str(&settings)?);
+        }
+        Ok(songs)
+    }
+
+    /// Remember `settings` for the song with content `hash`, leaving
+    /// other songs' rows alone
+    pub fn save_song_setting(&self, hash: &str, settings: &SongSettings) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO song_settings (hash, settings) VALUES (?1, ?2)",
+            [hash, &serde_json::to_string(settings)?],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the settings remembered for the song with content `hash`
+    pub fn delete_song_setting(&self, hash: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM song_settings WHERE hash = ?1", [hash])?;
+        Ok(())
+    }
+
+    /// Add a play of `path` to the history
+    pub fn record_play(&self, path: &str, played_at: u64, settings: &SongSettings) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO plays (path, played_at, settings) VALUES (?1, ?2, ?3)",
+            params![path, played_at as i64, serde_json::to_string(settings)?],
+        )?;
+        Ok(())
+    }
+
+    /// Up to `limit` plays since `since_ms` (Unix time), newest first
+    pub fn history(&self, since_ms: u64, limit: u32) -> Result<Vec<Play>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, played_at, settings FROM plays WHERE played_at >= ?1
+             ORDER BY played_at DESC, id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![since_ms as i64, limit], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut plays = Vec::new();
+        for row in rows {
+            let (path, played_at, settings) = row?;
+            plays.push(Play {
+                title: library::title_from_path(&path),
+                path,
+                played_at: played_at as u64,
+                settings: serde_json::from_str(&settings)?,
+            });
+        }
+        Ok(plays)
+    }
+
+    /// Up to `limit` songs played most since `since_ms` (Unix time); ties
+    /// go to the one played last
+    pub fn most_played(&self, since_ms: u64, limit: u32) -> Result<Vec<PlayCount>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, COUNT(*), MAX(played_at) FROM plays WHERE played_at >= ?1
+             GROUP BY path ORDER BY COUNT(*) DESC, MAX(played_at) DESC LIMIT ?2",
+        )?;
+        let counts = stmt
+            .query_map(params![since_ms as i64, limit], |row| {
+                let path: String = row.get(0)?;
+                Ok(PlayCount {
+                    title: library::title_from_path(&path),
+                    path,
+                    plays: row.get(1)?,
+                    last_played: row.get::<_, i64>(2)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(counts)
+    }
+}
+
+/// A `songs` row as an entry, without its tags
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<LibraryEntry> {
+    let track_names: String = row.get(3)?;
+    Ok(LibraryEntry {
+        path: row.get(0)?,
+        title: row.get(1)?,
+        favorite: row.get(2)?,
+        tags: Vec::new(),
+        track_names: serde_json::from_str(&track_names).unwrap_or_default(),
+        difficulty: row.get(4)?,
+    })
+}
+
+/// `text` matched literally by LIKE, with `\` as the escape character
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Bring over `library.json` and `song_settings.json`, left in place
+/// in case an older version is run again
+fn import_json(conn: &Connection, dir: &Path) -> Result<()> {
+    if let Ok(content) = fs::read_to_string(dir.join("library.json")) {
+        let library: Library = serde_json::from_str(&content)?;
+        write_library(conn, &library)?;
+    }
+    if let Ok(content) = fs::read_to_string(dir.join("song_settings.json")) {
+        let store: SongSettingsStore = serde_json::from_str(&content)?;
+        write_song_settings(conn, &store.songs)?;
+    }
+    Ok(())
+}
+
+fn write_library(conn: &Connection, library: &Library) -> Result<()> {
+    let mut upsert = conn.prepare(
+        "INSERT INTO songs (path, title, favorite, track_names, difficulty)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (path) DO UPDATE SET title = ?2, favorite = ?3,
+             track_names = ?4, difficulty = ?5",
+    )?;
+    let mut clear_tags = conn.prepare("DELETE FROM tags WHERE path = ?1")?;
+    let mut add_tag = conn.prepare("INSERT OR IGNORE INTO tags (path, tag) VALUES (?1, ?2)")?;
+    for entry in &library.entries {
+        upsert.execute(params![
+            entry.path,
+            entry.title,
+            entry.favorite,
+            serde_json::to_string(&entry.track_names)?,
+            entry.difficulty,
+        ])?;
+        clear_tags.execute([&entry.path])?;
+        for tag in &entry.tags {
+            add_tag.execute([&entry.path, tag])?;
+        }
+    }
+    Ok(())
+}
+
+fn write_song_settings(conn: &Connection, songs: &HashMap<String, SongSettings>) -> Result<()> {
+    conn.execute("DELETE FROM song_settings", [])?;
+    let mut insert = conn.prepare("INSERT INTO song_settings (hash, settings) VALUES (?1, ?2)")?;
+    for (hash, settings) in songs {
+        insert.execute([hash, &serde_json::to_string(settings)?])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Arrangement, SplitHandConfig};
+
+    fn test_db() -> LibraryDb {
+        LibraryDb::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn sample_library() -> Library {
+        let mut library = Library::default();
+        library.entry_mut("songs/Moonlight Sonata.mid").track_names = vec!["Piano".into()];
+        library.add_tag("songs/Moonlight Sonata.mid", "Classical");
+        library.set_difficulty("songs/Moonlight Sonata.mid", 4);
+        library.entry_mut("songs/battle_theme.mid").track_names = vec!["Moonlit Flute".into()];
+        library.set_favorite("songs/battle_theme.mid", true);
+        library.add_tag("songs/battle_theme.mid", "game");
+        library.add_tag("songs/battle_theme.mid", "fast");
+        library.entry_mut("songs/Tavern.mml");
+        library.set_difficulty("songs/Tavern.mml", 2);
+        library
+    }
+
+    fn titles(entries: Vec<LibraryEntry>) -> Vec<String> {
+        entries.into_iter().map(|e| e.title).collect()
+    }
+
+    #[test]
+    fn test_library_round_trip() {
+        let db = test_db();
+        let mut library = sample_library();
+        db.save_library(&library).unwrap();
+
+        // Saving again replaces tags rather than adding to them
+        library.remove_tag("songs/battle_theme.mid", "fast");
+        db.save_library(&library).unwrap();
+
+        let loaded = db.library().unwrap();
+        assert_eq!(loaded.entries.len(), 3);
+        assert_eq!(loaded.entries[0].title, "Moonlight Sonata");
+        assert_eq!(loaded.entries[0].track_names, ["Piano"]);
+        assert_eq!(loaded.entries[0].difficulty, Some(4));
+        assert!(loaded.entries[1].favorite);
+        assert_eq!(loaded.entries[1].tags, ["game"]);
+        assert_eq!(loaded.entries[2].difficulty, Some(2));
+    }
+
+    #[test]
+    fn test_query() {
+        let db = test_db();
+        db.save_library(&sample_library()).unwrap();
+        let query = |query: LibraryQuery| titles(db.query(&query).unwrap());
+
+        // Text matches titles and track names, ignoring case
+        let text = |text: &str| LibraryQuery {
+            text: Some(text.into()),
+            ..Default::default()
+        };
+        assert_eq!(query(text("MOON")), ["battle_theme", "Moonlight Sonata"]);
+        // Wildcards in the text are taken literally
+        assert_eq!(query(text("e_t")), ["battle_theme"]);
+        assert!(query(text("%")).is_empty());
+
+        assert_eq!(
+            query(LibraryQuery {
+                tags: vec!["GAME".into(), "fast".into()],
+                ..Default::default()
+            }),
+            ["battle_theme"]
+        );
+        assert_eq!(
+            query(LibraryQuery {
+                favorite: Some(false),
+                sort: LibrarySort::Difficulty,
+                ..Default::default()
+            }),
+            ["Tavern", "Moonlight Sonata"]
+        );
+        assert_eq!(
+            query(LibraryQuery {
+                min_difficulty: Some(3),
+                ..Default::default()
+            }),
+            ["Moonlight Sonata"]
+        );
+        assert_eq!(
+            query(LibraryQuery {
+                sort: LibrarySort::Added,
+                limit: Some(2),
+                ..Default::default()
+            }),
+            ["Tavern", "battle_theme"]
+        );
+    }
+
+    fn settings(transpose: i32) -> SongSettings {
+        SongSettings {
+            transpose,
+            tempo_factor: 0.9,
+            max_polyphony: 4,
+            arrangement: Arrangement::default(),
+            split_hands: SplitHandConfig::default(),
+            disabled_tracks: vec![1],
+            bookmarks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_song_settings() {
+        let db = test_db();
+        db.save_song_setting("abc", &settings(-3)).unwrap();
+        db.save_song_setting("def", &settings(2)).unwrap();
+        db.save_song_setting("abc", &settings(5)).unwrap();
+        assert_eq!(db.song_settings("abc").unwrap().unwrap().transpose, 5);
+        assert!(db.song_settings("ghi").unwrap().is_none());
+
+        db.delete_song_setting("abc").unwrap();
+        let songs = db.all_song_settings().unwrap();
+        assert_eq!(songs.keys().collect::<Vec<_>>(), ["def"]);
+    }
+
+    #[test]
+    fn test_play_history() {
+        let db = test_db();
+        db.record_play("songs/a.mid", 1000, &settings(0)).unwrap();
+        db.record_play("songs/b.mid", 2000, &settings(2)).unwrap();
+        db.record_play("songs/a.mid", 3000, &settings(-1)).unwrap();
+        db.record_play("songs/c.mid", 4000, &settings(0)).unwrap();
+
+        let history = db.history(0, 10).unwrap();
+        let played: Vec<(&str, u64)> = history
+            .iter()
+            .map(|play| (play.title.as_str(), play.played_at))
+            .collect();
+        assert_eq!(played, [("c", 4000), ("a", 3000), ("b", 2000), ("a", 1000)]);
+        assert_eq!(history[1].settings.transpose, -1);
+        assert_eq!(db.history(2500, 10).unwrap().len(), 2);
+        assert_eq!(db.history(0, 1).unwrap().len(), 1);
+
+        let counts = db.most_played(0, 10).unwrap();
+        let counts: Vec<(&str, u32)> = counts
+            .iter()
+            .map(|count| (count.title.as_str(), count.plays))
+            .collect();
+        assert_eq!(counts, [("a", 2), ("c", 1), ("b", 1)]);
+        let recent = db.most_played(2000, 10).unwrap();
+        assert_eq!(recent[0].plays, 1);
+        assert_eq!(recent[0].last_played, 4000);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let dir = std::env::temp_dir().join(format!("wwmp-migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = test_db();
+        let version = |db: &LibraryDb| -> i32 {
+            db.conn
+                .query_row("PRAGMA user_version", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        // A failed import leaves the version alone, to be tried again
+        fs::write(dir.join("library.json"), "{").unwrap();
+        assert!(db.migrate(&dir).is_err());
+        assert_eq!(version(&db), 0);
+
+        fs::write(
+            dir.join("library.json"),
+            serde_json::to_string(&sample_library()).unwrap(),
+        )
+        .unwrap();
+        db.migrate(&dir).unwrap();
+        assert_eq!(version(&db), SCHEMA_VERSION);
+        assert_eq!(db.library().unwrap().entries.len(), 3);
+
+        // Once current, the JSON files are not read again
+        fs::write(dir.join("library.json"), "{").unwrap();
+        db.migrate(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,114 +3,2136 @@
     windows_subsystem = "windows"
 )]
 
+mod abc;
+mod analysis;
+mod arrange;
+mod benchmark;
+mod cli;
+mod clock;
 mod config;
+mod config_watch;
+mod ensemble;
+mod error;
+mod export;
+mod gamepad;
+mod hotkeys;
+mod instance;
 mod keyboard;
+mod keyboard_hook;
+mod library;
+mod library_db;
 mod mapper;
 mod midi;
+mod midi_clock;
+mod midi_input;
+mod midi_output;
+mod midi_remote;
+mod mml;
+mod musicxml;
+mod nearest_mapper;
+mod osc_server;
+mod overlay;
+mod parse_cache;
+mod paste;
 mod playback;
+mod presets;
+mod recent;
+mod recorder;
+mod remote;
+#[cfg(feature = "rest-api")]
+mod rest_server;
+mod rtp_midi;
+mod scripting;
+mod setlist;
+mod shuffle;
+mod song_settings;
+mod step;
+mod tempo;
+#[cfg(test)]
+mod test_util;
+mod tray;
+mod ws_server;
 
 use anyhow::Result;
-use tauri::State;
-use std::sync::Mutex;
+use tauri::{AppHandle, ClipboardManager, Manager, State};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
 
-use crate::midi::MidiFile;
-use crate::playback::PlaybackEngine;
-use crate::config::AppConfig;
+use crate::midi::{Beat, Marker, MidiFile};
+use crate::midi_input::InputConnection;
+use crate::playback::{
+    PanicHandle, PlaybackEngine, PlaybackEvent, PlaybackState, PracticeLoop, Transport,
+    PLAYBACK_EVENT,
+};
+use crate::presets::PresetInfo;
+use crate::config::{
+    AppConfig, Arrangement, ConfigError, ConfigReport, DrumKey, LayoutMode, MetronomeConfig,
+    ProfileList, ProfileStore, Reduction, Scale, SplitHandConfig, VelocityCurve,
+};
+use crate::ensemble::{Conductor, Ensemble, EnsemblePlayer, Player};
+use crate::error::CommandError;
+use crate::hotkeys::HotkeyCapture;
+use crate::instance::OpenedFile;
+use crate::library::{Library, LibraryEntry, ScanSummary, ScannedSong, SearchResult};
+use crate::library_db::{LibraryDb, LibraryQuery, Play, PlayCount};
+use crate::recent::{LastSession, RecentFile, RecentFiles};
+use crate::recorder::{RecordSource, Recording};
+use crate::setlist::{Gap, Setlist, UP_NEXT_EVENT};
+use crate::song_settings::{SongSettings, SongSettingsStore};
+use crate::step::{StepChord, StepSession, STEP_EVENT};
 
 pub struct AppState {
     pub config: Mutex<AppConfig>,
+    /// What was wrong with `config.json` when it was last loaded
+    pub config_report: Mutex<ConfigReport>,
     pub midi_file: Mutex<Option<MidiFile>>,
     pub playback: Mutex<PlaybackEngine>,
+    /// Lock-free stop for the panic hotkey
+    pub panic: PanicHandle,
+    pub recent_files: Mutex<RecentFiles>,
+    pub library: Mutex<Library>,
+    pub song_settings: Mutex<SongSettingsStore>,
+    pub profiles: Mutex<ProfileStore>,
+    /// Open MIDI controller connection for remote transport, if any
+    pub midi_remote: Mutex<Option<InputConnection>>,
+    /// Open MIDI clock connection playback follows, if any
+    pub midi_clock: Mutex<Option<InputConnection>>,
+    /// Remote-control clients listening for events
+    pub remote_subscribers: Mutex<Vec<mpsc::Sender<String>>>,
+    /// Songs to play next, before falling back to the current folder
+    pub queue: Mutex<VecDeque<String>>,
+    /// Whether queued songs go back on the end of the queue once played
+    pub repeat_queue: AtomicBool,
+    /// Setlist being performed, if any; takes over from the queue
+    pub setlist: Mutex<Option<Setlist>>,
+    /// Performance being recorded, if any
+    pub recording: Mutex<Option<Recording>>,
+    /// Ensemble this instance conducts or plays in, if any
+    pub ensemble: Mutex<Option<Ensemble>>,
+    /// Bumped for each scheduled start so only the latest one fires
+    pub scheduled_start: AtomicU64,
+    /// Practice session stepping through the song chord by chord, if any
+    pub step: Mutex<Option<StepSession>>,
+    /// File given on the command line, until the window asks for it
+    pub initial_file: Mutex<Option<String>>,
+}
+
+/// Remember the current song-specific settings for the loaded file
+fn remember_song_settings(state: &AppState) {
+    let midi_file = state.midi_file.lock().unwrap();
+    if let Some(ref midi) = *midi_file {
+        let config = state.config.lock().unwrap();
+        let mut store = state.song_settings.lock().unwrap();
+        let _ = store.set(
+            &midi.hash,
+            SongSettings::from_config(&config, &midi.disabled_tracks, &midi.bookmarks),
+        );
+    }
+}
+
+/// Parsing runs on a worker thread, so large files don't hold up the
+/// window; `LOAD_PROGRESS_EVENT`s report how far it has got
+#[tauri::command]
+async fn load_midi_file(path: String, app: AppHandle) -> Result<midi::MidiInfo, CommandError> {
+    tauri::async_runtime::spawn_blocking(move || load_song(&app, &path))
+        .await
+        .map_err(|e| CommandError::from(e.to_string()))?
+}
+
+/// The loaded song's info, with warnings for the current transpose
+#[tauri::command]
+fn get_song_info(state: State<AppState>) -> Option<midi::MidiInfo> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let transpose = state.config.lock().unwrap().transpose;
+    Some(midi_file.as_ref()?.info(transpose))
+}
+
+/// Load a snippet from the clipboard, such as ABC or MML shared in chat
+/// or a keysheet
+#[tauri::command]
+fn load_from_clipboard(app: AppHandle) -> Result<paste::PastedFile, CommandError> {
+    let text = app
+        .clipboard_manager()
+        .read_text()
+        .map_err(anyhow::Error::from)?
+        .filter(|text| !text.trim().is_empty())
+        .ok_or("The clipboard has no text")?;
+    paste::open_text(&app, &text)
+}
+
+/// Load the file WWMP was launched with, if any. Called once by the
+/// window when it is ready to show it.
+#[tauri::command]
+fn take_initial_file(
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Option<OpenedFile>, CommandError> {
+    let path = state.initial_file.lock().unwrap().take();
+    match path {
+        Some(path) => instance::open_file(&app, &path).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Emitted with the transpose `auto_transpose` picked for a new song
+const AUTO_TRANSPOSE_EVENT: &str = "song://auto-transpose";
+
+/// Emitted with a `LoadProgress` while a song is parsed
+const LOAD_PROGRESS_EVENT: &str = "song://load-progress";
+
+/// Entries `get_history` and `get_most_played` return unless asked for
+/// another number
+const HISTORY_LIMIT: u32 = 50;
+
+/// Add the song at `path`, just played through, to the history
+fn record_play(state: &AppState, path: &str) {
+    let midi_file = state.midi_file.lock().unwrap();
+    // The tracks and bookmarks are only known while the song is loaded
+    let (disabled_tracks, bookmarks) = match midi_file.as_ref() {
+        Some(midi) if midi.path == path => (midi.disabled_tracks.clone(), midi.bookmarks.clone()),
+        _ => (Vec::new(), Vec::new()),
+    };
+    let config = state.config.lock().unwrap();
+    let settings = SongSettings::from_config(&config, &disabled_tracks, &bookmarks);
+    if let Ok(db) = LibraryDb::open() {
+        let _ = db.record_play(path, clock::now_ms(), &settings);
+    }
+}
+
+/// Load a song, re-applying its remembered settings, and record it in
+/// the recent list and library
+fn load_song(app: &AppHandle, path: &str) -> Result<midi::MidiInfo, CommandError> {
+    let state = app.state::<AppState>();
+    let pairing = state.config.lock().unwrap().note_pairing;
+    let mut midi_file = midi::load_file_with_progress(path, pairing, |fraction| {
+        let progress = midi::LoadProgress {
+            path: path.to_string(),
+            fraction,
+        };
+        let _ = app.emit_all(LOAD_PROGRESS_EVENT, progress);
+    })?;
+
+    // Start from the profile's own values, so a song with nothing
+    // remembered doesn't carry over the last song's transpose, tempo etc.
+    let profile = state.profiles.lock().unwrap().active_config().cloned();
+    if let Some(profile) = profile {
+        SongSettings::from_config(&profile, &[], &[]).apply(&mut state.config.lock().unwrap());
+    }
+
+    // Re-apply settings remembered for this song
+    let remembered = state
+        .song_settings
+        .lock()
+        .unwrap()
+        .get(&midi_file.hash)
+        .cloned();
+    if let Some(settings) = remembered {
+        settings.apply(&mut state.config.lock().unwrap());
+        midi_file.disabled_tracks = settings.disabled_tracks;
+        midi_file.bookmarks = settings.bookmarks;
+    } else {
+        let mut config = state.config.lock().unwrap();
+        if config.auto_detect_key {
+            if let Some(key) = analysis::detect_key(&midi_file.events) {
+                config.transpose = analysis::transpose_for_key(&key, &midi_file.events, &config);
+            }
+        } else if config.auto_transpose {
+            if let Some(mapper) = mapper::mapper_for(&config) {
+                let fits =
+                    analysis::transpose_fits(&midi_file.enabled_events(), mapper.as_ref(), &config);
+                config.transpose = analysis::best_transpose(&fits, config.transpose_objective);
+                let _ = app.emit_all(AUTO_TRANSPOSE_EVENT, config.transpose);
+            }
+        }
+    }
+
+    let transpose = state.config.lock().unwrap().transpose;
+    let info = midi_file.info(transpose);
+    *state.midi_file.lock().unwrap() = Some(midi_file);
+
+    let config = state.config.lock().unwrap();
+    let mut recent = state.recent_files.lock().unwrap();
+    recent.record(path, &config);
+    let _ = recent.save();
+
+    let mut library = state.library.lock().unwrap();
+    library.record_load(path, &info);
+    let _ = library.save();
+
+    tray::set_current_song(app, Some(&library::title_from_path(path)));
+    Ok(info)
+}
+
+#[tauri::command]
+fn detect_key(state: State<AppState>) -> Option<analysis::KeyEstimate> {
+    let midi_file = state.midi_file.lock().unwrap();
+    analysis::detect_key(&midi_file.as_ref()?.events)
+}
+
+/// Rate how hard a song is to perform with the current settings, or the
+/// ones remembered for it, and keep the rating in the library
+#[tauri::command]
+fn estimate_difficulty(
+    path: String,
+    state: State<AppState>,
+) -> Result<analysis::Difficulty, CommandError> {
+    let mut config = state.config.lock().unwrap().clone();
+    let mut midi_file = midi::load_file(&path, config.note_pairing)?;
+    let remembered = state
+        .song_settings
+        .lock()
+        .unwrap()
+        .get(&midi_file.hash)
+        .cloned();
+    if let Some(settings) = remembered {
+        settings.apply(&mut config);
+        midi_file.disabled_tracks = settings.disabled_tracks;
+    }
+
+    let events = playback::song_timeline(&midi_file, &config)?;
+    let difficulty = analysis::estimate_difficulty(&events, &config);
+
+    let mut library = state.library.lock().unwrap();
+    library.set_difficulty(&path, difficulty.rating);
+    let _ = library.save();
+    Ok(difficulty)
+}
+
+/// When the loaded song's first keystroke happens, e.g. to show how
+/// much silence `skip_silence` would skip
+#[tauri::command]
+fn get_first_note_time(state: State<AppState>) -> Result<u64, CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let events = playback::song_timeline(midi, &config)?;
+    Ok(events.first().map_or(0, |e| e.time_ms))
+}
+
+/// Find the fastest tempo at which the loaded song stays within
+/// `max_keys_per_second`, optionally switching to it
+#[tauri::command]
+fn suggest_tempo(
+    apply: bool,
+    state: State<AppState>,
+) -> Result<analysis::TempoSuggestion, CommandError> {
+    let suggestion = {
+        let midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+        let config = state.config.lock().unwrap();
+        let events = playback::song_timeline(midi, &config)?;
+        analysis::suggest_tempo(&events, config.max_keys_per_second, config.tempo_factor)
+    };
+
+    if apply {
+        apply_tempo(&state, suggestion.tempo_factor)?;
+    }
+    Ok(suggestion)
+}
+
+/// Check that this machine can fire keystrokes on time, using a dense
+/// synthetic song that sends no keys. Takes about five seconds.
+#[tauri::command(async)]
+fn run_timing_benchmark() -> benchmark::TimingReport {
+    benchmark::run()
+}
+
+/// How often each key will be pressed in the loaded song with the
+/// current config, for a heatmap of the layout
+#[tauri::command]
+fn get_key_usage(state: State<AppState>) -> Result<Vec<analysis::KeyUsage>, CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let events = playback::song_timeline(midi, &config)?;
+    Ok(analysis::key_usage(&events, &config))
+}
+
+/// Playable, dropped and accidental note counts for the loaded song at
+/// every transpose from -24 to +24, for a chart of the best choices
+#[tauri::command]
+fn get_transpose_fits(state: State<AppState>) -> Result<Vec<analysis::TransposeFit>, CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let mapper =
+        mapper::mapper_for(&config).ok_or_else(|| format!("Unknown mapper: {}", config.mapper))?;
+    Ok(analysis::transpose_fits(
+        &midi.enabled_events(),
+        mapper.as_ref(),
+        &config,
+    ))
+}
+
+/// Key presses per second, or per bar, across the loaded song with the
+/// current config, for a density strip under the seek bar
+#[tauri::command]
+fn get_note_density(
+    per_bar: bool,
+    state: State<AppState>,
+) -> Result<Vec<analysis::DensityBucket>, CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let events = playback::song_timeline(midi, &config)?;
+    let starts: Vec<u64> = if per_bar {
+        midi.beats
+            .iter()
+            .filter(|b| b.beat == 1)
+            .map(|b| b.time_ms)
+            .collect()
+    } else {
+        let end = events.last().map_or(0, |e| e.time_ms);
+        (0..=end).step_by(1000).collect()
+    };
+    Ok(analysis::note_density(
+        &events,
+        &starts,
+        config.tempo_factor,
+    ))
+}
+
+/// Transpose the loaded song so its detected tonic lands on degree 1
+#[tauri::command]
+fn apply_detected_key(state: State<AppState>) -> Result<i32, CommandError> {
+    let transpose = {
+        let midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+        let key = analysis::detect_key(&midi.events).ok_or("No notes to analyze")?;
+        let config = state.config.lock().unwrap();
+        analysis::transpose_for_key(&key, &midi.events, &config)
+    };
+
+    update_config(&state, |c| c.transpose = transpose)?;
+    remember_song_settings(&state);
+    Ok(transpose)
+}
+
+/// Start playing the loaded song, if any
+fn start_playback(state: &AppState) -> Result<(), CommandError> {
+    start_playback_with(state, |_| {})
+}
+
+/// Start the loaded song the moment a scheduled start comes due. The
+/// schedule already says when the song begins, so this machine's start
+/// delay and count-in are left out.
+fn start_scheduled_playback(state: &AppState) -> Result<(), CommandError> {
+    start_playback_with(state, |config| {
+        config.start_delay_ms = 0;
+        config.metronome.count_in = false;
+    })
+}
+
+/// Start the loaded song with `adjust` applied to a copy of the config
+fn start_playback_with(
+    state: &AppState,
+    adjust: impl FnOnce(&mut AppConfig),
+) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let config = state.config.lock().unwrap();
+
+    if let Some(ref midi) = *midi_file {
+        let mut adjusted = config.clone();
+        adjust(&mut adjusted);
+        let mut playback = state.playback.lock().unwrap();
+        playback.start(midi, &adjusted)?;
+
+        // Remember the settings this file was last played with
+        let mut recent = state.recent_files.lock().unwrap();
+        recent.record(&midi.path, &config);
+        let _ = recent.save();
+    }
+    Ok(())
+}
+
+/// Load and play the next queued song, or else the next MIDI file in
+/// the current song's folder
+fn play_next_song(app: &AppHandle) -> Result<(), CommandError> {
+    let state = app.state::<AppState>();
+    let queued = {
+        let mut queue = state.queue.lock().unwrap();
+        let queued = queue.pop_front();
+        if let Some(path) = &queued {
+            if state.repeat_queue.load(Ordering::SeqCst) {
+                queue.push_back(path.clone());
+            }
+        }
+        queued
+    };
+    let next = match queued {
+        Some(path) => path,
+        None => {
+            let midi_file = state.midi_file.lock().unwrap();
+            let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+            let next = library::next_in_folder(&midi.path).ok_or("No next song in folder")?;
+            next.to_string_lossy().into_owned()
+        }
+    };
+
+    stop_all(&state);
+    load_song(app, &next)?;
+    start_playback(&state)
+}
+
+/// Load the setlist's next song and start it after the gap, or leave it
+/// for the play/pause hotkey. Tells the overlay what is up next.
+fn advance_setlist(app: &AppHandle) -> Result<(), CommandError> {
+    let state = app.state::<AppState>();
+    let up_next = {
+        let mut setlist = state.setlist.lock().unwrap();
+        let Some(active) = setlist.as_mut() else {
+            return Ok(());
+        };
+        let up_next = active.advance();
+        if up_next.is_none() {
+            *setlist = None;
+        }
+        up_next
+    };
+    remote::publish(&state, UP_NEXT_EVENT, &up_next);
+    let _ = app.emit_all(UP_NEXT_EVENT, up_next.clone());
+
+    let Some(up_next) = up_next else {
+        return Ok(());
+    };
+    load_song(app, &up_next.path)?;
+    if let Some(ms) = up_next.starts_in_ms {
+        // A scheduled start skips the start delay and count-in, so the
+        // song begins when the gap ends
+        clock::start_at(app, clock::now_ms() + ms);
+    }
+    Ok(())
+}
+
+/// Pause or resume a running song, or start the loaded one
+fn toggle_playback(state: &AppState) -> Result<(), CommandError> {
+    {
+        let mut playback = state.playback.lock().unwrap();
+        if playback.is_playing() {
+            playback.pause();
+            return Ok(());
+        }
+    }
+    start_playback(state)
+}
+
+/// Stop the song along with any start still waiting in `clock::start_at`,
+/// such as the next song after a setlist gap
+fn stop_all(state: &AppState) {
+    clock::cancel_scheduled_start(state);
+    state.playback.lock().unwrap().stop();
+}
+
+/// `stop_all` without the engine's mutex, so it works even while another
+/// thread holds it
+fn panic_all(state: &AppState) {
+    clock::cancel_scheduled_start(state);
+    state.panic.trigger();
+}
+
+/// Stop the song and play it again from the start, start delay and all,
+/// for when the opening of a performance goes wrong
+fn restart_playback(state: &AppState) -> Result<(), CommandError> {
+    if state.midi_file.lock().unwrap().is_none() {
+        return Err(CommandError::NoSongLoaded);
+    }
+    stop_all(state);
+    start_playback(state)
+}
+
+/// In step mode, play the waiting chord and show the next one, which is
+/// also returned (None once the song is done)
+fn step_next_chord(app: &AppHandle) -> Result<Option<StepChord>, CommandError> {
+    let state = app.state::<AppState>();
+    let tempo_factor = state.config.lock().unwrap().tempo_factor;
+    let mut step = state.step.lock().unwrap();
+    let session = step.as_mut().ok_or("Step mode is not active")?;
+    session.advance(tempo_factor);
+
+    let next = session.current();
+    let _ = app.emit_all(STEP_EVENT, next.clone());
+    Ok(next)
+}
+
+/// Set the transpose, re-mapping the song live if it is playing
+fn apply_transpose(state: &AppState, semitones: i32) -> Result<(), Vec<ConfigError>> {
+    update_config(state, |c| c.transpose = semitones)?;
+    remember_song_settings(state);
+    Ok(())
+}
+
+/// Set the tempo factor, taking effect at once if a song is playing
+fn apply_tempo(state: &AppState, factor: f64) -> Result<(), Vec<ConfigError>> {
+    update_config(state, |c| c.tempo_factor = factor)?;
+    remember_song_settings(state);
+    Ok(())
+}
+
+#[tauri::command]
+fn play(state: State<AppState>) -> Result<(), CommandError> {
+    start_playback(&state)
+}
+
+#[tauri::command]
+fn pause(state: State<AppState>) -> Result<(), CommandError> {
+    let mut playback = state.playback.lock().unwrap();
+    playback.pause();
+    Ok(())
+}
+
+/// Stop or resume sending keystrokes without interrupting playback
+#[tauri::command]
+fn set_keys_muted(muted: bool, state: State<AppState>) -> Result<(), CommandError> {
+    state.playback.lock().unwrap().set_muted(muted);
+    Ok(())
+}
+
+#[tauri::command]
+fn restart(state: State<AppState>) -> Result<(), CommandError> {
+    restart_playback(&state)
+}
+
+#[tauri::command]
+fn stop(state: State<AppState>) -> Result<(), CommandError> {
+    stop_all(&state);
+    Ok(())
+}
+
+/// Loop a section of the loaded song, starting slow and speeding up by a
+/// step each time through until it plays at the set tempo
+#[tauri::command]
+fn start_practice(practice: PracticeLoop, state: State<AppState>) -> Result<(), CommandError> {
+    practice.check()?;
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let mut playback = state.playback.lock().unwrap();
+    playback
+        .start_practice(midi, &config, &practice)
+        .map_err(CommandError::from)
+}
+
+/// Practice loop over `start_ms..end_ms` of the loaded song, for the
+/// commands that find the section by name or bar
+fn practice_section(
+    state: State<AppState>,
+    start_ms: u64,
+    end_ms: u64,
+    start_percent: f64,
+    step_percent: f64,
+) -> Result<(), CommandError> {
+    let practice = PracticeLoop {
+        start_ms,
+        end_ms,
+        start_percent,
+        step_percent,
+    };
+    start_practice(practice, state)
+}
+
+/// Play only `start_ms..end_ms` of the loaded song, e.g. just the chorus
+#[tauri::command]
+fn play_range(start_ms: u64, end_ms: u64, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let mut playback = state.playback.lock().unwrap();
+    playback
+        .start_range(midi, &config, start_ms, end_ms)
+        .map_err(CommandError::from)
+}
+
+/// Start the loaded song at a wall-clock time (Unix ms), read against
+/// the configured NTP server if any. Returns the ms left until the start.
+#[tauri::command]
+fn play_at(timestamp_ms: u64, app: AppHandle, state: State<AppState>) -> Result<u64, CommandError> {
+    if state.midi_file.lock().unwrap().is_none() {
+        return Err(CommandError::NoSongLoaded);
+    }
+    let server = state.config.lock().unwrap().ntp_server.clone();
+    let offset_ms = if server.is_empty() {
+        0
+    } else {
+        clock::ntp_offset_ms(&server).map_err(|e| format!("NTP query failed: {}", e))?
+    };
+
+    let local_ms = (timestamp_ms as i64 - offset_ms).max(0) as u64;
+    let now_ms = clock::now_ms();
+    if local_ms <= now_ms {
+        return Err("Start time has already passed".into());
+    }
+    clock::start_at(&app, local_ms);
+    Ok(local_ms - now_ms)
+}
+
+/// Apply `change` to the config only if the result passes validation.
+/// A song that is playing picks up the new config immediately.
+fn update_config(
+    state: &AppState,
+    change: impl FnOnce(&mut AppConfig),
+) -> Result<(), Vec<ConfigError>> {
+    let mut config = state.config.lock().unwrap();
+    let mut updated = config.clone();
+    change(&mut updated);
+
+    let errors = updated.validate();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    *config = updated;
+    state.playback.lock().unwrap().update_config(&config);
+    Ok(())
+}
+
+/// Save the config file, and the active profile along with it so
+/// switching away and back keeps the change
+fn save_config(state: &AppState) -> Result<(), CommandError> {
+    let config = state.config.lock().unwrap();
+    config.save()?;
+    let mut profiles = state.profiles.lock().unwrap();
+    profiles.update_active(&config);
+    profiles.save().map_err(CommandError::from)
+}
+
+/// Replace the whole config at once, as loading a profile or file does:
+/// checked and shared with the playing song like a single setting, with
+/// the active profile kept in step and the hotkeys re-registered. Saving
+/// `config.json` is left to `replace_config`.
+fn apply_config(app: &AppHandle, config: AppConfig) -> Result<AppConfig, CommandError> {
+    let state = app.state::<AppState>();
+    update_config(&state, |c| *c = config)?;
+    let config = state.config.lock().unwrap().clone();
+
+    {
+        let mut profiles = state.profiles.lock().unwrap();
+        profiles.update_active(&config);
+        profiles.save()?;
+    }
+    hotkeys::register_hotkeys(app, &config.hotkeys)?;
+    Ok(config)
+}
+
+/// `apply_config`, then save the config file
+fn replace_config(app: &AppHandle, config: AppConfig) -> Result<AppConfig, CommandError> {
+    let config = apply_config(app, config)?;
+    config.save()?;
+    Ok(config)
+}
+
+/// The whole transport state at once, so the UI can restore itself
+/// after a reload
+#[tauri::command]
+fn get_playback_state(state: State<AppState>) -> PlaybackState {
+    let midi_file = state.midi_file.lock().unwrap();
+    let config = state.config.lock().unwrap();
+    let playback = state.playback.lock().unwrap();
+    let transport = playback.transport();
+    let (position_ms, duration_ms) = match transport {
+        Transport::Stopped => (0, midi_file.as_ref().map_or(0, |m| m.info.duration_ms)),
+        _ => playback.progress(),
+    };
+    PlaybackState {
+        state: transport,
+        position_ms,
+        duration_ms,
+        current_file: midi_file.as_ref().map(|m| m.path.clone()),
+        tempo_factor: config.tempo_factor,
+        transpose: config.transpose,
+    }
+}
+
+/// The bar and beat playback is on, with its time signature; None when
+/// stopped or before the first beat
+#[tauri::command]
+fn get_bar_position(state: State<AppState>) -> Option<Beat> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let playback = state.playback.lock().unwrap();
+    if playback.transport() == Transport::Stopped {
+        return None;
+    }
+    let (position_ms, _) = playback.progress();
+    midi_file.as_ref()?.beat_at(position_ms).copied()
+}
+
+/// The song last stopped part way through, if any
+#[tauri::command]
+fn get_last_session(state: State<AppState>) -> Option<LastSession> {
+    state.recent_files.lock().unwrap().last_session.clone()
+}
+
+/// Reload the song last stopped part way through and play on from there
+#[tauri::command]
+fn resume_last_session(app: AppHandle) -> Result<midi::MidiInfo, CommandError> {
+    let state = app.state::<AppState>();
+    let session = state
+        .recent_files
+        .lock()
+        .unwrap()
+        .last_session
+        .clone()
+        .ok_or("Nothing to resume")?;
+
+    stop_all(&state);
+    let info = load_song(&app, &session.path)?;
+
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    let mut playback = state.playback.lock().unwrap();
+    playback.start_from(midi, &config, session.position_ms)?;
+    Ok(info)
+}
+
+#[tauri::command]
+fn seek(position_ms: u64, state: State<AppState>) {
+    state.playback.lock().unwrap().seek(position_ms);
+}
+
+/// Jump to a Marker or CuePoint of the loaded song by name
+#[tauri::command]
+fn seek_to_marker(name: String, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let marker = midi
+        .marker(&name)
+        .ok_or_else(|| format!("No marker named \"{}\"", name))?;
+    state.playback.lock().unwrap().seek(marker.time_ms);
+    Ok(())
+}
+
+/// Practice loop over the section a marker starts, up to the next marker
+#[tauri::command]
+fn practice_marker(
+    name: String,
+    start_percent: f64,
+    step_percent: f64,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    let (start_ms, end_ms) = {
+        let midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+        midi.marker_section(&name)
+            .ok_or_else(|| format!("No marker named \"{}\"", name))?
+    };
+    practice_section(state, start_ms, end_ms, start_percent, step_percent)
+}
+
+/// Jump to the downbeat of a bar, counting from 1
+#[tauri::command]
+fn seek_to_bar(bar: u32, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let (start_ms, _) = midi
+        .bar_section(bar, bar)
+        .ok_or_else(|| format!("The song has no bar {}", bar))?;
+    state.playback.lock().unwrap().seek(start_ms);
+    Ok(())
+}
+
+/// Play only bars `first` to `last`, inclusive
+#[tauri::command]
+fn play_bars(first: u32, last: u32, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let (start_ms, end_ms) = midi
+        .bar_section(first, last)
+        .ok_or_else(|| format!("The song has no bars {}-{}", first, last))?;
+    let config = state.config.lock().unwrap();
+    let mut playback = state.playback.lock().unwrap();
+    playback
+        .start_range(midi, &config, start_ms, end_ms)
+        .map_err(CommandError::from)
+}
+
+/// Practice loop over bars `first` to `last`, inclusive
+#[tauri::command]
+fn practice_bars(
+    first: u32,
+    last: u32,
+    start_percent: f64,
+    step_percent: f64,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    let (start_ms, end_ms) = {
+        let midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+        midi.bar_section(first, last)
+            .ok_or_else(|| format!("The song has no bars {}-{}", first, last))?
+    };
+    practice_section(state, start_ms, end_ms, start_percent, step_percent)
+}
+
+/// Abort playback and release all keys, even if the engine is busy
+#[tauri::command]
+fn panic(state: State<AppState>) {
+    panic_all(&state);
+}
+
+#[tauri::command]
+fn set_tempo(factor: f64, state: State<AppState>) -> Result<(), CommandError> {
+    apply_tempo(&state, factor)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn set_transpose(semitones: i32, state: State<AppState>) -> Result<(), CommandError> {
+    apply_transpose(&state, semitones)?;
+    Ok(())
+}
+
+/// Set the chord size limit, remembered for the loaded song and saved
+/// to the config file
+#[tauri::command]
+fn set_max_polyphony(max_notes: u8, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.max_polyphony = max_notes)?;
+    remember_song_settings(&state);
+    state
+        .config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(CommandError::from)
+}
+
+/// Play the audio preview alongside the keystrokes, and save the config
+#[tauri::command]
+fn set_audio_preview(enabled: bool, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.audio_preview = enabled)?;
+    state
+        .config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(CommandError::from)
+}
+
+/// Set the audio preview's loudness (0.0 to 1.0), saved to the config file
+#[tauri::command]
+fn set_preview_volume(volume: f64, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.preview_volume = volume)?;
+    state
+        .config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(CommandError::from)
+}
+
+/// Send the audio preview to an output device, by the id the window lists
+/// it under (empty for the system default), saved to the config file
+#[tauri::command]
+fn set_preview_device(device_id: String, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.preview_device = device_id)?;
+    state
+        .config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(CommandError::from)
+}
+
+fn capture_hotkey(app: &AppHandle, action: &str) -> Result<Option<HotkeyCapture>, CommandError> {
+    let state = app.state::<AppState>();
+    let bindings = state.config.lock().unwrap().hotkeys.clone();
+    if !bindings.bindings().iter().any(|(name, _)| *name == action) {
+        return Err(format!("Unknown hotkey '{}'", action).into());
+    }
+    let Some(combo) = hotkeys::capture_next()? else {
+        return Ok(None);
+    };
+    let conflicts = hotkeys::conflicts(&combo, action, &state.config.lock().unwrap());
+    let accelerator = combo.to_string();
+
+    if conflicts.is_empty() {
+        update_config(&state, |c| {
+            if let Some(binding) = c.hotkeys.binding_mut(action) {
+                *binding = accelerator.clone();
+            }
+        })?;
+        let config = state.config.lock().unwrap();
+        config.save()?;
+        hotkeys::register_hotkeys(app, &config.hotkeys)?;
+    }
+    Ok(Some(HotkeyCapture {
+        accelerator,
+        conflicts,
+    }))
+}
+
+/// Bind `action` (a hotkeys field such as `play_pause`) to the next key
+/// combo pressed, saved to the config file unless it clashes with another
+/// hotkey or an instrument key; the clashes are returned instead. None if
+/// Escape was pressed or no key came.
+#[tauri::command]
+async fn capture_next_hotkey(
+    action: String,
+    app: AppHandle,
+) -> Result<Option<HotkeyCapture>, CommandError> {
+    tauri::async_runtime::spawn_blocking(move || capture_hotkey(&app, &action))
+        .await
+        .map_err(|e| CommandError::from(e.to_string()))?
+}
+
+/// Set the delay before playback starts, saved to the config file
+#[tauri::command]
+fn set_start_delay(delay_ms: u64, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.start_delay_ms = delay_ms)?;
+    state
+        .config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(CommandError::from)
+}
+
+/// Set the MIDI note the layout's reference key plays, saved to the
+/// config file
+#[tauri::command]
+fn set_reference_note(note: u8, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.reference_midi_note = note)?;
+    state
+        .config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(CommandError::from)
+}
+
+/// Replace the layout's key rows, lowest first, and save the config,
+/// returning the warnings for the new layout. Unknown keys, and repeated
+/// ones unless the layout allows them, are reported per slot and nothing
+/// changes.
+#[tauri::command]
+fn update_key_mapping(
+    rows: Vec<Vec<String>>,
+    state: State<AppState>,
+) -> Result<Vec<ConfigError>, CommandError> {
+    let rows: Vec<Vec<String>> = rows
+        .into_iter()
+        .map(|row| row.into_iter().map(|key| key.trim().to_string()).collect())
+        .collect();
+    {
+        let mut candidate = state.config.lock().unwrap().clone();
+        candidate.key_mapping.rows = rows.clone();
+        if !candidate.key_mapping.allow_duplicate_keys {
+            let duplicates = candidate.duplicate_keys();
+            if !duplicates.is_empty() {
+                return Err(duplicates.into());
+            }
+        }
+    }
+    update_config(&state, |c| c.key_mapping.rows = rows)?;
+    let config = state.config.lock().unwrap();
+    config.save()?;
+    Ok(config.warnings())
+}
+
+/// Switch between diatonic and chromatic layouts, saved to the config file
+#[tauri::command]
+fn set_layout_mode(mode: LayoutMode, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.key_mapping.mode = mode)?;
+    save_config(&state)
+}
+
+/// Set the scale diatonic keys follow, saved to the config file
+#[tauri::command]
+fn set_scale(scale: Scale, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.key_mapping.scale = scale)?;
+    save_config(&state)
+}
+
+/// Stop playback and start stepping through the loaded song, returning
+/// the first chord
+fn begin_steps(app: &AppHandle, follow_along: bool) -> Result<Option<StepChord>, CommandError> {
+    let state = app.state::<AppState>();
+    stop_all(&state);
+    let session = {
+        let midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+        let config = state.config.lock().unwrap();
+        let events = playback::song_timeline(midi, &config)?;
+        StepSession::new(&events, follow_along)
+    };
+
+    let first = session.current();
+    *state.step.lock().unwrap() = Some(session);
+    let _ = app.emit_all(STEP_EVENT, first.clone());
+    Ok(first)
+}
+
+/// Practice the loaded song one chord at a time: nothing plays until
+/// `step_next` (or its hotkey). Returns the first chord.
+#[tauri::command]
+fn start_step_mode(app: AppHandle) -> Result<Option<StepChord>, CommandError> {
+    begin_steps(&app, false)
+}
+
+/// Play along with the loaded song: the next chord is shown and the song
+/// waits until you press its keys yourself. Returns the first chord.
+#[tauri::command]
+fn start_follow_along(
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Option<StepChord>, CommandError> {
+    // Both watch the keyboard through the same hook
+    if state.recording.lock().unwrap().is_some() {
+        return Err("Stop recording first".into());
+    }
+    let first = begin_steps(&app, true)?;
+
+    let handle = app.clone();
+    keyboard_hook::set_watcher(Some(Box::new(move |combo, is_down| {
+        if !is_down {
+            return;
+        }
+        let state = handle.state::<AppState>();
+        let mut step = state.step.lock().unwrap();
+        if let Some(session) = step.as_mut() {
+            if session.press(&combo.key, combo.modifier()) {
+                let _ = handle.emit_all(STEP_EVENT, session.current());
+            }
+        }
+    })));
+    Ok(first)
+}
+
+#[tauri::command]
+fn step_next(app: AppHandle) -> Result<Option<StepChord>, CommandError> {
+    step_next_chord(&app)
+}
+
+#[tauri::command]
+fn stop_step_mode(state: State<AppState>) {
+    let session = state.step.lock().unwrap().take();
+    if session.is_some_and(|s| s.follows_along()) {
+        keyboard_hook::set_watcher(None);
+    }
+}
+
+/// Set when the metronome clicks, saved to the config file
+#[tauri::command]
+fn set_metronome(metronome: MetronomeConfig, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.metronome = metronome)?;
+    save_config(&state)
+}
+
+/// Set the keys percussion notes play, saved to the config file
+#[tauri::command]
+fn set_drum_keys(drum_keys: Vec<DrumKey>, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.drum_keys = drum_keys)?;
+    save_config(&state)
+}
+
+#[tauri::command]
+fn get_mappers() -> Vec<String> {
+    mapper::mapper_names()
+}
+
+#[tauri::command]
+fn set_arrangement(arrangement: Arrangement, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.arrangement = arrangement)?;
+    remember_song_settings(&state);
+    Ok(())
+}
+
+/// Choose which notes a chord over the polyphony limit keeps, saved to
+/// the config file
+#[tauri::command]
+fn set_reduction(reduction: Reduction, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.reduction = reduction)?;
+    save_config(&state)
+}
+
+/// Assign notes to the left and right hands, remembered per song since
+/// the split usually depends on the song's tracks
+#[tauri::command]
+fn set_split_hands(
+    split_hands: SplitHandConfig,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    update_config(&state, |c| c.split_hands = split_hands)?;
+    remember_song_settings(&state);
+    Ok(())
+}
+
+/// Select a note mapper by name; empty follows the layout mode. Saved to
+/// the config file.
+#[tauri::command]
+fn set_mapper(name: String, state: State<AppState>) -> Result<(), CommandError> {
+    update_config(&state, |c| c.mapper = name)?;
+    save_config(&state)
+}
+
+#[tauri::command]
+fn set_track_enabled(
+    track: usize,
+    enabled: bool,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    {
+        let mut midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_mut().ok_or(CommandError::NoSongLoaded)?;
+        midi.disabled_tracks.retain(|&t| t != track);
+        if !enabled {
+            midi.disabled_tracks.push(track);
+            midi.disabled_tracks.sort_unstable();
+        }
+    }
+    remember_song_settings(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_song_settings(state: State<AppState>) -> Option<SongSettings> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref()?;
+    state.song_settings.lock().unwrap().get(&midi.hash).cloned()
 }
 
 #[tauri::command]
-fn load_midi_file(path: String, state: State<AppState>) -> Result<midi::MidiInfo, String> {
-    let midi_file = midi::load_file(&path).map_err(|e| e.to_string())?;
-    let info = midi_file.info();
-    *state.midi_file.lock().unwrap() = Some(midi_file);
-    Ok(info)
+fn clear_song_settings(state: State<AppState>) -> Result<(), CommandError> {
+    let mut midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_mut().ok_or(CommandError::NoSongLoaded)?;
+    midi.disabled_tracks.clear();
+    midi.bookmarks.clear();
+
+    let mut store = state.song_settings.lock().unwrap();
+    store.remove(&midi.hash).map_err(CommandError::from)
+}
+
+/// Bookmarks of the loaded song, sorted by time
+#[tauri::command]
+fn get_bookmarks(state: State<AppState>) -> Vec<Marker> {
+    let midi_file = state.midi_file.lock().unwrap();
+    midi_file
+        .as_ref()
+        .map_or_else(Vec::new, |midi| midi.bookmarks.clone())
+}
+
+/// Name a point in the loaded song, replacing a bookmark of the same name
+#[tauri::command]
+fn add_bookmark(name: String, time_ms: u64, state: State<AppState>) -> Result<(), CommandError> {
+    if name.trim().is_empty() {
+        return Err("Bookmark name cannot be empty".into());
+    }
+    {
+        let mut midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_mut().ok_or(CommandError::NoSongLoaded)?;
+        midi.set_bookmark(&name, time_ms);
+    }
+    remember_song_settings(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn remove_bookmark(name: String, state: State<AppState>) -> Result<(), CommandError> {
+    {
+        let mut midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_mut().ok_or(CommandError::NoSongLoaded)?;
+        if !midi.remove_bookmark(&name) {
+            return Err(format!("No bookmark named \"{}\"", name).into());
+        }
+    }
+    remember_song_settings(&state);
+    Ok(())
 }
 
 #[tauri::command]
-fn play(state: State<AppState>) -> Result<(), String> {
+fn seek_to_bookmark(name: String, state: State<AppState>) -> Result<(), CommandError> {
     let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let bookmark = midi
+        .bookmark(&name)
+        .ok_or_else(|| format!("No bookmark named \"{}\"", name))?;
+    state.playback.lock().unwrap().seek(bookmark.time_ms);
+    Ok(())
+}
+
+/// Practice loop from a bookmark up to the next one
+#[tauri::command]
+fn practice_bookmark(
+    name: String,
+    start_percent: f64,
+    step_percent: f64,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    let (start_ms, end_ms) = {
+        let midi_file = state.midi_file.lock().unwrap();
+        let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+        midi.bookmark_section(&name)
+            .ok_or_else(|| format!("No bookmark named \"{}\"", name))?
+    };
+    practice_section(state, start_ms, end_ms, start_percent, step_percent)
+}
+
+#[tauri::command]
+fn get_config(state: State<AppState>) -> AppConfig {
+    state.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn get_presets() -> Vec<PresetInfo> {
+    presets::list_presets()
+}
+
+#[tauri::command]
+fn apply_preset(name: String, app: AppHandle) -> Result<AppConfig, CommandError> {
+    let preset = presets::find_preset(&name)?;
+
+    let mut config = app.state::<AppState>().config.lock().unwrap().clone();
+    preset.apply(&mut config);
+    replace_config(&app, config)
+}
+
+/// Settings that were reset or warned about when `config.json` was
+/// last loaded
+#[tauri::command]
+fn get_config_report(state: State<AppState>) -> ConfigReport {
+    state.config_report.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn validate_config(state: State<AppState>) -> Vec<ConfigError> {
+    state.config.lock().unwrap().validate()
+}
+
+/// Config problems allowed on purpose, such as doubled-up layout keys
+#[tauri::command]
+fn get_config_warnings(state: State<AppState>) -> Vec<ConfigError> {
+    state.config.lock().unwrap().warnings()
+}
+
+#[tauri::command]
+fn export_config(path: String, state: State<AppState>) -> Result<(), CommandError> {
     let config = state.config.lock().unwrap();
+    config.export_to(&path).map_err(CommandError::from)
+}
 
-    if let Some(ref midi) = *midi_file {
-        let mut playback = state.playback.lock().unwrap();
-        playback.start(midi, &config).map_err(|e| e.to_string())?;
+#[tauri::command]
+fn import_config(path: String, app: AppHandle) -> Result<AppConfig, CommandError> {
+    let imported = AppConfig::import_from(&path)?;
+    replace_config(&app, imported)
+}
+
+#[tauri::command]
+fn get_profiles(state: State<AppState>) -> ProfileList {
+    state.profiles.lock().unwrap().list()
+}
+
+#[tauri::command]
+fn create_profile(name: String, state: State<AppState>) -> Result<(), CommandError> {
+    let config = state.config.lock().unwrap();
+    let mut profiles = state.profiles.lock().unwrap();
+    profiles.create(&name, &config)?;
+    profiles.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn switch_profile(name: String, app: AppHandle) -> Result<AppConfig, CommandError> {
+    let state = app.state::<AppState>();
+    let config = {
+        let current = state.config.lock().unwrap();
+        state.profiles.lock().unwrap().switch(&name, &current)?
+    };
+    replace_config(&app, config)
+}
+
+#[tauri::command]
+fn rename_profile(
+    old_name: String,
+    new_name: String,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    let mut profiles = state.profiles.lock().unwrap();
+    profiles.rename(&old_name, &new_name)?;
+    profiles.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn delete_profile(name: String, state: State<AppState>) -> Result<(), CommandError> {
+    let mut profiles = state.profiles.lock().unwrap();
+    profiles.delete(&name)?;
+    profiles.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn get_recent_files(state: State<AppState>) -> Vec<RecentFile> {
+    state.recent_files.lock().unwrap().entries.clone()
+}
+
+/// Forget every cached parse, so songs are read from their files again
+#[tauri::command]
+fn clear_parse_cache() -> Result<(), CommandError> {
+    parse_cache::clear().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn clear_recent_files(state: State<AppState>) -> Result<(), CommandError> {
+    let mut recent = state.recent_files.lock().unwrap();
+    recent.clear();
+    recent.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn set_favorite(path: String, favorite: bool, state: State<AppState>) -> Result<(), CommandError> {
+    let mut library = state.library.lock().unwrap();
+    library.set_favorite(&path, favorite);
+    library.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn add_tag(path: String, tag: String, state: State<AppState>) -> Result<(), CommandError> {
+    let mut library = state.library.lock().unwrap();
+    library.add_tag(&path, &tag);
+    library.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn remove_tag(path: String, tag: String, state: State<AppState>) -> Result<(), CommandError> {
+    let mut library = state.library.lock().unwrap();
+    library.remove_tag(&path, &tag);
+    library.save().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn get_favorites(state: State<AppState>) -> Vec<LibraryEntry> {
+    state.library.lock().unwrap().favorites()
+}
+
+#[tauri::command]
+fn get_songs_by_tag(tag: String, state: State<AppState>) -> Vec<LibraryEntry> {
+    state.library.lock().unwrap().by_tag(&tag)
+}
+
+#[tauri::command]
+fn get_all_tags(state: State<AppState>) -> Vec<String> {
+    state.library.lock().unwrap().all_tags()
+}
+
+#[tauri::command]
+fn search_library(query: String, state: State<AppState>) -> Vec<SearchResult> {
+    state.library.lock().unwrap().search(&query)
+}
+
+/// Songs matching every filter in `query`, straight from the library
+/// database
+#[tauri::command]
+fn query_library(query: LibraryQuery) -> Result<Vec<LibraryEntry>, CommandError> {
+    LibraryDb::open()?.query(&query).map_err(CommandError::from)
+}
+
+/// Songs played through to the end, newest first. `since_ms` (Unix
+/// time) narrows it to, say, the current stream.
+#[tauri::command]
+fn get_history(since_ms: Option<u64>, limit: Option<u32>) -> Result<Vec<Play>, CommandError> {
+    let db = LibraryDb::open()?;
+    let history = db.history(since_ms.unwrap_or(0), limit.unwrap_or(HISTORY_LIMIT))?;
+    Ok(history)
+}
+
+/// Songs played through most often, since `since_ms` (Unix time) if given
+#[tauri::command]
+fn get_most_played(
+    since_ms: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<PlayCount>, CommandError> {
+    let db = LibraryDb::open()?;
+    let counts = db.most_played(since_ms.unwrap_or(0), limit.unwrap_or(HISTORY_LIMIT))?;
+    Ok(counts)
+}
+
+/// Add every song under `folder` to the library. Files are parsed in
+/// parallel, each reported with a `library::SCAN_EVENT` as it finishes.
+/// They skip the parse cache, which only holds a few hundred songs and
+/// is kept for the ones actually opened.
+fn scan_folder(app: &AppHandle, folder: &str) -> Result<ScanSummary, CommandError> {
+    let state = app.state::<AppState>();
+    let pairing = state.config.lock().unwrap().note_pairing;
+    let files = library::song_files(Path::new(folder));
+    let mut summary = ScanSummary::default();
+
+    let parse = |file: &Path| midi::load_file_uncached(&file.to_string_lossy(), pairing);
+    library::parse_all(&files, parse, |file, result| {
+        let path = file.to_string_lossy().into_owned();
+        let (info, error) = match result {
+            Ok(midi_file) => {
+                summary.added += 1;
+                state
+                    .library
+                    .lock()
+                    .unwrap()
+                    .record_load(&path, &midi_file.info);
+                (Some(midi_file.info), None)
+            }
+            Err(e) => {
+                summary.failed += 1;
+                (None, Some(e.to_string()))
+            }
+        };
+        let scanned = ScannedSong {
+            path,
+            done: summary.added + summary.failed,
+            total: files.len(),
+            info,
+            error,
+        };
+        let _ = app.emit_all(library::SCAN_EVENT, scanned);
+    });
+
+    state.library.lock().unwrap().save()?;
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn scan_library_folder(folder: String, app: AppHandle) -> Result<ScanSummary, CommandError> {
+    tauri::async_runtime::spawn_blocking(move || scan_folder(&app, &folder))
+        .await
+        .map_err(|e| CommandError::from(e.to_string()))?
+}
+
+#[tauri::command]
+fn queue_song(path: String, state: State<AppState>) {
+    state.queue.lock().unwrap().push_back(path);
+}
+
+#[tauri::command]
+fn get_queue(state: State<AppState>) -> Vec<String> {
+    state.queue.lock().unwrap().iter().cloned().collect()
+}
+
+#[tauri::command]
+fn clear_queue(state: State<AppState>) {
+    state.queue.lock().unwrap().clear();
+}
+
+/// Queue every song carrying `tag`, returning how many were added
+#[tauri::command]
+fn queue_tag(tag: String, state: State<AppState>) -> usize {
+    let songs = state.library.lock().unwrap().by_tag(&tag);
+    let mut queue = state.queue.lock().unwrap();
+    queue.extend(songs.iter().map(|entry| entry.path.clone()));
+    songs.len()
+}
+
+/// Shuffle the queue, from `seed` if given so the order can be replayed.
+/// Returns the seed used.
+#[tauri::command]
+fn shuffle_queue(seed: Option<u64>, state: State<AppState>) -> u64 {
+    let seed = seed.unwrap_or_else(clock::now_ms);
+    let mut queue = state.queue.lock().unwrap();
+    shuffle::shuffle(queue.make_contiguous(), seed);
+    seed
+}
+
+/// Keep cycling through the queue instead of emptying it
+#[tauri::command]
+fn set_repeat_queue(enabled: bool, state: State<AppState>) {
+    state.repeat_queue.store(enabled, Ordering::SeqCst);
+}
+
+#[tauri::command]
+fn next_song(app: AppHandle) -> Result<(), CommandError> {
+    play_next_song(&app)
+}
+
+/// Perform `songs` in order, with `gap` between one song and the next
+#[tauri::command]
+fn start_setlist(songs: Vec<String>, gap: Gap, app: AppHandle) -> Result<(), CommandError> {
+    let setlist = Setlist::new(songs, gap);
+    let first = setlist.current().ok_or("The setlist is empty")?.to_string();
+
+    let state = app.state::<AppState>();
+    stop_all(&state);
+    load_song(&app, &first)?;
+    *state.setlist.lock().unwrap() = Some(setlist);
+    start_playback(&state)
+}
+
+/// End the setlist after the current song, dropping a pending start
+#[tauri::command]
+fn stop_setlist(state: State<AppState>) {
+    clock::cancel_scheduled_start(&state);
+    *state.setlist.lock().unwrap() = None;
+}
+
+#[tauri::command]
+fn get_setlist(state: State<AppState>) -> Option<Setlist> {
+    state.setlist.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn get_midi_inputs() -> Result<Vec<String>, CommandError> {
+    midi_input::list_ports().map_err(CommandError::from)
+}
+
+/// (Re)open the MIDI remote port from the current config, closing any
+/// earlier connection. Disabled remotes just close the port.
+#[tauri::command]
+fn connect_midi_remote(app: AppHandle, state: State<AppState>) -> Result<(), CommandError> {
+    let remote = state.config.lock().unwrap().midi_remote.clone();
+    let mut connection = state.midi_remote.lock().unwrap();
+    *connection = None;
+    if remote.enabled {
+        *connection = Some(midi_remote::connect(app, &remote.port)?);
     }
     Ok(())
 }
 
+/// (Re)open the MIDI clock port from the current config, closing any
+/// earlier connection. Disabled clock sync just closes the port.
 #[tauri::command]
-fn pause(state: State<AppState>) -> Result<(), String> {
-    let mut playback = state.playback.lock().unwrap();
-    playback.pause();
+fn connect_midi_clock(app: AppHandle, state: State<AppState>) -> Result<(), CommandError> {
+    let clock = state.config.lock().unwrap().midi_clock.clone();
+    let mut connection = state.midi_clock.lock().unwrap();
+    *connection = None;
+    if clock.enabled {
+        *connection = Some(midi_clock::connect(app, &clock.port)?);
+    }
     Ok(())
 }
 
 #[tauri::command]
-fn stop(state: State<AppState>) -> Result<(), String> {
-    let mut playback = state.playback.lock().unwrap();
-    playback.stop();
+fn get_midi_outputs() -> Result<Vec<String>, CommandError> {
+    midi_output::list_ports().map_err(CommandError::from)
+}
+
+/// (Re)open the MIDI clock output port from the current config. Applies
+/// from the next song started; disabled output closes the port.
+#[tauri::command]
+fn connect_midi_clock_output(state: State<AppState>) -> Result<(), CommandError> {
+    let output = state.config.lock().unwrap().midi_clock_output.clone();
+    let connection = if output.enabled {
+        Some(midi_output::connect(&output.port)?)
+    } else {
+        None
+    };
+    state.playback.lock().unwrap().set_clock_output(connection);
     Ok(())
 }
 
+/// Start capturing a manual performance from the keyboard or a MIDI port
 #[tauri::command]
-fn set_tempo(factor: f64, state: State<AppState>) -> Result<(), String> {
-    let mut config = state.config.lock().unwrap();
-    config.tempo_factor = factor;
+fn start_recording(source: RecordSource, state: State<AppState>) -> Result<(), CommandError> {
+    let mut recording = state.recording.lock().unwrap();
+    if recording.is_some() {
+        return Err("Already recording".into());
+    }
+    let following = state
+        .step
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|s| s.follows_along());
+    if following {
+        return Err("Stop follow-along first".into());
+    }
+    let config = state.config.lock().unwrap().clone();
+    *recording = Some(Recording::start(source, &config)?);
     Ok(())
 }
 
+/// Stop recording and save it as a MIDI file, returning the note count
 #[tauri::command]
-fn set_transpose(semitones: i32, state: State<AppState>) -> Result<(), String> {
-    let mut config = state.config.lock().unwrap();
-    config.transpose = semitones;
+fn stop_recording(path: String, state: State<AppState>) -> Result<usize, CommandError> {
+    let recording = state
+        .recording
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or("Not recording")?;
+    recording.stop(&path).map_err(CommandError::from)
+}
+
+/// Conduct an ensemble, accepting players on the configured port
+#[tauri::command]
+fn ensemble_host(app: AppHandle, state: State<AppState>) -> Result<(), CommandError> {
+    let ensemble = state.config.lock().unwrap().ensemble.clone();
+    let conductor = Conductor::host(app, ensemble.port, ensemble.token)?;
+    *state.ensemble.lock().unwrap() = Some(Ensemble::Conductor(conductor));
     Ok(())
 }
 
+/// Join a conductor's ensemble at `address` (host:port) under `name`,
+/// with the configured ensemble token
+fn join_ensemble(app: &AppHandle, address: &str, name: &str) -> Result<(), CommandError> {
+    let state = app.state::<AppState>();
+    let token = state.config.lock().unwrap().ensemble.token.clone();
+    let player = Player::join(app.clone(), address, name, &token)?;
+    *state.ensemble.lock().unwrap() = Some(Ensemble::Player(player));
+    Ok(())
+}
+
+/// Join a conductor's ensemble off the main thread, since connecting
+/// and syncing clocks can take a few seconds
 #[tauri::command]
-fn get_config(state: State<AppState>) -> AppConfig {
-    state.config.lock().unwrap().clone()
+async fn ensemble_join(address: String, name: String, app: AppHandle) -> Result<(), CommandError> {
+    tauri::async_runtime::spawn_blocking(move || join_ensemble(&app, &address, &name))
+        .await
+        .map_err(|e| CommandError::from(e.to_string()))?
+}
+
+/// Leave the ensemble, or stop conducting and disconnect every player
+#[tauri::command]
+fn ensemble_leave(state: State<AppState>) {
+    *state.ensemble.lock().unwrap() = None;
+}
+
+#[tauri::command]
+fn get_ensemble_players(state: State<AppState>) -> Result<Vec<EnsemblePlayer>, CommandError> {
+    match &*state.ensemble.lock().unwrap() {
+        Some(Ensemble::Conductor(conductor)) => Ok(conductor.players()),
+        _ => Err("Not conducting an ensemble".into()),
+    }
+}
+
+/// Give a player a subset of the song's tracks; empty plays them all
+#[tauri::command]
+fn ensemble_assign(
+    name: String,
+    tracks: Vec<usize>,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    match &*state.ensemble.lock().unwrap() {
+        Some(Ensemble::Conductor(conductor)) => {
+            conductor.assign(&name, tracks).map_err(CommandError::from)
+        }
+        _ => Err("Not conducting an ensemble".into()),
+    }
+}
+
+/// Send the loaded song to every player and start everyone together
+#[tauri::command]
+fn ensemble_start(app: AppHandle, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let lead_ms = state.config.lock().unwrap().ensemble.lead_ms;
+    match &*state.ensemble.lock().unwrap() {
+        Some(Ensemble::Conductor(conductor)) => conductor
+            .perform(&app, midi, lead_ms)
+            .map_err(CommandError::from),
+        _ => Err("Not conducting an ensemble".into()),
+    }
+}
+
+/// Stop the performance on every player and here
+#[tauri::command]
+fn ensemble_stop(state: State<AppState>) -> Result<(), CommandError> {
+    match &*state.ensemble.lock().unwrap() {
+        Some(Ensemble::Conductor(conductor)) => conductor.stop(),
+        _ => return Err("Not conducting an ensemble".into()),
+    }
+    stop_all(&state);
+    Ok(())
+}
+
+/// Save the loaded song's keystrokes as a standalone AutoHotkey script
+#[tauri::command]
+fn export_ahk(path: String, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    export::export_ahk(&path, midi, &config).map_err(CommandError::from)
+}
+
+/// Save the arrangement as it will be played as a new MIDI file
+#[tauri::command]
+fn export_midi(path: String, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    export::export_midi(&path, midi, &config).map_err(CommandError::from)
+}
+
+/// Save the keys to press as a text keysheet, grouped by bar
+#[tauri::command]
+fn export_keysheet(path: String, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    export::export_keysheet(&path, midi, &config).map_err(CommandError::from)
+}
+
+/// Save the arrangement as it will be played as MusicXML sheet music
+#[tauri::command]
+fn export_musicxml(path: String, state: State<AppState>) -> Result<(), CommandError> {
+    let midi_file = state.midi_file.lock().unwrap();
+    let midi = midi_file.as_ref().ok_or(CommandError::NoSongLoaded)?;
+    let config = state.config.lock().unwrap();
+    export::export_musicxml(&path, midi, &config).map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn show_overlay(app: AppHandle, state: State<AppState>) -> Result<(), CommandError> {
+    let overlay = state.config.lock().unwrap().overlay.clone();
+    overlay::show(&app, &overlay).map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn hide_overlay(app: AppHandle) -> Result<(), CommandError> {
+    overlay::hide(&app).map_err(CommandError::from)
+}
+
+/// Move the overlay and remember where it goes next time
+#[tauri::command]
+fn set_overlay_position(
+    x: f64,
+    y: f64,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<(), CommandError> {
+    overlay::move_to(&app, x, y)?;
+
+    let mut config = state.config.lock().unwrap();
+    config.overlay.x = x;
+    config.overlay.y = y;
+    config.save().map_err(CommandError::from)
 }
 
 #[tauri::command]
-fn test_key(key: String, modifier: String) -> Result<(), String> {
+fn test_key(key: String, modifier: String) -> Result<(), CommandError> {
     let mod_type = match modifier.as_str() {
         "shift" => keyboard::Modifier::Shift,
         "ctrl" => keyboard::Modifier::Ctrl,
         _ => keyboard::Modifier::None,
     };
 
-    keyboard::press_key(&key, mod_type).map_err(|e| e.to_string())?;
+    keyboard::press_key(&key, mod_type)?;
     std::thread::sleep(std::time::Duration::from_millis(50));
-    keyboard::release_key(&key, mod_type).map_err(|e| e.to_string())?;
+    keyboard::release_key(&key, mod_type)?;
 
     Ok(())
 }
 
+/// Time per note of `test_scale` at 100% tempo
+const TEST_SCALE_STEP_MS: f64 = 400.0;
+
+/// Play every note of one octave the layout can reach, lowest first,
+/// after the start delay and at the set tempo, to check the game's
+/// keybinds before a performance. `octave` counts from the reference
+/// note's octave. Returns the keys that will be pressed.
+#[tauri::command]
+fn test_scale(octave: i32, state: State<AppState>) -> Result<Vec<String>, CommandError> {
+    if state.playback.lock().unwrap().is_playing() {
+        return Err("Stop playback first".into());
+    }
+    let config = state.config.lock().unwrap().clone();
+    let keystrokes = mapper::octave_keystrokes(octave, &config);
+    if keystrokes.is_empty() {
+        return Err("The layout has no keys in that octave".into());
+    }
+    for keystroke in &keystrokes {
+        if !keyboard::is_supported_key(&keystroke.key) {
+            return Err(CommandError::UnknownKey {
+                key: keystroke.key.clone(),
+            });
+        }
+    }
+
+    let step =
+        std::time::Duration::from_secs_f64(TEST_SCALE_STEP_MS / 1000.0 / config.tempo_factor);
+    let labels = keystrokes
+        .iter()
+        .map(|k| k.modifier.label(&k.key))
+        .collect();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(config.start_delay_ms));
+        for keystroke in keystrokes {
+            let _ = keyboard::press_key(&keystroke.key, keystroke.modifier);
+            std::thread::sleep(step / 2);
+            let _ = keyboard::release_key(&keystroke.key, keystroke.modifier);
+            std::thread::sleep(step / 2);
+        }
+    });
+    Ok(labels)
+}
+
+/// What `curve` turns each velocity 0-127 into, for drawing it before
+/// it is saved
+#[tauri::command]
+fn preview_velocity_curve(curve: VelocityCurve) -> Result<Vec<u8>, CommandError> {
+    let errors = AppConfig {
+        velocity_curve: curve.clone(),
+        ..AppConfig::default()
+    }
+    .validate();
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+    Ok((0..=127).map(|v| curve.apply(v)).collect())
+}
+
 fn main() {
-    let config = AppConfig::load().unwrap_or_default();
+    keyboard::install_panic_hook();
+    nearest_mapper::register();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
+    // Two instances would fight over the keyboard; hand our arguments to
+    // the running one instead. If the check itself fails, start anyway.
+    let instance_lock = match instance::acquire(&args) {
+        Ok(Some(listener)) => Some(listener),
+        Ok(None) => return,
+        Err(_) => None,
+    };
+
+    let (config, config_report) = AppConfig::load_or_recover();
+    let profiles = ProfileStore::load(&config).unwrap_or_else(|_| ProfileStore::new(&config));
+
+    let playback = PlaybackEngine::new();
+    let panic_handle = playback.panic_handle();
 
     let app_state = AppState {
         config: Mutex::new(config),
+        config_report: Mutex::new(config_report),
         midi_file: Mutex::new(None),
-        playback: Mutex::new(PlaybackEngine::new()),
+        playback: Mutex::new(playback),
+        panic: panic_handle,
+        recent_files: Mutex::new(RecentFiles::load().unwrap_or_default()),
+        library: Mutex::new(Library::load().unwrap_or_default()),
+        song_settings: Mutex::new(SongSettingsStore::load().unwrap_or_default()),
+        profiles: Mutex::new(profiles),
+        midi_remote: Mutex::new(None),
+        midi_clock: Mutex::new(None),
+        remote_subscribers: Mutex::new(Vec::new()),
+        queue: Mutex::new(VecDeque::new()),
+        repeat_queue: AtomicBool::new(false),
+        setlist: Mutex::new(None),
+        recording: Mutex::new(None),
+        ensemble: Mutex::new(None),
+        scheduled_start: AtomicU64::new(0),
+        step: Mutex::new(None),
+        initial_file: Mutex::new(instance::file_argument(&args).map(String::from)),
     };
 
     tauri::Builder::default()
         .manage(app_state)
+        .system_tray(tray::build())
+        .on_system_tray_event(tray::handle_event)
+        .on_window_event(tray::handle_window_event)
+        .setup(move |app| {
+            if let Some(listener) = instance_lock {
+                instance::listen(app.handle(), listener);
+            }
+
+            // Hot-reload is a convenience; the app works without it
+            let _ = config_watch::watch_config(app.handle());
+
+            let state = app.state::<AppState>();
+            let handle = app.handle();
+            state.playback.lock().unwrap().set_listener(move |event| {
+                let state = handle.state::<AppState>();
+                remote::publish(&state, PLAYBACK_EVENT, &event);
+
+                // Remember where a song was left off, to resume it later
+                let session = match &event {
+                    PlaybackEvent::Stopped { path, position_ms } => Some(LastSession {
+                        path: path.clone(),
+                        position_ms: *position_ms,
+                    }),
+                    _ => None,
+                };
+                if matches!(
+                    event,
+                    PlaybackEvent::Stopped { .. } | PlaybackEvent::Finished { .. }
+                ) {
+                    let mut recent = state.recent_files.lock().unwrap();
+                    recent.last_session = session;
+                    let _ = recent.save();
+                }
+
+                // Carry on with the setlist or queued songs
+                if let PlaybackEvent::Finished { path } = &event {
+                    record_play(&state, path);
+                    let performing = state.setlist.lock().unwrap().is_some();
+                    let queued = !state.queue.lock().unwrap().is_empty();
+                    let handle = handle.clone();
+                    if performing {
+                        std::thread::spawn(move || advance_setlist(&handle));
+                    } else if queued {
+                        std::thread::spawn(move || play_next_song(&handle));
+                    }
+                }
+
+                let _ = handle.emit_all(PLAYBACK_EVENT, event);
+            });
+
+            // A hotkey taken by another program shouldn't stop the app
+            let hotkeys = state.config.lock().unwrap().hotkeys.clone();
+            let _ = hotkeys::register_hotkeys(&app.handle(), &hotkeys);
+            hotkeys::listen_gamepad(app.handle());
+
+            let remote = state.config.lock().unwrap().midi_remote.clone();
+            if remote.enabled {
+                let connection = midi_remote::connect(app.handle(), &remote.port).ok();
+                *state.midi_remote.lock().unwrap() = connection;
+            }
+
+            let clock = state.config.lock().unwrap().midi_clock.clone();
+            if clock.enabled {
+                let connection = midi_clock::connect(app.handle(), &clock.port).ok();
+                *state.midi_clock.lock().unwrap() = connection;
+            }
+
+            let clock_output = state.config.lock().unwrap().midi_clock_output.clone();
+            if clock_output.enabled {
+                let connection = midi_output::connect(&clock_output.port).ok();
+                state.playback.lock().unwrap().set_clock_output(connection);
+            }
+
+            let websocket = state.config.lock().unwrap().websocket.clone();
+            if websocket.enabled {
+                let _ = ws_server::start(app.handle(), websocket.port);
+            }
+
+            let osc = state.config.lock().unwrap().osc.clone();
+            if osc.enabled {
+                let address = if osc.allow_lan {
+                    "0.0.0.0"
+                } else {
+                    "127.0.0.1"
+                };
+                let _ = osc_server::start(app.handle(), address, osc.port);
+            }
+
+            #[cfg(feature = "rest-api")]
+            {
+                let rest = state.config.lock().unwrap().rest.clone();
+                if rest.enabled {
+                    let _ = rest_server::start(app.handle(), rest.port);
+                }
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             load_midi_file,
+            get_song_info,
+            load_from_clipboard,
+            take_initial_file,
+            detect_key,
+            estimate_difficulty,
+            suggest_tempo,
+            get_transpose_fits,
+            get_note_density,
+            get_first_note_time,
+            get_key_usage,
+            run_timing_benchmark,
+            apply_detected_key,
             play,
             pause,
+            set_keys_muted,
             stop,
+            restart,
+            play_at,
+            start_practice,
+            play_range,
+            get_playback_state,
+            get_bar_position,
+            get_last_session,
+            resume_last_session,
+            seek,
+            seek_to_marker,
+            practice_marker,
+            seek_to_bar,
+            play_bars,
+            practice_bars,
+            panic,
             set_tempo,
             set_transpose,
+            set_max_polyphony,
+            set_start_delay,
+            capture_next_hotkey,
+            set_audio_preview,
+            set_preview_volume,
+            set_preview_device,
+            set_reference_note,
+            update_key_mapping,
+            set_layout_mode,
+            set_scale,
+            set_drum_keys,
+            set_metronome,
+            start_step_mode,
+            start_follow_along,
+            step_next,
+            stop_step_mode,
+            set_arrangement,
+            set_reduction,
+            set_split_hands,
+            get_mappers,
+            set_mapper,
+            set_track_enabled,
+            get_song_settings,
+            clear_song_settings,
+            get_bookmarks,
+            add_bookmark,
+            remove_bookmark,
+            seek_to_bookmark,
+            practice_bookmark,
             get_config,
+            get_presets,
+            apply_preset,
+            validate_config,
+            get_config_report,
+            get_config_warnings,
+            export_config,
+            import_config,
+            get_profiles,
+            create_profile,
+            switch_profile,
+            rename_profile,
+            delete_profile,
+            get_recent_files,
+            clear_recent_files,
+            clear_parse_cache,
+            set_favorite,
+            add_tag,
+            remove_tag,
+            get_favorites,
+            get_songs_by_tag,
+            get_all_tags,
+            search_library,
+            query_library,
+            get_history,
+            get_most_played,
+            scan_library_folder,
+            queue_song,
+            get_queue,
+            clear_queue,
+            next_song,
+            queue_tag,
+            shuffle_queue,
+            set_repeat_queue,
+            start_setlist,
+            stop_setlist,
+            get_setlist,
+            get_midi_inputs,
+            connect_midi_remote,
+            connect_midi_clock,
+            get_midi_outputs,
+            connect_midi_clock_output,
+            start_recording,
+            stop_recording,
+            ensemble_host,
+            ensemble_join,
+            ensemble_leave,
+            get_ensemble_players,
+            ensemble_assign,
+            ensemble_start,
+            ensemble_stop,
+            export_ahk,
+            export_midi,
+            export_keysheet,
+            export_musicxml,
+            show_overlay,
+            hide_overlay,
+            set_overlay_position,
             test_key,
+            test_scale,
+            preview_velocity_curve,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
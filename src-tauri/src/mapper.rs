@@ -1,13 +1,9 @@
-use crate::config::AppConfig;
-use crate::keyboard::Modifier;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Once, RwLock};
 
-/// Represents an octave in the game instrument
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Octave {
-    Low,
-    Medium,
-    High,
-}
+use crate::config::{AccidentalFallback, AccidentalSpelling, AppConfig, LayoutMode, Scale};
+use crate::keyboard::Modifier;
+use crate::midi::NoteEvent;
 
 /// Represents an accidental (sharp/flat/natural)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,8 +16,7 @@ pub enum Accidental {
 /// A note that can be played on the in-game instrument
 #[derive(Debug, Clone)]
 pub struct InstrumentNote {
-    pub octave: Octave,
-    pub degree: u8,        // 1-7
+    pub key_index: usize, // position across all layout rows
     pub accidental: Accidental,
 }
 
@@ -42,102 +37,324 @@ impl Accidental {
     }
 }
 
-/// Semitone offsets for each scale degree in a major scale
-const DEGREE_SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// A strategy for turning song notes into keystrokes. Implement this to
+/// support a new game layout and add it with `register_mapper`.
+pub trait NoteMapper: Send + Sync {
+    /// Keystroke that plays `note`, or None if the layout can't play it
+    fn map(&self, note: &NoteEvent, config: &AppConfig) -> Option<KeyStroke>;
+}
+
+/// Treats successive keys as scale degrees, using Shift/Ctrl for accidentals
+struct DiatonicMapper;
+
+impl NoteMapper for DiatonicMapper {
+    fn map(&self, note: &NoteEvent, config: &AppConfig) -> Option<KeyStroke> {
+        let note = layout_to_instrument(note.note, LayoutMode::Diatonic, config)?;
+        note_to_keystroke(&note, config)
+    }
+}
+
+/// Treats successive keys as semitones
+struct ChromaticMapper;
+
+impl NoteMapper for ChromaticMapper {
+    fn map(&self, note: &NoteEvent, config: &AppConfig) -> Option<KeyStroke> {
+        let note = layout_to_instrument(note.note, LayoutMode::Chromatic, config)?;
+        note_to_keystroke(&note, config)
+    }
+}
+
+/// Plays only percussion, using `drum_keys`
+struct DrumMapper;
+
+impl NoteMapper for DrumMapper {
+    fn map(&self, note: &NoteEvent, config: &AppConfig) -> Option<KeyStroke> {
+        if note.channel != DRUM_CHANNEL {
+            return None;
+        }
+        drum_to_keystroke(note.note, config)
+    }
+}
+
+type MapperRegistry = RwLock<BTreeMap<String, Arc<dyn NoteMapper>>>;
+
+static MAPPERS: MapperRegistry = RwLock::new(BTreeMap::new());
+static BUILTIN_MAPPERS: Once = Once::new();
+
+fn mappers() -> &'static MapperRegistry {
+    BUILTIN_MAPPERS.call_once(|| {
+        let mut mappers = MAPPERS.write().unwrap();
+        mappers.insert("diatonic".into(), Arc::new(DiatonicMapper));
+        mappers.insert("chromatic".into(), Arc::new(ChromaticMapper));
+        mappers.insert("drums".into(), Arc::new(DrumMapper));
+    });
+    &MAPPERS
+}
+
+/// Make a mapper selectable by name through `AppConfig::mapper`,
+/// replacing any mapper already registered under that name
+pub fn register_mapper(name: &str, mapper: impl NoteMapper + 'static) {
+    mappers()
+        .write()
+        .unwrap()
+        .insert(name.to_string(), Arc::new(mapper));
+}
+
+/// Names of all registered mappers
+pub fn mapper_names() -> Vec<String> {
+    mappers().read().unwrap().keys().cloned().collect()
+}
+
+/// The mapper selected by the config: `mapper` if set, otherwise the one
+/// matching the layout mode
+pub fn mapper_for(config: &AppConfig) -> Option<Arc<dyn NoteMapper>> {
+    let name = match (config.mapper.as_str(), config.key_mapping.mode) {
+        ("", LayoutMode::Diatonic) => "diatonic",
+        ("", LayoutMode::Chromatic) => "chromatic",
+        ("", LayoutMode::Drums) => "drums",
+        (name, _) => name,
+    };
+    mappers().read().unwrap().get(name).cloned()
+}
 
 /// Map a MIDI note to an instrument note
 /// Returns None if the note is out of range
 pub fn midi_to_instrument(midi_note: u8, config: &AppConfig) -> Option<InstrumentNote> {
+    layout_to_instrument(midi_note, config.key_mapping.mode, config)
+}
+
+/// Map a MIDI note to an instrument note as if the keys followed `mode`
+fn layout_to_instrument(
+    midi_note: u8,
+    mode: LayoutMode,
+    config: &AppConfig,
+) -> Option<InstrumentNote> {
     let transposed = midi_note as i32 + config.transpose;
     let reference = config.reference_midi_note as i32;
 
-    // Calculate semitones from reference (Medium octave, degree 1)
+    // Calculate semitones from the reference key
     let semitones_from_ref = transposed - reference;
 
-    // Calculate octave offset and position within octave
-    let octave_offset = semitones_from_ref.div_euclid(12);
-    let within_octave = semitones_from_ref.rem_euclid(12) as u8;
+    // Calculate the key offset from the reference key
+    let (key_offset, accidental) = match mode {
+        // Drums are bound per note instead; see `drum_to_keystroke`
+        LayoutMode::Drums => return None,
+        // Every semitone has its own key, so no modifiers are ever needed
+        LayoutMode::Chromatic => (semitones_from_ref, Accidental::Natural),
+        LayoutMode::Diatonic => {
+            let octave_offset = semitones_from_ref.div_euclid(12);
+            let within_octave = semitones_from_ref.rem_euclid(12) as u8;
 
-    // Find the best matching degree and accidental
-    let (degree, accidental) = find_degree_and_accidental(within_octave)?;
+            // Find the best matching degree and accidental
+            let intervals = config.key_mapping.scale.intervals();
+            let spelling = config.accidental_spelling;
+            let (mut degree, mut accidental) = find_degree_and_accidental(
+                within_octave,
+                intervals,
+                prefers_flats(spelling, config),
+            )?;
+            if accidental != Accidental::Natural && !config.accidentals {
+                // Instrument can't play sharps/flats
+                let spelling = match config.accidental_fallback {
+                    AccidentalFallback::Skip => return None,
+                    AccidentalFallback::Nearest => spelling,
+                    AccidentalFallback::KeySignature => AccidentalSpelling::KeySignature,
+                };
+                (degree, _) = find_degree_and_accidental(
+                    within_octave,
+                    intervals,
+                    prefers_flats(spelling, config),
+                )?;
+                accidental = Accidental::Natural;
+            }
 
-    // Calculate final octave (Medium + offset)
-    let octave = match octave_offset {
-        -1 => Octave::Low,
-        0 => Octave::Medium,
-        1 => Octave::High,
-        _ => return None, // Out of range
+            let degrees = intervals.len() as i32;
+            (octave_offset * degrees + degree as i32 - 1, accidental)
+        }
     };
 
+    let key_index = config.key_mapping.reference_index as i32 + key_offset;
+    if key_index < 0 || key_index as usize >= config.key_mapping.key_count() {
+        return None; // Out of range
+    }
+
     Some(InstrumentNote {
-        octave,
-        degree,
+        key_index: key_index as usize,
         accidental,
     })
 }
 
-/// Find the scale degree and accidental for a given semitone position within an octave
-fn find_degree_and_accidental(semitones: u8) -> Option<(u8, Accidental)> {
+/// Find the scale degree and accidental for a given semitone position within an octave,
+/// given the semitone offset of each degree of the scale. A note that is
+/// both a sharp and a flat is spelled as a flat if `prefer_flats`. The
+/// flat of degree 1 in the next octave (e.g. B in a pentatonic scale) is
+/// returned as degree `intervals.len() + 1`.
+fn find_degree_and_accidental(
+    semitones: u8,
+    intervals: &[i32],
+    prefer_flats: bool,
+) -> Option<(u8, Accidental)> {
+    let semitones = semitones as i32;
+
     // Check for exact match (natural note)
-    for (i, &deg_semi) in DEGREE_SEMITONES.iter().enumerate() {
-        if deg_semi as u8 == semitones {
-            return Some((i as u8 + 1, Accidental::Natural));
-        }
+    if let Some(i) = intervals.iter().position(|&d| d == semitones) {
+        return Some((i as u8 + 1, Accidental::Natural));
     }
 
-    // Check for sharp (degree + 1 semitone)
-    for (i, &deg_semi) in DEGREE_SEMITONES.iter().enumerate() {
-        if (deg_semi + 1) as u8 == semitones {
-            return Some((i as u8 + 1, Accidental::Sharp));
+    // Sharp: degree + 1 semitone
+    let sharp = intervals
+        .iter()
+        .position(|&d| d + 1 == semitones)
+        .map(|i| (i as u8 + 1, Accidental::Sharp));
+
+    // Flat: degree - 1 semitone, including degree 1 an octave up
+    let flat = intervals
+        .iter()
+        .chain(std::iter::once(&12))
+        .position(|&d| d - 1 == semitones)
+        .map(|i| (i as u8 + 1, Accidental::Flat));
+
+    if prefer_flats {
+        flat.or(sharp)
+    } else {
+        sharp.or(flat)
+    }
+}
+
+/// Whether `spelling` picks flats for this layout
+fn prefers_flats(spelling: AccidentalSpelling, config: &AppConfig) -> bool {
+    match spelling {
+        AccidentalSpelling::Sharps => false,
+        AccidentalSpelling::Flats => true,
+        AccidentalSpelling::KeySignature => {
+            let minor = matches!(
+                config.key_mapping.scale,
+                Scale::NaturalMinor | Scale::MinorPentatonic
+            );
+            // Key signatures follow the relative major
+            let tonic = config.reference_midi_note % 12 + if minor { 3 } else { 0 };
+            matches!(tonic % 12, 1 | 3 | 5 | 6 | 8 | 10)
         }
     }
+}
+
+/// MIDI pitch (before transpose) sounded by the natural key at `key_index`
+pub fn key_pitch(key_index: usize, config: &AppConfig) -> i32 {
+    let offset = key_index as i32 - config.key_mapping.reference_index as i32;
+    let reference = config.reference_midi_note as i32;
 
-    // Check for flat (degree - 1 semitone)
-    for (i, &deg_semi) in DEGREE_SEMITONES.iter().enumerate() {
-        if deg_semi > 0 && (deg_semi - 1) as u8 == semitones {
-            return Some((i as u8 + 1, Accidental::Flat));
+    match config.key_mapping.mode {
+        LayoutMode::Chromatic | LayoutMode::Drums => reference + offset,
+        LayoutMode::Diatonic => {
+            let intervals = config.key_mapping.scale.intervals();
+            let degrees = intervals.len() as i32;
+            let octave = offset.div_euclid(degrees);
+            let degree = offset.rem_euclid(degrees) as usize;
+            reference + octave * 12 + intervals[degree]
         }
     }
+}
+
+/// MIDI pitch (before transpose) a keystroke plays; the reverse of
+/// `midi_to_instrument` plus `note_to_keystroke`, or `drum_keys` for drums
+pub fn keystroke_to_midi(key: &str, modifier: Modifier, config: &AppConfig) -> Option<u8> {
+    let mapping = &config.key_mapping;
+    if mapping.mode == LayoutMode::Drums {
+        return config
+            .drum_keys
+            .iter()
+            .find(|d| d.key.eq_ignore_ascii_case(key))
+            .map(|d| d.note);
+    }
 
-    // Edge case: semitone 11 could be flat of degree 1 in next octave
-    // But we handle this at the octave level instead
+    // Alternates play the same pitch as their primary key
+    let primary = mapping
+        .alternates
+        .iter()
+        .find(|(_, alts)| alts.iter().any(|a| a.eq_ignore_ascii_case(key)))
+        .map_or(key, |(primary, _)| primary.as_str());
+    let key_index = mapping
+        .keys()
+        .position(|k| k.eq_ignore_ascii_case(primary))?;
 
-    None
+    let accidental = match (mapping.mode, modifier) {
+        (LayoutMode::Diatonic, Modifier::Shift) => 1,
+        (LayoutMode::Diatonic, Modifier::Ctrl) => -1,
+        _ => 0,
+    };
+    let pitch = key_pitch(key_index, config) + accidental;
+    (0..=127).contains(&pitch).then_some(pitch as u8)
 }
 
-/// Convert an instrument note to a keystroke
-pub fn note_to_keystroke(note: &InstrumentNote, config: &AppConfig) -> Option<KeyStroke> {
-    let keys = match note.octave {
-        Octave::High => &config.key_mapping.high,
-        Octave::Medium => &config.key_mapping.medium,
-        Octave::Low => &config.key_mapping.low,
+/// Lowest and highest pitch the layout can play (inclusive)
+pub fn playable_range(config: &AppConfig) -> (i32, i32) {
+    let last = config.key_mapping.key_count().saturating_sub(1);
+    (key_pitch(0, config), key_pitch(last, config))
+}
+
+/// Keystrokes for each semitone of one octave, starting `octave` octaves
+/// above the reference note (negative for below), skipping notes the
+/// layout cannot play. Transpose is ignored.
+pub fn octave_keystrokes(octave: i32, config: &AppConfig) -> Vec<KeyStroke> {
+    let mut config = config.clone();
+    config.transpose = 0;
+    let Some(mapper) = mapper_for(&config) else {
+        return Vec::new();
     };
 
-    let index = (note.degree - 1) as usize;
-    if index >= keys.len() {
-        return None;
-    }
+    let first = config.reference_midi_note as i32 + octave * 12;
+    (first..first + 12)
+        .filter(|pitch| (0..=127).contains(pitch))
+        .filter_map(|pitch| {
+            let note = NoteEvent {
+                start_ms: 0,
+                duration_ms: 0,
+                note: pitch as u8,
+                velocity: 100,
+                track: 0,
+                channel: 0,
+            };
+            mapper.map(&note, &config)
+        })
+        .collect()
+}
+
+/// Convert an instrument note to a keystroke
+pub fn note_to_keystroke(note: &InstrumentNote, config: &AppConfig) -> Option<KeyStroke> {
+    let key = config.key_mapping.key(note.key_index)?;
 
     Some(KeyStroke {
-        key: keys[index].clone(),
+        key: key.clone(),
         modifier: note.accidental.to_modifier(),
     })
 }
 
+/// General MIDI percussion channel (channel 10, zero-based)
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// Look up the key bound to a percussion note (untransposed)
+pub fn drum_to_keystroke(midi_note: u8, config: &AppConfig) -> Option<KeyStroke> {
+    config
+        .drum_keys
+        .iter()
+        .find(|d| d.note == midi_note)
+        .map(|d| KeyStroke {
+            key: d.key.clone(),
+            modifier: Modifier::None,
+        })
+}
+
 /// Analyze MIDI note range and suggest optimal transpose value
-pub fn suggest_transpose(midi_notes: &[u8], reference: u8) -> i32 {
+pub fn suggest_transpose(midi_notes: &[u8], config: &AppConfig) -> i32 {
     if midi_notes.is_empty() {
         return 0;
     }
 
     let min_note = *midi_notes.iter().min().unwrap() as i32;
     let max_note = *midi_notes.iter().max().unwrap() as i32;
-    let ref_note = reference as i32;
 
-    // Playable range: Low octave degree 1 to High octave degree 7
-    // That's reference - 12 to reference + 23 (roughly 3 octaves)
-    let playable_min = ref_note - 12;
-    let playable_max = ref_note + 23;
+    // Playable range: lowest key to highest key of the layout
+    let (playable_min, playable_max) = playable_range(config);
 
     // Try different transpose values to find optimal fit
     let mut best_transpose = 0;
@@ -162,25 +379,187 @@ pub fn suggest_transpose(midi_notes: &[u8], reference: u8) -> i32 {
 mod tests {
     use super::*;
 
+    const MAJOR: &[i32] = &[0, 2, 4, 5, 7, 9, 11];
+
+    fn sharp_first(semitones: u8) -> Option<(u8, Accidental)> {
+        find_degree_and_accidental(semitones, MAJOR, false)
+    }
+
     #[test]
     fn test_degree_semitones() {
         // C major scale: C=0, D=2, E=4, F=5, G=7, A=9, B=11
-        assert_eq!(find_degree_and_accidental(0), Some((1, Accidental::Natural)));
-        assert_eq!(find_degree_and_accidental(2), Some((2, Accidental::Natural)));
-        assert_eq!(find_degree_and_accidental(4), Some((3, Accidental::Natural)));
-        assert_eq!(find_degree_and_accidental(5), Some((4, Accidental::Natural)));
-        assert_eq!(find_degree_and_accidental(7), Some((5, Accidental::Natural)));
-        assert_eq!(find_degree_and_accidental(9), Some((6, Accidental::Natural)));
-        assert_eq!(find_degree_and_accidental(11), Some((7, Accidental::Natural)));
+        assert_eq!(sharp_first(0), Some((1, Accidental::Natural)));
+        assert_eq!(sharp_first(2), Some((2, Accidental::Natural)));
+        assert_eq!(sharp_first(4), Some((3, Accidental::Natural)));
+        assert_eq!(sharp_first(5), Some((4, Accidental::Natural)));
+        assert_eq!(sharp_first(7), Some((5, Accidental::Natural)));
+        assert_eq!(sharp_first(9), Some((6, Accidental::Natural)));
+        assert_eq!(sharp_first(11), Some((7, Accidental::Natural)));
     }
 
     #[test]
     fn test_sharps() {
         // C# = 1, D# = 3, F# = 6, G# = 8, A# = 10
-        assert_eq!(find_degree_and_accidental(1), Some((1, Accidental::Sharp)));
-        assert_eq!(find_degree_and_accidental(3), Some((2, Accidental::Sharp)));
-        assert_eq!(find_degree_and_accidental(6), Some((4, Accidental::Sharp)));
-        assert_eq!(find_degree_and_accidental(8), Some((5, Accidental::Sharp)));
-        assert_eq!(find_degree_and_accidental(10), Some((6, Accidental::Sharp)));
+        assert_eq!(sharp_first(1), Some((1, Accidental::Sharp)));
+        assert_eq!(sharp_first(3), Some((2, Accidental::Sharp)));
+        assert_eq!(sharp_first(6), Some((4, Accidental::Sharp)));
+        assert_eq!(sharp_first(8), Some((5, Accidental::Sharp)));
+        assert_eq!(sharp_first(10), Some((6, Accidental::Sharp)));
+    }
+
+    #[test]
+    fn test_flats() {
+        assert_eq!(
+            find_degree_and_accidental(1, MAJOR, true),
+            Some((2, Accidental::Flat))
+        );
+
+        // B in C major pentatonic is the flat of the next C
+        let pentatonic = Scale::MajorPentatonic;
+        assert_eq!(
+            find_degree_and_accidental(11, pentatonic.intervals(), false),
+            Some((6, Accidental::Flat))
+        );
+
+        let mut config = AppConfig {
+            accidental_spelling: AccidentalSpelling::KeySignature,
+            ..AppConfig::default()
+        };
+        config.key_mapping.scale = pentatonic;
+        let note = midi_to_instrument(71, &config).unwrap();
+        assert_eq!(note.accidental, Accidental::Flat);
+        assert_eq!(key_pitch(note.key_index, &config) - 1, 71);
+
+        // F major has a flat in its signature
+        config.reference_midi_note = 65;
+        assert!(prefers_flats(config.accidental_spelling, &config));
+    }
+
+    #[test]
+    fn test_octave_keystrokes() {
+        let mut config = AppConfig::default();
+        let labels = |config: &AppConfig, octave| {
+            octave_keystrokes(octave, config)
+                .iter()
+                .map(|k| k.modifier.label(&k.key))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            labels(&config, 0),
+            [
+                "A", "Shift+A", "S", "Shift+S", "D", "F", "Shift+F", "G", "Shift+G", "H",
+                "Shift+H", "J"
+            ]
+        );
+
+        config.accidentals = false;
+        assert_eq!(labels(&config, 1), ["Q", "W", "E", "R", "T", "Y", "U"]);
+        assert!(labels(&config, 2).is_empty());
+    }
+
+    #[test]
+    fn test_accidental_fallback() {
+        let mut config = AppConfig {
+            accidentals: false,
+            ..AppConfig::default()
+        };
+        let key = |config: &AppConfig, note| {
+            let note = midi_to_instrument(note, config)?;
+            assert_eq!(note.accidental, Accidental::Natural);
+            Some(key_pitch(note.key_index, config))
+        };
+        assert_eq!(key(&config, 61), None);
+
+        // C#4 snaps to C4 when spelled as a sharp, D4 as a flat
+        config.accidental_fallback = AccidentalFallback::Nearest;
+        assert_eq!(key(&config, 61), Some(60));
+        config.accidental_spelling = AccidentalSpelling::Flats;
+        assert_eq!(key(&config, 61), Some(62));
+
+        // C major has no flats, whatever the spelling
+        config.accidental_fallback = AccidentalFallback::KeySignature;
+        assert_eq!(key(&config, 61), Some(60));
+    }
+
+    #[test]
+    fn test_default_layout_range() {
+        let config = AppConfig::default();
+        assert_eq!(playable_range(&config), (48, 83));
+
+        let low = midi_to_instrument(48, &config).unwrap();
+        assert_eq!(note_to_keystroke(&low, &config).unwrap().key, "Z");
+        let medium = midi_to_instrument(60, &config).unwrap();
+        assert_eq!(note_to_keystroke(&medium, &config).unwrap().key, "A");
+        let sharp = midi_to_instrument(78, &config).unwrap();
+        let keystroke = note_to_keystroke(&sharp, &config).unwrap();
+        assert_eq!(
+            (keystroke.key.as_str(), keystroke.modifier),
+            ("R", Modifier::Shift)
+        );
+
+        assert!(midi_to_instrument(47, &config).is_none());
+        assert!(midi_to_instrument(84, &config).is_none());
+    }
+
+    #[test]
+    fn test_keystroke_to_midi() {
+        let config = AppConfig::default();
+        assert_eq!(keystroke_to_midi("A", Modifier::None, &config), Some(60));
+        assert_eq!(keystroke_to_midi("R", Modifier::Shift, &config), Some(78));
+        assert_eq!(keystroke_to_midi("z", Modifier::Ctrl, &config), Some(47));
+        assert_eq!(keystroke_to_midi("F1", Modifier::None, &config), None);
+    }
+
+    #[test]
+    fn test_pentatonic_layout() {
+        let mut config = AppConfig::default();
+        config.key_mapping.scale = Scale::MajorPentatonic;
+        config.key_mapping.reference_index = 5;
+
+        // G4 is degree 4 of the pentatonic scale, 3 keys above the reference
+        let note = midi_to_instrument(67, &config).unwrap();
+        assert_eq!(note_to_keystroke(&note, &config).unwrap().key, "S");
+        // C5 starts the next octave, 5 keys above the reference
+        let note = midi_to_instrument(72, &config).unwrap();
+        assert_eq!(note_to_keystroke(&note, &config).unwrap().key, "F");
+    }
+
+    #[test]
+    fn test_chromatic_layout() {
+        let mut config = AppConfig::default();
+        config.key_mapping.mode = LayoutMode::Chromatic;
+        config.key_mapping.reference_index = 0;
+
+        // C#4 is the second key, with no modifier
+        let note = midi_to_instrument(61, &config).unwrap();
+        let keystroke = note_to_keystroke(&note, &config).unwrap();
+        assert_eq!(
+            (keystroke.key.as_str(), keystroke.modifier),
+            ("X", Modifier::None)
+        );
+
+        assert_eq!(playable_range(&config), (60, 80));
+        assert!(midi_to_instrument(59, &config).is_none());
+        assert!(midi_to_instrument(81, &config).is_none());
+    }
+
+    #[test]
+    fn test_drum_keys() {
+        let mut config = AppConfig::default();
+        config.key_mapping.mode = LayoutMode::Drums;
+        let drum = |note, channel| {
+            let event = NoteEvent {
+                channel,
+                ..crate::test_util::note(0, 100, note)
+            };
+            DrumMapper.map(&event, &config).map(|k| (k.key, k.modifier))
+        };
+
+        assert_eq!(drum(38, DRUM_CHANNEL), Some(("X".into(), Modifier::None)));
+        assert_eq!(drum(42, DRUM_CHANNEL), Some(("C".into(), Modifier::None)));
+        // Unbound percussion and melodic channels play nothing
+        assert_eq!(drum(39, DRUM_CHANNEL), None);
+        assert_eq!(drum(38, 0), None);
+        assert!(midi_to_instrument(60, &config).is_none());
     }
 }
@@ -1,8 +1,18 @@
 use anyhow::Result;
-use midly::{Smf, Timing, TrackEventKind, MidiMessage};
+use midly::{
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fs;
 
+use crate::config::{NotePairing, Reduction};
+use crate::mml;
+use crate::parse_cache;
+use crate::tempo::TempoMap;
+
 /// Information about a loaded MIDI file
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MidiInfo {
@@ -11,55 +21,490 @@ pub struct MidiInfo {
     pub note_count: usize,
     pub min_note: u8,
     pub max_note: u8,
+    /// TrackName meta events, in track order
+    pub track_names: Vec<String>,
+    /// Marker and CuePoint meta events, in song order
+    pub markers: Vec<Marker>,
+    /// Problems the file loaded in spite of
+    #[serde(default)]
+    pub warnings: Vec<LoadWarning>,
+}
+
+/// Something wrong with a file that didn't stop it loading
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LoadWarning {
+    /// A track that breaks off before its end; what came before the
+    /// damage still plays
+    DamagedTrack { track: usize },
+    /// The header promised more tracks than the file holds
+    MissingTracks { expected: usize, found: usize },
+    /// A track with nothing in it but perhaps a name
+    EmptyTrack { track: usize },
+    /// Notes whose note-off came at the same moment as the note-on
+    ZeroLengthNotes { track: usize, count: usize },
+    /// Notes never turned off, held to the end of their track
+    UnclosedNotes { track: usize, count: usize },
+    /// Notes the current transpose puts outside MIDI's 0-127
+    OutOfRangeNotes { count: usize, transpose: i32 },
+}
+
+/// Warning for the notes `transpose` pushes outside 0-127, if any
+pub fn transpose_warning(events: &[NoteEvent], transpose: i32) -> Option<LoadWarning> {
+    let count = events
+        .iter()
+        .filter(|e| !(0..=127).contains(&(e.note as i32 + transpose)))
+        .count();
+    (count > 0).then_some(LoadWarning::OutOfRangeNotes { count, transpose })
+}
+
+/// How far loading a song has got
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadProgress {
+    pub path: String,
+    /// Fraction of the song parsed, 0 to 1
+    pub fraction: f64,
+}
+
+/// A named point in the song, from a Marker or CuePoint meta event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    pub name: String,
+    pub time_ms: u64,
 }
 
 /// A single note event with timing
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NoteEvent {
     pub start_ms: u64,
     pub duration_ms: u64,
     pub note: u8,
     pub velocity: u8,
+    /// Index of the track this note came from
+    pub track: usize,
+    /// MIDI channel (0-15; 9 is General MIDI percussion)
+    pub channel: u8,
+}
+
+/// A metronome beat in song time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Beat {
+    pub time_ms: u64,
+    /// Bar number, from 1
+    pub bar: u32,
+    /// Beat within the bar, from 1 (the downbeat)
+    pub beat: u8,
+    /// Time signature of the bar, e.g. (6, 8)
+    pub time_signature: (u8, u8),
+}
+
+/// A note-on still waiting for its note-off
+#[derive(Debug, Clone, Copy)]
+struct PendingNote {
+    note: u8,
+    channel: u8,
+    start_ms: u64,
+    velocity: u8,
+}
+
+/// A General MIDI program change on one track and channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramChange {
+    pub time_ms: u64,
+    pub track: usize,
+    pub channel: u8,
+    pub program: u8,
+}
+
+/// The program sounding on `track` and `channel` at `time_ms`, from
+/// `changes` in time order; 0 (piano) before any change
+pub fn program_at(changes: &[ProgramChange], track: usize, channel: u8, time_ms: u64) -> u8 {
+    changes
+        .iter()
+        .take_while(|change| change.time_ms <= time_ms)
+        .filter(|change| change.track == track && change.channel == channel)
+        .last()
+        .map_or(0, |change| change.program)
 }
 
 /// Represents a loaded and processed MIDI file
 #[derive(Debug)]
 pub struct MidiFile {
+    pub path: String,
+    /// SHA-256 of the file contents, used to key per-song data
+    pub hash: String,
     pub info: MidiInfo,
     pub events: Vec<NoteEvent>,
+    /// Length of one bar at the opening tempo and time signature
+    pub bar_ms: u64,
+    /// Opening time signature as (beats per bar, beat unit), e.g. (3, 4)
+    pub time_signature: (u8, u8),
+    /// Every beat of the song, following tempo and time signature changes
+    pub beats: Vec<Beat>,
+    /// Tracks excluded from playback
+    pub disabled_tracks: Vec<usize>,
+    /// Points named by the user, sorted by time
+    pub bookmarks: Vec<Marker>,
+    /// Every program change in the song, in time order; see `program_at`
+    pub programs: Vec<ProgramChange>,
 }
 
 impl MidiFile {
-    pub fn info(&self) -> MidiInfo {
-        self.info.clone()
+    /// The song's info, warning of any notes `transpose` pushes outside
+    /// the MIDI range
+    pub fn info(&self, transpose: i32) -> MidiInfo {
+        let mut info = self.info.clone();
+        let range_warning = transpose_warning(&self.events, transpose);
+        info.warnings.extend(range_warning);
+        info
+    }
+
+    /// Note events from enabled tracks only
+    pub fn enabled_events(&self) -> Vec<NoteEvent> {
+        self.events
+            .iter()
+            .filter(|e| !self.disabled_tracks.contains(&e.track))
+            .cloned()
+            .collect()
+    }
+
+    /// The beat the song is on at `position_ms`, or None before the first
+    pub fn beat_at(&self, position_ms: u64) -> Option<&Beat> {
+        let next = self.beats.partition_point(|b| b.time_ms <= position_ms);
+        next.checked_sub(1).map(|i| &self.beats[i])
+    }
+
+    /// Bars `first` to `last` inclusive, as (start, end) ms: from the
+    /// downbeat of `first` to the next bar's downbeat or the end of the
+    /// song. None if the song has no bar `first`.
+    pub fn bar_section(&self, first: u32, last: u32) -> Option<(u64, u64)> {
+        if last < first {
+            return None;
+        }
+        let downbeat = |bar| {
+            self.beats
+                .iter()
+                .find(|b| b.bar == bar && b.beat == 1)
+                .map(|b| b.time_ms)
+        };
+        let start = downbeat(first)?;
+        let end = downbeat(last + 1).unwrap_or(self.info.duration_ms);
+        Some((start, end))
     }
+
+    /// Song time (ms) of a position counted in quarter notes from the
+    /// start, as MIDI clock and song position pointers count it
+    pub fn quarter_ms(&self, quarters: f64) -> u64 {
+        let mut counted = 0.0;
+        for (i, beat) in self.beats.iter().enumerate() {
+            let length = beat_quarters(beat);
+            if quarters < counted + length || i + 1 == self.beats.len() {
+                let into = (quarters - counted) / length * beat_span_ms(&self.beats, i);
+                return (beat.time_ms as f64 + into).max(0.0) as u64;
+            }
+            counted += length;
+        }
+        (quarters * DEFAULT_QUARTER_MS) as u64
+    }
+
+    /// Length of a quarter note (ms) at song position `position_ms`
+    pub fn quarter_length_ms(&self, position_ms: u64) -> f64 {
+        if self.beats.is_empty() {
+            return DEFAULT_QUARTER_MS;
+        }
+        let i = self
+            .beats
+            .partition_point(|b| b.time_ms <= position_ms)
+            .saturating_sub(1);
+        beat_span_ms(&self.beats, i) / beat_quarters(&self.beats[i])
+    }
+
+    /// The first marker called `name`, ignoring case
+    pub fn marker(&self, name: &str) -> Option<&Marker> {
+        find_marker(&self.info.markers, name)
+    }
+
+    /// The section a marker starts, as (start, end) ms: up to the next
+    /// marker later in the song, or the end of the song
+    pub fn marker_section(&self, name: &str) -> Option<(u64, u64)> {
+        marker_section(&self.info.markers, name, self.info.duration_ms)
+    }
+
+    /// The first bookmark called `name`, ignoring case
+    pub fn bookmark(&self, name: &str) -> Option<&Marker> {
+        find_marker(&self.bookmarks, name)
+    }
+
+    /// The section a bookmark starts, up to the next bookmark
+    pub fn bookmark_section(&self, name: &str) -> Option<(u64, u64)> {
+        marker_section(&self.bookmarks, name, self.info.duration_ms)
+    }
+
+    /// Add a bookmark, replacing any other of the same name
+    pub fn set_bookmark(&mut self, name: &str, time_ms: u64) {
+        self.remove_bookmark(name);
+        self.bookmarks.push(Marker {
+            name: name.trim().to_string(),
+            time_ms,
+        });
+        self.bookmarks.sort_by_key(|b| b.time_ms);
+    }
+
+    /// Remove a bookmark; returns whether there was one
+    pub fn remove_bookmark(&mut self, name: &str) -> bool {
+        let before = self.bookmarks.len();
+        self.bookmarks
+            .retain(|b| !b.name.eq_ignore_ascii_case(name.trim()));
+        self.bookmarks.len() != before
+    }
+}
+
+/// Quarter note at 120 BPM, for songs without a beat grid (ms)
+const DEFAULT_QUARTER_MS: f64 = 500.0;
+
+/// Length of a beat in quarter notes, e.g. 0.5 for an eighth in 6/8
+pub fn beat_quarters(beat: &Beat) -> f64 {
+    4.0 / beat.time_signature.1.max(1) as f64
 }
 
-/// Load and parse a MIDI file
-pub fn load_file(path: &str) -> Result<MidiFile> {
+/// Length of `beats[i]` (ms), up to the next beat; the last beat is as
+/// long as the one before it
+pub fn beat_span_ms(beats: &[Beat], i: usize) -> f64 {
+    let beat = &beats[i];
+    match (beats.get(i + 1), i.checked_sub(1)) {
+        (Some(next), _) => (next.time_ms - beat.time_ms) as f64,
+        (None, Some(previous)) => (beat.time_ms - beats[previous].time_ms) as f64,
+        (None, None) => DEFAULT_QUARTER_MS * beat_quarters(beat),
+    }
+}
+
+fn find_marker<'a>(markers: &'a [Marker], name: &str) -> Option<&'a Marker> {
+    markers
+        .iter()
+        .find(|m| m.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// From the marker called `name` to the next later one in `markers`
+/// (sorted by time), or `end_ms`
+fn marker_section(markers: &[Marker], name: &str, end_ms: u64) -> Option<(u64, u64)> {
+    let start = find_marker(markers, name)?.time_ms;
+    let end = markers
+        .iter()
+        .map(|m| m.time_ms)
+        .find(|&t| t > start)
+        .unwrap_or(end_ms);
+    Some((start, end))
+}
+
+/// Hex-encoded SHA-256 of raw file data
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Load and parse a MIDI or MML file, ending overlapping notes of one pitch
+/// as `pairing` says
+pub fn load_file(path: &str, pairing: NotePairing) -> Result<MidiFile> {
+    load_file_with_progress(path, pairing, |_| {})
+}
+
+/// `load_file`, calling `progress` with the fraction of the song parsed
+/// so far, in steps of about a percent
+pub fn load_file_with_progress(
+    path: &str,
+    pairing: NotePairing,
+    mut progress: impl FnMut(f64),
+) -> Result<MidiFile> {
     let data = fs::read(path)?;
-    let smf = Smf::parse(&data)?;
+    let hash = content_hash(&data);
+    if let Some(midi) = parse_cache::get(path, &hash, pairing) {
+        progress(1.0);
+        return Ok(midi);
+    }
+
+    let midi = parse_data(path, &data, hash, pairing, &mut progress)?;
+    // A cache that can't be written only costs the next load some time
+    let _ = parse_cache::put(&midi, pairing);
+    Ok(midi)
+}
 
-    let ticks_per_beat = match smf.header.timing {
-        Timing::Metrical(tpb) => tpb.as_int() as u32,
-        Timing::Timecode(fps, sub) => (fps.as_f32() * sub as f32) as u32,
+/// `load_file` without the parse cache, for looking over many files at
+/// once, e.g. a library scan, without pushing the songs actually opened
+/// out of the cache
+pub fn load_file_uncached(path: &str, pairing: NotePairing) -> Result<MidiFile> {
+    let data = fs::read(path)?;
+    let hash = content_hash(&data);
+    parse_data(path, &data, hash, pairing, &mut |_| {})
+}
+
+fn parse_data(
+    path: &str,
+    data: &[u8],
+    hash: String,
+    pairing: NotePairing,
+    progress: &mut dyn FnMut(f64),
+) -> Result<MidiFile> {
+    let mut midi = if has_extension(path, "mml") {
+        // MML plays by way of an equivalent MIDI file, keyed by the MML text
+        let smf = mml::to_smf(&String::from_utf8_lossy(data))?;
+        parse_with_progress(path, &smf, pairing, progress)?
+    } else {
+        parse_with_progress(path, data, pairing, progress)?
     };
+    midi.hash = hash;
+    Ok(midi)
+}
+
+/// File extensions `load_file` can read
+const SONG_EXTENSIONS: &[&str] = &["mid", "midi", "mml"];
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
 
-    // Build tempo map (microseconds per beat at each tick)
-    let tempo_map = build_tempo_map(&smf);
+/// Whether `load_file` can read the file at `path`
+pub fn is_song_file(path: &str) -> bool {
+    SONG_EXTENSIONS.iter().any(|ext| has_extension(path, ext))
+}
+
+/// Read a file's tracks one at a time, so damage to one track costs only
+/// the rest of that track rather than the whole file. Only an unreadable
+/// header is an error.
+fn read_tracks(data: &[u8]) -> Result<(Header, Vec<Track<'_>>, Vec<LoadWarning>)> {
+    let (header, track_iter) = midly::parse(data)?;
+    let expected = track_iter.size_hint().0;
+    let mut tracks = Vec::new();
+    let mut warnings = Vec::new();
+    for (index, events) in track_iter.enumerate() {
+        let (track, whole) = match events {
+            Ok(events) => read_track(events),
+            Err(_) => (Vec::new(), false),
+        };
+        if !whole {
+            warnings.push(LoadWarning::DamagedTrack { track: index });
+        }
+        tracks.push(track);
+    }
+    if tracks.len() < expected {
+        warnings.push(LoadWarning::MissingTracks {
+            expected,
+            found: tracks.len(),
+        });
+    }
+    Ok((header, tracks, warnings))
+}
+
+/// The events of a track up to any damage, and whether it was read to
+/// the end. midly stops quietly at an event it can't read, which shows
+/// as the events running out before the bytes do.
+fn read_track(mut events: midly::EventIter) -> (Track, bool) {
+    let mut track = Vec::new();
+    loop {
+        let unread = events.unread().len();
+        match events.next() {
+            Some(Ok(event)) => track.push(event),
+            Some(Err(_)) => return (track, false),
+            None => return (track, unread == 0),
+        }
+    }
+}
+
+/// Parse the contents of the MIDI file at `path`
+fn parse_file(path: &str, data: &[u8], pairing: NotePairing) -> Result<MidiFile> {
+    parse_with_progress(path, data, pairing, &mut |_| {})
+}
+
+fn parse_with_progress(
+    path: &str,
+    data: &[u8],
+    pairing: NotePairing,
+    progress: &mut dyn FnMut(f64),
+) -> Result<MidiFile> {
+    let (header, tracks, mut warnings) = read_tracks(data)?;
+    let event_count: usize = tracks.iter().map(Vec::len).sum();
+    let report_every = (event_count / 100).max(1);
+    let mut parsed = 0;
+
+    // Ticks are u64 throughout: a track's deltas can add up past u32
+    // in very long or high-resolution files
+    let ticks_per_beat = match header.timing {
+        Timing::Metrical(tpb) => tpb.as_int() as u64,
+        Timing::Timecode(fps, sub) => (fps.as_f32() * sub as f32) as u64,
+    };
+
+    // Format 2 files hold independent sequences, each with its own
+    // tempo, which play one after another with a marker at each start.
+    // Other files share one timeline and tempo map across tracks.
+    let sequential = header.format == Format::Sequential;
+
+    let shared_tempo_map = TempoMap::from_tracks(&tracks, ticks_per_beat);
 
     // Extract all note events
     let mut events = Vec::new();
-    let mut pending_notes: Vec<(u8, u64, u8)> = Vec::new(); // (note, start_ms, velocity)
+    let mut pending_notes: Vec<PendingNote> = Vec::new();
+    let mut track_names = Vec::new();
+    let mut markers = Vec::new();
+    let mut sequence_beats = Vec::new();
+    let mut programs = Vec::new();
+    let mut offset_ms = 0;
+
+    for (track_index, track) in tracks.iter().enumerate() {
+        let own_tempo_map;
+        let tempo_map = if sequential {
+            own_tempo_map = TempoMap::from_tracks(std::slice::from_ref(track), ticks_per_beat);
+            markers.push(Marker {
+                name: format!("Sequence {}", track_index + 1),
+                time_ms: offset_ms,
+            });
+            &own_tempo_map
+        } else {
+            &shared_tempo_map
+        };
+        let mut current_tick: u64 = 0;
 
-    for track in &smf.tracks {
-        let mut current_tick: u32 = 0;
+        let empty = track.iter().all(|event| {
+            matches!(
+                event.kind,
+                TrackEventKind::Meta(MetaMessage::TrackName(_) | MetaMessage::EndOfTrack)
+            )
+        });
+        if empty {
+            warnings.push(LoadWarning::EmptyTrack { track: track_index });
+        }
 
         for event in track {
-            current_tick += event.delta.as_int();
-            let current_ms = ticks_to_ms(current_tick, ticks_per_beat, &tempo_map);
+            parsed += 1;
+            if parsed % report_every == 0 {
+                progress(parsed as f64 / event_count as f64);
+            }
+            current_tick += event.delta.as_int() as u64;
+            let current_ms = offset_ms + tempo_map.ticks_to_ms(current_tick);
+
+            if let TrackEventKind::Meta(midly::MetaMessage::TrackName(name)) = event.kind {
+                let name = String::from_utf8_lossy(name).trim().to_string();
+                if !name.is_empty() {
+                    track_names.push(name);
+                }
+            }
 
-            if let TrackEventKind::Midi { message, .. } = event.kind {
+            if let TrackEventKind::Meta(MetaMessage::Marker(name) | MetaMessage::CuePoint(name)) =
+                event.kind
+            {
+                let name = String::from_utf8_lossy(name).trim().to_string();
+                if !name.is_empty() {
+                    markers.push(Marker {
+                        name,
+                        time_ms: current_ms,
+                    });
+                }
+            }
+
+            if let TrackEventKind::Midi { channel, message } = event.kind {
+                let channel = channel.as_int();
                 match message {
                     MidiMessage::NoteOn { key, vel } => {
                         let note = key.as_int();
@@ -67,15 +512,42 @@ pub fn load_file(path: &str) -> Result<MidiFile> {
 
                         if velocity > 0 {
                             // Note on
-                            pending_notes.push((note, current_ms, velocity));
+                            pending_notes.push(PendingNote {
+                                note,
+                                channel,
+                                start_ms: current_ms,
+                                velocity,
+                            });
                         } else {
                             // Note off (velocity 0)
-                            finish_note(&mut pending_notes, &mut events, note, current_ms);
+                            finish_note(
+                                &mut pending_notes,
+                                &mut events,
+                                (channel, note),
+                                current_ms,
+                                track_index,
+                                pairing,
+                            );
                         }
                     }
                     MidiMessage::NoteOff { key, .. } => {
                         let note = key.as_int();
-                        finish_note(&mut pending_notes, &mut events, note, current_ms);
+                        finish_note(
+                            &mut pending_notes,
+                            &mut events,
+                            (channel, note),
+                            current_ms,
+                            track_index,
+                            pairing,
+                        );
+                    }
+                    MidiMessage::ProgramChange { program } => {
+                        programs.push(ProgramChange {
+                            time_ms: current_ms,
+                            track: track_index,
+                            channel,
+                            program: program.as_int(),
+                        });
                     }
                     _ => {}
                 }
@@ -83,19 +555,58 @@ pub fn load_file(path: &str) -> Result<MidiFile> {
         }
 
         // Close any remaining pending notes at track end
-        let track_end_ms = ticks_to_ms(current_tick, ticks_per_beat, &tempo_map);
-        for (note, start_ms, velocity) in pending_notes.drain(..) {
+        let track_end_ms = offset_ms + tempo_map.ticks_to_ms(current_tick);
+        if !pending_notes.is_empty() {
+            warnings.push(LoadWarning::UnclosedNotes {
+                track: track_index,
+                count: pending_notes.len(),
+            });
+        }
+        for pending in pending_notes.drain(..) {
             events.push(NoteEvent {
-                start_ms,
-                duration_ms: track_end_ms.saturating_sub(start_ms),
-                note,
-                velocity,
+                start_ms: pending.start_ms,
+                duration_ms: track_end_ms.saturating_sub(pending.start_ms),
+                note: pending.note,
+                velocity: pending.velocity,
+                track: track_index,
+                channel: pending.channel,
             });
         }
+
+        // The next sequence starts where this one ends, bars continuing
+        if sequential {
+            let tracks = std::slice::from_ref(track);
+            let bars = sequence_beats.last().map_or(0, |b: &Beat| b.bar);
+            // A beat closing the last sequence is this one's downbeat
+            if sequence_beats
+                .last()
+                .is_some_and(|b| b.time_ms == offset_ms)
+            {
+                sequence_beats.pop();
+            }
+            let beats = beat_grid(tracks, tempo_map, track_end_ms - offset_ms);
+            sequence_beats.extend(beats.into_iter().map(|b| Beat {
+                time_ms: offset_ms + b.time_ms,
+                bar: bars + b.bar,
+                ..b
+            }));
+            offset_ms = track_end_ms;
+        }
+    }
+
+    let mut zero_length = vec![0; tracks.len()];
+    for event in events.iter().filter(|e| e.duration_ms == 0) {
+        zero_length[event.track] += 1;
+    }
+    for (track, count) in zero_length.into_iter().enumerate() {
+        if count > 0 {
+            warnings.push(LoadWarning::ZeroLengthNotes { track, count });
+        }
     }
 
     // Sort by start time
     events.sort_by_key(|e| e.start_ms);
+    markers.sort_by_key(|m| m.time_ms);
 
     // Calculate stats
     let duration_ms = events.iter().map(|e| e.start_ms + e.duration_ms).max().unwrap_or(0);
@@ -103,81 +614,312 @@ pub fn load_file(path: &str) -> Result<MidiFile> {
     let max_note = events.iter().map(|e| e.note).max().unwrap_or(127);
 
     let info = MidiInfo {
-        track_count: smf.tracks.len(),
+        track_count: tracks.len(),
         duration_ms,
         note_count: events.len(),
         min_note,
         max_note,
+        track_names,
+        markers,
+        warnings,
+    };
+
+    let (bar_ms, time_signature, beats) = if sequential {
+        let first = &tracks[..tracks.len().min(1)];
+        let tempo_map = TempoMap::from_tracks(first, ticks_per_beat);
+        let (bar_ms, time_signature) = opening_meter(first, &tempo_map);
+        (bar_ms, time_signature, sequence_beats)
+    } else {
+        let (bar_ms, time_signature) = opening_meter(&tracks, &shared_tempo_map);
+        let beats = beat_grid(&tracks, &shared_tempo_map, duration_ms);
+        (bar_ms, time_signature, beats)
     };
 
-    Ok(MidiFile { info, events })
+    Ok(MidiFile {
+        path: path.to_string(),
+        hash: content_hash(data),
+        info,
+        events,
+        bar_ms,
+        time_signature,
+        beats,
+        disabled_tracks: Vec::new(),
+        bookmarks: Vec::new(),
+        programs,
+    })
 }
 
+/// End the pending note of `channel` and `note` that `pairing` picks
 fn finish_note(
-    pending: &mut Vec<(u8, u64, u8)>,
+    pending: &mut Vec<PendingNote>,
     events: &mut Vec<NoteEvent>,
-    note: u8,
+    (channel, note): (u8, u8),
     end_ms: u64,
+    track: usize,
+    pairing: NotePairing,
 ) {
-    if let Some(idx) = pending.iter().position(|(n, _, _)| *n == note) {
-        let (note, start_ms, velocity) = pending.remove(idx);
+    let mut matching = pending
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.channel == channel && p.note == note)
+        .map(|(i, _)| i);
+    let found = match pairing {
+        NotePairing::Fifo => matching.next(),
+        NotePairing::Lifo => matching.next_back(),
+    };
+    if let Some(idx) = found {
+        let pending = pending.remove(idx);
         events.push(NoteEvent {
-            start_ms,
-            duration_ms: end_ms.saturating_sub(start_ms),
+            start_ms: pending.start_ms,
+            duration_ms: end_ms.saturating_sub(pending.start_ms),
             note,
-            velocity,
+            velocity: pending.velocity,
+            track,
+            channel: pending.channel,
         });
     }
 }
 
-/// Build a tempo map: Vec of (tick, microseconds_per_beat)
-fn build_tempo_map(smf: &Smf) -> Vec<(u32, u32)> {
-    let mut tempo_map = vec![(0u32, 500_000u32)]; // Default: 120 BPM
+/// Bar length and time signature at the start of the song, from the first
+/// time signature (4/4 if none) and the tempo in effect at tick 0
+fn opening_meter(tracks: &[Track], tempo_map: &TempoMap) -> (u64, (u8, u8)) {
+    let tempo = tempo_map.tempo_at(0);
+
+    let (numerator, denominator_pow) = tracks
+        .iter()
+        .flatten()
+        .find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::TimeSignature(num, pow, _, _)) => Some((num, pow)),
+            _ => None,
+        })
+        .filter(|&(num, pow)| num > 0 && pow <= 6)
+        .unwrap_or((4, 2));
 
-    for track in &smf.tracks {
-        let mut current_tick: u32 = 0;
+    // Tempo is per quarter note; the denominator sets the beat length
+    let beat_us = (tempo as u64 * 4) >> denominator_pow;
+    let bar_ms = (beat_us * numerator as u64 / 1000).max(1);
+    (bar_ms, (numerator, 1 << denominator_pow))
+}
 
+/// Every beat from the start of the song to `end_ms`. A time signature
+/// change always starts a new bar, even if the old one was incomplete.
+fn beat_grid(tracks: &[Track], tempo_map: &TempoMap, end_ms: u64) -> Vec<Beat> {
+    // (tick, beats per bar, power of two of the beat unit)
+    let mut meters: Vec<(u64, u8, u8)> = Vec::new();
+    for track in tracks {
+        let mut current_tick: u64 = 0;
         for event in track {
-            current_tick += event.delta.as_int();
+            current_tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Meta(MetaMessage::TimeSignature(num, pow, _, _)) = event.kind {
+                if num > 0 && pow <= 6 {
+                    meters.push((current_tick, num, pow));
+                }
+            }
+        }
+    }
+    meters.sort_by_key(|&(tick, _, _)| tick);
 
-            if let TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) = event.kind {
-                tempo_map.push((current_tick, tempo.as_int()));
+    let mut beats = Vec::new();
+    let (mut numerator, mut denominator_pow) = (4, 2);
+    let mut next_meter = 0;
+    let (mut tick, mut bar, mut beat) = (0u64, 1u32, 1u8);
+    loop {
+        while let Some(&(change, num, pow)) = meters.get(next_meter) {
+            if change > tick {
+                break;
+            }
+            numerator = num;
+            denominator_pow = pow;
+            next_meter += 1;
+            if beat != 1 {
+                bar += 1;
+                beat = 1;
             }
         }
+
+        let time_ms = tempo_map.ticks_to_ms(tick);
+        if time_ms > end_ms {
+            break;
+        }
+        beats.push(Beat {
+            time_ms,
+            bar,
+            beat,
+            time_signature: (numerator, 1 << denominator_pow),
+        });
+
+        // Tempo is per quarter note; the denominator sets the beat length
+        tick += ((tempo_map.ticks_per_beat() * 4) >> denominator_pow).max(1);
+        if let Some(&(change, _, _)) = meters.get(next_meter) {
+            tick = tick.min(change);
+        }
+        beat += 1;
+        if beat > numerator {
+            bar += 1;
+            beat = 1;
+        }
     }
+    beats
+}
+
+/// Ticks per beat in files we write; at a fixed 120 BPM this is just
+/// under one tick per millisecond
+const SAVE_TICKS_PER_BEAT: u16 = 480;
 
-    tempo_map.sort_by_key(|(tick, _)| *tick);
-    tempo_map
+/// Write notes to a single-track MIDI file at 120 BPM, keeping each
+/// note's channel
+pub fn save_file(path: &str, notes: &[NoteEvent]) -> Result<()> {
+    fs::write(path, notes_to_smf(notes)?)?;
+    Ok(())
 }
 
-/// Convert ticks to milliseconds using the tempo map
-fn ticks_to_ms(tick: u32, ticks_per_beat: u32, tempo_map: &[(u32, u32)]) -> u64 {
-    let mut ms: f64 = 0.0;
-    let mut prev_tick: u32 = 0;
-    let mut current_tempo: u32 = 500_000; // Default 120 BPM
+/// Notes as the contents of a single-track MIDI file at 120 BPM
+pub fn notes_to_smf(notes: &[NoteEvent]) -> Result<Vec<u8>> {
+    let to_ticks = |ms: u64| ms * SAVE_TICKS_PER_BEAT as u64 / 500;
 
-    for &(tempo_tick, tempo) in tempo_map {
-        if tempo_tick >= tick {
-            break;
+    let mut events = vec![(0, TrackEventKind::Meta(MetaMessage::Tempo(500_000.into())))];
+    for note in notes {
+        let midi = |message| TrackEventKind::Midi {
+            channel: note.channel.into(),
+            message,
+        };
+        let key = note.note.into();
+        let start = to_ticks(note.start_ms);
+        events.push((
+            start,
+            midi(MidiMessage::NoteOn {
+                key,
+                vel: note.velocity.max(1).into(),
+            }),
+        ));
+        // Even the shortest note lasts a tick, so it can be heard
+        events.push((
+            to_ticks(note.start_ms + note.duration_ms).max(start + 1),
+            midi(MidiMessage::NoteOff { key, vel: 0.into() }),
+        ));
+    }
+    encode_smf(SAVE_TICKS_PER_BEAT, vec![events])
+}
+
+/// A standard MIDI file of `tracks`, each a list of (tick, event) in any
+/// order, written as `in_play_order` sorts them
+pub fn encode_smf(
+    ticks_per_beat: u16,
+    tracks: Vec<Vec<(u64, TrackEventKind<'static>)>>,
+) -> Result<Vec<u8>> {
+    let format = if tracks.len() == 1 {
+        Format::SingleTrack
+    } else {
+        Format::Parallel
+    };
+    let tracks = tracks
+        .into_iter()
+        .map(|events| {
+            let mut last_tick = 0;
+            let mut track: Track = in_play_order(events)
+                .into_iter()
+                .map(|(tick, kind)| {
+                    let delta = (tick - last_tick) as u32;
+                    last_tick = tick;
+                    TrackEvent {
+                        delta: delta.into(),
+                        kind,
+                    }
+                })
+                .collect();
+            track.push(TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            });
+            track
+        })
+        .collect();
+
+    let smf = Smf {
+        header: Header::new(format, Timing::Metrical(ticks_per_beat.into())),
+        tracks,
+    };
+    let mut data = Vec::new();
+    smf.write_std(&mut data)?;
+    Ok(data)
+}
+
+/// `events` sorted by tick. At the same tick note-offs come before
+/// note-ons, so a repeated note re-strikes, except the off of a note that
+/// starts at that tick: it follows its on, so the note doesn't hang.
+fn in_play_order(
+    mut events: Vec<(u64, TrackEventKind<'static>)>,
+) -> Vec<(u64, TrackEventKind<'static>)> {
+    events.sort_by_key(|(tick, kind)| (*tick, is_note_on(kind)));
+    let mut sounding: HashMap<(u8, u8), usize> = HashMap::new();
+    let mut ordered = Vec::with_capacity(events.len());
+    let mut waiting = Vec::new();
+    let mut events = events.into_iter().peekable();
+    while let Some((tick, kind)) = events.next() {
+        match note_of(&kind) {
+            Some((note, true)) => *sounding.entry(note).or_default() += 1,
+            Some((note, false)) => match sounding.get_mut(&note).filter(|count| **count > 0) {
+                Some(count) => *count -= 1,
+                // Not on yet: its on comes later at this tick
+                None => {
+                    waiting.push((tick, kind));
+                    continue;
+                }
+            },
+            None => {}
         }
+        ordered.push((tick, kind));
 
-        // Add time for ticks in previous tempo region
-        let delta_ticks = tempo_tick.saturating_sub(prev_tick);
-        ms += (delta_ticks as f64 * current_tempo as f64) / (ticks_per_beat as f64 * 1000.0);
+        if events.peek().is_none_or(|(next, _)| *next != tick) {
+            for (tick, kind) in waiting.drain(..) {
+                let count = note_of(&kind).and_then(|(note, _)| sounding.get_mut(&note));
+                if let Some(count) = count {
+                    *count = count.saturating_sub(1);
+                }
+                ordered.push((tick, kind));
+            }
+        }
+    }
+    ordered
+}
 
-        prev_tick = tempo_tick;
-        current_tempo = tempo;
+/// The (channel, key) a note-on or note-off is for, and whether it's an on
+fn note_of(kind: &TrackEventKind) -> Option<((u8, u8), bool)> {
+    match *kind {
+        TrackEventKind::Midi { channel, message } => match message {
+            MidiMessage::NoteOn { key, vel } => Some(((channel.as_int(), key.as_int()), vel > 0)),
+            MidiMessage::NoteOff { key, .. } => Some(((channel.as_int(), key.as_int()), false)),
+            _ => None,
+        },
+        _ => None,
     }
+}
 
-    // Add remaining ticks at current tempo
-    let delta_ticks = tick.saturating_sub(prev_tick);
-    ms += (delta_ticks as f64 * current_tempo as f64) / (ticks_per_beat as f64 * 1000.0);
+fn is_note_on(kind: &TrackEventKind) -> bool {
+    note_of(kind).is_some_and(|(_, on)| on)
+}
 
-    ms as u64
+/// Keep the top and bottom voices of a chord sorted highest first, then
+/// the loudest inner notes up to `max_notes`
+fn reduce_voicing(mut group: Vec<NoteEvent>, max_notes: usize) -> Vec<NoteEvent> {
+    let bass = group.pop();
+    let mut kept = vec![group.remove(0)];
+    group.sort_by_key(|e| Reverse(e.velocity));
+    kept.extend(group.into_iter().take(max_notes.saturating_sub(2)));
+    if max_notes >= 2 {
+        kept.extend(bass);
+    }
+    kept.sort_by_key(|e| Reverse(e.note));
+    kept
 }
 
 /// Apply polyphony limit to events at similar timestamps
-pub fn limit_polyphony(events: &mut Vec<NoteEvent>, max_notes: usize, tolerance_ms: u64) {
+pub fn limit_polyphony(
+    events: &mut Vec<NoteEvent>,
+    max_notes: usize,
+    tolerance_ms: u64,
+    reduction: Reduction,
+) {
     if max_notes == 0 || events.is_empty() {
         return;
     }
@@ -195,13 +937,17 @@ pub fn limit_polyphony(events: &mut Vec<NoteEvent>, max_notes: usize, tolerance_
             group_end += 1;
         }
 
-        // If group exceeds max polyphony, keep only highest notes
         let group_size = group_end - i + 1;
         if group_size > max_notes {
-            // Sort group by note (descending) and keep top N
             let mut group: Vec<_> = events[i..=group_end].to_vec();
-            group.sort_by(|a, b| b.note.cmp(&a.note));
-            group.truncate(max_notes);
+            group.sort_by_key(|e| Reverse(e.note));
+            let group = match reduction {
+                Reduction::Highest => {
+                    group.truncate(max_notes);
+                    group
+                }
+                Reduction::Voicing => reduce_voicing(group, max_notes),
+            };
 
             // Replace in events
             events.splice(i..=group_end, group);
@@ -211,3 +957,414 @@ pub fn limit_polyphony(events: &mut Vec<NoteEvent>, max_notes: usize, tolerance_
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::note;
+
+    #[test]
+    fn test_voicing_reduction() {
+        let chord = || {
+            [(48, 60), (60, 40), (64, 90), (67, 50), (72, 70)]
+                .map(|(n, velocity)| NoteEvent {
+                    velocity,
+                    ..note(0, 500, n)
+                })
+                .to_vec()
+        };
+        let notes = |events: Vec<NoteEvent>| events.iter().map(|e| e.note).collect::<Vec<_>>();
+
+        let mut highest = chord();
+        limit_polyphony(&mut highest, 3, 10, Reduction::Highest);
+        assert_eq!(notes(highest), [72, 67, 64]);
+
+        // Melody and bass stay; the loudest inner note fills in
+        let mut voicing = chord();
+        limit_polyphony(&mut voicing, 3, 10, Reduction::Voicing);
+        assert_eq!(notes(voicing), [72, 64, 48]);
+
+        let mut single = chord();
+        limit_polyphony(&mut single, 1, 10, Reduction::Voicing);
+        assert_eq!(notes(single), [72]);
+    }
+
+    /// A note-on or note-off `delta` ticks after the previous event
+    fn key_event(delta: u32, channel: u8, key: u8, on: bool) -> TrackEvent<'static> {
+        let vel: u8 = if on { 100 } else { 0 };
+        let (key, vel) = (key.into(), vel.into());
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: channel.into(),
+                message: if on {
+                    MidiMessage::NoteOn { key, vel }
+                } else {
+                    MidiMessage::NoteOff { key, vel }
+                },
+            },
+        }
+    }
+
+    /// A file of `tracks`, at 120 BPM unless they set a tempo
+    fn file_data(format: Format, ticks_per_beat: u16, tracks: Vec<Track>) -> Vec<u8> {
+        let smf = Smf {
+            header: Header::new(format, Timing::Metrical(ticks_per_beat.into())),
+            tracks,
+        };
+        let mut data = Vec::new();
+        smf.write_std(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_long_file_ticks() {
+        // A note held for 17 maximal deltas at 32767 ticks per beat
+        // spans more ticks than fit in a u32
+        let max = midly::num::u28::max_value();
+        let mut track = vec![key_event(0, 0, 60, true)];
+        track.extend((0..16).map(|_| TrackEvent {
+            delta: max,
+            kind: TrackEventKind::Meta(MetaMessage::Text(b"")),
+        }));
+        track.push(key_event(max.as_int(), 0, 60, false));
+        let data = file_data(Format::SingleTrack, 32767, vec![track]);
+
+        let midi = parse_file("long.mid", &data, NotePairing::Fifo).unwrap();
+        let ticks = 17 * max.as_int() as u64;
+        assert!(ticks > u32::MAX as u64);
+        let expected_ms = (ticks as f64 * 500_000.0 / (32767.0 * 1000.0)) as u64;
+        assert_eq!(midi.events[0].duration_ms, expected_ms);
+        assert_eq!(midi.info.duration_ms, expected_ms);
+        assert!(expected_ms - midi.beats.last().unwrap().time_ms <= 500);
+    }
+
+    #[test]
+    fn test_note_pairing() {
+        // At 500 ticks per beat and 120 BPM a tick is a millisecond
+        let spans = |track: Vec<TrackEvent>, pairing| {
+            let data = file_data(Format::SingleTrack, 500, vec![track]);
+            let midi = parse_file("pairs.mid", &data, pairing).unwrap();
+            let mut spans: Vec<(u8, u64, u64)> = midi
+                .events
+                .iter()
+                .map(|e| (e.channel, e.start_ms, e.duration_ms))
+                .collect();
+            spans.sort_unstable();
+            spans
+        };
+
+        // The same pitch on two channels keeps its own note-offs
+        let channels = vec![
+            key_event(0, 0, 60, true),
+            key_event(100, 1, 60, true),
+            key_event(100, 1, 60, false),
+            key_event(800, 0, 60, false),
+        ];
+        assert_eq!(
+            spans(channels, NotePairing::Fifo),
+            [(0, 0, 1000), (1, 100, 100)]
+        );
+
+        // Overlapping notes of one pitch on one channel
+        let overlap = || {
+            vec![
+                key_event(0, 0, 60, true),
+                key_event(100, 0, 60, true),
+                key_event(100, 0, 60, false),
+                key_event(100, 0, 60, false),
+            ]
+        };
+        assert_eq!(
+            spans(overlap(), NotePairing::Fifo),
+            [(0, 0, 200), (0, 100, 200)]
+        );
+        assert_eq!(
+            spans(overlap(), NotePairing::Lifo),
+            [(0, 0, 300), (0, 100, 100)]
+        );
+    }
+
+    #[test]
+    fn test_write_order() {
+        let on = |channel: u8, key: u8| TrackEventKind::Midi {
+            channel: channel.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+        };
+        let off = |channel: u8, key: u8| TrackEventKind::Midi {
+            channel: channel.into(),
+            message: MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            },
+        };
+        // A repeated note ends before it re-strikes, while a note both
+        // starting and ending at a tick ends after it starts
+        let events = vec![
+            (0, on(0, 60)),
+            (10, on(0, 60)),
+            (10, off(0, 60)),
+            (10, off(0, 62)),
+            (10, on(0, 62)),
+            (20, off(0, 60)),
+        ];
+        let order: Vec<_> = in_play_order(events)
+            .iter()
+            .map(|(tick, kind)| (*tick, note_of(kind)))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (0, Some(((0, 60), true))),
+                (10, Some(((0, 60), false))),
+                (10, Some(((0, 60), true))),
+                (10, Some(((0, 62), true))),
+                (10, Some(((0, 62), false))),
+                (20, Some(((0, 60), false))),
+            ]
+        );
+
+        // Zero-length notes still sound once saved
+        let note = NoteEvent {
+            start_ms: 0,
+            duration_ms: 0,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            channel: 0,
+        };
+        let data = notes_to_smf(&[note]).unwrap();
+        let midi = parse_file("short.mid", &data, NotePairing::Fifo).unwrap();
+        assert_eq!(midi.events.len(), 1);
+        assert!(midi.events[0].duration_ms > 0);
+    }
+
+    #[test]
+    fn test_damaged_tracks() {
+        let piano = vec![key_event(0, 1, 48, true), key_event(500, 1, 48, false)];
+        let text = TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Text(b"xx")),
+        };
+        let melody = vec![
+            key_event(0, 0, 60, true),
+            key_event(500, 0, 60, false),
+            text,
+        ];
+        let mut data = file_data(Format::Parallel, 500, vec![piano, melody]);
+        // Overwrite the closing text with a real-time byte, which files
+        // can't hold
+        let end = data.len() - 6;
+        data[end..].copy_from_slice(&[0, 0xF8, 0, 0, 0, 0]);
+
+        let midi = parse_file("damaged.mid", &data, NotePairing::Fifo).unwrap();
+        assert_eq!(midi.events.len(), 2);
+        assert_eq!(midi.info.warnings, [LoadWarning::DamagedTrack { track: 1 }]);
+
+        // A file cut off after its first track
+        let piano = vec![key_event(0, 0, 48, true), key_event(500, 0, 48, false)];
+        let mut data = file_data(Format::Parallel, 500, vec![piano, Vec::new()]);
+        data.truncate(data.len() - 8);
+        let midi = parse_file("cut.mid", &data, NotePairing::Fifo).unwrap();
+        assert_eq!(midi.info.track_count, 1);
+        assert_eq!(
+            midi.info.warnings,
+            [LoadWarning::MissingTracks {
+                expected: 2,
+                found: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_load_warnings() {
+        let name = TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::TrackName(b"Strings")),
+        };
+        let melody = vec![
+            key_event(0, 0, 60, true),
+            key_event(0, 0, 60, false),
+            key_event(0, 0, 62, true),
+            key_event(0, 0, 64, true),
+            key_event(500, 0, 64, false),
+        ];
+        let data = file_data(Format::Parallel, 500, vec![vec![name], melody]);
+        let midi = parse_file("rough.mid", &data, NotePairing::Fifo).unwrap();
+        assert_eq!(
+            midi.info.warnings,
+            [
+                LoadWarning::EmptyTrack { track: 0 },
+                LoadWarning::UnclosedNotes { track: 1, count: 1 },
+                LoadWarning::ZeroLengthNotes { track: 1, count: 1 },
+            ]
+        );
+
+        assert_eq!(transpose_warning(&midi.events, 63), None);
+        assert_eq!(
+            transpose_warning(&midi.events, 64),
+            Some(LoadWarning::OutOfRangeNotes {
+                count: 1,
+                transpose: 64
+            })
+        );
+        assert_eq!(midi.info(0).warnings.len(), 3);
+        assert_eq!(midi.info(64).warnings.len(), 4);
+    }
+
+    #[test]
+    fn test_track_programs() {
+        let program = |delta: u32, program: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::ProgramChange {
+                    program: program.into(),
+                },
+            },
+        };
+        // Strings, changing to a trumpet part way through
+        let strings = vec![program(0, 48), key_event(0, 0, 60, true), program(100, 56)];
+        let piano = vec![key_event(0, 1, 64, true)];
+        let data = file_data(Format::Parallel, 500, vec![strings, piano]);
+
+        let midi = parse_file("programs.mid", &data, NotePairing::Fifo).unwrap();
+        assert_eq!(midi.programs.len(), 2);
+        assert_eq!(program_at(&midi.programs, 0, 0, 0), 48);
+        assert_eq!(program_at(&midi.programs, 0, 0, 99), 48);
+        assert_eq!(program_at(&midi.programs, 0, 0, 100), 56);
+        // Other channels and tracks keep their own programs
+        assert_eq!(program_at(&midi.programs, 0, 1, 100), 0);
+        assert_eq!(program_at(&midi.programs, 1, 0, 100), 0);
+    }
+
+    #[test]
+    fn test_sequential_format() {
+        // Two half-note sequences, the second at half the tempo
+        let slow = TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(1_000_000.into())),
+        };
+        let tracks = vec![
+            vec![key_event(0, 0, 60, true), key_event(500, 0, 60, false)],
+            vec![
+                slow,
+                key_event(0, 0, 64, true),
+                key_event(500, 0, 64, false),
+            ],
+        ];
+        let data = file_data(Format::Sequential, 500, tracks);
+        let midi = parse_file("suite.mid", &data, NotePairing::Fifo).unwrap();
+
+        let notes: Vec<(u8, u64, u64)> = midi
+            .events
+            .iter()
+            .map(|e| (e.note, e.start_ms, e.duration_ms))
+            .collect();
+        assert_eq!(notes, [(60, 0, 500), (64, 500, 1000)]);
+        assert_eq!(midi.marker_section("Sequence 2"), Some((500, 1500)));
+
+        let beats: Vec<(u64, u32)> = midi.beats.iter().map(|b| (b.time_ms, b.bar)).collect();
+        assert_eq!(beats, [(0, 1), (500, 2), (1500, 2)]);
+
+        assert_eq!(midi.bar_section(2, 2), Some((500, 1500)));
+        assert_eq!(midi.bar_section(1, 1), Some((0, 500)));
+        assert_eq!(midi.bar_section(3, 4), None);
+
+        let beat = midi.beat_at(1499).unwrap();
+        assert_eq!((beat.bar, beat.beat, beat.time_signature), (2, 1, (4, 4)));
+        assert_eq!(midi.beat_at(1500).unwrap().beat, 2);
+    }
+
+    #[test]
+    fn test_marker_section() {
+        let marker = |name: &str, time_ms| Marker {
+            name: name.to_string(),
+            time_ms,
+        };
+        let midi = MidiFile {
+            path: "song.mid".into(),
+            hash: String::new(),
+            info: MidiInfo {
+                track_count: 1,
+                duration_ms: 9000,
+                note_count: 0,
+                min_note: 0,
+                max_note: 127,
+                track_names: Vec::new(),
+                markers: vec![marker("Verse", 1000), marker("Chorus", 4000)],
+                warnings: Vec::new(),
+            },
+            events: Vec::new(),
+            bar_ms: 2000,
+            time_signature: (4, 4),
+            beats: Vec::new(),
+            disabled_tracks: Vec::new(),
+            bookmarks: Vec::new(),
+            programs: Vec::new(),
+        };
+        assert_eq!(midi.marker_section("verse"), Some((1000, 4000)));
+        assert_eq!(midi.marker_section("Chorus"), Some((4000, 9000)));
+        assert_eq!(midi.marker_section("Bridge"), None);
+
+        // Bookmarks make sections of their own, ignoring the markers
+        let mut midi = midi;
+        midi.set_bookmark("Solo", 6000);
+        midi.set_bookmark("Intro", 0);
+        assert_eq!(midi.bookmark_section("intro"), Some((0, 6000)));
+        midi.set_bookmark("Intro", 2000);
+        assert_eq!(midi.bookmarks.len(), 2);
+        assert_eq!(midi.bookmark_section("Intro"), Some((2000, 6000)));
+        assert!(midi.remove_bookmark("solo"));
+        assert_eq!(midi.bookmark_section("Intro"), Some((2000, 9000)));
+    }
+
+    #[test]
+    fn test_quarter_ms() {
+        // Two quarters at 120 BPM, then eighths of 6/8 at 150 BPM
+        let beat = |time_ms, beat, time_signature| Beat {
+            time_ms,
+            bar: 1,
+            beat,
+            time_signature,
+        };
+        let midi = MidiFile {
+            path: "meters.mid".into(),
+            hash: String::new(),
+            info: MidiInfo {
+                track_count: 1,
+                duration_ms: 2000,
+                note_count: 0,
+                min_note: 0,
+                max_note: 127,
+                track_names: Vec::new(),
+                markers: Vec::new(),
+                warnings: Vec::new(),
+            },
+            events: Vec::new(),
+            bar_ms: 2000,
+            time_signature: (4, 4),
+            beats: vec![
+                beat(0, 1, (4, 4)),
+                beat(500, 2, (4, 4)),
+                beat(1000, 1, (6, 8)),
+                beat(1200, 2, (6, 8)),
+            ],
+            disabled_tracks: Vec::new(),
+            bookmarks: Vec::new(),
+            programs: Vec::new(),
+        };
+        assert_eq!(midi.quarter_ms(1.5), 750);
+        assert_eq!(midi.quarter_ms(2.0), 1000);
+        assert_eq!(midi.quarter_ms(2.5), 1200);
+        // Past the last beat, its length carries on
+        assert_eq!(midi.quarter_ms(3.5), 1600);
+
+        assert_eq!(midi.quarter_length_ms(600), 500.0);
+        assert_eq!(midi.quarter_length_ms(1100), 400.0);
+        assert_eq!(midi.quarter_length_ms(5000), 400.0);
+    }
+}
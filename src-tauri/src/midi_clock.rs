@@ -0,0 +1,327 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::{AppHandle, Manager};
+
+use crate::config::{MAX_TEMPO, MIN_TEMPO};
+use crate::hotkeys::TEMPO_CHANGED_EVENT;
+use crate::midi::{beat_quarters, beat_span_ms, Beat};
+use crate::midi_input::{self, InputConnection};
+use crate::midi_output::OutputConnection;
+use crate::AppState;
+
+/// MIDI clock runs at 24 ticks per quarter note
+const TICKS_PER_QUARTER: u64 = 24;
+
+/// How far playback may wander from the clock before it is pulled back
+const MAX_DRIFT_MS: u64 = 80;
+
+/// Tempo changes smaller than this are clock jitter, not a new tempo
+const TEMPO_TOLERANCE: f64 = 0.005;
+
+/// What the external clock asks of playback. Positions are in quarter
+/// notes from the start of the song.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockAction {
+    /// Play from the top
+    Start,
+    /// Resume at `quarters`
+    Continue {
+        quarters: f64,
+    },
+    Stop,
+    /// The song position pointer moved
+    Seek {
+        quarters: f64,
+    },
+    /// A quarter note passed at the clock's current rate
+    Beat {
+        quarters: f64,
+        ms_per_quarter: f64,
+    },
+}
+
+/// Tracks the position and rate of incoming MIDI clock
+#[derive(Debug, Default)]
+pub struct ClockFollower {
+    running: bool,
+    /// Clock ticks since the song start
+    ticks: u64,
+    /// Arrival times (ms) of the last quarter note's worth of ticks
+    tick_times: VecDeque<f64>,
+}
+
+impl ClockFollower {
+    /// Turn a raw MIDI message arriving at `now_ms` into an action
+    pub fn handle(&mut self, message: &[u8], now_ms: f64) -> Option<ClockAction> {
+        let quarters = |ticks: u64| ticks as f64 / TICKS_PER_QUARTER as f64;
+        match *message {
+            [0xFA] => {
+                self.running = true;
+                self.ticks = 0;
+                self.tick_times.clear();
+                Some(ClockAction::Start)
+            }
+            [0xFB] => {
+                self.running = true;
+                self.tick_times.clear();
+                Some(ClockAction::Continue {
+                    quarters: quarters(self.ticks),
+                })
+            }
+            [0xFC] => {
+                self.running = false;
+                Some(ClockAction::Stop)
+            }
+            // Song position pointer, in sixteenth notes
+            [0xF2, low, high] => {
+                let sixteenths = ((high as u64) << 7) | low as u64;
+                self.ticks = sixteenths * TICKS_PER_QUARTER / 4;
+                self.tick_times.clear();
+                Some(ClockAction::Seek {
+                    quarters: quarters(self.ticks),
+                })
+            }
+            // DAWs send clock while stopped too, which doesn't move the song
+            [0xF8] if self.running => {
+                self.ticks += 1;
+                self.tick_times.push_back(now_ms);
+                if self.tick_times.len() > TICKS_PER_QUARTER as usize + 1 {
+                    self.tick_times.pop_front();
+                }
+                let measured = self.tick_times.len() == TICKS_PER_QUARTER as usize + 1;
+                if !measured || self.ticks % TICKS_PER_QUARTER != 0 {
+                    return None;
+                }
+                Some(ClockAction::Beat {
+                    quarters: quarters(self.ticks),
+                    ms_per_quarter: self.tick_times.back()? - self.tick_times.front()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Listen for MIDI clock on the configured port and keep playback of the
+/// loaded song locked to it
+pub fn connect(app: AppHandle, port_name: &str) -> Result<InputConnection> {
+    let mut follower = ClockFollower::default();
+    let started = Instant::now();
+
+    midi_input::connect(port_name, move |message| {
+        let now_ms = started.elapsed().as_secs_f64() * 1000.0;
+        if let Some(action) = follower.handle(message, now_ms) {
+            follow(&app, action);
+        }
+    })
+}
+
+fn follow(app: &AppHandle, action: ClockAction) {
+    let state = app.state::<AppState>();
+    let midi_file = state.midi_file.lock().unwrap();
+    let Some(midi) = midi_file.as_ref() else {
+        return;
+    };
+
+    match action {
+        ClockAction::Start | ClockAction::Continue { .. } => {
+            let quarters = match action {
+                ClockAction::Continue { quarters } => quarters,
+                _ => 0.0,
+            };
+            let position_ms = midi.quarter_ms(quarters);
+            let mut config = state.config.lock().unwrap().clone();
+            let mut playback = state.playback.lock().unwrap();
+            if action != ClockAction::Start && playback.is_paused() {
+                playback.seek(position_ms);
+                playback.pause();
+                return;
+            }
+            // The backing track is already rolling, so no delay
+            config.start_delay_ms = 0;
+            let _ = playback.start_from(midi, &config, position_ms);
+        }
+        ClockAction::Stop => {
+            let mut playback = state.playback.lock().unwrap();
+            if playback.is_playing() && !playback.is_paused() {
+                playback.pause();
+            }
+        }
+        ClockAction::Seek { quarters } => {
+            state
+                .playback
+                .lock()
+                .unwrap()
+                .seek(midi.quarter_ms(quarters));
+        }
+        ClockAction::Beat {
+            quarters,
+            ms_per_quarter,
+        } => {
+            let expected_ms = midi.quarter_ms(quarters);
+            let factor =
+                (midi.quarter_length_ms(expected_ms) / ms_per_quarter).clamp(MIN_TEMPO, MAX_TEMPO);
+            let current = state.config.lock().unwrap().tempo_factor;
+            if (factor - current).abs() > TEMPO_TOLERANCE
+                && crate::update_config(&state, |c| c.tempo_factor = factor).is_ok()
+            {
+                let _ = app.emit_all(TEMPO_CHANGED_EVENT, factor);
+            }
+
+            let playback = state.playback.lock().unwrap();
+            let (position_ms, _) = playback.progress();
+            if playback.is_playing()
+                && !playback.is_paused()
+                && position_ms.abs_diff(expected_ms) > MAX_DRIFT_MS
+            {
+                playback.seek(expected_ms);
+            }
+        }
+    }
+}
+
+/// Song time (ms) of every MIDI clock tick over `beats`, 24 to the
+/// quarter note, following tempo and time signature changes
+pub fn clock_ticks(beats: &[Beat]) -> Vec<u64> {
+    let mut ticks = Vec::new();
+    for (i, beat) in beats.iter().enumerate() {
+        let count = (beat_quarters(beat) * TICKS_PER_QUARTER as f64).round() as u64;
+        let span_ms = beat_span_ms(beats, i);
+        ticks.extend((0..count).map(|t| beat.time_ms + (span_ms * t as f64 / count as f64) as u64));
+    }
+    ticks
+}
+
+/// Sends MIDI clock and song position for a playing song, so external
+/// gear or a DAW can follow it
+#[derive(Debug)]
+pub struct ClockSender {
+    output: Arc<Mutex<OutputConnection>>,
+    ticks: Vec<u64>,
+    next: usize,
+}
+
+impl ClockSender {
+    pub fn new(output: Arc<Mutex<OutputConnection>>, beats: &[Beat]) -> Self {
+        Self {
+            output,
+            ticks: clock_ticks(beats),
+            next: 0,
+        }
+    }
+
+    fn send(&self, message: &[u8]) {
+        self.output.lock().unwrap().send(message);
+    }
+
+    /// Point receivers at the sixteenth nearest `position_ms` and start
+    /// them from there
+    pub fn start(&mut self, position_ms: f64) {
+        let tick = self.ticks.partition_point(|&t| (t as f64) < position_ms);
+        let sixteenths = (tick as u64 + 3) / 6;
+        self.next = (sixteenths * 6) as usize;
+        if sixteenths == 0 {
+            self.send(&[0xFA]);
+        } else {
+            let sixteenths = sixteenths.min(0x3FFF) as u16;
+            self.send(&[0xF2, (sixteenths & 0x7F) as u8, (sixteenths >> 7) as u8]);
+            self.send(&[0xFB]);
+        }
+    }
+
+    pub fn stop(&self) {
+        self.send(&[0xFC]);
+    }
+
+    pub fn resume(&self) {
+        self.send(&[0xFB]);
+    }
+
+    /// Send the ticks due by `position_ms`
+    pub fn advance(&mut self, position_ms: f64) {
+        while self
+            .ticks
+            .get(self.next)
+            .is_some_and(|&t| t as f64 <= position_ms)
+        {
+            self.send(&[0xF8]);
+            self.next += 1;
+        }
+    }
+
+    /// Receivers only take a song position while stopped, so a jump is a
+    /// stop and a start
+    pub fn seek(&mut self, position_ms: f64) {
+        self.stop();
+        self.start(position_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_follower() {
+        let mut follower = ClockFollower::default();
+        let at_120 = |follower: &mut ClockFollower, ticks: u64, from_ms: f64| {
+            // 120 BPM is a quarter note per 500 ms
+            (1..=ticks)
+                .filter_map(|i| {
+                    let now_ms = from_ms + i as f64 * 500.0 / TICKS_PER_QUARTER as f64;
+                    follower.handle(&[0xF8], now_ms)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Clock while stopped is ignored
+        assert!(at_120(&mut follower, 48, 0.0).is_empty());
+
+        assert_eq!(follower.handle(&[0xFA], 0.0), Some(ClockAction::Start));
+        // The first quarter only starts the measurement
+        assert!(at_120(&mut follower, 24, 0.0).is_empty());
+        let beats = at_120(&mut follower, 48, 500.0);
+        assert_eq!(beats.len(), 2);
+        let ClockAction::Beat {
+            quarters,
+            ms_per_quarter,
+        } = beats[1]
+        else {
+            panic!("expected a beat, got {:?}", beats[1]);
+        };
+        assert_eq!(quarters, 3.0);
+        assert!((ms_per_quarter - 500.0).abs() < 1e-6);
+
+        // Stop, then the DAW moves to bar 3 (sixteenth 32) and continues
+        assert_eq!(follower.handle(&[0xFC], 0.0), Some(ClockAction::Stop));
+        assert_eq!(
+            follower.handle(&[0xF2, 32, 0], 0.0),
+            Some(ClockAction::Seek { quarters: 8.0 })
+        );
+        assert_eq!(
+            follower.handle(&[0xFB], 0.0),
+            Some(ClockAction::Continue { quarters: 8.0 })
+        );
+        assert_eq!(follower.handle(&[0x90, 60, 100], 0.0), None);
+    }
+
+    #[test]
+    fn test_clock_ticks() {
+        let beat = |time_ms, time_signature| Beat {
+            time_ms,
+            bar: 1,
+            beat: 1,
+            time_signature,
+        };
+        // A quarter of 480 ms, then 6/8 eighths of 240 ms
+        let beats = [beat(0, (4, 4)), beat(480, (6, 8)), beat(720, (6, 8))];
+        let ticks = clock_ticks(&beats);
+        assert_eq!(ticks.len(), 24 + 12 + 12);
+        assert_eq!(&ticks[..3], [0, 20, 40]);
+        assert_eq!(ticks[24], 480);
+        assert_eq!(ticks[25], 500);
+        assert_eq!(ticks[47], 720 + 220);
+    }
+}
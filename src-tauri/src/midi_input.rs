@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use std::sync::mpsc;
+use std::thread;
+
+#[cfg(windows)]
+use midir::{MidiInput, MidiInputConnection};
+
+#[cfg(windows)]
+use crate::error::BackendUnavailable;
+use crate::rtp_midi::{self, Session};
+
+/// Keeps a MIDI input port open until dropped
+#[derive(Debug)]
+pub struct InputConnection {
+    /// Dropping this wakes the connection thread so it closes the port
+    _close: mpsc::Sender<()>,
+}
+
+/// Names of the MIDI input ports currently available
+#[cfg(windows)]
+pub fn list_ports() -> Result<Vec<String>> {
+    let input = MidiInput::new("WWMP")?;
+    Ok(input
+        .ports()
+        .iter()
+        .filter_map(|port| input.port_name(port).ok())
+        .collect())
+}
+
+#[cfg(windows)]
+fn open(
+    port_name: &str,
+    mut on_message: impl FnMut(&[u8]) + Send + 'static,
+) -> Result<MidiInputConnection<()>> {
+    let input = MidiInput::new("WWMP")?;
+    let ports = input.ports();
+    let port = ports
+        .iter()
+        .find(|port| {
+            port_name.is_empty() || input.port_name(port).ok().as_deref() == Some(port_name)
+        })
+        .ok_or_else(|| anyhow!("MIDI input not found: {}", port_name))?;
+
+    input
+        .connect(
+            port,
+            "wwmp-input",
+            move |_, message, _| on_message(message),
+            (),
+        )
+        .map_err(|e| BackendUnavailable(format!("Failed to open MIDI input: {}", e)).into())
+}
+
+/// Listen on the named input port (or the first one if `port_name` is
+/// empty), calling `on_message` with each raw MIDI message. Names like
+/// `rtpmidi:5004` host a network session on that UDP port instead, see
+/// `rtp_midi::parse_address`.
+pub fn connect(
+    port_name: &str,
+    on_message: impl FnMut(&[u8]) + Send + 'static,
+) -> Result<InputConnection> {
+    let (close_tx, close_rx) = mpsc::channel::<()>();
+    if let Some(address) = port_name.strip_prefix(rtp_midi::PORT_PREFIX) {
+        let (port, allowed) = rtp_midi::parse_address(address)?;
+        let session = Session::bind(port, allowed)?;
+        thread::spawn(move || session.run(close_rx, on_message));
+        return Ok(InputConnection { _close: close_tx });
+    }
+
+    let port_name = port_name.to_string();
+    let (ready_tx, ready_rx) = mpsc::channel();
+
+    // The connection lives on its own thread so it never has to be shared
+    thread::spawn(move || match open(&port_name, on_message) {
+        Ok(_connection) => {
+            let _ = ready_tx.send(Ok(()));
+            let _ = close_rx.recv();
+        }
+        Err(e) => {
+            let _ = ready_tx.send(Err(e));
+        }
+    });
+
+    ready_rx
+        .recv()
+        .map_err(|_| anyhow!("MIDI input thread exited"))??;
+    Ok(InputConnection { _close: close_tx })
+}
+
+// Non-Windows stubs for development
+#[cfg(not(windows))]
+pub fn list_ports() -> Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(windows))]
+fn open(port_name: &str, on_message: impl FnMut(&[u8]) + Send + 'static) -> Result<()> {
+    let _ = on_message;
+    println!("STUB: midi_input::open({})", port_name);
+    Ok(())
+}
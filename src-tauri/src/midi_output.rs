@@ -0,0 +1,76 @@
+use anyhow::Result;
+
+#[cfg(windows)]
+use anyhow::anyhow;
+#[cfg(windows)]
+use midir::{MidiOutput, MidiOutputConnection};
+
+#[cfg(windows)]
+use crate::error::BackendUnavailable;
+
+/// An open MIDI output port, closed when dropped
+pub struct OutputConnection {
+    #[cfg(windows)]
+    connection: MidiOutputConnection,
+}
+
+impl std::fmt::Debug for OutputConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("OutputConnection")
+    }
+}
+
+/// Names of the MIDI output ports currently available
+#[cfg(windows)]
+pub fn list_ports() -> Result<Vec<String>> {
+    let output = MidiOutput::new("WWMP")?;
+    Ok(output
+        .ports()
+        .iter()
+        .filter_map(|port| output.port_name(port).ok())
+        .collect())
+}
+
+/// Open the named output port, or the first one if `port_name` is empty
+#[cfg(windows)]
+pub fn connect(port_name: &str) -> Result<OutputConnection> {
+    let output = MidiOutput::new("WWMP")?;
+    let ports = output.ports();
+    let port = ports
+        .iter()
+        .find(|port| {
+            port_name.is_empty() || output.port_name(port).ok().as_deref() == Some(port_name)
+        })
+        .ok_or_else(|| anyhow!("MIDI output not found: {}", port_name))?;
+
+    let connection = output
+        .connect(port, "wwmp-output")
+        .map_err(|e| BackendUnavailable(format!("Failed to open MIDI output: {}", e)))?;
+    Ok(OutputConnection { connection })
+}
+
+impl OutputConnection {
+    /// Send one raw MIDI message. A port that went away is not worth
+    /// interrupting playback over, so errors are dropped.
+    #[cfg(windows)]
+    pub fn send(&mut self, message: &[u8]) {
+        let _ = self.connection.send(message);
+    }
+
+    #[cfg(not(windows))]
+    pub fn send(&mut self, message: &[u8]) {
+        let _ = message;
+    }
+}
+
+// Non-Windows stubs for development
+#[cfg(not(windows))]
+pub fn list_ports() -> Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(windows))]
+pub fn connect(port_name: &str) -> Result<OutputConnection> {
+    println!("STUB: midi_output::connect({})", port_name);
+    Ok(OutputConnection {})
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+use tauri::{AppHandle, Manager};
+
+use crate::config::{RemoteAction, RemoteBinding, RemoteTrigger};
+use crate::midi_input::{self, InputConnection};
+use crate::AppState;
+
+/// Turns raw controller messages into bound actions
+#[derive(Debug, Default)]
+pub struct RemoteMatcher {
+    /// Controllers currently in their "on" half, so a held pedal that
+    /// keeps sending values only triggers once
+    held: Vec<u8>,
+}
+
+impl RemoteMatcher {
+    /// Find the action bound to a raw MIDI message, if any. Channels are
+    /// ignored so any controller setup works without configuration.
+    pub fn handle(&mut self, message: &[u8], bindings: &[RemoteBinding]) -> Option<RemoteAction> {
+        let LiveEvent::Midi { message, .. } = LiveEvent::parse(message).ok()? else {
+            return None;
+        };
+        let trigger = match message {
+            MidiMessage::Controller { controller, value } => {
+                let controller = controller.as_int();
+                if value < 64 {
+                    self.held.retain(|&c| c != controller);
+                    return None;
+                }
+                if self.held.contains(&controller) {
+                    return None;
+                }
+                self.held.push(controller);
+                RemoteTrigger::Cc { controller }
+            }
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                RemoteTrigger::Note { note: key.as_int() }
+            }
+            _ => return None,
+        };
+        bindings
+            .iter()
+            .find(|binding| binding.trigger == trigger)
+            .map(|binding| binding.action)
+    }
+}
+
+/// Open the configured controller port and dispatch bound messages.
+/// Bindings are read from the config per message so edits apply at once.
+pub fn connect(app: AppHandle, port_name: &str) -> Result<InputConnection> {
+    let mut matcher = RemoteMatcher::default();
+
+    midi_input::connect(port_name, move |message| {
+        let state = app.state::<AppState>();
+        let action = {
+            let config = state.config.lock().unwrap();
+            matcher.handle(message, &config.midi_remote.bindings)
+        };
+
+        let _ = match action {
+            Some(RemoteAction::Play) => crate::start_playback(&state),
+            Some(RemoteAction::Pause) => {
+                state.playback.lock().unwrap().pause();
+                Ok(())
+            }
+            Some(RemoteAction::Stop) => {
+                crate::stop_all(&state);
+                Ok(())
+            }
+            Some(RemoteAction::NextSong) => crate::play_next_song(&app),
+            None => Ok(()),
+        };
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_matcher() {
+        let bindings = vec![
+            RemoteBinding {
+                trigger: RemoteTrigger::Cc { controller: 64 },
+                action: RemoteAction::Play,
+            },
+            RemoteBinding {
+                trigger: RemoteTrigger::Note { note: 36 },
+                action: RemoteAction::Stop,
+            },
+        ];
+
+        let mut matcher = RemoteMatcher::default();
+        let mut handle = |message: &[u8]| matcher.handle(message, &bindings);
+
+        // Sustain pedal on channel 2: fires once while held, re-arms on release
+        assert_eq!(handle(&[0xB1, 64, 127]), Some(RemoteAction::Play));
+        assert_eq!(handle(&[0xB1, 64, 100]), None);
+        assert_eq!(handle(&[0xB1, 64, 0]), None);
+        assert_eq!(handle(&[0xB1, 64, 127]), Some(RemoteAction::Play));
+
+        // Pad hit, and its note-on-with-zero-velocity release
+        assert_eq!(handle(&[0x99, 36, 100]), Some(RemoteAction::Stop));
+        assert_eq!(handle(&[0x99, 36, 0]), None);
+        assert_eq!(handle(&[0x90, 60, 100]), None);
+        assert_eq!(handle(&[]), None);
+    }
+}
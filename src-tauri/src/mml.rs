@@ -0,0 +1,277 @@
+use anyhow::{bail, Result};
+use midly::{MetaMessage, MidiMessage, TrackEventKind};
+
+use crate::midi;
+
+/// Ticks per quarter note of the generated file
+const TICKS_PER_BEAT: u64 = 480;
+
+/// Semitones above C of the note letters a to g
+const LETTERS: [u8; 7] = [9, 11, 0, 2, 4, 5, 7];
+
+/// Volumes run from v0 to v15
+const MAX_VOLUME: u32 = 15;
+
+/// One comma-separated part, as it is read
+struct Voice {
+    events: Vec<(u64, TrackEventKind<'static>)>,
+    tick: u64,
+    octave: i32,
+    /// Length of notes without one of their own (ticks)
+    length: u64,
+    velocity: u8,
+    /// Set by `&`: the next note carries on the last one if the same pitch
+    tie: bool,
+    /// Pitch of the last note and the index of its note-off
+    last: Option<(u8, usize)>,
+}
+
+impl Voice {
+    fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            tick: 0,
+            octave: 4,
+            length: TICKS_PER_BEAT,
+            velocity: velocity(8),
+            tie: false,
+            last: None,
+        }
+    }
+
+    /// Sound `note` (or rest, for None) for `ticks`
+    fn play(&mut self, note: Option<u8>, ticks: u64) {
+        let tied = self.tie && note.is_some() && self.last.map(|(n, _)| n) == note;
+        match (note, self.last) {
+            (Some(_), Some((_, off))) if tied => {
+                if let Some(event) = self.events.get_mut(off) {
+                    event.0 += ticks;
+                }
+            }
+            (Some(note), _) if self.velocity > 0 => {
+                let (key, vel) = (note.into(), self.velocity.into());
+                self.events
+                    .push((self.tick, midi(MidiMessage::NoteOn { key, vel })));
+                self.events.push((
+                    self.tick + ticks,
+                    midi(MidiMessage::NoteOff { key, vel: 0.into() }),
+                ));
+                self.last = Some((note, self.events.len() - 1));
+            }
+            _ => self.last = None,
+        }
+        self.tie = false;
+        self.tick += ticks;
+    }
+}
+
+fn midi(message: MidiMessage) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: 0.into(),
+        message,
+    }
+}
+
+/// MIDI velocity of MML volume `volume`
+fn velocity(volume: u32) -> u8 {
+    (volume.min(MAX_VOLUME) * 127 / MAX_VOLUME) as u8
+}
+
+/// Reads numbers and lengths off the MML text
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl Reader<'_> {
+    fn number(&mut self) -> Option<u32> {
+        let mut value: Option<u32> = None;
+        while let Some(&(_, c)) = self.chars.peek() {
+            let Some(digit) = c.to_digit(10) else {
+                break;
+            };
+            self.chars.next();
+            value = Some(value.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+        }
+        value
+    }
+
+    /// A note length such as `8` or `4.`, in ticks; `default` if there is
+    /// no number
+    fn length(&mut self, default: u64, at: usize) -> Result<u64> {
+        let mut ticks = match self.number() {
+            Some(n @ 1..=192) => TICKS_PER_BEAT * 4 / n as u64,
+            Some(n) => bail!("Invalid note length {} at character {}", n, at),
+            None => default,
+        };
+        let mut dot = ticks;
+        while self.chars.next_if(|&(_, c)| c == '.').is_some() {
+            dot /= 2;
+            ticks += dot;
+        }
+        Ok(ticks)
+    }
+}
+
+/// Convert Music Macro Language (`t120 o4 l8 cdefgab>c`) to a standard
+/// MIDI file. Parts separated by `,` or `;` become tracks; `o4c` is
+/// middle C, and `n` numbers notes from `o0c`.
+pub fn to_smf(text: &str) -> Result<Vec<u8>> {
+    let text = text.to_ascii_lowercase();
+    let mut reader = Reader {
+        chars: text.char_indices().peekable(),
+    };
+    let mut voices = vec![Voice::new()];
+    let mut tempos = Vec::new();
+
+    while let Some((at, c)) = reader.chars.next() {
+        let voice = voices.last_mut().unwrap();
+        match c {
+            'a'..='g' => {
+                let mut semitone = LETTERS[(c as u8 - b'a') as usize] as i32;
+                while let Some((_, accidental)) =
+                    reader.chars.next_if(|&(_, c)| matches!(c, '+' | '#' | '-'))
+                {
+                    semitone += if accidental == '-' { -1 } else { 1 };
+                }
+                let ticks = reader.length(voice.length, at)?;
+                let note = (voice.octave + 1) * 12 + semitone;
+                let Ok(note @ 0..=127) = u8::try_from(note) else {
+                    bail!("Note out of range at character {}", at);
+                };
+                voice.play(Some(note), ticks);
+            }
+            'n' => {
+                let Some(number @ 0..=115) = reader.number() else {
+                    bail!("Expected a note number 0-115 at character {}", at);
+                };
+                let ticks = voice.length;
+                voice.play(Some(number as u8 + 12), ticks);
+            }
+            'r' => {
+                let ticks = reader.length(voice.length, at)?;
+                voice.play(None, ticks);
+            }
+            'o' => match reader.number() {
+                Some(octave @ 0..=9) => voice.octave = octave as i32,
+                _ => bail!("Expected an octave 0-9 at character {}", at),
+            },
+            '>' => voice.octave += 1,
+            '<' => voice.octave -= 1,
+            'l' => voice.length = reader.length(voice.length, at)?,
+            // Below 4 BPM a beat no longer fits in a MIDI tempo's 24 bits
+            't' => match reader.number() {
+                Some(bpm @ 4..=1000) => tempos.push((voice.tick, 60_000_000 / bpm)),
+                _ => bail!("Expected a tempo 4-1000 at character {}", at),
+            },
+            'v' => match reader.number() {
+                Some(volume @ 0..=MAX_VOLUME) => voice.velocity = velocity(volume),
+                _ => bail!("Expected a volume 0-15 at character {}", at),
+            },
+            '&' => voice.tie = true,
+            ',' | ';' => voices.push(Voice::new()),
+            c if c.is_whitespace() => {}
+            c => bail!("Unexpected '{}' at character {}", c, at),
+        }
+    }
+
+    // Tempo is shared by all parts, so it all goes in the first track
+    for (tick, tempo) in tempos {
+        let event = TrackEventKind::Meta(MetaMessage::Tempo(tempo.into()));
+        voices[0].events.push((tick, event));
+    }
+
+    let tracks = voices.into_iter().map(|voice| voice.events).collect();
+    midi::encode_smf(TICKS_PER_BEAT as u16, tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::Smf;
+
+    /// (tick, note, on) of every note event, by track
+    fn notes(text: &str) -> Vec<Vec<(u64, u8, bool)>> {
+        let data = to_smf(text).unwrap();
+        let smf = Smf::parse(&data).unwrap();
+        smf.tracks
+            .iter()
+            .map(|track| {
+                let mut tick = 0;
+                track
+                    .iter()
+                    .filter_map(|event| {
+                        tick += event.delta.as_int() as u64;
+                        match event.kind {
+                            TrackEventKind::Midi {
+                                message: MidiMessage::NoteOn { key, .. },
+                                ..
+                            } => Some((tick, key.as_int(), true)),
+                            TrackEventKind::Midi {
+                                message: MidiMessage::NoteOff { key, .. },
+                                ..
+                            } => Some((tick, key.as_int(), false)),
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_notes_and_lengths() {
+        // Eighths, a dotted quarter, a rest, sharps and flats
+        assert_eq!(
+            notes("l8 c d+ e-4. r8 >c"),
+            [[
+                (0, 60, true),
+                (240, 60, false),
+                (240, 63, true),
+                (480, 63, false),
+                (480, 63, true),
+                (1200, 63, false),
+                (1440, 72, true),
+                (1680, 72, false),
+            ]]
+        );
+        // n numbers from o0c, and o4c is middle C
+        assert_eq!(notes("n48"), notes("o4c"));
+    }
+
+    #[test]
+    fn test_ties_and_parts() {
+        assert_eq!(
+            notes("c8&c8 c, o3 v0 c d"),
+            [
+                vec![
+                    (0, 60, true),
+                    (480, 60, false),
+                    (480, 60, true),
+                    (960, 60, false)
+                ],
+                // v0 notes are silent
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tempo_in_first_track() {
+        let data = to_smf("c, t60 c").unwrap();
+        let smf = Smf::parse(&data).unwrap();
+        let tempo = smf.tracks[0].iter().find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Some(tempo.as_int()),
+            _ => None,
+        });
+        assert_eq!(tempo, Some(1_000_000));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(to_smf("o9b+").is_err());
+        assert!(to_smf("c0").is_err());
+        assert!(to_smf("x").is_err());
+        assert!(to_smf("t").is_err());
+        assert!(to_smf("t3c").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::midi::NoteEvent;
+
+/// Grid units per quarter note; notes are quantized to sixteenths
+const DIVISIONS: u64 = 4;
+
+/// Note lengths MusicXML can show without ties, longest first, with
+/// their type name and whether they are dotted
+const NOTE_TYPES: &[(u64, &str, bool)] = &[
+    (16, "whole", false),
+    (12, "half", true),
+    (8, "half", false),
+    (6, "quarter", true),
+    (4, "quarter", false),
+    (3, "eighth", true),
+    (2, "eighth", false),
+    (1, "16th", false),
+];
+
+const STEPS: [(&str, i8); 12] = [
+    ("C", 0),
+    ("C", 1),
+    ("D", 0),
+    ("D", 1),
+    ("E", 0),
+    ("F", 0),
+    ("F", 1),
+    ("G", 0),
+    ("G", 1),
+    ("A", 0),
+    ("A", 1),
+    ("B", 0),
+];
+
+/// One event of the single notation voice: a chord, or a rest if empty
+struct Segment {
+    start: u64,
+    length: u64,
+    pitches: Vec<u8>,
+}
+
+/// Render notes as a single-part MusicXML score. Notes are quantized to
+/// sixteenths and simplified to one voice: each chord lasts until the
+/// next one starts, and notes crossing a barline are tied.
+pub fn render(
+    title: &str,
+    notes: &[NoteEvent],
+    bar_ms: u64,
+    time_signature: (u8, u8),
+    tempo_factor: f64,
+) -> String {
+    let (beats, beat_type) = time_signature;
+    let measure_units = (beats as u64 * DIVISIONS * 4 / beat_type as u64).max(1);
+    let unit_ms = bar_ms as f64 / measure_units as f64;
+    let to_units = |ms: u64| (ms as f64 / unit_ms).round() as u64;
+
+    // Chords by quantized start, remembering where each ends
+    let mut chords: BTreeMap<u64, (Vec<u8>, u64)> = BTreeMap::new();
+    for note in notes {
+        let start = to_units(note.start_ms);
+        let end = to_units(note.start_ms + note.duration_ms).max(start + 1);
+        let (pitches, chord_end) = chords.entry(start).or_default();
+        if !pitches.contains(&note.note) {
+            pitches.push(note.note);
+        }
+        *chord_end = (*chord_end).max(end);
+    }
+
+    let mut segments = Vec::new();
+    let mut position = 0;
+    let starts: Vec<u64> = chords.keys().copied().collect();
+    for (i, (&start, (pitches, end))) in chords.iter().enumerate() {
+        if start > position {
+            segments.push(Segment {
+                start: position,
+                length: start - position,
+                pitches: Vec::new(),
+            });
+        }
+        let until = starts.get(i + 1).map_or(*end, |&next| (*end).min(next));
+        let mut pitches = pitches.clone();
+        pitches.sort_unstable();
+        segments.push(Segment {
+            start,
+            length: until - start,
+            pitches,
+        });
+        position = until;
+    }
+    // Fill out the last bar
+    let total = position.div_ceil(measure_units).max(1) * measure_units;
+    if total > position {
+        segments.push(Segment {
+            start: position,
+            length: total - position,
+            pitches: Vec::new(),
+        });
+    }
+
+    let mut measures = vec![String::new(); (total / measure_units) as usize];
+    for segment in &segments {
+        // Split at barlines, then into lengths that have a note type
+        let mut pieces = Vec::new();
+        let mut at = segment.start;
+        let end = segment.start + segment.length;
+        while at < end {
+            let bar_end = (at / measure_units + 1) * measure_units;
+            let mut remaining = end.min(bar_end) - at;
+            while remaining > 0 {
+                let &(length, kind, dotted) = NOTE_TYPES
+                    .iter()
+                    .find(|(length, _, _)| *length <= remaining)
+                    .unwrap();
+                pieces.push((at, length, kind, dotted));
+                at += length;
+                remaining -= length;
+            }
+        }
+
+        for (i, &(at, length, kind, dotted)) in pieces.iter().enumerate() {
+            let measure = &mut measures[(at / measure_units) as usize];
+            let tie_start = !segment.pitches.is_empty() && i + 1 < pieces.len();
+            let tie_stop = !segment.pitches.is_empty() && i > 0;
+            write_note(
+                measure,
+                &segment.pitches,
+                length,
+                kind,
+                dotted,
+                tie_start,
+                tie_stop,
+            );
+        }
+    }
+
+    // Bass clef for songs that sit mostly below G3
+    let average = notes.iter().map(|n| n.note as u64).sum::<u64>() / notes.len().max(1) as u64;
+    let clef = if !notes.is_empty() && average < 55 {
+        ("F", 4)
+    } else {
+        ("G", 2)
+    };
+    let quarter_ms = unit_ms * DIVISIONS as f64;
+    let bpm = (60_000.0 / quarter_ms * tempo_factor).round();
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+    xml.push_str(
+        "<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \
+         \"http://www.musicxml.org/dtds/partwise.dtd\">\n",
+    );
+    xml.push_str("<score-partwise version=\"4.0\">\n");
+    let _ = writeln!(
+        xml,
+        "  <work><work-title>{}</work-title></work>",
+        escape(title)
+    );
+    xml.push_str("  <part-list>\n");
+    xml.push_str("    <score-part id=\"P1\"><part-name>Instrument</part-name></score-part>\n");
+    xml.push_str("  </part-list>\n");
+    xml.push_str("  <part id=\"P1\">\n");
+    for (i, measure) in measures.iter().enumerate() {
+        let _ = writeln!(xml, "    <measure number=\"{}\">", i + 1);
+        if i == 0 {
+            let _ = writeln!(
+                xml,
+                "      <attributes><divisions>{}</divisions><key><fifths>0</fifths></key>\
+                 <time><beats>{}</beats><beat-type>{}</beat-type></time>\
+                 <clef><sign>{}</sign><line>{}</line></clef></attributes>",
+                DIVISIONS, beats, beat_type, clef.0, clef.1
+            );
+            let _ = writeln!(
+                xml,
+                "      <direction placement=\"above\"><direction-type><metronome>\
+                 <beat-unit>quarter</beat-unit><per-minute>{}</per-minute></metronome>\
+                 </direction-type><sound tempo=\"{}\"/></direction>",
+                bpm, bpm
+            );
+        }
+        xml.push_str(measure);
+        xml.push_str("    </measure>\n");
+    }
+    xml.push_str("  </part>\n");
+    xml.push_str("</score-partwise>\n");
+    xml
+}
+
+fn write_note(
+    measure: &mut String,
+    pitches: &[u8],
+    length: u64,
+    kind: &str,
+    dotted: bool,
+    tie_start: bool,
+    tie_stop: bool,
+) {
+    let dot = if dotted { "<dot/>" } else { "" };
+    if pitches.is_empty() {
+        let _ = writeln!(
+            measure,
+            "      <note><rest/><duration>{}</duration><type>{}</type>{}</note>",
+            length, kind, dot
+        );
+        return;
+    }
+
+    let mut ties = String::new();
+    let mut tied = String::new();
+    if tie_stop {
+        ties.push_str("<tie type=\"stop\"/>");
+        tied.push_str("<tied type=\"stop\"/>");
+    }
+    if tie_start {
+        ties.push_str("<tie type=\"start\"/>");
+        tied.push_str("<tied type=\"start\"/>");
+    }
+    let notations = if tied.is_empty() {
+        String::new()
+    } else {
+        format!("<notations>{}</notations>", tied)
+    };
+
+    for (i, &pitch) in pitches.iter().enumerate() {
+        let chord = if i > 0 { "<chord/>" } else { "" };
+        let (step, alter) = STEPS[pitch as usize % 12];
+        let alter = if alter != 0 {
+            format!("<alter>{}</alter>", alter)
+        } else {
+            String::new()
+        };
+        let octave = pitch as i32 / 12 - 1;
+        let _ = writeln!(
+            measure,
+            "      <note>{}<pitch><step>{}</step>{}<octave>{}</octave></pitch>\
+             <duration>{}</duration>{}<type>{}</type>{}{}</note>",
+            chord, step, alter, octave, length, ties, kind, dot, notations
+        );
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::note;
+
+    #[test]
+    fn test_render_measures() {
+        // 4/4 at 120 BPM: a quarter is 500 ms and a bar 2000 ms
+        let notes = [
+            note(0, 500, 60),
+            note(0, 500, 64),
+            note(1500, 1000, 61), // crosses into bar 2
+        ];
+        let xml = render("A & B", &notes, 2000, (4, 4), 1.0);
+
+        assert!(xml.contains("<work-title>A &amp; B</work-title>"));
+        assert_eq!(xml.matches("<measure ").count(), 2);
+        assert!(xml.contains("<note><chord/><pitch><step>E</step>"));
+        assert!(xml.contains("<step>C</step><alter>1</alter><octave>4</octave>"));
+        assert_eq!(xml.matches("<tie type=\"start\"/>").count(), 1);
+        assert!(xml.contains("<per-minute>120</per-minute>"));
+    }
+}
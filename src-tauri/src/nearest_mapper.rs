@@ -0,0 +1,68 @@
+use crate::config::AppConfig;
+use crate::mapper::{
+    key_pitch, midi_to_instrument, note_to_keystroke, playable_range, register_mapper, Accidental,
+    InstrumentNote, KeyStroke, NoteMapper,
+};
+use crate::midi::NoteEvent;
+
+/// Plays every note: notes the layout can play map as usual, others are
+/// folded into range by octaves and snapped to the closest natural key.
+/// Suits songs full of accidentals on instruments without Shift/Ctrl.
+pub struct NearestNoteMapper;
+
+impl NoteMapper for NearestNoteMapper {
+    fn map(&self, note: &NoteEvent, config: &AppConfig) -> Option<KeyStroke> {
+        if let Some(exact) = midi_to_instrument(note.note, config) {
+            return note_to_keystroke(&exact, config);
+        }
+
+        let (low, high) = playable_range(config);
+        let mut pitch = note.note as i32 + config.transpose;
+        while pitch < low && pitch + 12 <= high {
+            pitch += 12;
+        }
+        while pitch > high && pitch - 12 >= low {
+            pitch -= 12;
+        }
+
+        let key_index = (0..config.key_mapping.key_count())
+            .min_by_key(|&i| (key_pitch(i, config) - pitch).abs())?;
+        let nearest = InstrumentNote {
+            key_index,
+            accidental: Accidental::Natural,
+        };
+        note_to_keystroke(&nearest, config)
+    }
+}
+
+/// Add the mapper to the registry as `nearest`
+pub fn register() {
+    register_mapper("nearest", NearestNoteMapper);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::note;
+
+    #[test]
+    fn test_nearest_note() {
+        let config = AppConfig {
+            accidentals: false,
+            ..AppConfig::default()
+        };
+        let key = |n| {
+            NearestNoteMapper
+                .map(&note(0, 100, n), &config)
+                .unwrap()
+                .key
+        };
+
+        // C4 is playable as is; C#4 snaps down to C4
+        assert_eq!(key(60), "A");
+        assert_eq!(key(61), "A");
+        // C2 and C7 fold into the layout's three octaves
+        assert_eq!(key(36), "Z");
+        assert_eq!(key(96), "Q");
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{anyhow, bail, Result};
+use std::net::UdpSocket;
+use std::thread;
+use tauri::AppHandle;
+
+use crate::remote::{self, RemoteCommand};
+
+/// Address prefix for all WWMP messages, e.g. `/wwmp/play`
+const PREFIX: &str = "/wwmp/";
+
+/// A decoded OSC argument
+#[derive(Debug, Clone, PartialEq)]
+enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+impl OscArg {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            OscArg::Int(i) => Some(*i as f64),
+            OscArg::Float(f) => Some(*f as f64),
+            OscArg::Str(_) => None,
+        }
+    }
+}
+
+/// Listen for OSC messages on `bind_address:port` and run the matching
+/// remote commands (`/wwmp/play`, `/wwmp/tempo 0.9`, ...)
+pub fn start(app: AppHandle, bind_address: &str, port: u16) -> Result<()> {
+    let socket = UdpSocket::bind((bind_address, port))?;
+
+    thread::spawn(move || {
+        let mut buf = [0u8; 1536];
+        while let Ok(len) = socket.recv(&mut buf) {
+            // Malformed or unknown messages are ignored, as OSC has no replies
+            if let Ok(command) = parse_packet(&buf[..len]) {
+                let _ = remote::execute(&app, command);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Turn a single OSC message into a remote command
+fn parse_packet(packet: &[u8]) -> Result<RemoteCommand> {
+    let (address, args) = decode_message(packet)?;
+    let name = address
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| anyhow!("Not a WWMP address: {}", address))?;
+    let number = || args.first().and_then(OscArg::as_f64);
+    let text = || match args.first() {
+        Some(OscArg::Str(s)) => Some(s.clone()),
+        _ => None,
+    };
+
+    let command = match name {
+        "play" => RemoteCommand::Play,
+        "pause" => RemoteCommand::Pause,
+        "stop" => RemoteCommand::Stop,
+        "next" => RemoteCommand::Next,
+        "tempo" => RemoteCommand::SetTempo {
+            factor: number().ok_or_else(|| anyhow!("/wwmp/tempo needs a number"))?,
+        },
+        "seek" => RemoteCommand::Seek {
+            position_ms: number()
+                .ok_or_else(|| anyhow!("/wwmp/seek needs a number"))?
+                .max(0.0) as u64,
+        },
+        "load" => RemoteCommand::Load {
+            path: text().ok_or_else(|| anyhow!("/wwmp/load needs a path"))?,
+        },
+        "queue" => RemoteCommand::Queue {
+            path: text().ok_or_else(|| anyhow!("/wwmp/queue needs a path"))?,
+        },
+        _ => bail!("Unknown OSC address: {}", address),
+    };
+    Ok(command)
+}
+
+/// Decode an OSC message into its address and arguments
+fn decode_message(packet: &[u8]) -> Result<(String, Vec<OscArg>)> {
+    let mut pos = 0;
+    let address = read_string(packet, &mut pos)?;
+    if !address.starts_with('/') {
+        bail!("Not an OSC message");
+    }
+
+    // Messages without a type tag string carry no arguments
+    if pos >= packet.len() {
+        return Ok((address, Vec::new()));
+    }
+    let tags = read_string(packet, &mut pos)?;
+    let tags = tags
+        .strip_prefix(',')
+        .ok_or_else(|| anyhow!("Missing OSC type tags"))?;
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        let arg = match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(read_word(packet, &mut pos)?)),
+            'f' => OscArg::Float(f32::from_be_bytes(read_word(packet, &mut pos)?)),
+            's' => OscArg::Str(read_string(packet, &mut pos)?),
+            _ => bail!("Unsupported OSC argument type: {}", tag),
+        };
+        args.push(arg);
+    }
+    Ok((address, args))
+}
+
+/// Read a NUL-terminated string padded to a multiple of 4 bytes
+fn read_string(packet: &[u8], pos: &mut usize) -> Result<String> {
+    let rest = packet.get(*pos..).unwrap_or_default();
+    let len = rest
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| anyhow!("Unterminated OSC string"))?;
+    let text = String::from_utf8(rest[..len].to_vec())?;
+    *pos += (len + 4) & !3;
+    Ok(text)
+}
+
+fn read_word(packet: &[u8], pos: &mut usize) -> Result<[u8; 4]> {
+    let word = packet
+        .get(*pos..*pos + 4)
+        .ok_or_else(|| anyhow!("Truncated OSC argument"))?;
+    *pos += 4;
+    Ok(word.try_into()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_osc_commands() {
+        // "/wwmp/tempo" ",f" 0.5
+        let mut packet = b"/wwmp/tempo\0,f\0\0".to_vec();
+        packet.extend_from_slice(&0.5f32.to_be_bytes());
+        assert!(matches!(
+            parse_packet(&packet).unwrap(),
+            RemoteCommand::SetTempo { factor } if factor == 0.5
+        ));
+
+        let packet = b"/wwmp/load\0\0,s\0\0song.mid\0\0\0\0";
+        assert!(matches!(
+            parse_packet(packet).unwrap(),
+            RemoteCommand::Load { path } if path == "song.mid"
+        ));
+
+        assert!(matches!(
+            parse_packet(b"/wwmp/play\0\0").unwrap(),
+            RemoteCommand::Play
+        ));
+        assert!(parse_packet(b"/other/play\0").is_err());
+        assert!(parse_packet(b"/wwmp/tempo\0,\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use tauri::{AppHandle, LogicalPosition, Manager, WindowBuilder, WindowUrl};
+
+use crate::config::OverlayConfig;
+
+/// Window label of the overlay
+pub const OVERLAY_LABEL: &str = "overlay";
+
+/// Show the always-on-top overlay, creating it on first use. It listens
+/// to the same playback events as the main window.
+pub fn show(app: &AppHandle, overlay: &OverlayConfig) -> Result<()> {
+    if let Some(window) = app.get_window(OVERLAY_LABEL) {
+        window.set_position(LogicalPosition::new(overlay.x, overlay.y))?;
+        window.show()?;
+        return Ok(());
+    }
+
+    WindowBuilder::new(app, OVERLAY_LABEL, WindowUrl::App("overlay.html".into()))
+        .title("WWMP Overlay")
+        .inner_size(overlay.width, overlay.height)
+        .position(overlay.x, overlay.y)
+        .decorations(false)
+        .transparent(true)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .resizable(false)
+        // Never steal focus from the game
+        .focused(false)
+        .build()?;
+    Ok(())
+}
+
+pub fn hide(app: &AppHandle) -> Result<()> {
+    if let Some(window) = app.get_window(OVERLAY_LABEL) {
+        window.hide()?;
+    }
+    Ok(())
+}
+
+/// Move the overlay, if it is open
+pub fn move_to(app: &AppHandle, x: f64, y: f64) -> Result<()> {
+    if let Some(window) = app.get_window(OVERLAY_LABEL) {
+        window.set_position(LogicalPosition::new(x, y))?;
+    }
+    Ok(())
+}
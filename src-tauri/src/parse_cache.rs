@@ -0,0 +1,183 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::{AppConfig, NotePairing};
+use crate::midi::{Beat, MidiFile, MidiInfo, NoteEvent, ProgramChange};
+
+/// Bumped whenever parsing changes what it extracts, so entries written
+/// by older versions are parsed again
+const CACHE_VERSION: u32 = 2;
+
+/// Songs kept in the cache; the least recently opened go first
+const MAX_ENTRIES: usize = 200;
+
+/// What parsing a file extracts, as stored in the cache
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedParse {
+    version: u32,
+    info: MidiInfo,
+    events: Vec<NoteEvent>,
+    bar_ms: u64,
+    time_signature: (u8, u8),
+    beats: Vec<Beat>,
+    programs: Vec<ProgramChange>,
+}
+
+impl CachedParse {
+    fn from_midi(midi: &MidiFile) -> Self {
+        Self {
+            version: CACHE_VERSION,
+            info: midi.info.clone(),
+            events: midi.events.clone(),
+            bar_ms: midi.bar_ms,
+            time_signature: midi.time_signature,
+            beats: midi.beats.clone(),
+            programs: midi.programs.clone(),
+        }
+    }
+
+    /// The song at `path` with content `hash`, or None if written by
+    /// another version
+    fn into_midi(self, path: &str, hash: &str) -> Option<MidiFile> {
+        (self.version == CACHE_VERSION).then(|| MidiFile {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            info: self.info,
+            events: self.events,
+            bar_ms: self.bar_ms,
+            time_signature: self.time_signature,
+            beats: self.beats,
+            disabled_tracks: Vec::new(),
+            bookmarks: Vec::new(),
+            programs: self.programs,
+        })
+    }
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    Ok(AppConfig::config_dir()?.join("parse_cache"))
+}
+
+/// Note pairing changes which notes a file holds, so it is part of the key
+fn entry_path(dir: &Path, hash: &str, pairing: NotePairing) -> PathBuf {
+    dir.join(format!("{}-{:?}.json", hash, pairing).to_lowercase())
+}
+
+/// The parsed song at `path`, if a file with content `hash` was parsed
+/// before with the same note pairing
+pub fn get(path: &str, hash: &str, pairing: NotePairing) -> Option<MidiFile> {
+    let entry = entry_path(&cache_dir().ok()?, hash, pairing);
+    let content = fs::read_to_string(&entry).ok()?;
+    let cached: CachedParse = serde_json::from_str(&content).ok()?;
+
+    // Opening a song keeps it in the cache longer
+    if let Ok(file) = fs::File::options().append(true).open(&entry) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    cached.into_midi(path, hash)
+}
+
+/// Remember a freshly parsed song, making room if the cache is full
+pub fn put(midi: &MidiFile, pairing: NotePairing) -> Result<()> {
+    let dir = cache_dir()?;
+    fs::create_dir_all(&dir)?;
+    let content = serde_json::to_string(&CachedParse::from_midi(midi))?;
+    fs::write(entry_path(&dir, &midi.hash, pairing), content)?;
+
+    let mut entries: Vec<(SystemTime, PathBuf)> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    for path in least_recent(&mut entries, MAX_ENTRIES) {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// Entries beyond the `keep` most recently used
+fn least_recent(entries: &mut [(SystemTime, PathBuf)], keep: usize) -> Vec<PathBuf> {
+    entries.sort_by_key(|(modified, _)| Reverse(*modified));
+    entries
+        .iter()
+        .skip(keep)
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Empty the cache, e.g. when its entries are suspected to be wrong
+pub fn clear() -> Result<()> {
+    let dir = cache_dir()?;
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_trip() {
+        let midi = MidiFile {
+            path: "old/song.mid".into(),
+            hash: "abc".into(),
+            info: MidiInfo {
+                track_count: 1,
+                duration_ms: 500,
+                note_count: 1,
+                min_note: 60,
+                max_note: 60,
+                track_names: vec!["Piano".into()],
+                markers: Vec::new(),
+                warnings: Vec::new(),
+            },
+            events: vec![NoteEvent {
+                start_ms: 0,
+                duration_ms: 500,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                channel: 0,
+            }],
+            bar_ms: 2000,
+            time_signature: (4, 4),
+            beats: Vec::new(),
+            disabled_tracks: vec![0],
+            bookmarks: Vec::new(),
+            programs: Vec::new(),
+        };
+        let json = serde_json::to_string(&CachedParse::from_midi(&midi)).unwrap();
+        let cached: CachedParse = serde_json::from_str(&json).unwrap();
+        let loaded = cached.into_midi("new/song.mid", "abc").unwrap();
+        assert_eq!(loaded.path, "new/song.mid");
+        assert_eq!(loaded.info.track_names, ["Piano"]);
+        assert_eq!(loaded.events[0].duration_ms, 500);
+        // Per-song choices come from the song settings, not the cache
+        assert!(loaded.disabled_tracks.is_empty());
+
+        let mut stale = CachedParse::from_midi(&midi);
+        stale.version = CACHE_VERSION + 1;
+        assert!(stale.into_midi("song.mid", "abc").is_none());
+    }
+
+    #[test]
+    fn test_least_recent() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut entries = vec![
+            (at(20), PathBuf::from("b")),
+            (at(10), PathBuf::from("a")),
+            (at(30), PathBuf::from("c")),
+        ];
+        assert_eq!(
+            least_recent(&mut entries, 1),
+            [PathBuf::from("b"), PathBuf::from("a")]
+        );
+        assert!(least_recent(&mut entries, 3).is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use std::fs;
+use tauri::{AppHandle, Manager};
+
+use crate::abc;
+use crate::config::AppConfig;
+use crate::error::CommandError;
+use crate::keyboard::Modifier;
+use crate::mapper::keystroke_to_midi;
+use crate::midi::{self, MidiInfo, NoteEvent};
+use crate::mml;
+use crate::AppState;
+
+/// Pasted keysheets have no timing, so each bar gets this long: a 4/4
+/// bar at 120 BPM
+const KEYSHEET_BAR_MS: u64 = 2000;
+
+/// What pasted text was read as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasteFormat {
+    Abc,
+    Keysheet,
+    Mml,
+}
+
+/// A song made from pasted text, saved as a MIDI file so it plays, resumes
+/// and shows up in the recent list like any other
+#[derive(Debug, Clone, Serialize)]
+pub struct PastedFile {
+    pub path: String,
+    pub format: PasteFormat,
+    pub info: MidiInfo,
+}
+
+/// Pasted text as the contents of a MIDI file, with its format and
+/// title if it has one. ABC is known by its `K:` field and a keysheet (as
+/// `export_keysheet` writes them) by every token being a key of the
+/// layout; anything else is tried as MML.
+pub fn convert(text: &str, config: &AppConfig) -> Result<(PasteFormat, Option<String>, Vec<u8>)> {
+    if abc::is_abc(text) {
+        return Ok((PasteFormat::Abc, abc::title(text), abc::to_smf(text)?));
+    }
+    if let Ok((title, notes)) = keysheet_notes(text, config) {
+        return Ok((PasteFormat::Keysheet, title, midi::notes_to_smf(&notes)?));
+    }
+    match mml::to_smf(text) {
+        Ok(smf) => Ok((PasteFormat::Mml, None, smf)),
+        Err(e) => bail!("Not ABC, MML or a keysheet ({})", e),
+    }
+}
+
+/// The title and notes of a keysheet: `|`-separated bars of key labels
+/// such as `Shift+F`, `[A D]` chords and `-` for an empty bar. Chords are
+/// spread evenly over their bar, each held until the next.
+fn keysheet_notes(text: &str, config: &AppConfig) -> Result<(Option<String>, Vec<NoteEvent>)> {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    // A title is followed by a blank line
+    let (title, lines) = match lines.as_slice() {
+        [title, "", rest @ ..] if !rest.is_empty() => (Some(title.to_string()), rest),
+        lines => (None, lines),
+    };
+
+    let mut notes = Vec::new();
+    let bars = lines
+        .iter()
+        .copied()
+        .filter(|line| !line.is_empty())
+        .flat_map(|line| line.split('|'));
+    for (bar, text) in bars.enumerate() {
+        let chords = keysheet_chords(text.trim());
+        let span_ms = KEYSHEET_BAR_MS / chords.len().max(1) as u64;
+        for (i, chord) in chords.iter().enumerate() {
+            for label in chord {
+                let (modifier, key) = match label.split_once('+') {
+                    Some(("Shift", key)) => (Modifier::Shift, key),
+                    Some(("Ctrl", key)) => (Modifier::Ctrl, key),
+                    _ => (Modifier::None, *label),
+                };
+                let note = keystroke_to_midi(key, modifier, config)
+                    .ok_or_else(|| anyhow!("'{}' is not a key of the layout", label))?;
+                notes.push(NoteEvent {
+                    start_ms: bar as u64 * KEYSHEET_BAR_MS + i as u64 * span_ms,
+                    duration_ms: span_ms,
+                    note,
+                    velocity: 100,
+                    track: 0,
+                    channel: 0,
+                });
+            }
+        }
+    }
+    if notes.is_empty() {
+        bail!("The keysheet has no keys");
+    }
+    Ok((title, notes))
+}
+
+/// The key labels of each chord in one keysheet bar
+fn keysheet_chords(bar: &str) -> Vec<Vec<&str>> {
+    if bar == "-" {
+        return Vec::new();
+    }
+    let mut chords = Vec::new();
+    let mut rest = bar;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let (chord, after) = match rest.strip_prefix('[') {
+            Some(inner) => inner.split_once(']').unwrap_or((inner, "")),
+            None => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+        };
+        chords.push(chord.split_whitespace().collect());
+        rest = after;
+    }
+    chords
+}
+
+/// Save pasted text as a song in the config folder and load it
+pub fn open_text(app: &AppHandle, text: &str) -> Result<PastedFile, CommandError> {
+    let config = app.state::<AppState>().config.lock().unwrap().clone();
+    let (format, title, smf) = convert(text, &config)?;
+
+    // Named by content too, so pasting the same snippet again reuses it
+    let title: String = title
+        .unwrap_or_else(|| "Pasted song".to_string())
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .collect();
+    let hash = midi::content_hash(text.as_bytes());
+    let dir = AppConfig::config_dir()?.join("pasted");
+    fs::create_dir_all(&dir).map_err(anyhow::Error::from)?;
+    let path = dir.join(format!("{} {}.mid", title.trim(), &hash[..8]));
+    fs::write(&path, smf).map_err(anyhow::Error::from)?;
+
+    let path = path.to_string_lossy().into_owned();
+    let info = crate::load_song(app, &path)?;
+    Ok(PastedFile { path, format, info })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keysheet_notes() {
+        let config = AppConfig::default();
+        let (title, notes) =
+            keysheet_notes("Song\n\n[A D] S | Shift+R | - | A\n", &config).unwrap();
+        assert_eq!(title.as_deref(), Some("Song"));
+        let starts: Vec<(u64, u8)> = notes.iter().map(|n| (n.start_ms, n.note)).collect();
+        assert_eq!(
+            starts,
+            [(0, 60), (0, 64), (1000, 62), (2000, 78), (6000, 60)]
+        );
+        assert_eq!(notes[2].duration_ms, 1000);
+
+        assert!(keysheet_notes("A F1", &config).is_err());
+        assert!(keysheet_notes("-", &config).is_err());
+    }
+
+    #[test]
+    fn test_convert_detects_format() {
+        let config = AppConfig::default();
+        let format = |text| convert(text, &config).unwrap().0;
+        assert_eq!(format("X:1\nT:Tune\nK:D\nDEF"), PasteFormat::Abc);
+        assert_eq!(format("Song\n\nA S | D"), PasteFormat::Keysheet);
+        assert_eq!(format("t140 o4 l8 cdefgab>c"), PasteFormat::Mml);
+        assert!(convert("Hello, world!", &config).is_err());
+    }
+}
@@ -1,21 +1,226 @@
-use anyhow::Result;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::arrange;
 use crate::config::AppConfig;
 use crate::keyboard::{self, Modifier};
-use crate::mapper::{midi_to_instrument, note_to_keystroke};
-use crate::midi::{limit_polyphony, MidiFile, NoteEvent};
+use crate::library;
+use crate::mapper;
+use crate::midi::{limit_polyphony, program_at, Beat, MidiFile, NoteEvent};
+use crate::midi_clock::ClockSender;
+use crate::midi_output::OutputConnection;
+use crate::scripting;
 
 /// Scheduled keystroke event
 #[derive(Debug, Clone)]
-struct ScheduledEvent {
-    time_ms: u64,
-    key: String,
-    modifier: Modifier,
-    is_key_down: bool,
+pub struct ScheduledEvent {
+    /// Song time, before the tempo factor is applied
+    pub time_ms: u64,
+    pub key: String,
+    pub modifier: Modifier,
+    pub is_key_down: bool,
+    /// Track and channel of the note this keystroke plays
+    pub track: usize,
+    pub channel: u8,
+}
+
+/// Event name under which `PlaybackEvent`s are emitted to the UI
+pub const PLAYBACK_EVENT: &str = "playback://progress";
+
+/// Sleep between checks of the playback thread for due events
+pub(crate) const TICK: Duration = Duration::from_micros(500);
+
+/// How often the playback thread reports its position
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Playback progress reported while a song runs
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PlaybackEvent {
+    /// Waiting out the start delay
+    Countdown { title: String, remaining_ms: u64 },
+    Playing {
+        title: String,
+        position_ms: u64,
+        duration_ms: u64,
+    },
+    /// A practice loop starting its next time through the section
+    Repetition {
+        /// From 1
+        repetition: usize,
+        repetitions: usize,
+        tempo_percent: f64,
+    },
+    /// A metronome beat; `bar` is 0 during the count-in
+    Beat { bar: u32, beat: u8 },
+    /// A note of the audio preview starting or stopping, at the time the
+    /// game sounds it (the keystroke less `latency_offset_ms`), with the
+    /// General MIDI program of its track so the preview can voice it
+    Note { note: u8, on: bool, program: u8 },
+    /// The song reached a beat, for a conductor display; sent whether or
+    /// not the metronome is on
+    Position {
+        bar: u32,
+        beat: u8,
+        time_signature: (u8, u8),
+    },
+    /// The song at `path` played through to the end. The path is the one
+    /// played, which another song may have replaced as the loaded one by
+    /// the time this arrives.
+    Finished { path: String },
+    /// Playback of the song at `path` was stopped before the end, at this
+    /// song position
+    Stopped { path: String, position_ms: u64 },
+}
+
+/// Whether a song is playing, for `PlaybackState`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// Everything the UI needs to redraw the transport after a reload
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackState {
+    pub state: Transport,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+    pub current_file: Option<String>,
+    pub tempo_factor: f64,
+    pub transpose: i32,
+}
+
+/// A section to loop, speeding up each time through until it is played
+/// at the set tempo
+#[derive(Debug, Clone, Deserialize)]
+pub struct PracticeLoop {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// Tempo of the first repetition, as a percentage of the set tempo
+    pub start_percent: f64,
+    /// Added to the tempo percentage after each repetition
+    pub step_percent: f64,
+}
+
+impl PracticeLoop {
+    /// Why the loop cannot be played, if it cannot
+    pub fn check(&self) -> Result<(), String> {
+        if self.end_ms <= self.start_ms {
+            return Err("The section must end after it starts".into());
+        }
+        if !(self.start_percent > 0.0 && self.start_percent <= 100.0) {
+            return Err(format!(
+                "Starting tempo must be above 0% and at most 100%, got {}%",
+                self.start_percent
+            ));
+        }
+        // Smaller steps would mean hundreds of repetitions, or with a tiny
+        // enough one, building the ramp would never finish
+        if self.step_percent.is_nan() || self.step_percent < 1.0 {
+            return Err(format!(
+                "Tempo step must be at least 1%, got {}%",
+                self.step_percent
+            ));
+        }
+        Ok(())
+    }
+
+    /// Tempo percentage of each repetition, ending with one at 100%
+    pub fn tempo_percents(&self) -> Vec<f64> {
+        let mut percents = Vec::new();
+        let mut percent = self.start_percent;
+        while percent < 100.0 {
+            percents.push(percent);
+            percent += self.step_percent;
+        }
+        percents.push(100.0);
+        percents
+    }
+}
+
+/// Receives `PlaybackEvent`s from the playback thread
+#[derive(Clone)]
+struct Listener(Arc<dyn Fn(PlaybackEvent) + Send + Sync>);
+
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Listener")
+    }
+}
+
+/// Config shared with the playback thread so changes apply mid-song
+#[derive(Debug, Default)]
+struct LiveSettings {
+    config: Mutex<AppConfig>,
+    /// Bumped on every update so the thread knows to re-read `config`
+    generation: AtomicU64,
+    /// Song position (ms) to jump to on the next tick
+    seek_to: Mutex<Option<u64>>,
+    /// Song position (ms) as of the last tick, and the song's length
+    position_ms: AtomicU64,
+    duration_ms: AtomicU64,
+    /// Keep time and the audio preview going without sending keystrokes
+    muted: AtomicBool,
+}
+
+/// Stops playback without going through the engine's mutex, so it works
+/// even while another thread holds it
+#[derive(Debug, Clone)]
+pub struct PanicHandle {
+    is_playing: Arc<AtomicBool>,
+    is_paused: Arc<AtomicBool>,
+}
+
+impl PanicHandle {
+    /// Abort the playback thread and release every key
+    pub fn trigger(&self) {
+        self.is_playing.store(false, Ordering::SeqCst);
+        self.is_paused.store(false, Ordering::SeqCst);
+        let _ = keyboard::release_all();
+    }
+}
+
+/// One launch of the playback thread. A restart starts a new run, and
+/// the old thread must then leave keys and shared state to the new one.
+struct Run {
+    id: u64,
+    latest: Arc<AtomicU64>,
+    is_playing: Arc<AtomicBool>,
+}
+
+impl Run {
+    /// No newer song has been launched since this one
+    fn is_current(&self) -> bool {
+        self.latest.load(Ordering::SeqCst) == self.id
+    }
+
+    /// Still current and not stopped
+    fn is_active(&self) -> bool {
+        self.is_current() && self.is_playing.load(Ordering::SeqCst)
+    }
+}
+
+/// Held by the playback thread: if it panics mid-chord, releases every
+/// key and marks playback stopped while unwinding
+struct UnwindGuard<'a> {
+    run: &'a Run,
+}
+
+impl Drop for UnwindGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() && self.run.is_current() {
+            let _ = keyboard::release_all();
+            self.run.is_playing.store(false, Ordering::SeqCst);
+        }
+    }
 }
 
 /// Playback engine state
@@ -23,6 +228,12 @@ struct ScheduledEvent {
 pub struct PlaybackEngine {
     is_playing: Arc<AtomicBool>,
     is_paused: Arc<AtomicBool>,
+    /// Id of the latest launch; see `Run`
+    run: Arc<AtomicU64>,
+    live: Arc<LiveSettings>,
+    listener: Option<Listener>,
+    /// Port that MIDI clock and song position are sent to, if any
+    clock_output: Option<Arc<Mutex<OutputConnection>>>,
 }
 
 impl PlaybackEngine {
@@ -30,78 +241,430 @@ impl PlaybackEngine {
         Self {
             is_playing: Arc::new(AtomicBool::new(false)),
             is_paused: Arc::new(AtomicBool::new(false)),
+            run: Arc::new(AtomicU64::new(0)),
+            live: Arc::new(LiveSettings::default()),
+            listener: None,
+            clock_output: None,
         }
     }
 
+    /// Report progress of every song played from now on to `listener`
+    pub fn set_listener(&mut self, listener: impl Fn(PlaybackEvent) + Send + Sync + 'static) {
+        self.listener = Some(Listener(Arc::new(listener)));
+    }
+
+    /// Send MIDI clock for songs played from now on to `output`, or stop
+    /// sending it
+    pub fn set_clock_output(&mut self, output: Option<OutputConnection>) {
+        self.clock_output = output.map(|output| Arc::new(Mutex::new(output)));
+    }
+
     /// Start playback of the MIDI file
     pub fn start(&mut self, midi: &MidiFile, config: &AppConfig) -> Result<()> {
+        self.launch(
+            midi,
+            config,
+            midi.enabled_events(),
+            midi.beats.clone(),
+            vec![1.0],
+            None,
+        )
+    }
+
+    /// Start the song part way through, at song position `from_ms`
+    pub fn start_from(&mut self, midi: &MidiFile, config: &AppConfig, from_ms: u64) -> Result<()> {
+        let notes = range_notes(&midi.enabled_events(), from_ms, u64::MAX);
+        self.launch(
+            midi,
+            config,
+            notes,
+            midi.beats.clone(),
+            vec![1.0],
+            Some(from_ms),
+        )
+    }
+
+    /// Play only the part of the song within `start_ms..end_ms`, keeping
+    /// song positions so progress reads as in the full song
+    pub fn start_range(
+        &mut self,
+        midi: &MidiFile,
+        config: &AppConfig,
+        start_ms: u64,
+        end_ms: u64,
+    ) -> Result<()> {
+        if start_ms >= end_ms {
+            bail!("Range must end after it starts");
+        }
+        let notes = range_notes(&midi.enabled_events(), start_ms, end_ms);
+        let beats = midi
+            .beats
+            .iter()
+            .filter(|b| b.time_ms < end_ms)
+            .cloned()
+            .collect();
+        self.launch(midi, config, notes, beats, vec![1.0], Some(start_ms))
+    }
+
+    /// Loop a section of the song, speeding up each time through
+    pub fn start_practice(
+        &mut self,
+        midi: &MidiFile,
+        config: &AppConfig,
+        practice: &PracticeLoop,
+    ) -> Result<()> {
+        let (start_ms, end_ms) = (practice.start_ms, practice.end_ms);
+        let notes = section_notes(&midi.enabled_events(), start_ms, end_ms);
+        let beats = midi
+            .beats
+            .iter()
+            .filter(|b| (start_ms..end_ms).contains(&b.time_ms))
+            .map(|b| Beat {
+                time_ms: b.time_ms - start_ms,
+                ..*b
+            })
+            .collect();
+        let ramp = practice
+            .tempo_percents()
+            .iter()
+            .map(|percent| percent / 100.0)
+            .collect();
+        // The section's notes are moved to start at 0, so the song's own
+        // start position doesn't apply
+        self.launch(midi, config, notes, beats, ramp, Some(0))
+    }
+
+    /// Play `notes` once for each entry of `ramp`, at that fraction of
+    /// the configured tempo, from song position `from_ms` (by default,
+    /// the start or the first note; see `start_position_ms`)
+    fn launch(
+        &mut self,
+        midi: &MidiFile,
+        config: &AppConfig,
+        notes: Vec<NoteEvent>,
+        beats: Vec<Beat>,
+        ramp: Vec<f64>,
+        from_ms: Option<u64>,
+    ) -> Result<()> {
         // Stop any existing playback
         self.stop();
+        self.update_config(config);
+        *self.live.seek_to.lock().unwrap() = None;
 
         // Build event timeline
-        let events = build_timeline(midi, config)?;
+        let mut events = build_timeline(&notes, &beats, config)?;
         if events.is_empty() {
             return Ok(());
         }
 
-        let is_playing = self.is_playing.clone();
+        let run = Run {
+            id: self.run.fetch_add(1, Ordering::SeqCst) + 1,
+            latest: self.run.clone(),
+            is_playing: self.is_playing.clone(),
+        };
         let is_paused = self.is_paused.clone();
+        let live = self.live.clone();
         let start_delay = config.start_delay_ms;
-        let tempo_factor = config.tempo_factor;
+        let mut current = config.clone();
+        let title = library::title_from_path(&midi.path);
+        let path = midi.path.clone();
+        let programs = midi.programs.clone();
+        let duration_ms = events.last().map_or(0, |e| e.time_ms);
+        let start_ms = from_ms.unwrap_or_else(|| start_position_ms(&midi.events, config)) as f64;
+        live.position_ms.store(start_ms as u64, Ordering::SeqCst);
+        live.duration_ms.store(duration_ms, Ordering::SeqCst);
+        let mut count_in = if config.metronome.count_in {
+            count_in_beats(start_delay, midi.bar_ms, midi.time_signature.0)
+        } else {
+            Vec::new()
+        };
+        let listener = self.listener.clone();
+        let mut clock = self
+            .clock_output
+            .clone()
+            .map(|output| ClockSender::new(output, &beats));
+        let report = move |event: PlaybackEvent| {
+            if let Some(Listener(listener)) = &listener {
+                listener(event);
+            }
+        };
 
-        is_playing.store(true, Ordering::SeqCst);
+        run.is_playing.store(true, Ordering::SeqCst);
         is_paused.store(false, Ordering::SeqCst);
 
         // Spawn playback thread
         thread::spawn(move || {
-            let start_time = Instant::now();
+            let _guard = UnwindGuard { run: &run };
+            let mut generation = live.generation.load(Ordering::SeqCst);
             let mut event_index = 0;
+            let mut preview_index = 0;
+            let sounds_at = |event: &ScheduledEvent, config: &AppConfig| {
+                event
+                    .time_ms
+                    .saturating_add_signed(-config.latency_offset_ms) as f64
+            };
+            let skipped_beats = beats.partition_point(|b| (b.time_ms as f64) < start_ms);
+            let mut beat_index = skipped_beats;
 
-            // Initial delay
-            thread::sleep(Duration::from_millis(start_delay));
-
-            while event_index < events.len() && is_playing.load(Ordering::SeqCst) {
-                // Handle pause
-                while is_paused.load(Ordering::SeqCst) && is_playing.load(Ordering::SeqCst) {
-                    thread::sleep(Duration::from_millis(10));
+            // Initial delay, counting down so the player can get ready
+            let delay_start = Instant::now();
+            let delay_end = delay_start + Duration::from_millis(start_delay);
+            count_in.reverse();
+            while run.is_active() {
+                let now = Instant::now();
+                let remaining = delay_end.saturating_duration_since(now);
+                if remaining.is_zero() {
+                    break;
                 }
+                let elapsed_ms = now.duration_since(delay_start).as_millis() as u64;
+                while let Some(&(_, beat)) = count_in.last().filter(|(at, _)| *at <= elapsed_ms) {
+                    count_in.pop();
+                    report(PlaybackEvent::Beat { bar: 0, beat });
+                }
+                report(PlaybackEvent::Countdown {
+                    title: title.clone(),
+                    remaining_ms: remaining.as_millis() as u64,
+                });
 
-                if !is_playing.load(Ordering::SeqCst) {
-                    break;
+                let next_beat = count_in.last().map_or(remaining, |&(at, _)| {
+                    Duration::from_millis(at.saturating_sub(elapsed_ms))
+                });
+                thread::sleep(remaining.min(next_beat).min(Duration::from_millis(100)));
+            }
+
+            // Song position advances by wall time scaled by the tempo in
+            // effect at that moment, so tempo can change mid-song
+            let mut position_ms = start_ms;
+            let mut last_tick = Instant::now();
+            let mut last_report: Option<Instant> = None;
+
+            if let Some(clock) = &mut clock {
+                clock.start(position_ms);
+            }
+
+            let mut repetition = 0;
+            loop {
+                if ramp.len() > 1 {
+                    report(PlaybackEvent::Repetition {
+                        repetition: repetition + 1,
+                        repetitions: ramp.len(),
+                        tempo_percent: ramp[repetition] * 100.0,
+                    });
                 }
 
-                let elapsed = start_time.elapsed().as_millis() as u64;
-                let scaled_elapsed = (elapsed as f64 * tempo_factor) as u64;
+                while event_index < events.len() && run.is_active() {
+                    // Handle pause
+                    let paused = is_paused.load(Ordering::SeqCst);
+                    if let Some(clock) = clock.as_ref().filter(|_| paused) {
+                        clock.stop();
+                    }
+                    while is_paused.load(Ordering::SeqCst) && run.is_active() {
+                        thread::sleep(Duration::from_millis(10));
+                        last_tick = Instant::now();
+                    }
 
-                // Process all events that should have fired by now
-                while event_index < events.len() {
-                    let event = &events[event_index];
-                    if event.time_ms > scaled_elapsed {
+                    if !run.is_active() {
                         break;
                     }
+                    if let Some(clock) = clock.as_ref().filter(|_| paused) {
+                        clock.resume();
+                    }
 
-                    // Fire the event
-                    let _ = if event.is_key_down {
-                        keyboard::press_key(&event.key, event.modifier)
-                    } else {
-                        keyboard::release_key(&event.key, event.modifier)
-                    };
+                    // Pick up settings changed since the last tick
+                    let latest = live.generation.load(Ordering::SeqCst);
+                    if latest != generation {
+                        generation = latest;
+                        let updated = live.config.lock().unwrap().clone();
+                        if needs_remap(&current, &updated) {
+                            if let Ok(remapped) = build_timeline(&notes, &beats, &updated) {
+                                let _ = keyboard::release_all();
+                                // Events up to here already fired under the old mapping
+                                event_index =
+                                    remapped.partition_point(|e| e.time_ms as f64 <= position_ms);
+                                preview_index = remapped
+                                    .partition_point(|e| sounds_at(e, &updated) <= position_ms);
+                                events = remapped;
+                            }
+                        }
+                        current = updated;
+                    }
 
-                    event_index += 1;
+                    if let Some(target) = live.seek_to.lock().unwrap().take() {
+                        let _ = keyboard::release_all();
+                        position_ms = target as f64;
+                        event_index = events.partition_point(|e| (e.time_ms as f64) < position_ms);
+                        preview_index =
+                            events.partition_point(|e| sounds_at(e, &current) < position_ms);
+                        beat_index = beats.partition_point(|b| (b.time_ms as f64) < position_ms);
+                        if let Some(clock) = &mut clock {
+                            clock.seek(position_ms);
+                        }
+                    }
+
+                    let now = Instant::now();
+                    position_ms += now.duration_since(last_tick).as_secs_f64()
+                        * 1000.0
+                        * current.tempo_factor
+                        * ramp[repetition];
+                    last_tick = now;
+                    live.position_ms.store(position_ms as u64, Ordering::SeqCst);
+                    if let Some(clock) = &mut clock {
+                        clock.advance(position_ms);
+                    }
+
+                    if last_report.is_none_or(|t| now.duration_since(t) >= PROGRESS_INTERVAL) {
+                        last_report = Some(now);
+                        report(PlaybackEvent::Playing {
+                            title: title.clone(),
+                            position_ms: position_ms as u64,
+                            duration_ms,
+                        });
+                    }
+
+                    while let Some(beat) = beats.get(beat_index) {
+                        if beat.time_ms as f64 > position_ms {
+                            break;
+                        }
+                        report(PlaybackEvent::Position {
+                            bar: beat.bar,
+                            beat: beat.beat,
+                            time_signature: beat.time_signature,
+                        });
+                        if current.metronome.during_playback {
+                            report(PlaybackEvent::Beat {
+                                bar: beat.bar,
+                                beat: beat.beat,
+                            });
+                        }
+                        beat_index += 1;
+                    }
+
+                    while let Some(event) = events.get(preview_index) {
+                        if sounds_at(event, &current) > position_ms {
+                            break;
+                        }
+                        if current.audio_preview {
+                            if let Some(note) =
+                                mapper::keystroke_to_midi(&event.key, event.modifier, &current)
+                            {
+                                report(PlaybackEvent::Note {
+                                    note,
+                                    on: event.is_key_down,
+                                    program: program_at(
+                                        &programs,
+                                        event.track,
+                                        event.channel,
+                                        event.time_ms,
+                                    ),
+                                });
+                            }
+                        }
+                        preview_index += 1;
+                    }
+
+                    // Process all events that should have fired by now
+                    while event_index < events.len() && run.is_active() {
+                        let event = &events[event_index];
+                        if event.time_ms as f64 > position_ms {
+                            break;
+                        }
+
+                        // Fire the event
+                        if live.muted.load(Ordering::SeqCst) {
+                            event_index += 1;
+                            continue;
+                        }
+                        let _ = if event.is_key_down {
+                            keyboard::press_key(&event.key, event.modifier)
+                        } else {
+                            keyboard::release_key(&event.key, event.modifier)
+                        };
+
+                        event_index += 1;
+                    }
+
+                    // Small sleep to avoid busy-waiting
+                    thread::sleep(TICK);
                 }
 
-                // Small sleep to avoid busy-waiting
-                thread::sleep(Duration::from_micros(500));
+                let finished = event_index >= events.len();
+                if !finished || repetition + 1 >= ramp.len() || !run.is_active() {
+                    break;
+                }
+                // Go around again, a little faster
+                let _ = keyboard::release_all();
+                repetition += 1;
+                event_index = 0;
+                preview_index = 0;
+                beat_index = skipped_beats;
+                position_ms = start_ms;
+                last_tick = Instant::now();
+                if let Some(clock) = &mut clock {
+                    clock.seek(position_ms);
+                }
+            }
+
+            if let Some(clock) = &clock {
+                clock.stop();
+            }
+            // A newer song owns the keys and the shared state now
+            if !run.is_current() {
+                return;
             }
 
             // Release all keys when done
+            let finished = event_index >= events.len();
             let _ = keyboard::release_all();
-            is_playing.store(false, Ordering::SeqCst);
+            run.is_playing.store(false, Ordering::SeqCst);
+            report(if finished {
+                PlaybackEvent::Finished { path }
+            } else {
+                PlaybackEvent::Stopped {
+                    path,
+                    position_ms: position_ms as u64,
+                }
+            });
         });
 
         Ok(())
     }
 
+    /// Handle for aborting playback without locking the engine
+    pub fn panic_handle(&self) -> PanicHandle {
+        PanicHandle {
+            is_playing: self.is_playing.clone(),
+            is_paused: self.is_paused.clone(),
+        }
+    }
+
+    /// Share updated settings with the running song; mapping changes
+    /// such as transpose are re-applied from the current position
+    pub fn update_config(&self, config: &AppConfig) {
+        *self.live.config.lock().unwrap() = config.clone();
+        self.live.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Jump to a position in the playing song; notes held across the
+    /// jump are released rather than carried over
+    pub fn seek(&self, position_ms: u64) {
+        if self.is_playing() {
+            *self.live.seek_to.lock().unwrap() = Some(position_ms);
+        }
+    }
+
+    /// Stop or resume sending keystrokes while the song keeps playing,
+    /// for rehearsing along without playing in-game. Lasts across songs.
+    pub fn set_muted(&self, muted: bool) {
+        self.live.muted.store(muted, Ordering::SeqCst);
+        if muted {
+            let _ = keyboard::release_all();
+        }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.live.muted.load(Ordering::SeqCst)
+    }
+
     /// Pause playback
     pub fn pause(&mut self) {
         if self.is_playing.load(Ordering::SeqCst) {
@@ -131,6 +694,24 @@ impl PlaybackEngine {
     pub fn is_paused(&self) -> bool {
         self.is_paused.load(Ordering::SeqCst)
     }
+
+    pub fn transport(&self) -> Transport {
+        if !self.is_playing() {
+            Transport::Stopped
+        } else if self.is_paused() {
+            Transport::Paused
+        } else {
+            Transport::Playing
+        }
+    }
+
+    /// Position and length of the song playing or last played, in ms
+    pub fn progress(&self) -> (u64, u64) {
+        (
+            self.live.position_ms.load(Ordering::SeqCst),
+            self.live.duration_ms.load(Ordering::SeqCst),
+        )
+    }
 }
 
 impl Default for PlaybackEngine {
@@ -139,49 +720,524 @@ impl Default for PlaybackEngine {
     }
 }
 
-/// Build a timeline of keyboard events from MIDI events
-fn build_timeline(midi: &MidiFile, config: &AppConfig) -> Result<Vec<ScheduledEvent>> {
-    let mut events = midi.events.clone();
+/// Rotates through alternate keys when the same key repeats quickly
+#[derive(Default)]
+struct KeyAlternation {
+    /// Primary key -> (last start time, position in rotation)
+    last_used: HashMap<String, (u64, usize)>,
+}
+
+impl KeyAlternation {
+    fn pick(&mut self, key: &str, start_ms: u64, config: &AppConfig) -> String {
+        let alternates = match config.key_mapping.alternates.get(key) {
+            Some(alts) if !alts.is_empty() => alts,
+            _ => return key.to_string(),
+        };
+
+        let slot = match self.last_used.get(key) {
+            Some(&(last_ms, slot))
+                if start_ms.saturating_sub(last_ms) < config.alternate_window_ms =>
+            {
+                (slot + 1) % (alternates.len() + 1)
+            }
+            _ => 0,
+        };
+        self.last_used.insert(key.to_string(), (start_ms, slot));
+
+        if slot == 0 {
+            key.to_string()
+        } else {
+            alternates[slot - 1].clone()
+        }
+    }
+}
+
+/// The notes starting within `start_ms..end_ms`, cut off at its end
+fn range_notes(notes: &[NoteEvent], start_ms: u64, end_ms: u64) -> Vec<NoteEvent> {
+    notes
+        .iter()
+        .filter(|n| (start_ms..end_ms).contains(&n.start_ms))
+        .map(|n| NoteEvent {
+            duration_ms: n.duration_ms.min(end_ms - n.start_ms),
+            ..n.clone()
+        })
+        .collect()
+}
+
+/// The notes of a section as `range_notes`, moved to start at 0
+fn section_notes(notes: &[NoteEvent], start_ms: u64, end_ms: u64) -> Vec<NoteEvent> {
+    range_notes(notes, start_ms, end_ms)
+        .into_iter()
+        .map(|n| NoteEvent {
+            start_ms: n.start_ms - start_ms,
+            ..n
+        })
+        .collect()
+}
+
+/// Count-in clicks filling a start delay, as (ms into the delay, beat
+/// number), timed so the next beat after the last one is the song's
+/// first downbeat
+fn count_in_beats(start_delay_ms: u64, bar_ms: u64, beats_per_bar: u8) -> Vec<(u64, u8)> {
+    let beats_per_bar = beats_per_bar.max(1) as u64;
+    let beat_ms = (bar_ms / beats_per_bar).max(1);
+    let count = start_delay_ms / beat_ms;
+    (1..=count)
+        .rev()
+        .map(|before| {
+            let beat = beats_per_bar - (before - 1) % beats_per_bar;
+            (start_delay_ms - before * beat_ms, beat as u8)
+        })
+        .collect()
+}
+
+/// Whether a settings change alters the keystrokes `build_timeline`
+/// makes. Only the settings it reads count, so tempo and other live
+/// changes don't rebuild the timeline or run the script again.
+fn needs_remap(old: &AppConfig, new: &AppConfig) -> bool {
+    old.transpose != new.transpose
+        || old.reference_midi_note != new.reference_midi_note
+        || old.key_mapping != new.key_mapping
+        || old.accidentals != new.accidentals
+        || old.accidental_spelling != new.accidental_spelling
+        || old.accidental_fallback != new.accidental_fallback
+        || old.drum_keys != new.drum_keys
+        || old.mapper != new.mapper
+        || old.script_path != new.script_path
+        || old.velocity_curve != new.velocity_curve
+        || old.note_floor != new.note_floor
+        || old.note_ceiling != new.note_ceiling
+        || old.arrangement != new.arrangement
+        || old.split_hands != new.split_hands
+        || old.rolls != new.rolls
+        || old.retrigger_ms != new.retrigger_ms
+        || old.max_polyphony != new.max_polyphony
+        || old.reduction != new.reduction
+        || old.alternate_window_ms != new.alternate_window_ms
+        || old.max_repeat_rate != new.max_repeat_rate
+        || old.latency_offset_ms != new.latency_offset_ms
+}
+
+/// Where playback begins in a song: its first note less the lead-in if
+/// `skip_silence` is on, otherwise the very start. Every track counts, so
+/// ensemble players with different parts start from the same point.
+pub fn start_position_ms(notes: &[NoteEvent], config: &AppConfig) -> u64 {
+    match notes.iter().map(|n| n.start_ms).min() {
+        Some(first) if config.skip_silence => first.saturating_sub(config.lead_in_ms),
+        _ => 0,
+    }
+}
+
+/// The keystrokes a song plays with the given config, as `start` would
+/// schedule them
+pub fn song_timeline(midi: &MidiFile, config: &AppConfig) -> Result<Vec<ScheduledEvent>> {
+    build_timeline(&midi.enabled_events(), &midi.beats, config)
+}
+
+/// Build a timeline of keyboard events from MIDI events, with `beats`
+/// giving the song's beat grid in the same time as the notes
+fn build_timeline(
+    notes: &[NoteEvent],
+    beats: &[Beat],
+    config: &AppConfig,
+) -> Result<Vec<ScheduledEvent>> {
+    let mapper =
+        mapper::mapper_for(config).ok_or_else(|| anyhow!("Unknown mapper: {}", config.mapper))?;
+    let mut events = arrange::shape_velocities(notes.to_vec(), &config.velocity_curve);
+
+    // User scripts see the song before any of our own simplification,
+    // but with velocities on the configured curve
+    if !config.script_path.is_empty() {
+        events = scripting::run_script(&config.script_path, events)?;
+    }
+
+    let mut events = arrange::arrange(events, beats, config);
 
     // Apply polyphony limit
-    limit_polyphony(&mut events, config.max_polyphony as usize, 10);
+    limit_polyphony(
+        &mut events,
+        config.max_polyphony as usize,
+        10,
+        config.reduction,
+    );
 
-    let mut scheduled = Vec::new();
+    let mut strokes = Vec::new();
+    let mut alternation = KeyAlternation::default();
+    let mut last_press: HashMap<(String, Modifier), u64> = HashMap::new();
+    let min_repeat_ms = if config.max_repeat_rate > 0.0 {
+        (1000.0 / config.max_repeat_rate) as u64
+    } else {
+        0
+    };
 
     for note_event in &events {
-        // Map MIDI note to instrument note
-        let instrument_note = match midi_to_instrument(note_event.note, config) {
-            Some(n) => n,
-            None => continue, // Skip out-of-range notes
-        };
-
-        // Get keystroke for this note
-        let keystroke = match note_to_keystroke(&instrument_note, config) {
+        let mut keystroke = match mapper.map(note_event, config) {
             Some(k) => k,
-            None => continue,
+            None => continue, // Skip unmappable or out-of-range notes
         };
+        keystroke.key = alternation.pick(&keystroke.key, note_event.start_ms, config);
 
+        // Drop repeats of a key faster than the game can take them. Chords
+        // come back from limit_polyphony by pitch, not by start, so a
+        // press can be a few ms earlier than the last one.
+        let pressed = (keystroke.key.clone(), keystroke.modifier);
+        if let Some(&last_ms) = last_press.get(&pressed) {
+            if note_event.start_ms.saturating_sub(last_ms) < min_repeat_ms {
+                continue;
+            }
+        }
+        last_press.insert(pressed, note_event.start_ms);
+        strokes.push((note_event, keystroke));
+    }
+
+    let mut scheduled = Vec::new();
+    for (i, (note_event, keystroke)) in strokes.iter().enumerate() {
         // Schedule key down
         scheduled.push(ScheduledEvent {
             time_ms: note_event.start_ms,
             key: keystroke.key.clone(),
             modifier: keystroke.modifier,
             is_key_down: true,
+            track: note_event.track,
+            channel: note_event.channel,
         });
 
         // Schedule key up
-        // Use minimum duration of 30ms to ensure the keypress registers
-        let duration = note_event.duration_ms.max(30);
+        let duration = match config.key_mapping.tap_ms {
+            // Fixed taps, let go before the same key is struck again
+            Some(tap_ms) => strokes[i + 1..]
+                .iter()
+                .find(|(next, k)| k.key == keystroke.key && next.start_ms > note_event.start_ms)
+                .map_or(tap_ms, |(next, _)| {
+                    tap_ms.min(next.start_ms - note_event.start_ms)
+                }),
+            // Use minimum duration of 30ms to ensure the keypress registers
+            None => note_event.duration_ms.max(30),
+        };
         scheduled.push(ScheduledEvent {
             time_ms: note_event.start_ms + duration,
-            key: keystroke.key,
+            key: keystroke.key.clone(),
             modifier: keystroke.modifier,
             is_key_down: false,
+            track: note_event.track,
+            channel: note_event.channel,
         });
     }
 
+    // Make up for input lag; keystrokes can't move before the start
+    for event in &mut scheduled {
+        event.time_ms = event
+            .time_ms
+            .saturating_add_signed(config.latency_offset_ms);
+    }
+
     // Sort by time
     scheduled.sort_by_key(|e| e.time_ms);
 
     Ok(scheduled)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiInfo;
+    use crate::test_util::note;
+
+    #[test]
+    fn test_practice_tempo_ramp() {
+        let practice = PracticeLoop {
+            start_ms: 0,
+            end_ms: 1000,
+            start_percent: 60.0,
+            step_percent: 15.0,
+        };
+        assert_eq!(practice.tempo_percents(), [60.0, 75.0, 90.0, 100.0]);
+        assert!(practice.check().is_ok());
+
+        let creeping = PracticeLoop {
+            step_percent: 0.5,
+            ..practice
+        };
+        assert!(creeping.check().is_err());
+    }
+
+    #[test]
+    fn test_range_notes() {
+        let notes = [500, 1000, 1800, 2500].map(|start_ms| NoteEvent {
+            start_ms,
+            duration_ms: 400,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            channel: 0,
+        });
+        let range: Vec<_> = range_notes(&notes, 1000, 2000)
+            .iter()
+            .map(|n| (n.start_ms, n.duration_ms))
+            .collect();
+        assert_eq!(range, [(1000, 400), (1800, 200)]);
+        assert_eq!(section_notes(&notes, 1000, 2000)[1].start_ms, 800);
+    }
+
+    #[test]
+    fn test_latency_offset() {
+        let notes = [0, 1000].map(|start_ms| NoteEvent {
+            start_ms,
+            duration_ms: 200,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            channel: 0,
+        });
+        let times = |config: &AppConfig| {
+            build_timeline(&notes, &[], config)
+                .unwrap()
+                .iter()
+                .map(|e| e.time_ms)
+                .collect::<Vec<_>>()
+        };
+        let mut config = AppConfig {
+            latency_offset_ms: -50,
+            ..AppConfig::default()
+        };
+        assert_eq!(times(&config), [0, 150, 950, 1150]);
+        config.latency_offset_ms = 40;
+        assert_eq!(times(&config), [40, 240, 1040, 1240]);
+    }
+
+    #[test]
+    fn test_tap_mode() {
+        let notes = [(0, 900), (100, 900), (1000, 50)].map(|(start_ms, duration_ms)| NoteEvent {
+            start_ms,
+            duration_ms,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            channel: 0,
+        });
+        let mut config = AppConfig::default();
+        config.key_mapping.tap_ms = Some(80);
+        let releases: Vec<u64> = build_timeline(&notes, &[], &config)
+            .unwrap()
+            .iter()
+            .filter(|e| !e.is_key_down)
+            .map(|e| e.time_ms)
+            .collect();
+        // The first tap is cut short by the same key 100 ms later
+        assert_eq!(releases, [80, 180, 1080]);
+    }
+
+    #[test]
+    fn test_repeat_rate() {
+        let notes = [0, 40, 80, 120, 160, 500].map(|start_ms| NoteEvent {
+            start_ms,
+            duration_ms: 30,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            channel: 0,
+        });
+        let config = AppConfig {
+            max_repeat_rate: 10.0,
+            ..AppConfig::default()
+        };
+        let presses: Vec<u64> = build_timeline(&notes, &[], &config)
+            .unwrap()
+            .iter()
+            .filter(|e| e.is_key_down)
+            .map(|e| e.time_ms)
+            .collect();
+        assert_eq!(presses, [0, 120, 500]);
+    }
+
+    #[test]
+    fn test_repeat_rate_limited_chord() {
+        // Limited to two notes, the chord comes back highest first, so C#
+        // is seen before the earlier C on the same key
+        let notes = [(0, 60), (3, 48), (5, 61)].map(|(start_ms, note)| NoteEvent {
+            start_ms,
+            duration_ms: 300,
+            note,
+            velocity: 100,
+            track: 0,
+            channel: 0,
+        });
+        let config = AppConfig {
+            max_repeat_rate: 10.0,
+            max_polyphony: 2,
+            ..AppConfig::default()
+        };
+        let mut presses: Vec<(u64, Modifier)> = build_timeline(&notes, &[], &config)
+            .unwrap()
+            .iter()
+            .filter(|e| e.is_key_down)
+            .map(|e| (e.time_ms, e.modifier))
+            .collect();
+        presses.sort_by_key(|&(time_ms, _)| time_ms);
+        assert_eq!(presses, [(0, Modifier::None), (5, Modifier::Shift)]);
+    }
+
+    #[test]
+    fn test_count_in_beats() {
+        // 3/4 with 500 ms beats: 2.2 s of delay fits four clicks
+        assert_eq!(
+            count_in_beats(2200, 1500, 3),
+            [(200, 3), (700, 1), (1200, 2), (1700, 3)]
+        );
+        assert!(count_in_beats(300, 2000, 4).is_empty());
+    }
+
+    #[test]
+    fn test_start_position() {
+        // The first note is on the second track
+        let notes = [(3200, 0), (3000, 1)].map(|(start_ms, track)| NoteEvent {
+            start_ms,
+            duration_ms: 100,
+            note: 60,
+            velocity: 100,
+            track,
+            channel: 0,
+        });
+        let mut config = AppConfig::default();
+        assert_eq!(start_position_ms(&notes, &config), 0);
+        config.skip_silence = true;
+        assert_eq!(start_position_ms(&notes, &config), 2500);
+        config.lead_in_ms = 5000;
+        assert_eq!(start_position_ms(&notes, &config), 0);
+    }
+
+    #[test]
+    fn test_practice_skip_silence() {
+        let midi = MidiFile {
+            path: "song.mid".into(),
+            hash: String::new(),
+            info: MidiInfo {
+                track_count: 1,
+                duration_ms: 10000,
+                note_count: 2,
+                min_note: 60,
+                max_note: 60,
+                track_names: Vec::new(),
+                markers: Vec::new(),
+                warnings: Vec::new(),
+            },
+            events: vec![note(3000, 500, 60), note(8000, 500, 60)],
+            bar_ms: 2000,
+            time_signature: (4, 4),
+            beats: Vec::new(),
+            disabled_tracks: Vec::new(),
+            bookmarks: Vec::new(),
+            programs: Vec::new(),
+        };
+        let config = AppConfig {
+            skip_silence: true,
+            // Stay in the countdown so the position doesn't move
+            start_delay_ms: 60_000,
+            ..AppConfig::default()
+        };
+        let practice = PracticeLoop {
+            start_ms: 8000,
+            end_ms: 9000,
+            start_percent: 100.0,
+            step_percent: 0.0,
+        };
+
+        // Every repetition starts at the section's first note, not at the
+        // song's first note less the lead-in
+        let mut engine = PlaybackEngine::new();
+        engine.start_practice(&midi, &config, &practice).unwrap();
+        assert_eq!(engine.progress().0, 0);
+        engine.stop();
+    }
+
+    #[test]
+    fn test_unwind_guard() {
+        let is_playing = Arc::new(AtomicBool::new(true));
+        let latest = Arc::new(AtomicU64::new(1));
+        let crash = |id| {
+            let run = Run {
+                id,
+                latest: latest.clone(),
+                is_playing: is_playing.clone(),
+            };
+            thread::spawn(move || {
+                let _guard = UnwindGuard { run: &run };
+                panic!("mid-chord");
+            })
+            .join()
+        };
+
+        // A thread that has been superseded leaves the new song playing
+        assert!(crash(0).is_err());
+        assert!(is_playing.load(Ordering::SeqCst));
+
+        assert!(crash(1).is_err());
+        assert!(!is_playing.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_run() {
+        let engine = PlaybackEngine::new();
+        let run = Run {
+            id: engine.run.fetch_add(1, Ordering::SeqCst) + 1,
+            latest: engine.run.clone(),
+            is_playing: engine.is_playing.clone(),
+        };
+        engine.is_playing.store(true, Ordering::SeqCst);
+        assert!(run.is_active());
+
+        // Restarting makes the old run give way even though playback is on
+        engine.run.fetch_add(1, Ordering::SeqCst);
+        assert!(!run.is_current());
+        assert!(!run.is_active());
+        assert!(engine.is_playing());
+    }
+
+    #[test]
+    fn test_key_alternation() {
+        let mut config = AppConfig::default();
+        config
+            .key_mapping
+            .alternates
+            .insert("A".to_string(), vec!["K".to_string()]);
+
+        let mut alternation = KeyAlternation::default();
+        let picks: Vec<String> = [0, 100, 200, 1000, 1100]
+            .iter()
+            .map(|&t| alternation.pick("A", t, &config))
+            .collect();
+        assert_eq!(picks, vec!["A", "K", "A", "A", "K"]);
+
+        // Keys without alternates are left alone
+        assert_eq!(alternation.pick("S", 0, &config), "S");
+    }
+
+    #[test]
+    fn test_panic_handle() {
+        let engine = PlaybackEngine::new();
+        engine.is_playing.store(true, Ordering::SeqCst);
+        engine.is_paused.store(true, Ordering::SeqCst);
+
+        // Works through a clone, without the engine
+        engine.panic_handle().clone().trigger();
+        assert!(!engine.is_playing());
+        assert!(!engine.is_paused());
+    }
+
+    #[test]
+    fn test_needs_remap() {
+        let old = AppConfig::default();
+        let faster = AppConfig {
+            tempo_factor: 1.5,
+            preview_volume: 0.2,
+            ..old.clone()
+        };
+        assert!(!needs_remap(&old, &faster));
+
+        let transposed = AppConfig {
+            transpose: 2,
+            ..old.clone()
+        };
+        assert!(needs_remap(&old, &transposed));
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::{AppConfig, KeyMapping, LayoutMode, Scale};
+
+/// A built-in instrument layout for a specific game
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Key rows from the lowest pitch to the highest
+    pub rows: &'static [&'static [&'static str]],
+    /// Position of the key that plays the reference note
+    pub reference_index: usize,
+    pub mode: LayoutMode,
+    /// Whether Shift/Ctrl sharps and flats exist
+    pub accidentals: bool,
+}
+
+/// Preset summary for the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetInfo {
+    pub name: String,
+    pub description: String,
+    pub rows: usize,
+    pub key_count: usize,
+    pub mode: LayoutMode,
+    pub accidentals: bool,
+}
+
+const ROWS_21_KEY: &[&[&str]] = &[
+    &["Z", "X", "C", "V", "B", "N", "M"],
+    &["A", "S", "D", "F", "G", "H", "J"],
+    &["Q", "W", "E", "R", "T", "Y", "U"],
+];
+
+const ROWS_SKY_15_KEY: &[&[&str]] = &[
+    &["Y", "U", "I", "O", "P"],
+    &["H", "J", "K", "L", ";"],
+    &["N", "M", ",", ".", "/"],
+];
+
+/// Roblox virtual piano white keys, C2-C7; black keys are Shift+key
+const ROWS_ROBLOX_61_KEY: &[&[&str]] = &[
+    &["1", "2", "3", "4", "5", "6", "7"],
+    &["8", "9", "0", "Q", "W", "E", "R"],
+    &["T", "Y", "U", "I", "O", "P", "A"],
+    &["S", "D", "F", "G", "H", "J", "K"],
+    &["L", "Z", "X", "C", "V", "B", "N"],
+    &["M"],
+];
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "Where Winds Meet",
+        description: "21 keys, Shift for sharps and Ctrl for flats",
+        rows: ROWS_21_KEY,
+        reference_index: 7,
+        mode: LayoutMode::Diatonic,
+        accidentals: true,
+    },
+    Preset {
+        name: "Genshin Windsong Lyre",
+        description: "21 keys, natural notes only",
+        rows: ROWS_21_KEY,
+        reference_index: 7,
+        mode: LayoutMode::Diatonic,
+        accidentals: false,
+    },
+    Preset {
+        name: "Genshin Floral Zither",
+        description: "21 keys, natural notes only",
+        rows: ROWS_21_KEY,
+        reference_index: 7,
+        mode: LayoutMode::Diatonic,
+        accidentals: false,
+    },
+    Preset {
+        name: "Sky 15-key",
+        description: "15 keys over two octaves, natural notes only",
+        rows: ROWS_SKY_15_KEY,
+        reference_index: 0,
+        mode: LayoutMode::Diatonic,
+        accidentals: false,
+    },
+    Preset {
+        name: "Roblox 61-key",
+        description: "Virtual piano, 36 white keys with Shift for sharps",
+        rows: ROWS_ROBLOX_61_KEY,
+        reference_index: 14,
+        mode: LayoutMode::Diatonic,
+        accidentals: true,
+    },
+];
+
+impl Preset {
+    pub fn info(&self) -> PresetInfo {
+        PresetInfo {
+            name: self.name.to_string(),
+            description: self.description.to_string(),
+            rows: self.rows.len(),
+            key_count: self.rows.iter().map(|r| r.len()).sum(),
+            mode: self.mode,
+            accidentals: self.accidentals,
+        }
+    }
+
+    /// Write this preset's layout into `config`
+    pub fn apply(&self, config: &mut AppConfig) {
+        config.key_mapping = KeyMapping {
+            rows: self
+                .rows
+                .iter()
+                .map(|row| row.iter().map(|k| k.to_string()).collect())
+                .collect(),
+            reference_index: self.reference_index,
+            mode: self.mode,
+            scale: Scale::Major,
+            alternates: BTreeMap::new(),
+            tap_ms: None,
+            allow_duplicate_keys: false,
+        };
+        config.accidentals = self.accidentals;
+    }
+}
+
+pub fn list_presets() -> Vec<PresetInfo> {
+    PRESETS.iter().map(Preset::info).collect()
+}
+
+pub fn find_preset(name: &str) -> Result<&'static Preset> {
+    PRESETS
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("Unknown preset: {}", name))
+}
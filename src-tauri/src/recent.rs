@@ -0,0 +1,96 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AppConfig;
+use crate::library::title_from_path;
+
+/// Maximum number of entries kept in the recent-files list
+const MAX_RECENT_FILES: usize = 10;
+
+/// A recently loaded MIDI file and the settings it was last used with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentFile {
+    pub path: String,
+    pub title: String,
+    pub transpose: i32,
+    pub tempo_factor: f64,
+    pub max_polyphony: u8,
+    /// Unix timestamp (seconds) of the last load or playback
+    pub last_used: u64,
+}
+
+/// Where playback of the last song stopped, to resume from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastSession {
+    pub path: String,
+    pub position_ms: u64,
+}
+
+/// Most-recently-used list of MIDI files, newest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecentFiles {
+    pub entries: Vec<RecentFile>,
+    /// Song stopped part way through, if the last one played was
+    #[serde(default)]
+    pub last_session: Option<LastSession>,
+}
+
+impl RecentFiles {
+    /// Get the recent-files path
+    fn recent_path() -> Result<PathBuf> {
+        Ok(AppConfig::config_dir()?.join("recent.json"))
+    }
+
+    /// Load the list from disk, or return an empty list if not found
+    pub fn load() -> Result<Self> {
+        let path = Self::recent_path()?;
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Save the list to disk
+    pub fn save(&self) -> Result<()> {
+        let dir = AppConfig::config_dir()?;
+        fs::create_dir_all(&dir)?;
+
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(Self::recent_path()?, content)?;
+        Ok(())
+    }
+
+    /// Move `path` to the front of the list with the given settings
+    pub fn record(&mut self, path: &str, config: &AppConfig) {
+        self.entries.retain(|e| e.path != path);
+        self.entries.insert(
+            0,
+            RecentFile {
+                path: path.to_string(),
+                title: title_from_path(path),
+                transpose: config.transpose,
+                tempo_factor: config.tempo_factor,
+                max_polyphony: config.max_polyphony,
+                last_used: unix_now(),
+            },
+        );
+        self.entries.truncate(MAX_RECENT_FILES);
+    }
+
+    /// Remove all entries
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::config::{AppConfig, LayoutMode};
+use crate::keyboard_hook;
+use crate::mapper::{keystroke_to_midi, DRUM_CHANNEL};
+use crate::midi::{self, NoteEvent};
+use crate::midi_input::{self, InputConnection};
+
+/// Velocity given to notes played on the computer keyboard
+const KEYBOARD_VELOCITY: u8 = 100;
+
+/// Where a recording takes its notes from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordSource {
+    /// Keys pressed for the in-game instrument, mapped back to pitches
+    Keyboard,
+    /// A MIDI input port, e.g. a keyboard controller, or a network
+    /// session as `rtpmidi:<udp port>[@<allowed ips>]`
+    Midi { port: String },
+}
+
+/// Collects notes as they are played, timed from when recording started
+#[derive(Debug)]
+struct Recorder {
+    started: Instant,
+    /// Sounding notes by what started them (a key name or channel/note),
+    /// so the release ends the right note
+    held: HashMap<String, NoteEvent>,
+    notes: Vec<NoteEvent>,
+}
+
+impl Recorder {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            held: HashMap::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    fn press(&mut self, id: String, note: u8, channel: u8, velocity: u8) {
+        // Key repeat sends more presses while a key is held
+        if self.held.contains_key(&id) {
+            return;
+        }
+        let start_ms = self.elapsed_ms();
+        self.held.insert(
+            id,
+            NoteEvent {
+                start_ms,
+                duration_ms: 0,
+                note,
+                velocity,
+                track: 0,
+                channel,
+            },
+        );
+    }
+
+    fn release(&mut self, id: &str) {
+        let now = self.elapsed_ms();
+        if let Some(mut note) = self.held.remove(id) {
+            note.duration_ms = now - note.start_ms;
+            self.notes.push(note);
+        }
+    }
+
+    /// All recorded notes in start order, ending any still held
+    fn finish(&mut self) -> Vec<NoteEvent> {
+        let ids: Vec<String> = self.held.keys().cloned().collect();
+        for id in ids {
+            self.release(&id);
+        }
+        let mut notes = std::mem::take(&mut self.notes);
+        notes.sort_by_key(|n| n.start_ms);
+        notes
+    }
+}
+
+/// A recording in progress; dropping it without `stop` discards it
+pub struct Recording {
+    recorder: Arc<Mutex<Recorder>>,
+    source: RecordSource,
+    _connection: Option<InputConnection>,
+}
+
+impl Recording {
+    /// Start recording. Keyboard recordings reverse the current layout,
+    /// so the config is the one in use while playing.
+    pub fn start(source: RecordSource, config: &AppConfig) -> Result<Self> {
+        let recorder = Arc::new(Mutex::new(Recorder::new()));
+
+        let connection = match &source {
+            RecordSource::Keyboard => {
+                let recorder = recorder.clone();
+                let config = config.clone();
+                let channel = match config.key_mapping.mode {
+                    LayoutMode::Drums => DRUM_CHANNEL,
+                    _ => 0,
+                };
+                keyboard_hook::set_watcher(Some(Box::new(move |combo, is_down| {
+                    let mut recorder = recorder.lock().unwrap();
+                    if !is_down {
+                        recorder.release(&combo.key);
+                        return;
+                    }
+                    if let Some(note) = keystroke_to_midi(&combo.key, combo.modifier(), &config) {
+                        recorder.press(combo.key.clone(), note, channel, KEYBOARD_VELOCITY);
+                    }
+                })));
+                None
+            }
+            RecordSource::Midi { port } => {
+                let recorder = recorder.clone();
+                let connection = midi_input::connect(port, move |message| {
+                    if let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(message) {
+                        let channel = channel.as_int();
+                        let mut recorder = recorder.lock().unwrap();
+                        match message {
+                            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                                let id = format!("{}:{}", channel, key);
+                                recorder.press(id, key.as_int(), channel, vel.as_int());
+                            }
+                            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                                recorder.release(&format!("{}:{}", channel, key));
+                            }
+                            _ => {}
+                        }
+                    }
+                })?;
+                Some(connection)
+            }
+        };
+
+        Ok(Self {
+            recorder,
+            source,
+            _connection: connection,
+        })
+    }
+
+    /// Stop recording and save the performance as a MIDI file.
+    /// Returns the number of notes recorded.
+    pub fn stop(self, path: &str) -> Result<usize> {
+        let notes = self.recorder.lock().unwrap().finish();
+        midi::save_file(path, &notes)?;
+        Ok(notes.len())
+    }
+}
+
+impl Drop for Recording {
+    /// Unhook keyboard recordings, whether stopped or discarded
+    fn drop(&mut self) {
+        if let RecordSource::Keyboard = self.source {
+            keyboard_hook::set_watcher(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder_pairs_presses() {
+        let mut recorder = Recorder::new();
+        recorder.press("A".into(), 60, 0, 100);
+        recorder.press("A".into(), 60, 0, 100); // key repeat
+        recorder.press("S".into(), 62, 0, 100);
+        recorder.release("A");
+        recorder.release("D"); // never pressed
+
+        let notes = recorder.finish();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].note, 60);
+        assert_eq!(notes[1].note, 62);
+    }
+}
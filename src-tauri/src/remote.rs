@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver};
+use tauri::{AppHandle, Manager};
+
+use crate::error::CommandError;
+use crate::AppState;
+
+/// A command accepted from remote-control clients
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RemoteCommand {
+    Load {
+        path: String,
+    },
+    Play,
+    Pause,
+    Stop,
+    Seek {
+        position_ms: u64,
+    },
+    SetTempo {
+        factor: f64,
+    },
+    /// Add a song to the play queue
+    Queue {
+        path: String,
+    },
+    /// Play the next queued song, or the next one in the folder
+    Next,
+}
+
+/// Run a remote command the same way the matching Tauri command would
+pub fn execute(app: &AppHandle, command: RemoteCommand) -> Result<(), CommandError> {
+    let state = app.state::<AppState>();
+    match command {
+        RemoteCommand::Load { path } => crate::load_song(app, &path).map(|_| ()),
+        RemoteCommand::Play => crate::start_playback(&state),
+        RemoteCommand::Pause => {
+            state.playback.lock().unwrap().pause();
+            Ok(())
+        }
+        RemoteCommand::Stop => {
+            crate::stop_all(&state);
+            Ok(())
+        }
+        RemoteCommand::Seek { position_ms } => {
+            state.playback.lock().unwrap().seek(position_ms);
+            Ok(())
+        }
+        RemoteCommand::SetTempo { factor } => Ok(crate::apply_tempo(&state, factor)?),
+        RemoteCommand::Queue { path } => {
+            state.queue.lock().unwrap().push_back(path);
+            Ok(())
+        }
+        RemoteCommand::Next => crate::play_next_song(app),
+    }
+}
+
+/// Receive every event published from now on, as JSON text
+pub fn subscribe(state: &AppState) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    state.remote_subscribers.lock().unwrap().push(tx);
+    rx
+}
+
+/// Push an event to all remote subscribers, forgetting disconnected ones
+pub fn publish(state: &AppState, event: &str, payload: &impl Serialize) {
+    let message = serde_json::json!({ "event": event, "payload": payload }).to_string();
+    state
+        .remote_subscribers
+        .lock()
+        .unwrap()
+        .retain(|tx| tx.send(message.clone()).is_ok());
+}
+
+/// JSON reply sent back to a client after running a command, with the
+/// error's message and code if it failed
+pub fn reply(result: Result<(), CommandError>) -> String {
+    match result {
+        Ok(()) => serde_json::json!({ "ok": true }),
+        Err(error) => {
+            let code =
+                serde_json::to_value(&error).map_or(serde_json::Value::Null, |v| v["code"].clone());
+            serde_json::json!({ "ok": false, "error": error.to_string(), "code": code })
+        }
+    }
+    .to_string()
+}
@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Result};
+use std::io::Cursor;
+use std::thread;
+use tauri::{AppHandle, Manager};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::error::CommandError;
+use crate::remote::{self, RemoteCommand};
+use crate::AppState;
+
+/// Serve the REST API on `http://127.0.0.1:<port>`. `POST /<command>`
+/// runs a `RemoteCommand` (e.g. `POST /load {"path": ...}`) and must be
+/// sent as `application/json`, and `GET /queue` lists queued songs.
+pub fn start(app: AppHandle, port: u16) -> Result<()> {
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| anyhow!("Failed to start REST API: {}", e))?;
+
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let (status, body) = handle(&app, &mut request);
+            let _ = request.respond(json_response(status, body));
+        }
+    });
+    Ok(())
+}
+
+fn handle(app: &AppHandle, request: &mut Request) -> (u16, String) {
+    let url = request.url().to_string();
+    let name = url.split('?').next().unwrap_or_default().trim_matches('/');
+
+    match (request.method(), name) {
+        (Method::Get, "queue") => {
+            let state = app.state::<AppState>();
+            let queue = state.queue.lock().unwrap();
+            (200, serde_json::json!(*queue).to_string())
+        }
+        // Browsers only send JSON to another origin after asking first,
+        // which we never answer, so web pages can't post commands here
+        (Method::Post, _) if !is_json(request) => (
+            415,
+            remote::reply(Err("Content-Type must be application/json".into())),
+        ),
+        (Method::Post, _) => {
+            let mut body = String::new();
+            if request.as_reader().read_to_string(&mut body).is_err() {
+                let error = CommandError::ParseError {
+                    message: "Unreadable request body".into(),
+                };
+                return (400, remote::reply(Err(error)));
+            }
+            match parse_command(name, &body) {
+                Ok(command) => {
+                    let result = remote::execute(app, command);
+                    let status = if result.is_ok() { 200 } else { 422 };
+                    (status, remote::reply(result))
+                }
+                Err(e) => (400, remote::reply(Err(e))),
+            }
+        }
+        _ => (
+            404,
+            remote::reply(Err(format!("No route for {}", url).into())),
+        ),
+    }
+}
+
+fn is_json(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .and_then(|header| header.value.as_str().split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Build a command from the URL path and an optional JSON object body
+fn parse_command(name: &str, body: &str) -> Result<RemoteCommand, CommandError> {
+    let mut value = if body.trim().is_empty() {
+        serde_json::json!({})
+    } else {
+        serde_json::from_str(body)?
+    };
+    value
+        .as_object_mut()
+        .ok_or_else(|| CommandError::ParseError {
+            message: "Request body must be a JSON object".into(),
+        })?
+        .insert("command".into(), name.into());
+    Ok(serde_json::from_value(value)?)
+}
+
+fn json_response(status: u16, body: String) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
@@ -0,0 +1,369 @@
+use anyhow::{anyhow, ensure, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Input port names of this form (`rtpmidi:5004`) open an AppleMIDI
+/// network session listening on that port, instead of a local device
+pub const PORT_PREFIX: &str = "rtpmidi:";
+
+/// Leads every session packet, where RTP packets start with a version
+const SIGNATURE: [u8; 2] = [0xFF, 0xFF];
+const PROTOCOL_VERSION: u32 = 2;
+
+/// How we show up in the other machine's session list
+const SESSION_NAME: &str = "WWMP";
+
+/// How long each socket is waited on before checking for a close
+const POLL: Duration = Duration::from_millis(50);
+
+/// A passive AppleMIDI participant: the other machine (rtpMIDI on
+/// Windows, Audio MIDI Setup on macOS) connects to it and sends notes.
+/// Sessions use two sockets, control on `port` and data on `port + 1`.
+#[derive(Debug)]
+pub struct Session {
+    control: UdpSocket,
+    data: UdpSocket,
+    ssrc: u32,
+    started: Instant,
+    /// Machines that may join; empty for this machine only
+    allowed: Vec<IpAddr>,
+    peers: Mutex<Vec<SocketAddr>>,
+}
+
+/// The UDP port and allowed machines from the part of a port name after
+/// `PORT_PREFIX`: `5004` takes sessions from this machine only, and
+/// `5004@192.168.1.20,192.168.1.21` from the listed machines
+pub fn parse_address(address: &str) -> Result<(u16, Vec<IpAddr>)> {
+    let (port, allowed) = address.split_once('@').unwrap_or((address, ""));
+    let port = port
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid network MIDI port: {}", port))?;
+    let allowed = allowed
+        .split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| {
+            ip.parse()
+                .map_err(|_| anyhow!("Invalid network MIDI peer address: {}", ip))
+        })
+        .collect::<Result<_>>()?;
+    Ok((port, allowed))
+}
+
+impl Session {
+    /// Listen on `port` and the one after it. With no `allowed` machines
+    /// only this one can connect, as the sockets are bound to 127.0.0.1.
+    pub fn bind(port: u16, allowed: Vec<IpAddr>) -> Result<Self> {
+        // The data port is the next one up, so the last port can't be used
+        ensure!(
+            port != 0 && port < u16::MAX,
+            "Network MIDI port must be between 1 and {}",
+            u16::MAX - 1
+        );
+        let address = if allowed.is_empty() {
+            Ipv4Addr::LOCALHOST
+        } else {
+            Ipv4Addr::UNSPECIFIED
+        };
+        let control = UdpSocket::bind((address, port))?;
+        let data = UdpSocket::bind((address, port + 1))?;
+        control.set_read_timeout(Some(POLL))?;
+        data.set_read_timeout(Some(POLL))?;
+        Ok(Self {
+            control,
+            data,
+            // Only needs to differ from the peer's, which picks at random too
+            ssrc: crate::clock::now_ms() as u32 ^ std::process::id(),
+            started: Instant::now(),
+            allowed,
+            peers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Answer the session protocol and pass each received MIDI message to
+    /// `on_message`, until the sender of `close` is dropped
+    pub fn run(self, close: mpsc::Receiver<()>, mut on_message: impl FnMut(&[u8]) + Send) {
+        let closed = AtomicBool::new(false);
+        // Each socket is waited on by its own thread, so a packet on one
+        // isn't held up by the other's timeout
+        thread::scope(|scope| {
+            scope.spawn(|| self.serve(&self.data, &closed, &mut on_message));
+            scope.spawn(|| self.serve(&self.control, &closed, &mut |_| {}));
+            let _ = close.recv();
+            closed.store(true, Ordering::Relaxed);
+        });
+
+        // Let the other side know rather than leaving a stale session
+        let bye = session_packet(b"BY", 0, self.ssrc);
+        for peer in self.peers.lock().unwrap().iter() {
+            let _ = self.control.send_to(&bye, peer);
+        }
+    }
+
+    /// Handle the packets arriving on one of the session's sockets until
+    /// `closed` is set. MIDI is only expected on the data socket.
+    fn serve(&self, socket: &UdpSocket, closed: &AtomicBool, on_message: &mut dyn FnMut(&[u8])) {
+        let mut buf = [0u8; 1536];
+        while !closed.load(Ordering::Relaxed) {
+            let Ok((len, from)) = socket.recv_from(&mut buf) else {
+                continue;
+            };
+            if !self.allows(from.ip()) {
+                continue;
+            }
+            let packet = &buf[..len];
+            if packet.starts_with(&SIGNATURE) {
+                let mut peers = self.peers.lock().unwrap();
+                if packet[2..].starts_with(b"IN") && !peers.contains(&from) {
+                    peers.push(from);
+                }
+                if packet[2..].starts_with(b"BY") {
+                    peers.retain(|&peer| peer != from);
+                }
+                drop(peers);
+                if let Some(reply) = answer(packet, self.ssrc, self.timestamp()) {
+                    let _ = socket.send_to(&reply, from);
+                }
+            } else {
+                for message in midi_messages(packet) {
+                    on_message(&message);
+                }
+            }
+        }
+    }
+
+    fn allows(&self, ip: IpAddr) -> bool {
+        if self.allowed.is_empty() {
+            ip.is_loopback()
+        } else {
+            self.allowed.contains(&ip)
+        }
+    }
+
+    /// Session clock, in the protocol's units of 100 microseconds
+    fn timestamp(&self) -> u64 {
+        (self.started.elapsed().as_micros() / 100) as u64
+    }
+}
+
+/// Signature, command, version, initiator token and our SSRC: the part
+/// common to invitations, acceptances and goodbyes
+fn session_packet(command: &[u8; 2], token: u32, ssrc: u32) -> Vec<u8> {
+    let mut packet = SIGNATURE.to_vec();
+    packet.extend_from_slice(command);
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.extend_from_slice(&token.to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+    packet
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Our reply to a session packet, if it needs one. Every invitation
+/// from an allowed machine is accepted, and clock syncs the peer starts
+/// are answered so it keeps the session open.
+fn answer(packet: &[u8], ssrc: u32, now: u64) -> Option<Vec<u8>> {
+    let command = packet.get(2..4)?;
+    match command {
+        b"IN" => {
+            let token = be_u32(packet, 8)?;
+            let mut reply = session_packet(b"OK", token, ssrc);
+            reply.extend_from_slice(SESSION_NAME.as_bytes());
+            reply.push(0);
+            Some(reply)
+        }
+        b"CK" => {
+            // SSRC, count, 3 bytes padding, then three 64-bit timestamps
+            let count = *packet.get(8)?;
+            let mut timestamps = packet.get(12..36)?.to_vec();
+            let slot = match count {
+                0 => 1,
+                1 => 2,
+                _ => return None,
+            };
+            timestamps[slot * 8..slot * 8 + 8].copy_from_slice(&now.to_be_bytes());
+
+            let mut reply = SIGNATURE.to_vec();
+            reply.extend_from_slice(b"CK");
+            reply.extend_from_slice(&ssrc.to_be_bytes());
+            reply.extend_from_slice(&[count + 1, 0, 0, 0]);
+            reply.extend_from_slice(&timestamps);
+            Some(reply)
+        }
+        _ => None,
+    }
+}
+
+/// The MIDI messages in an RTP-MIDI packet (RFC 6295), each complete with
+/// its status byte. Delta times and the recovery journal are skipped;
+/// SysEx is dropped.
+fn midi_messages(packet: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let Some(&first) = packet.first() else {
+        return messages;
+    };
+    if first >> 6 != 2 {
+        return messages;
+    }
+    let mut at = 12 + 4 * (first & 0x0F) as usize;
+
+    // Command section header: B J Z P and a 4 or 12 bit length
+    let Some(&flags) = packet.get(at) else {
+        return messages;
+    };
+    let mut len = (flags & 0x0F) as usize;
+    at += 1;
+    if flags & 0x80 != 0 {
+        let Some(&low) = packet.get(at) else {
+            return messages;
+        };
+        len = (len << 8) | low as usize;
+        at += 1;
+    }
+    let delta_first = flags & 0x20 != 0;
+    let Some(list) = packet.get(at..at + len) else {
+        return messages;
+    };
+
+    let mut i = 0;
+    let mut running = None;
+    while i < list.len() {
+        if i > 0 || delta_first {
+            // 1-4 bytes, all but the last with the high bit set
+            while list.get(i).is_some_and(|b| b & 0x80 != 0) {
+                i += 1;
+            }
+            i += 1;
+        }
+        let Some(&byte) = list.get(i) else {
+            break;
+        };
+        let status = if byte & 0x80 != 0 {
+            i += 1;
+            byte
+        } else {
+            match running {
+                Some(status) => status,
+                None => break,
+            }
+        };
+
+        let data_len = match status {
+            0xF0 => {
+                // SysEx runs to its end byte (or a segment boundary)
+                let end = list[i..]
+                    .iter()
+                    .position(|&b| matches!(b, 0xF0 | 0xF4 | 0xF7));
+                i += end.map_or(list.len() - i, |end| end + 1);
+                running = None;
+                continue;
+            }
+            0xF8..=0xFF => {
+                // Real-time messages leave running status alone
+                messages.push(vec![status]);
+                continue;
+            }
+            0xF1 | 0xF3 => 1,
+            0xF2 => 2,
+            0xF4..=0xF7 => 0,
+            _ => {
+                running = Some(status);
+                match status >> 4 {
+                    0xC | 0xD => 1,
+                    _ => 2,
+                }
+            }
+        };
+        if status >= 0xF0 {
+            running = None;
+        }
+        let Some(data) = list.get(i..i + data_len) else {
+            break;
+        };
+        let mut message = vec![status];
+        message.extend_from_slice(data);
+        messages.push(message);
+        i += data_len;
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rtp_packet(flags: u8, list: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 0x61, 0, 1, 0, 0, 0, 0, 0x12, 0x34, 0x56, 0x78];
+        packet.push(flags | list.len() as u8);
+        packet.extend_from_slice(list);
+        packet
+    }
+
+    #[test]
+    fn test_midi_messages() {
+        // Note on, a running-status note on, then a note off, with zero
+        // delta times between them
+        let packet = rtp_packet(0, &[0x90, 60, 100, 0, 64, 90, 0, 0x80, 60, 0]);
+        assert_eq!(
+            midi_messages(&packet),
+            [vec![0x90, 60, 100], vec![0x90, 64, 90], vec![0x80, 60, 0]]
+        );
+
+        // A leading delta time, a clock tick and a dropped SysEx
+        let packet = rtp_packet(0x20, &[0x81, 0x00, 0xF8, 0, 0xF0, 1, 2, 0xF7, 0, 0xC0, 5]);
+        assert_eq!(midi_messages(&packet), [vec![0xF8], vec![0xC0, 5]]);
+
+        // Session packets and truncated lists give nothing
+        assert!(midi_messages(&[0xFF, 0xFF, b'C', b'K']).is_empty());
+        assert!(midi_messages(&rtp_packet(0x05, &[0x90, 60])).is_empty());
+    }
+
+    #[test]
+    fn test_answer() {
+        let mut invitation = session_packet(b"IN", 0xCAFE, 7);
+        invitation.extend_from_slice(b"Piano\0");
+        let reply = answer(&invitation, 42, 0).unwrap();
+        assert_eq!(&reply[..16], session_packet(b"OK", 0xCAFE, 42));
+        assert_eq!(&reply[16..], b"WWMP\0");
+
+        // The peer starts a clock sync; we fill in the second timestamp
+        let mut sync = SIGNATURE.to_vec();
+        sync.extend_from_slice(b"CK");
+        sync.extend_from_slice(&7u32.to_be_bytes());
+        sync.extend_from_slice(&[0, 0, 0, 0]);
+        sync.extend_from_slice(&1000u64.to_be_bytes());
+        sync.extend_from_slice(&[0; 16]);
+        let reply = answer(&sync, 42, 55).unwrap();
+        assert_eq!(reply[8], 1);
+        assert_eq!(&reply[12..20], 1000u64.to_be_bytes());
+        assert_eq!(&reply[20..28], 55u64.to_be_bytes());
+
+        // Its final sync and goodbyes need no reply
+        sync[8] = 2;
+        assert_eq!(answer(&sync, 42, 60), None);
+        assert_eq!(answer(&session_packet(b"BY", 0, 7), 42, 0), None);
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("5004").unwrap(), (5004, vec![]));
+        let (port, allowed) = parse_address("5004@192.168.1.20, 192.168.1.21").unwrap();
+        assert_eq!(port, 5004);
+        assert_eq!(
+            allowed,
+            [
+                "192.168.1.20".parse::<IpAddr>().unwrap(),
+                "192.168.1.21".parse().unwrap()
+            ]
+        );
+        assert!(parse_address("midi").is_err());
+        assert!(parse_address("5004@nowhere").is_err());
+        assert!(Session::bind(u16::MAX, Vec::new()).is_err());
+    }
+}
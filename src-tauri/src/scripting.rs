@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use rhai::{Array, Dynamic, Engine, Map, Scope, INT};
+use std::fs;
+
+use crate::midi::NoteEvent;
+
+/// Upper bound on script work, so a runaway loop can't hang playback
+const MAX_OPERATIONS: u64 = 50_000_000;
+
+/// Run the Rhai script at `path` over the song's notes.
+///
+/// The script sees an `events` array of maps with `start_ms`,
+/// `duration_ms`, `note`, `velocity`, `track` and `channel`, and may
+/// reassign or edit it, e.g.
+///
+/// ```rhai
+/// events = events.filter(|e| e.velocity > 20);
+/// ```
+pub fn run_script(path: &str, events: Vec<NoteEvent>) -> Result<Vec<NoteEvent>> {
+    let source = fs::read_to_string(path)?;
+    transform(&source, events).map_err(|e| anyhow!("Script {}: {}", path, e))
+}
+
+fn transform(source: &str, events: Vec<NoteEvent>) -> Result<Vec<NoteEvent>> {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let array: Array = events.iter().map(to_map).collect();
+    let mut scope = Scope::new();
+    scope.push("events", array);
+
+    engine
+        .run_with_scope(&mut scope, source)
+        .map_err(|e| anyhow!("{}", e))?;
+
+    let array = scope
+        .get_value::<Array>("events")
+        .ok_or_else(|| anyhow!("`events` must stay an array"))?;
+    let mut events = array
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| from_map(value).map_err(|e| anyhow!("events[{}]: {}", i, e)))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Scripts may append notes anywhere; the timeline expects start order
+    events.sort_by_key(|e| e.start_ms);
+    Ok(events)
+}
+
+fn to_map(event: &NoteEvent) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("start_ms".into(), (event.start_ms as INT).into());
+    map.insert("duration_ms".into(), (event.duration_ms as INT).into());
+    map.insert("note".into(), (event.note as INT).into());
+    map.insert("velocity".into(), (event.velocity as INT).into());
+    map.insert("track".into(), (event.track as INT).into());
+    map.insert("channel".into(), (event.channel as INT).into());
+    map.into()
+}
+
+fn from_map(value: Dynamic) -> Result<NoteEvent> {
+    let map = value
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow!("expected a map"))?;
+    let field = |name: &str, max: INT| -> Result<INT> {
+        let value = map
+            .get(name)
+            .ok_or_else(|| anyhow!("missing `{}`", name))?
+            .as_int()
+            .map_err(|_| anyhow!("`{}` must be an integer", name))?;
+        if !(0..=max).contains(&value) {
+            return Err(anyhow!("`{}` must be 0-{}, got {}", name, max, value));
+        }
+        Ok(value)
+    };
+
+    Ok(NoteEvent {
+        start_ms: field("start_ms", INT::MAX)? as u64,
+        duration_ms: field("duration_ms", INT::MAX)? as u64,
+        note: field("note", 127)? as u8,
+        velocity: field("velocity", 127)? as u8,
+        track: field("track", INT::MAX)? as usize,
+        channel: field("channel", 15)? as u8,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::note;
+
+    #[test]
+    fn test_script_transform() {
+        let events = vec![
+            note(0, 100, 60),
+            NoteEvent {
+                velocity: 10,
+                ..note(500, 100, 62)
+            },
+        ];
+
+        // Drop quiet notes and add an echo an octave up
+        let script = r#"
+            events = events.filter(|e| e.velocity > 20);
+            let echo = events[0];
+            echo.start_ms += 250;
+            echo.note += 12;
+            events.push(echo);
+        "#;
+        let result = transform(script, events.clone()).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!((result[1].start_ms, result[1].note), (250, 72));
+
+        assert!(transform("events[0].note = 200;", events.clone()).is_err());
+        assert!(transform("events = 5;", events).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::library;
+
+/// Emitted with the song coming up next in the setlist, or null once
+/// the setlist is done
+pub const UP_NEXT_EVENT: &str = "setlist://up-next";
+
+/// What happens between two songs of a setlist
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Gap {
+    /// Start the next song after this long. The gap is the whole wait:
+    /// the start delay and count-in are not added on top.
+    Pause { ms: u64 },
+    /// Load the next song and wait for the play/pause hotkey
+    WaitForHotkey,
+}
+
+/// The song coming up after the current one
+#[derive(Debug, Clone, Serialize)]
+pub struct UpNext {
+    pub path: String,
+    pub title: String,
+    /// Position in the setlist, from 1
+    pub number: usize,
+    pub count: usize,
+    /// How long until it starts, or None if it waits for the hotkey
+    pub starts_in_ms: Option<u64>,
+}
+
+/// Songs played in order as one performance
+#[derive(Debug, Clone, Serialize)]
+pub struct Setlist {
+    pub songs: Vec<String>,
+    pub gap: Gap,
+    /// Index of the song playing or about to play
+    pub position: usize,
+}
+
+impl Setlist {
+    pub fn new(songs: Vec<String>, gap: Gap) -> Self {
+        Self {
+            songs,
+            gap,
+            position: 0,
+        }
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.songs.get(self.position).map(String::as_str)
+    }
+
+    /// Move on to the next song and describe it, or None at the end
+    pub fn advance(&mut self) -> Option<UpNext> {
+        self.position = (self.position + 1).min(self.songs.len());
+        let path = self.current()?.to_string();
+        Some(UpNext {
+            title: library::title_from_path(&path),
+            path,
+            number: self.position + 1,
+            count: self.songs.len(),
+            starts_in_ms: match self.gap {
+                Gap::Pause { ms } => Some(ms),
+                Gap::WaitForHotkey => None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let songs = vec!["a/One.mid".to_string(), "a/Two.mid".to_string()];
+        let mut setlist = Setlist::new(songs, Gap::WaitForHotkey);
+        assert_eq!(setlist.current(), Some("a/One.mid"));
+
+        let next = setlist.advance().unwrap();
+        assert_eq!((next.number, next.count), (2, 2));
+        assert_eq!(next.starts_in_ms, None);
+        assert_eq!(setlist.current(), Some("a/Two.mid"));
+
+        assert!(setlist.advance().is_none());
+        assert!(setlist.advance().is_none());
+        assert_eq!(setlist.current(), None);
+    }
+}
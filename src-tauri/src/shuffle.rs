@@ -0,0 +1,51 @@
+/// Small seeded generator (SplitMix64), so a shuffle can be repeated
+/// from its seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`; `n` must not be 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Put `items` in a random order that depends only on `seed`
+pub fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = Rng::new(seed);
+    for i in (1..items.len()).rev() {
+        items.swap(i, rng.below(i + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle_is_seeded() {
+        let songs: Vec<u32> = (0..20).collect();
+        let mut first = songs.clone();
+        let mut again = songs.clone();
+        shuffle(&mut first, 42);
+        shuffle(&mut again, 42);
+        assert_eq!(first, again);
+        assert_ne!(first, songs);
+
+        let mut sorted = first.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, songs);
+    }
+}
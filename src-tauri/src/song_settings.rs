@@ -0,0 +1,87 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::{AppConfig, Arrangement, SplitHandConfig};
+use crate::library_db::LibraryDb;
+use crate::midi::Marker;
+
+/// Settings remembered for a single song
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongSettings {
+    pub transpose: i32,
+    pub tempo_factor: f64,
+    pub max_polyphony: u8,
+    #[serde(default)]
+    pub arrangement: Arrangement,
+    #[serde(default)]
+    pub split_hands: SplitHandConfig,
+    /// Tracks excluded from playback
+    pub disabled_tracks: Vec<usize>,
+    /// Named points in the song, sorted by time
+    #[serde(default)]
+    pub bookmarks: Vec<Marker>,
+}
+
+impl SongSettings {
+    /// Capture the song-specific parts of the current config
+    pub fn from_config(
+        config: &AppConfig,
+        disabled_tracks: &[usize],
+        bookmarks: &[Marker],
+    ) -> Self {
+        Self {
+            transpose: config.transpose,
+            tempo_factor: config.tempo_factor,
+            max_polyphony: config.max_polyphony,
+            arrangement: config.arrangement,
+            split_hands: config.split_hands.clone(),
+            disabled_tracks: disabled_tracks.to_vec(),
+            bookmarks: bookmarks.to_vec(),
+        }
+    }
+
+    /// Write these settings back into the config
+    pub fn apply(&self, config: &mut AppConfig) {
+        config.transpose = self.transpose;
+        config.tempo_factor = self.tempo_factor;
+        config.max_polyphony = self.max_polyphony;
+        config.arrangement = self.arrangement;
+        config.split_hands = self.split_hands.clone();
+    }
+}
+
+/// Per-song settings keyed by file content hash, so renamed or moved
+/// files keep their overrides
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SongSettingsStore {
+    pub songs: HashMap<String, SongSettings>,
+}
+
+impl SongSettingsStore {
+    /// Load the store from the library database
+    pub fn load() -> Result<Self> {
+        Ok(Self {
+            songs: LibraryDb::open()?.all_song_settings()?,
+        })
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&SongSettings> {
+        self.songs.get(hash)
+    }
+
+    /// Remember `settings` for the song, saving just its row to the
+    /// library database
+    pub fn set(&mut self, hash: &str, settings: SongSettings) -> Result<()> {
+        let db = LibraryDb::open()?;
+        db.save_song_setting(hash, &settings)?;
+        self.songs.insert(hash.to_string(), settings);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Result<()> {
+        LibraryDb::open()?.delete_song_setting(hash)?;
+        self.songs.remove(hash);
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use serde::Serialize;
+use std::thread;
+use std::time::Duration;
+
+use crate::keyboard::{self, Modifier};
+use crate::playback::ScheduledEvent;
+
+/// Emitted with the chord step mode is waiting on, or null once the
+/// song is done
+pub const STEP_EVENT: &str = "step://chord";
+
+/// Key presses closer together than this form one step
+const CHORD_WINDOW_MS: u64 = 30;
+
+/// Longest a step's keys are held, so a sustained note does not keep
+/// the next step waiting
+const MAX_HOLD_MS: u64 = 1000;
+
+/// The chord shown to the player before it is played
+#[derive(Debug, Clone, Serialize)]
+pub struct StepChord {
+    /// Position of this chord in the song, from 0
+    pub index: usize,
+    pub total: usize,
+    /// Song time of the chord
+    pub time_ms: u64,
+    /// Keys to press, e.g. `["A", "Shift+F"]`
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Chord {
+    time_ms: u64,
+    keystrokes: Vec<(String, Modifier)>,
+    /// Until the first of its keys is released, in song time
+    hold_ms: u64,
+}
+
+/// Walks through a song one chord at a time
+#[derive(Debug)]
+pub struct StepSession {
+    chords: Vec<Chord>,
+    position: usize,
+    /// Whether the player's own key presses move the song on
+    follow_along: bool,
+    /// Keys of the waiting chord the player has pressed so far
+    pressed: Vec<(String, Modifier)>,
+}
+
+impl StepSession {
+    /// Group a song's keystrokes into chords. With `follow_along` the
+    /// player plays each chord themselves (see `press`).
+    pub fn new(events: &[ScheduledEvent], follow_along: bool) -> Self {
+        let mut chords: Vec<Chord> = Vec::new();
+        for (i, event) in events.iter().enumerate() {
+            if !event.is_key_down {
+                continue;
+            }
+            let keystroke = (event.key.clone(), event.modifier);
+            let released_ms = events[i..]
+                .iter()
+                .find(|e| !e.is_key_down && e.key == event.key && e.modifier == event.modifier)
+                .map_or(event.time_ms, |e| e.time_ms);
+
+            match chords.last_mut() {
+                Some(chord) if event.time_ms - chord.time_ms <= CHORD_WINDOW_MS => {
+                    if !chord.keystrokes.contains(&keystroke) {
+                        chord.keystrokes.push(keystroke);
+                    }
+                    chord.hold_ms = chord.hold_ms.min(released_ms - chord.time_ms);
+                }
+                _ => chords.push(Chord {
+                    time_ms: event.time_ms,
+                    keystrokes: vec![keystroke],
+                    hold_ms: released_ms - event.time_ms,
+                }),
+            }
+        }
+        Self {
+            chords,
+            position: 0,
+            follow_along,
+            pressed: Vec::new(),
+        }
+    }
+
+    pub fn follows_along(&self) -> bool {
+        self.follow_along
+    }
+
+    /// The chord waiting to be played, if the song is not over
+    pub fn current(&self) -> Option<StepChord> {
+        let chord = self.chords.get(self.position)?;
+        Some(StepChord {
+            index: self.position,
+            total: self.chords.len(),
+            time_ms: chord.time_ms,
+            keys: chord
+                .keystrokes
+                .iter()
+                .map(|(key, modifier)| modifier.label(key))
+                .collect(),
+        })
+    }
+
+    /// Play the waiting chord in the background, held as long as it is
+    /// in the song at `tempo_factor`, and move on to the next one
+    pub fn advance(&mut self, tempo_factor: f64) {
+        let Some(chord) = self.chords.get(self.position).cloned() else {
+            return;
+        };
+        self.position += 1;
+        self.pressed.clear();
+
+        let hold_ms = (chord.hold_ms as f64 / tempo_factor) as u64;
+        thread::spawn(move || {
+            for (key, modifier) in &chord.keystrokes {
+                let _ = keyboard::press_key(key, *modifier);
+            }
+            thread::sleep(Duration::from_millis(hold_ms.clamp(30, MAX_HOLD_MS)));
+            for (key, modifier) in &chord.keystrokes {
+                let _ = keyboard::release_key(key, *modifier);
+            }
+        });
+    }
+
+    /// Follow-along: the player pressed a key. Once every key of the
+    /// waiting chord has been pressed, moves on and returns true.
+    pub fn press(&mut self, key: &str, modifier: Modifier) -> bool {
+        let Some(chord) = self.chords.get(self.position) else {
+            return false;
+        };
+        let Some(keystroke) = chord
+            .keystrokes
+            .iter()
+            .find(|(k, m)| k.eq_ignore_ascii_case(key) && *m == modifier)
+        else {
+            return false;
+        };
+        if !self.pressed.contains(keystroke) {
+            self.pressed.push(keystroke.clone());
+        }
+        if self.pressed.len() < chord.keystrokes.len() {
+            return false;
+        }
+        self.pressed.clear();
+        self.position += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    #[test]
+    fn test_step_chords() {
+        let events = [
+            event(0, "A", Modifier::None, true),
+            event(10, "F", Modifier::Shift, true),
+            event(200, "A", Modifier::None, false),
+            event(400, "F", Modifier::Shift, false),
+            event(500, "S", Modifier::None, true),
+            event(800, "S", Modifier::None, false),
+        ];
+        let mut session = StepSession::new(&events, true);
+        let first = session.current().unwrap();
+        assert_eq!(first.keys, ["A", "Shift+F"]);
+        assert_eq!(first.total, 2);
+        assert_eq!(session.chords[0].hold_ms, 200);
+        assert_eq!(session.chords[1].hold_ms, 300);
+
+        // Follow-along waits for the whole chord, ignoring wrong keys
+        assert!(!session.press("a", Modifier::None));
+        assert!(!session.press("F", Modifier::None));
+        assert!(session.press("F", Modifier::Shift));
+        assert_eq!(session.current().unwrap().keys, ["S"]);
+    }
+}
@@ -0,0 +1,187 @@
+use midly::{MetaMessage, Track, TrackEventKind};
+
+/// Tempo of a file with no tempo events: 120 BPM
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// A tempo taking effect at a tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TempoChange {
+    tick: u64,
+    /// Microseconds per quarter note
+    tempo: u32,
+    /// Time of `tick` from the start (ms)
+    ms: f64,
+}
+
+/// Converts between MIDI ticks and milliseconds on one timeline
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    ticks_per_beat: u64,
+    /// Sorted by tick, one per tick, the first at tick 0
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    /// Tempo map from (tick, microseconds per beat) pairs in any order.
+    /// Of several tempos at one tick, the last one given wins.
+    pub fn new(ticks_per_beat: u64, tempos: &[(u64, u32)]) -> Self {
+        let ticks_per_beat = ticks_per_beat.max(1);
+        let mut sorted = tempos.to_vec();
+        sorted.sort_by_key(|&(tick, _)| tick);
+
+        let mut changes = vec![TempoChange {
+            tick: 0,
+            tempo: DEFAULT_TEMPO,
+            ms: 0.0,
+        }];
+        for (tick, tempo) in sorted {
+            let tempo = tempo.max(1);
+            let last = changes[changes.len() - 1];
+            if tick == last.tick {
+                changes.last_mut().unwrap().tempo = tempo;
+                continue;
+            }
+            let ms = last.ms + span_ms(tick - last.tick, last.tempo, ticks_per_beat);
+            changes.push(TempoChange { tick, tempo, ms });
+        }
+        Self {
+            ticks_per_beat,
+            changes,
+        }
+    }
+
+    /// Tempo map of `tracks` sharing one timeline. Tempo belongs in the
+    /// first (conductor) track; the others are only read if it has none.
+    pub fn from_tracks(tracks: &[Track], ticks_per_beat: u64) -> Self {
+        let conductor = tempo_events(&tracks[..tracks.len().min(1)]);
+        let tempos = if conductor.is_empty() {
+            tempo_events(tracks)
+        } else {
+            conductor
+        };
+        Self::new(ticks_per_beat, &tempos)
+    }
+
+    pub fn ticks_per_beat(&self) -> u64 {
+        self.ticks_per_beat
+    }
+
+    fn change_at(&self, tick: u64) -> &TempoChange {
+        // The first change is at tick 0, so one always applies
+        let after = self.changes.partition_point(|c| c.tick <= tick);
+        &self.changes[after - 1]
+    }
+
+    /// Microseconds per quarter note at `tick`
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        self.change_at(tick).tempo
+    }
+
+    /// Exact time of `tick` from the start (ms)
+    pub fn tick_ms(&self, tick: u64) -> f64 {
+        let change = self.change_at(tick);
+        change.ms + span_ms(tick - change.tick, change.tempo, self.ticks_per_beat)
+    }
+
+    /// Time of `tick` from the start, in whole milliseconds
+    pub fn ticks_to_ms(&self, tick: u64) -> u64 {
+        self.tick_ms(tick) as u64
+    }
+
+    /// The tick nearest to `ms` from the start
+    pub fn ms_to_ticks(&self, ms: f64) -> u64 {
+        let after = self.changes.partition_point(|c| c.ms <= ms).max(1);
+        let change = &self.changes[after - 1];
+        let ticks = (ms - change.ms) * self.ticks_per_beat as f64 * 1000.0 / change.tempo as f64;
+        change.tick + ticks.round().max(0.0) as u64
+    }
+}
+
+/// Length of `ticks` at `tempo` (ms)
+fn span_ms(ticks: u64, tempo: u32, ticks_per_beat: u64) -> f64 {
+    ticks as f64 * tempo as f64 / (ticks_per_beat as f64 * 1000.0)
+}
+
+/// (tick, tempo) of every tempo event in `tracks`, in file order
+fn tempo_events(tracks: &[Track]) -> Vec<(u64, u32)> {
+    let mut tempos = Vec::new();
+    for track in tracks {
+        let mut current_tick: u64 = 0;
+        for event in track {
+            current_tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                tempos.push((current_tick, tempo.as_int()));
+            }
+        }
+    }
+    tempos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shuffle::Rng;
+    use midly::TrackEvent;
+
+    fn tempo(delta: u32, tempo: u32) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(tempo.into())),
+        }
+    }
+
+    #[test]
+    fn test_tick_ms() {
+        // 120 BPM for a beat, then 60 BPM
+        let map = TempoMap::new(480, &[(480, 1_000_000)]);
+        assert_eq!(map.ticks_to_ms(240), 250);
+        assert_eq!(map.ticks_to_ms(480), 500);
+        assert_eq!(map.ticks_to_ms(960), 1500);
+        assert_eq!(map.ms_to_ticks(1500.0), 960);
+        assert_eq!(map.tempo_at(479), 500_000);
+    }
+
+    #[test]
+    fn test_same_tick_last_wins() {
+        let map = TempoMap::new(100, &[(100, 250_000), (0, 1_000_000), (100, 2_000_000)]);
+        assert_eq!(map.tempo_at(0), 1_000_000);
+        assert_eq!(map.tempo_at(100), 2_000_000);
+        assert_eq!(map.ticks_to_ms(200), 3000);
+    }
+
+    #[test]
+    fn test_conductor_track() {
+        let conductor = vec![tempo(0, 1_000_000)];
+        let melody = vec![tempo(0, 250_000)];
+        let map = TempoMap::from_tracks(&[conductor, melody.clone()], 100);
+        assert_eq!(map.tempo_at(0), 1_000_000);
+
+        // Without tempo in the conductor track, the others count
+        let map = TempoMap::from_tracks(&[Vec::new(), melody], 100);
+        assert_eq!(map.tempo_at(0), 250_000);
+        assert_eq!(TempoMap::from_tracks(&[], 100).tempo_at(0), DEFAULT_TEMPO);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..50 {
+            let mut rng = Rng::new(seed);
+            let ticks_per_beat = 24 + rng.below(32_000) as u64;
+            let tempos: Vec<(u64, u32)> = (0..rng.below(20))
+                .map(|_| {
+                    let tick = rng.next_u64() % 10_000_000;
+                    (tick, 100_000 + rng.below(2_000_000) as u32)
+                })
+                .collect();
+            let map = TempoMap::new(ticks_per_beat, &tempos);
+
+            let mut last_ms = 0.0;
+            for tick in (0..200).map(|i| i * 60_000 + rng.below(60_000) as u64) {
+                let ms = map.tick_ms(tick);
+                assert!(ms >= last_ms);
+                assert_eq!(map.ms_to_ticks(ms), tick, "seed {}", seed);
+                last_ms = ms;
+            }
+        }
+    }
+}
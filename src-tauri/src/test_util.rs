@@ -0,0 +1,29 @@
+//! Shorthand for building events in unit tests
+
+use crate::keyboard::Modifier;
+use crate::midi::NoteEvent;
+use crate::playback::ScheduledEvent;
+
+/// A note on track and channel 0, at velocity 100
+pub fn note(start_ms: u64, duration_ms: u64, note: u8) -> NoteEvent {
+    NoteEvent {
+        start_ms,
+        duration_ms,
+        note,
+        velocity: 100,
+        track: 0,
+        channel: 0,
+    }
+}
+
+/// A key press or release on track and channel 0
+pub fn event(time_ms: u64, key: &str, modifier: Modifier, is_key_down: bool) -> ScheduledEvent {
+    ScheduledEvent {
+        time_ms,
+        key: key.to_string(),
+        modifier,
+        is_key_down,
+        track: 0,
+        channel: 0,
+    }
+}
@@ -0,0 +1,80 @@
+use tauri::{
+    AppHandle, CustomMenuItem, GlobalWindowEvent, Manager, SystemTray, SystemTrayEvent,
+    SystemTrayMenu, SystemTrayMenuItem, WindowEvent,
+};
+
+use crate::AppState;
+
+const APP_NAME: &str = "WWMP";
+
+/// Tray icon with a transport menu, so the main window can stay hidden
+pub fn build() -> SystemTray {
+    let menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("play_pause", "Play/Pause"))
+        .add_item(CustomMenuItem::new("stop", "Stop"))
+        .add_item(CustomMenuItem::new("next", "Next"))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("show", "Show Window"))
+        .add_item(CustomMenuItem::new("quit", "Quit"));
+
+    SystemTray::new().with_menu(menu).with_tooltip(APP_NAME)
+}
+
+pub fn handle_event(app: &AppHandle, event: SystemTrayEvent) {
+    match event {
+        SystemTrayEvent::LeftClick { .. } => show_main_window(app),
+        SystemTrayEvent::MenuItemClick { id, .. } => {
+            let state = app.state::<AppState>();
+            let _ = match id.as_str() {
+                "play_pause" => crate::toggle_playback(&state),
+                "stop" => {
+                    crate::stop_all(&state);
+                    Ok(())
+                }
+                "next" => crate::play_next_song(app),
+                "show" => {
+                    show_main_window(app);
+                    Ok(())
+                }
+                "quit" => {
+                    crate::panic_all(&state);
+                    app.exit(0);
+                    Ok(())
+                }
+                _ => Ok(()),
+            };
+        }
+        _ => {}
+    }
+}
+
+/// Hide the main window to the tray when it is minimized, if enabled
+pub fn handle_window_event(event: GlobalWindowEvent) {
+    let window = event.window();
+    if !matches!(event.event(), WindowEvent::Resized(_)) || window.label() != "main" {
+        return;
+    }
+
+    let state = window.state::<AppState>();
+    let to_tray = state.config.lock().unwrap().minimize_to_tray;
+    if to_tray && window.is_minimized().unwrap_or(false) {
+        let _ = window.hide();
+    }
+}
+
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
+
+/// Show the loaded song's title in the tray tooltip
+pub fn set_current_song(app: &AppHandle, title: Option<&str>) {
+    let tooltip = match title {
+        Some(title) => format!("{} - {}", APP_NAME, title),
+        None => APP_NAME.to_string(),
+    };
+    let _ = app.tray_handle().set_tooltip(&tooltip);
+}
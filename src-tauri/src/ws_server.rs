@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::{header, StatusCode};
+use tungstenite::Message;
+
+use crate::error::CommandError;
+use crate::remote::{self, RemoteCommand};
+use crate::AppState;
+
+/// Origins of the app's own windows. Browsers send the page's origin with
+/// every handshake, so any other origin is a web page trying to drive
+/// playback; clients outside a browser send none.
+const APP_ORIGINS: &[&str] = &[
+    "tauri://localhost",
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+];
+
+/// Serve remote control on `ws://127.0.0.1:<port>`. Clients send
+/// `RemoteCommand`s as JSON and receive published events.
+pub fn start(app: AppHandle, port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let app = app.clone();
+            thread::spawn(move || {
+                let _ = serve_client(&app, stream);
+            });
+        }
+    });
+    Ok(())
+}
+
+fn serve_client(app: &AppHandle, stream: TcpStream) -> Result<()> {
+    // The handshake gets a longer timeout, as a short one would cut off
+    // a request that arrives in pieces
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut socket = tungstenite::accept_hdr(stream, CheckOrigin)
+        .map_err(|e| anyhow!("WebSocket handshake failed: {}", e))?;
+    // Short read timeouts let one thread both read commands and push events
+    socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(50)))?;
+    let events = remote::subscribe(&app.state::<AppState>());
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let result = serde_json::from_str::<RemoteCommand>(&text)
+                    .map_err(CommandError::from)
+                    .and_then(|command| remote::execute(app, command));
+                socket.send(Message::Text(remote::reply(result)))?;
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        while let Ok(event) = events.try_recv() {
+            socket.send(Message::Text(event))?;
+        }
+    }
+}
+
+/// Turns away handshakes from web pages, which any site open in a browser
+/// could otherwise make to localhost
+struct CheckOrigin;
+
+impl Callback for CheckOrigin {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let origin = request
+            .headers()
+            .get(header::ORIGIN)
+            .map(|origin| origin.to_str().unwrap_or_default());
+        if allowed_origin(origin) {
+            Ok(response)
+        } else {
+            let mut error = ErrorResponse::new(Some("Origin not allowed".into()));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            Err(error)
+        }
+    }
+}
+
+fn allowed_origin(origin: Option<&str>) -> bool {
+    origin.is_none_or(|origin| APP_ORIGINS.contains(&origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_origin() {
+        assert!(allowed_origin(None));
+        assert!(allowed_origin(Some("tauri://localhost")));
+        assert!(allowed_origin(Some("https://tauri.localhost")));
+        assert!(!allowed_origin(Some("https://example.com")));
+        assert!(!allowed_origin(Some("")));
+    }
+}
@@ -1,22 +1,29 @@
 const { invoke } = window.__TAURI__.tauri;
 const { open } = window.__TAURI__.dialog;
+const { listen } = window.__TAURI__.event;
 
 // State
 let midiLoaded = false;
 
 // DOM Elements
 const openFileBtn = document.getElementById('openFile');
+const pasteSongBtn = document.getElementById('pasteSong');
 const fileNameSpan = document.getElementById('fileName');
 const fileInfoDiv = document.getElementById('fileInfo');
 const playBtn = document.getElementById('playBtn');
 const pauseBtn = document.getElementById('pauseBtn');
 const stopBtn = document.getElementById('stopBtn');
+const restartBtn = document.getElementById('restartBtn');
 const tempoSlider = document.getElementById('tempo');
 const tempoValue = document.getElementById('tempoValue');
 const transposeSlider = document.getElementById('transpose');
 const transposeValue = document.getElementById('transposeValue');
 const polyphonySelect = document.getElementById('polyphony');
 const delayInput = document.getElementById('delay');
+const audioPreviewToggle = document.getElementById('audioPreview');
+const previewVolumeSlider = document.getElementById('previewVolume');
+const previewVolumeValue = document.getElementById('previewVolumeValue');
+const previewDeviceSelect = document.getElementById('previewDevice');
 const statusSpan = document.getElementById('status');
 
 // File open handler
@@ -27,29 +34,31 @@ openFileBtn.addEventListener('click', async () => {
       filters: [{
         name: 'MIDI Files',
         extensions: ['mid', 'midi']
+      }, {
+        name: 'MML Files',
+        extensions: ['mml']
       }]
     });
 
     if (filePath) {
       setStatus('Loading...');
       const info = await invoke('load_midi_file', { path: filePath });
-
-      // Update UI
-      const fileName = filePath.split(/[/\\]/).pop();
-      fileNameSpan.textContent = fileName;
-
-      document.getElementById('duration').textContent = formatDuration(info.duration_ms);
-      document.getElementById('noteCount').textContent = info.note_count.toLocaleString();
-      document.getElementById('noteRange').textContent = `${midiNoteToName(info.min_note)} - ${midiNoteToName(info.max_note)}`;
-      document.getElementById('trackCount').textContent = info.track_count;
-
-      fileInfoDiv.classList.remove('hidden');
-      midiLoaded = true;
-      updatePlaybackButtons();
-      setStatus('File loaded');
+      showSong(filePath, info);
     }
   } catch (e) {
-    setStatus(`Error: ${e}`, true);
+    setStatus(`Error: ${errorMessage(e)}`, true);
+    console.error(e);
+  }
+});
+
+// Clipboard paste handler
+pasteSongBtn.addEventListener('click', async () => {
+  try {
+    setStatus('Loading...');
+    const pasted = await invoke('load_from_clipboard');
+    showSong(pasted.path, pasted.info);
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
     console.error(e);
   }
 });
@@ -63,7 +72,7 @@ playBtn.addEventListener('click', async () => {
     pauseBtn.disabled = false;
     stopBtn.disabled = false;
   } catch (e) {
-    setStatus(`Error: ${e}`, true);
+    setStatus(`Error: ${errorMessage(e)}`, true);
   }
 });
 
@@ -73,7 +82,7 @@ pauseBtn.addEventListener('click', async () => {
     setStatus('Paused');
     playBtn.disabled = false;
   } catch (e) {
-    setStatus(`Error: ${e}`, true);
+    setStatus(`Error: ${errorMessage(e)}`, true);
   }
 });
 
@@ -83,7 +92,19 @@ stopBtn.addEventListener('click', async () => {
     setStatus('Stopped');
     updatePlaybackButtons();
   } catch (e) {
-    setStatus(`Error: ${e}`, true);
+    setStatus(`Error: ${errorMessage(e)}`, true);
+  }
+});
+
+restartBtn.addEventListener('click', async () => {
+  try {
+    await invoke('restart');
+    setStatus('Playing...');
+    playBtn.disabled = true;
+    pauseBtn.disabled = false;
+    stopBtn.disabled = false;
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
   }
 });
 
@@ -103,11 +124,164 @@ transposeSlider.addEventListener('input', async () => {
   transposeValue.textContent = value > 0 ? `+${value}` : value;
   try {
     await invoke('set_transpose', { semitones: parseInt(value) });
+    await refreshWarnings();
+  } catch (e) {
+    console.error(e);
+  }
+});
+
+polyphonySelect.addEventListener('change', async () => {
+  try {
+    await invoke('set_max_polyphony', { maxNotes: parseInt(polyphonySelect.value) });
   } catch (e) {
     console.error(e);
   }
 });
 
+delayInput.addEventListener('change', async () => {
+  try {
+    await invoke('set_start_delay', { delayMs: parseInt(delayInput.value) });
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
+  }
+});
+
+audioPreviewToggle.addEventListener('change', async () => {
+  try {
+    await invoke('set_audio_preview', { enabled: audioPreviewToggle.checked });
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
+  }
+});
+
+previewVolumeSlider.addEventListener('input', () => {
+  previewVolumeValue.textContent = `${previewVolumeSlider.value}%`;
+  setPreviewVolume(previewVolumeSlider.value / 100);
+});
+
+previewVolumeSlider.addEventListener('change', async () => {
+  try {
+    await invoke('set_preview_volume', { volume: previewVolumeSlider.value / 100 });
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
+  }
+});
+
+previewDeviceSelect.addEventListener('change', async () => {
+  try {
+    await setPreviewDevice(previewDeviceSelect.value);
+    await invoke('set_preview_device', { deviceId: previewDeviceSelect.value });
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
+  }
+});
+
+// Audio preview: a soft tone per note, as the game should sound it
+let audioContext = null;
+let masterGain = null;
+let previewVolume = 0.5;
+let previewDevice = '';
+const sounding = new Map();
+
+function ensureAudio() {
+  if (!audioContext) {
+    audioContext = new AudioContext();
+    masterGain = audioContext.createGain();
+    masterGain.gain.value = previewVolume;
+    masterGain.connect(audioContext.destination);
+    if (previewDevice) {
+      audioContext.setSinkId?.(previewDevice).catch(console.error);
+    }
+  }
+}
+
+function setPreviewVolume(volume) {
+  previewVolume = volume;
+  if (masterGain) {
+    masterGain.gain.value = volume;
+  }
+}
+
+async function setPreviewDevice(deviceId) {
+  previewDevice = deviceId;
+  if (audioContext?.setSinkId) {
+    await audioContext.setSinkId(deviceId);
+  }
+}
+
+// Output devices the preview can be sent to, e.g. headphones rather than
+// the stream mix
+async function listPreviewDevices() {
+  if (!navigator.mediaDevices?.enumerateDevices) {
+    return;
+  }
+  const devices = await navigator.mediaDevices.enumerateDevices();
+  for (const device of devices.filter(d => d.kind === 'audiooutput' && d.deviceId !== 'default')) {
+    const option = document.createElement('option');
+    option.value = device.deviceId;
+    option.textContent = device.label || `Output ${previewDeviceSelect.options.length}`;
+    previewDeviceSelect.appendChild(option);
+  }
+}
+
+// Rough voice per General MIDI instrument family (program / 8), so the
+// preview resembles the original arrangement
+const FAMILY_VOICES = [
+  { wave: 'triangle', decay: 1.5 }, // piano
+  { wave: 'sine', decay: 0.8 }, // chromatic percussion
+  { wave: 'square', decay: 0 }, // organ
+  { wave: 'sawtooth', decay: 1.0 }, // guitar
+  { wave: 'triangle', decay: 0 }, // bass
+  { wave: 'sawtooth', decay: 0 }, // strings
+  { wave: 'sawtooth', decay: 0 }, // ensemble
+  { wave: 'sawtooth', decay: 0 }, // brass
+  { wave: 'square', decay: 0 }, // reed
+  { wave: 'sine', decay: 0 }, // pipe
+  { wave: 'square', decay: 0 }, // synth lead
+  { wave: 'sine', decay: 0 }, // synth pad
+  { wave: 'sawtooth', decay: 0 }, // synth effects
+  { wave: 'triangle', decay: 1.0 }, // ethnic
+  { wave: 'sine', decay: 0.3 }, // percussive
+  { wave: 'sine', decay: 0.5 }, // sound effects
+];
+
+function startNote(note, program = 0) {
+  ensureAudio();
+  stopNote(note);
+  const voice = FAMILY_VOICES[Math.floor(program / 8)] ?? FAMILY_VOICES[0];
+  const oscillator = audioContext.createOscillator();
+  const gain = audioContext.createGain();
+  oscillator.type = voice.wave;
+  oscillator.frequency.value = 440 * Math.pow(2, (note - 69) / 12);
+  // Harsher waves are louder at the same gain
+  gain.gain.value = voice.wave === 'sawtooth' || voice.wave === 'square' ? 0.12 : 0.3;
+  if (voice.decay > 0) {
+    // Plucked and struck instruments fade while held
+    gain.gain.setTargetAtTime(0, audioContext.currentTime, voice.decay / 3);
+  }
+  oscillator.connect(gain).connect(masterGain);
+  oscillator.start();
+  sounding.set(note, { oscillator, gain });
+}
+
+function stopNote(note) {
+  const voice = sounding.get(note);
+  if (voice) {
+    // Fade out briefly so notes don't click
+    voice.gain.gain.setTargetAtTime(0, audioContext.currentTime, 0.02);
+    voice.oscillator.stop(audioContext.currentTime + 0.1);
+    sounding.delete(note);
+  }
+}
+
+listen('playback://progress', ({ payload }) => {
+  if (payload.state === 'note') {
+    payload.on ? startNote(payload.note, payload.program) : stopNote(payload.note);
+  } else if (payload.state === 'finished' || payload.state === 'stopped') {
+    [...sounding.keys()].forEach(stopNote);
+  }
+});
+
 // Keyboard test handlers
 document.querySelectorAll('.key').forEach(key => {
   key.addEventListener('click', async () => {
@@ -121,16 +295,136 @@ document.querySelectorAll('.key').forEach(key => {
         key.style.background = '';
       }, 100);
     } catch (e) {
-      setStatus(`Key test failed: ${e}`, true);
+      setStatus(`Key test failed: ${errorMessage(e)}`, true);
     }
   });
 });
 
+// Transpose hotkeys move the slider too
+listen('playback://transpose', ({ payload }) => {
+  transposeSlider.value = payload;
+  transposeValue.textContent = payload > 0 ? `+${payload}` : payload;
+  refreshWarnings().catch(console.error);
+});
+
+// Files opened from Explorer ("Open with", double-click) while running
+listen('app://open-file', (event) => {
+  showSong(event.payload.path, event.payload.info);
+});
+
+// Settings reset or warned about when config.json changed on disk
+listen('config://report', ({ payload }) => {
+  showConfigReport(payload);
+});
+
+// Large files take a moment to parse
+listen('song://load-progress', ({ payload }) => {
+  if (payload.fraction < 1) {
+    setStatus(`Loading... ${Math.round(payload.fraction * 100)}%`);
+  }
+});
+
 // Helper functions
+function showSong(filePath, info) {
+  const fileName = filePath.split(/[/\\]/).pop();
+  fileNameSpan.textContent = fileName;
+
+  document.getElementById('duration').textContent = formatDuration(info.duration_ms);
+  document.getElementById('noteCount').textContent = info.note_count.toLocaleString();
+  document.getElementById('noteRange').textContent = `${midiNoteToName(info.min_note)} - ${midiNoteToName(info.max_note)}`;
+  document.getElementById('trackCount').textContent = info.track_count;
+  showWarnings(info.warnings || []);
+
+  fileInfoDiv.classList.remove('hidden');
+  midiLoaded = true;
+  updatePlaybackButtons();
+  setStatus('File loaded');
+}
+
+// Transposing can push notes out of range, or bring them back
+async function refreshWarnings() {
+  const info = await invoke('get_song_info');
+  if (info) {
+    showWarnings(info.warnings || []);
+  }
+}
+
+// List what's wrong with a file that loaded anyway
+function showWarnings(warnings) {
+  const list = document.getElementById('warningList');
+  list.replaceChildren(...warnings.map(warning => {
+    const item = document.createElement('li');
+    item.textContent = describeWarning(warning);
+    return item;
+  }));
+  document.getElementById('fileWarnings').classList.toggle('hidden', warnings.length === 0);
+}
+
+function describeWarning(warning) {
+  const track = `Track ${warning.track + 1}`;
+  const notes = count => `${count} note${count === 1 ? '' : 's'}`;
+  switch (warning.kind) {
+    case 'damaged_track':
+      return `${track} is damaged; only the part before the damage plays`;
+    case 'missing_tracks':
+      return `Only ${warning.found} of ${warning.expected} tracks could be found`;
+    case 'empty_track':
+      return `${track} is empty`;
+    case 'zero_length_notes':
+      return `${track} has ${notes(warning.count)} with no length`;
+    case 'unclosed_notes':
+      return `${track} has ${notes(warning.count)} never released, held to the end of the track`;
+    case 'out_of_range_notes':
+      return `${notes(warning.count)} fall outside the MIDI range at transpose ${warning.transpose}`;
+    default:
+      return warning.kind;
+  }
+}
+
+// Pick up where the backend is, e.g. after the window was reloaded
+async function restorePlaybackState() {
+  try {
+    const playback = await invoke('get_playback_state');
+    const tempo = Math.round(playback.tempo_factor * 100);
+    tempoSlider.value = tempo;
+    tempoValue.textContent = `${tempo}%`;
+    transposeSlider.value = playback.transpose;
+    transposeValue.textContent = playback.transpose > 0 ? `+${playback.transpose}` : playback.transpose;
+    const config = await invoke('get_config');
+    audioPreviewToggle.checked = config.audio_preview;
+    const volume = Math.round(config.preview_volume * 100);
+    previewVolumeSlider.value = volume;
+    previewVolumeValue.textContent = `${volume}%`;
+    setPreviewVolume(config.preview_volume);
+    await listPreviewDevices();
+    previewDeviceSelect.value = config.preview_device;
+    // A saved device that is no longer plugged in falls back to the default
+    if (previewDeviceSelect.value === config.preview_device) {
+      previewDevice = config.preview_device;
+    }
+
+    if (!playback.current_file) {
+      return;
+    }
+    fileNameSpan.textContent = playback.current_file.split(/[/\\]/).pop();
+    midiLoaded = true;
+    updatePlaybackButtons();
+    if (playback.state !== 'stopped') {
+      playBtn.disabled = playback.state === 'playing';
+      pauseBtn.disabled = false;
+      stopBtn.disabled = false;
+      setStatus(playback.state === 'playing' ? 'Playing...' : 'Paused');
+    }
+  } catch (e) {
+    console.error(e);
+  }
+}
+
 function updatePlaybackButtons() {
   playBtn.disabled = !midiLoaded;
   pauseBtn.disabled = true;
   stopBtn.disabled = true;
+  restartBtn.disabled = !midiLoaded;
 }
 
 function setStatus(message, isError = false) {
@@ -138,6 +432,37 @@ function setStatus(message, isError = false) {
   statusSpan.style.color = isError ? 'var(--accent)' : 'var(--success)';
 }
 
+// Commands fail with `{ code, ... }`; turn the common codes into advice
+function errorMessage(e) {
+  switch (e?.code) {
+    case 'file_not_found':
+      return `File not found. ${e.message}`;
+    case 'parse_error':
+      return `Not a readable MIDI file. ${e.message}`;
+    case 'unknown_key':
+      return `The key "${e.key}" cannot be pressed. Check the key mapping.`;
+    case 'backend_unavailable':
+      return `Could not send keys. Try running as administrator. (${e.message})`;
+    case 'no_song_loaded':
+      return 'Open a MIDI file first.';
+    case 'invalid_config':
+      return e.errors.map((err) => `${err.field}: ${err.message}`).join('; ');
+    default:
+      return e?.message ?? String(e);
+  }
+}
+
+// Settings `config.json` had that couldn't be used and were reset, or
+// that were kept but look wrong
+function showConfigReport(report) {
+  const describe = (errors) => errors.map((err) => `${err.field}: ${err.message}`).join('; ');
+  if (report.reset.length > 0) {
+    setStatus(`Settings reset to defaults: ${describe(report.reset)}`, true);
+  } else if (report.warnings.length > 0) {
+    setStatus(`Config warnings: ${describe(report.warnings)}`, true);
+  }
+}
+
 function formatDuration(ms) {
   const seconds = Math.floor(ms / 1000);
   const minutes = Math.floor(seconds / 60);
@@ -153,7 +478,19 @@ function midiNoteToName(note) {
 }
 
 // Initialize
-document.addEventListener('DOMContentLoaded', () => {
+document.addEventListener('DOMContentLoaded', async () => {
   updatePlaybackButtons();
   setStatus('Ready');
+  await restorePlaybackState();
+  showConfigReport(await invoke('get_config_report'));
+
+  // Launched with a file, e.g. by double-clicking a .mid
+  try {
+    const opened = await invoke('take_initial_file');
+    if (opened) {
+      showSong(opened.path, opened.info);
+    }
+  } catch (e) {
+    setStatus(`Error: ${errorMessage(e)}`, true);
+  }
 });